@@ -11,6 +11,8 @@ Soft Analytical Side-Channel Attack (SASCA)
 
    FactorGraph
    BPState
+   GraphAnalysis
+   VarTrace
 
 
 Deprecated
@@ -26,7 +28,7 @@ Deprecated
    SASCAGraph
 """
 
-__all__ = ["FactorGraph", "BPState"]
+__all__ = ["FactorGraph", "BPState", "GraphAnalysis", "VarTrace"]
 
 from .sascagraph import SASCAGraph
-from .factor_graph import FactorGraph, BPState
+from .factor_graph import FactorGraph, BPState, GraphAnalysis, VarTrace
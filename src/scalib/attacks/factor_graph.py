@@ -1,15 +1,70 @@
-from typing import Sequence, Mapping, Union, Optional
+from typing import (
+    Dict,
+    List,
+    NamedTuple,
+    Sequence,
+    Mapping,
+    Union,
+    Optional,
+    Tuple,
+)
 
 import numpy as np
 import numpy.typing as npt
 
 from scalib import _scalib_ext
 from scalib.config import get_config
+from scalib.modeling import LDAClassifier, RLDAClassifier
 
-__all__ = ["FactorGraph", "BPState"]
+__all__ = ["FactorGraph", "BPState", "GraphAnalysis", "VarTrace"]
 
 CstValue = Union[int, Sequence[int]]
 ValsAssign = Mapping[str, CstValue]
+EvidenceModel = Union[
+    Tuple[LDAClassifier, Sequence[int]], Tuple[RLDAClassifier, Sequence[int], int]
+]
+
+
+class GraphAnalysis(NamedTuple):
+    """Structure of a :class:`FactorGraph` and cost of running belief
+    propagation on it, see :meth:`FactorGraph.analyze`.
+    """
+
+    nmulti: int
+    "Number of executions the analysis is made for."
+    cyclic: bool
+    "Belief propagation is loopy (see :meth:`BPState.is_cyclic`)."
+    cycles: List[List[str]]
+    """Cycles of one execution (a cycle basis of the graph), given as the
+    variables along each cycle."""
+    multi_cycles: List[List[str]]
+    """Cycles through several executions (only if ``nmulti > 1``): path
+    between two ``SINGLE`` variables, which is closed by the same path in
+    another execution."""
+    treewidth: int
+    """Upper bound on the treewidth of the graph (with all the executions),
+    same as the ``width`` of :meth:`BPState.exact_cost`."""
+    edge_memory: int
+    "Memory (in bytes) of the beliefs on the edges of a :class:`BPState`."
+    var_memory: int
+    """Memory (in bytes) of the evidence and of the distributions of the
+    variables of a :class:`BPState`."""
+
+
+class VarTrace(NamedTuple):
+    """Evolution of a variable during belief propagation, see
+    :meth:`BPState.set_trace`.
+
+    Each field has one row per iteration: of shape `(it,)` for a ``SINGLE``
+    variable and `(it, nexec)` for a ``MULTI`` variable.
+    """
+
+    entropy: npt.NDArray[np.float64]
+    "Entropy (in bits) of the distribution of the variable."
+    argmax: npt.NDArray[np.uint32]
+    "Most likely value of the variable."
+    probability: Optional[npt.NDArray[np.float64]]
+    "Probability of the reference value, None if there is no reference value."
 
 
 class FactorGraph:
@@ -33,9 +88,9 @@ class FactorGraph:
     relationships that relate at least on `MULTI` variable.
 
     Relationships between variables are bitwise XOR, bitwise AND, bitwise OR,
-    bitiwise negation, modular addition, modular multiplication and lookup
-    table. A lookup table can describe any function that maps a single variable
-    to another variable.
+    bitiwise negation, modular addition, modular multiplication, multiplication
+    in the binary field GF(nc) and lookup table. A lookup table can describe any function that maps a single variable
+    (or a pair of variables) to another variable.
     Description of `nc`, the variables, and the relationships is given in a
     text format specified below.
 
@@ -96,17 +151,72 @@ class FactorGraph:
       is an identifier of the variable (allowed characters are letters, digits
       and underscore). One of the qualifiers `SINGLE` or `MULTI` must be given.
     - `VAR SINGLE|MULTI variable_name`: declares a variables.
+      A variable can have its own domain size with `VAR SINGLE x NC 16`
+      (values in `[0, 16)`). Such a variable can only appear in `BITS`
+      properties, or as the result of `HW` and `HD` properties.
     - `PROPERTY w = x^y^z`: declares a bitwise XOR property. There can be any
       number of operands.
     - `PROPERTY x = x&y`: declares a bitwise AND property.
     - `PROPERTY x = t[y]`: declares a LOOKUP property (`y` is the lookup of the
       table `t` at index `y`). No public variable is allowed in this property.
+    - `PROPERTY x = t[y, z]`: declares a two-input LOOKUP property (`x` is the
+      lookup of the 2D table `t` at row `y` and column `z`). No public
+      variable is allowed in this property.
+    - `PROPERTY x = y + z`: declares a modular addition property (modulo
+      `nc`). Operands can also be subtracted (`x = y - z`), negated
+      (`x = -y`) and multiplied by an integer constant (`x = 3*y - z`),
+      except for public operands that can only be added or subtracted.
+    - `PROPERTY x = y * z`: declares a modular multiplication property.
+    - `PROPERTY x = y`: declares an equality property.
     - `PROPERTY x = !y`: declares a bitwise NOT property.
       No public variable is allowed in this property.
+    - `PROPERTY x = y . z`: declares a multiplication in GF(nc) (e.g. the
+      AES MixColumns or multiplicative masking). There can be any number of
+      operands, and the result must be a variable (not a public). Requires a
+      `FIELD` statement.
+    - `PROPERTY x0, x1 = BITS(x)`: declares a decomposition of `x` into
+      parts, least significant first (`x = x0 + nc0*x1` where `nc0` is the
+      domain size of `x0`). The product of the domain sizes of the parts must
+      be the domain size of `x` (e.g. 8 bits `NC 2`, or two nibbles `NC 16`,
+      for a byte). No public variable is allowed in this property.
+    - `PROPERTY h = HW(x)`: declares a Hamming weight property, and
+      `PROPERTY h = HD(x, y)` declares a Hamming distance property. `h` must
+      be a variable, whose domain size is at least one more than the largest
+      Hamming weight (e.g. `VAR MULTI h NC 9` for `NC 256`), so that evidence
+      can be given from a Hamming weight template. Operands can be public.
+      `nc` must be a power of two.
+    - `FIELD <poly>`: specifies the reduction polynomial of GF(nc), in decimal
+      or hexadecimal (e.g. `FIELD 0x11b` for AES). It must be irreducible, of
+      degree log2(nc).
     - `TABLE` t = [0, 3, 2, 1]`: Declares a table that can be used in a LOOKUP.
       The values provided in the table must belong to the interval [0, nc).
       The initialization expression can be omitted from the graph description
       (e.g. `TABLE t`) and be given with `tables` parameter.
+      A table has either `nc` values (single-input lookup) or `nc*nc` values
+      (two-input lookup, `t[y, z]` being at index `y*nc+z`). Tables given with
+      the `tables` parameter may also be 2D arrays of shape `(nc, nc)`.
+    - Names that are not identifiers (e.g. names of local variables of
+      `GRAPH` instances such as `r0.t`) can be written between double quotes
+      (e.g. `PROPERTY y = "r0.t" ^ k`), where `"` and `\` are escaped as
+      `\"` and `\\`.
+    - `VAR MULTI x[16]` (or `PUB MULTI x[16]`): declares the indexed
+      variables `x[0]`, ..., `x[15]`, which are used as e.g. `x[3]` in
+      properties.
+    - `GRAPH NAME(x, k[16])` ... `END`: declares a reusable block of
+      statements (a template) with parameters `x` and `k`, where `k` is an
+      array of 16 variables (used as `k[0]`, ..., `k[15]` in the block).
+      Blocks contain `VAR`, `PUB` and `PROPERTY` statements and
+      instantiations of other blocks. Variables declared in a block are local
+      to each instance, other names refer to the variables of the graph.
+    - `NAME(a, b[0..16])` or `label: NAME(a, b[0..16])`: instantiates the
+      block `NAME`. Arguments are variables or ranges of indexed variables
+      (`b[0..16]` is `b[0]`, ..., `b[15]`). When a range is given for a
+      scalar parameter, the block is instantiated once per element of the
+      range (e.g. `SBOX(x[0..16], k[0..16])` instantiates `SBOX` 16 times).
+      The local variables and named properties of an instance are named
+      `label.name`, or `label[i].name` for the i-th element of the range,
+      where the default label is `NAME_0`, `NAME_1`, etc.
+      Errors in a block are reported with the instance and line number.
 
 
     **Note**: if the `MULTI` feature doesn't match your use-case, using only
@@ -129,8 +239,51 @@ class FactorGraph:
     ):
         if tables is None:
             tables = dict()
+        tables = {k: np.ravel(v) for k, v in tables.items()}
         self._inner = _scalib_ext.FactorGraph(graph_text, tables)
 
+    @classmethod
+    def from_netlist(
+        cls,
+        netlist: str,
+        format: str = "blif",
+        *,
+        single: Sequence[str] = (),
+        public: Sequence[str] = (),
+        probes: Sequence[str] = (),
+    ) -> "FactorGraph":
+        """Build a graph over bits (``NC 2``) from a gate-level netlist.
+
+        Every wire is a variable (or a public) and every gate is a property.
+        Supported netlists are BLIF (``.names`` logic covers that are a possibly
+        negated AND, OR or XOR of their inputs), and structural Verilog (a
+        single module with ``input``, ``output`` and ``wire`` declarations,
+        primitive gates ``and``, ``nand``, ``or``, ``nor``, ``xor``, ``xnor``,
+        ``not``, ``buf`` and ``assign`` statements such as ``y = a & ~b``).
+        The bits of a bus ``x`` are the variables ``x[0]``, ``x[1]``, etc.
+
+        Parameters
+        ----------
+        netlist:
+            The netlist source.
+        format:
+            ``"blif"`` or ``"verilog"``.
+        single:
+            Names of the SINGLE wires or buses (e.g. the key). Other inputs are
+            MULTI, and the output of a gate is MULTI if any of its inputs is.
+        public:
+            Names of the public wires or buses (e.g. the plaintext).
+        probes:
+            Names of the probed (or leaking) buses. Each of them is also a
+            variable ``x`` with ``NC 2**width`` (e.g. for evidence on a byte),
+            related to its bits by a ``BITS`` property.
+        """
+        fg = cls.__new__(cls)
+        fg._inner = _scalib_ext.FactorGraph.from_netlist(
+            netlist, format, list(single), list(public), list(probes)
+        )
+        return fg
+
     def sanity_check(self, pub_assignment: ValsAssign, var_assignment: ValsAssign):
         """Verify that the graph is compatible with example variable assignments.
 
@@ -157,12 +310,97 @@ class FactorGraph:
         """Return the names of the factors in the graph."""
         return self._inner.factor_names()
 
+    def analyze(self, nmulti: int) -> GraphAnalysis:
+        """Analyze the structure of the graph for ``nmulti`` executions.
+
+        This is cheap (it does not run belief propagation) and allows to check
+        a model before creating a :class:`BPState`: the cycles make belief
+        propagation loopy, the treewidth bounds the cost of
+        :meth:`BPState.bp_exact`, and the memory of the :class:`BPState` is
+        mostly ``edge_memory + var_memory``.
+        """
+        return GraphAnalysis(nmulti, *self._inner.analyze(nmulti))
+
+    def simplify(self, keep: Sequence[str]) -> "FactorGraph":
+        """Return a simplified graph, from which the variables that are not in
+        ``keep`` may be removed.
+
+        The simplification folds NOT (and equality) properties into the other
+        properties (e.g., into the negations of AND operands), merges cascaded
+        XORs, composes consecutive LOOKUPs and removes the leaf variables. The
+        joint distribution of the remaining variables does not change, as long
+        as ``keep`` contains all the variables that have evidence or whose
+        distribution is needed.
+
+        Removed variables are listed by :meth:`eliminated`. The distribution of
+        a removed variable that is equal to (or the NOT of) a remaining
+        variable can still be obtained with :meth:`BPState.get_distribution`.
+        """
+        fg = self.__class__.__new__(self.__class__)
+        fg._inner = self._inner.simplify(list(keep))
+        return fg
+
+    def eliminated(self) -> Dict[str, Optional[Tuple[str, bool]]]:
+        """Variables removed by :meth:`simplify`.
+
+        Each removed variable maps to ``(var, neg)`` if it is equal to the
+        variable ``var`` (or to its bitwise NOT if ``neg``), and to ``None``
+        if it was summed out.
+        """
+        return self._inner.eliminated()
+
+    def to_description(self) -> str:
+        """Return a description of the graph (in the graph description format).
+
+        The description includes the values of the tables, and building a
+        ``FactorGraph`` from it gives the same graph.
+        """
+        return self._inner.to_description()
+
+    def to_dot(self) -> str:
+        """Return a DOT (graphviz) representation of the graph.
+
+        Variables are ellipses, factors are boxes and public variables are
+        diamonds. `MULTI` nodes have a double border.
+        """
+        return self._inner.to_dot()
+
+    def to_graphml(self) -> str:
+        """Return a GraphML representation of the graph.
+
+        Nodes have ``kind`` (``"var"``, ``"public"`` or ``"factor"``),
+        ``name`` and ``multi`` attributes. Variables also have an ``nc``
+        attribute and factors an ``op`` attribute.
+        """
+        return self._inner.to_graphml()
+
 
 class BPState:
     """Belief propagation state associated to a :class:`FactorGraph`.
 
     This is a stateful object on which belief propagation operations can be run.
     See :class:`scalib.attacks.FactorGraph` for usage example.
+
+    Parameters
+    ----------
+    factor_graph:
+        The factor graph.
+    nexec:
+        Number of executions (for MULTI variables).
+    public_values:
+        For each public variable its value for all executions.
+    max_product:
+        If True, run max-product belief propagation instead of sum-product:
+        the resulting variable distributions are (normalized) max-marginals,
+        and :meth:`map_assignment` gives the maximum a posteriori assignment
+        of the variables (exact for acyclic graphs). The factor messages are
+        computed in O(nc^2) per pair of operands (no FFT), which is much
+        slower than sum-product for large nc.
+    log_domain:
+        If True, the products of distributions for SINGLE variables are
+        computed in the log domain. This avoids underflows when many
+        executions are combined (e.g. thousands of traces into a key
+        variable), see :meth:`get_log_distribution`.
     """
 
     def __init__(
@@ -170,11 +408,16 @@ class BPState:
         factor_graph: FactorGraph,
         nexec: int,
         public_values: Optional[ValsAssign] = None,
+        *,
+        max_product: bool = False,
+        log_domain: bool = False,
     ):
         if public_values is None:
             public_values = dict()
         self._fg = factor_graph
         self._inner = factor_graph._inner.new_bp(nexec, public_values)
+        self._inner.set_max_product(max_product)
+        self._inner.set_log_domain(log_domain)
 
     @property
     def fg(self) -> FactorGraph:
@@ -198,15 +441,60 @@ class BPState:
         else:
             self._inner.set_evidence(var, distribution)
 
+    def set_evidence_from_models(
+        self, traces: npt.NDArray[np.int16], models: Mapping[str, EvidenceModel]
+    ):
+        r"""Sets the prior distribution of MULTI variables from the leakage
+        traces, with their models.
+
+        This is equivalent to calling :meth:`set_evidence` with the
+        ``predict_proba`` of each model, but the models are evaluated in
+        parallel, without going through Python.
+
+        Parameters
+        ----------
+        traces :
+            Leakage traces of the executions, of shape `(nexec, ns)`.
+        models :
+            For each MULTI variable, its model (solved) and the columns of
+            `traces` given to the model: ``(lda, pois)`` for an
+            :class:`scalib.modeling.LDAClassifier`, or ``(rlda, pois, v)`` for
+            the variable `v` of an :class:`scalib.modeling.RLDAClassifier`.
+
+        .. code-block:: python
+
+            models = {"x": (lda, pois_x), "y": (rlda, pois_y, 0)}
+            bp.set_evidence_from_models(traces, models)
+        """
+        inner_models = []
+        for var, (model, pois, *v) in models.items():
+            if isinstance(model, LDAClassifier):
+                assert model.solved, "Call LDA.solve() before using the model."
+                inner = model.lda
+            elif isinstance(model, RLDAClassifier):
+                assert model._solved, "Call RLDA.solve() before using the model."
+                inner = model._inner
+            else:
+                raise TypeError(
+                    "The model must be an LDAClassifier or a RLDAClassifier."
+                )
+            inner_models.append((var, inner, list(pois), v[0] if v else 0))
+        self._inner.set_evidence_from_models(traces, inner_models, get_config())
+
     def bp_loopy(
         self,
         it: int,
         initialize_states: bool,
         clear_beliefs: bool = True,
-    ):
+        *,
+        damping: float = 0.0,
+        tol: Optional[float] = None,
+        schedule: str = "flooding",
+    ) -> int:
         """Runs belief propagation algorithm on the current state of the graph.
 
-        This is a shortcut for calls to :meth:`propagate_var` and :meth:`propagate_factor`. It is equivalent to:
+        This is a shortcut for calls to :meth:`propagate_var` and :meth:`propagate_factor`. With the default
+        parameters, it is equivalent to:
 
         .. code-block:: python
 
@@ -228,10 +516,30 @@ class BPState:
             Recommended after using :func:`BPState.set_evidence`.
         clear_beliefs:
             Whether to clear beliefs between vars -> factors. Setting to False can help debugging. Default value is True.
+        damping:
+            Damping of the factor -> var messages, in [0, 1): the new messages
+            are `(1-damping)*new + damping*previous`. Damping can help
+            convergence on cyclic graphs.
+        tol:
+            If not None, stop as soon as the maximum change of the factor ->
+            var messages (as probabilities) during an iteration is at most
+            `tol`.
+        schedule:
+            Order of the updates in an iteration:
+            `"flooding"` (all factors, then all variables),
+            `"sequential"` (each factor followed by its variables), or
+            `"sorted_sweep"` (same as sequential, with the factors sorted by
+            decreasing change of their messages at the previous iteration).
+
+        Returns
+        -------
+        The number of iterations that were run (at most `it`).
         """
         if initialize_states:
             self._inner.propagate_all_vars(get_config(), clear_beliefs)
-        self._inner.propagate_loopy_step(it, get_config(), clear_beliefs)
+        return self._inner.propagate_loopy(
+            it, get_config(), clear_beliefs, damping, schedule, tol
+        )
 
     def bp_acyclic(
         self,
@@ -256,6 +564,92 @@ class BPState:
             dest, clear_intermediates, clear_evidence, get_config()
         )
 
+    def extend(
+        self,
+        nexec: int,
+        public_values: Optional[ValsAssign] = None,
+        evidence: Optional[Mapping[str, npt.NDArray[np.float64]]] = None,
+    ):
+        """Adds executions (e.g., new attack traces) to the state.
+
+        The beliefs of the new executions are uniform, the ones of the
+        previous executions are kept. This stops the trace (see
+        :meth:`set_trace`).
+
+        Parameters
+        ----------
+        nexec:
+            Number of new executions.
+        public_values:
+            For each public variable its value for the new executions
+            (``SINGLE`` public variables cannot change).
+        evidence:
+            Prior distribution of some MULTI variables for the new executions,
+            each of shape `(nexec, nc)`. It is uniform for the other
+            variables.
+        """
+        if public_values is None:
+            public_values = dict()
+        if evidence is None:
+            evidence = dict()
+        self._inner.extend(nexec, public_values, dict(evidence))
+
+    def bp_incremental(self):
+        """Updates the distributions of the SINGLE variables with the executions
+        added by :meth:`extend` since the previous call.
+
+        This gives the same distributions as :meth:`bp_acyclic` on each SINGLE
+        variable, but the product of the messages of the previous executions
+        is cached, hence only the new executions are propagated (all of them at
+        the first call, and after :meth:`set_evidence` on a MULTI variable).
+        The distributions of the MULTI variables are not updated.
+
+        This only works if the graph is acyclic for any number of executions
+        (i.e., each connected component has at most one SINGLE variable).
+
+        .. code-block:: python
+
+            bp = BPState(graph, 0, {"p": np.zeros(0, dtype=np.uint32)})
+            for p, leakage in batches:
+                bp.extend(len(p), {"p": p}, {"y": leakage})
+                bp.bp_incremental()
+                k = bp.get_distribution("k")
+        """
+        self._inner.propagate_incremental(get_config())
+
+    def exact_cost(self) -> Tuple[int, int, int]:
+        """Cost of :meth:`bp_exact`, estimated from its junction tree.
+
+        Returns
+        -------
+        max_clique_size:
+            Number of entries of the largest clique table.
+        total_size:
+            Total number of entries of the clique tables (for all the
+            executions), to which the running time is proportional.
+        width:
+            Number of variables in the largest clique, minus one.
+        """
+        return self._inner.exact_cost()
+
+    def bp_exact(self, *, max_clique_size: int = 2**24):
+        """Computes the exact distributions of all the variables given the
+        evidence, also for cyclic graphs.
+
+        This uses variable elimination on a junction tree, whose cost is
+        exponential in the number of variables in its cliques (see
+        :meth:`exact_cost`), hence it is only practical for graphs with few
+        cycles, such as a masked S-box with a few shares.
+        In max-product mode, the distributions are the exact max-marginals.
+
+        Parameters
+        ----------
+        max_clique_size:
+            Fail (without computing anything) if a clique table has more
+            entries.
+        """
+        self._inner.propagate_exact(max_clique_size, get_config())
+
     def get_distribution(self, var: str) -> Optional[npt.NDArray[np.float64]]:
         r"""Returns the current distribution of a variable `var`.
 
@@ -272,13 +666,116 @@ class BPState:
             `(nc)`. Else, it has shape `(n,nc)`.
             If the variable has a uniform distribution, None may be returned
             (but this is not guaranteed).
+            For a variable removed by :meth:`FactorGraph.simplify`, the
+            distribution is the one of its alias, if any.
         """
         return self._inner.get_state(var)
 
+    def get_log_distribution(self, var: str) -> Optional[npt.NDArray[np.float64]]:
+        r"""Returns the current normalized log-distribution of a SINGLE variable.
+
+        This is only available with ``log_domain=True``: unlike
+        :meth:`get_distribution`, the natural log-probabilities do not
+        underflow.
+
+        Parameters
+        ----------
+        var : string
+            Identifier of the SINGLE variable.
+
+        Returns
+        -------
+        log_distribution : array_like, f64
+            Log-distribution of `var`, of shape `(nc,)`. None if the
+            distribution of `var` was not computed in the log domain.
+        """
+        return self._inner.get_state_log(var)
+
     def is_cyclic(self) -> bool:
         """Test is the graph is cyclic."""
         return self._inner.is_cyclic()
 
+    def map_assignment(self) -> ValsAssign:
+        """Most likely value of each variable according to its current distribution.
+
+        With ``max_product=True``, after running belief propagation, this is
+        the maximum a posteriori assignment of the variables.
+
+        Returns
+        -------
+        For each variable, its value (an integer if the variable is SINGLE,
+        an array of shape `(nexec,)` if it is MULTI).
+        """
+        return self._inner.map_assignment()
+
+    def entropy(self, var: str) -> Union[float, npt.NDArray[np.float64]]:
+        """Entropy (in bits) of the current distribution of a variable.
+
+        Returns
+        -------
+        A float if `var` is SINGLE, an array of shape `(nexec,)` if it is
+        MULTI.
+        """
+        return self._inner.entropy(var)
+
+    def argmax(self, var: str) -> CstValue:
+        """Most likely value of a variable according to its current
+        distribution.
+
+        Returns
+        -------
+        An integer if `var` is SINGLE, an array of shape `(nexec,)` if it is
+        MULTI.
+        """
+        return self._inner.argmax(var)
+
+    def probability(
+        self, var: str, value: CstValue
+    ) -> Union[float, npt.NDArray[np.float64]]:
+        """Probability of a value according to the current distribution of a
+        variable.
+
+        Parameters
+        ----------
+        var :
+            Identifier of the variable.
+        value :
+            An integer if `var` is SINGLE, a sequence of `nexec` integers if it
+            is MULTI.
+
+        Returns
+        -------
+        A float if `var` is SINGLE, an array of shape `(nexec,)` if it is
+        MULTI.
+        """
+        return self._inner.probability(var, value)
+
+    def set_trace(self, vars: Sequence[str], reference: Optional[ValsAssign] = None):
+        """Record the entropy, argmax and probability of the reference value of
+        variables after each iteration of :meth:`bp_loopy`.
+
+        This clears the previous trace. Recording stops if `vars` is empty.
+
+        Parameters
+        ----------
+        vars :
+            Identifiers of the variables to trace.
+        reference :
+            Reference value (e.g., the correct key) of some of the `vars`.
+        """
+        self._inner.set_trace(list(vars), dict(reference or {}))
+
+    def get_trace(self) -> Dict[str, VarTrace]:
+        """Trace recorded since the last call to :meth:`set_trace`.
+
+        Returns
+        -------
+        For each traced variable, its :class:`VarTrace`.
+        """
+        return {
+            var: VarTrace(*trace) for var, trace in self._inner.get_trace().items()
+        }
+
     def set_distribution(
         self, var: str, distribution: Optional[npt.NDArray[np.float64]]
     ):
@@ -372,6 +869,56 @@ class BPState:
         """
         return self._inner.propagate_factor_all(factor, get_config())
 
+    def rank_key(
+        self,
+        key_vars: Sequence[str],
+        key: Sequence[int],
+        acc_bit: float = 1.0,
+        method: str = "hist",
+        max_nb_bin: int = 2**26,
+    ) -> Tuple[float, float, float]:
+        r"""Estimate the rank of a full key whose sub-keys are SINGLE variables
+        of the graph, based on their current distribution.
+
+        This is equivalent to calling
+        :func:`scalib.postprocessing.rank_accuracy` with the costs
+        ``-np.log(self.get_distribution(var))`` for each variable in
+        ``key_vars``, but avoids copying the distributions to Python.
+        With ``log_domain=True``, the costs are taken from
+        :meth:`get_log_distribution` instead (no clamping of small
+        probabilities).
+
+        Parameters
+        ----------
+        key_vars :
+            Identifiers of the sub-key variables (must be SINGLE variables).
+        key :
+            Correct value for each of the sub-keys.
+        acc_bit :
+            Expected log2 accuracy for the key rank estimation (see
+            :func:`scalib.postprocessing.rank_accuracy`).
+        method :
+            Ranking method (see :func:`scalib.postprocessing.rank_accuracy`).
+        max_nb_bin :
+            Maximum number of bins to use.
+
+        Returns
+        -------
+        (rmin, r, rmax): (float, float, float)
+
+                - **rmin** is a lower bound for the key rank.
+                - **r** is the estimated key rank.
+                - **rmax** is an upper bound for the key rank.
+        """
+        return self._inner.rank_accuracy(
+            list(key_vars),
+            [int(k) for k in key],
+            2.0**acc_bit,
+            method,
+            max_nb_bin,
+            get_config(),
+        )
+
     def debug(self):
         """Debug-print the current state."""
         s = []
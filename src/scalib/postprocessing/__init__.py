@@ -1,6 +1,6 @@
 r"""
-Rank Estimation
-^^^^^^^^^^^^^^^
+Rank Estimation and Key Search
+^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
 
 .. currentmodule:: scalib.postprocessing
 
@@ -10,8 +10,10 @@ Rank Estimation
    :nosignatures:
 
    scalib.postprocessing.rankestimation
+   scalib.postprocessing.keysearch
 """
 
-__all__ = ["rankestimation"]
+__all__ = ["rankestimation", "keysearch"]
 
-from .rankestimation import rank_nbin, rank_accuracy
+from .rankestimation import rank_nbin, rank_accuracy, rank_accuracy_bounds
+from .keysearch import aes_key_search
@@ -0,0 +1,81 @@
+r"""Recovery of the full key by enumeration of the most likely keys.
+
+Key enumeration lists the full keys by decreasing likelihood, based on a score
+for each value of its sub-keys (as for rank estimation). Each enumerated key
+is tested against known plaintext/ciphertext pairs, and the search stops at the
+first key that matches, or after a given number of keys.
+
+The keys are tested in parallel, while the result is the same as for a
+sequential search.
+
+Examples
+--------
+
+.. code-block:: python
+
+    from scalib.postprocessing import aes_key_search
+    # costs of shape (16, 256), e.g. negative log-probabilities of the key
+    # bytes; plaintexts and ciphertexts of shape (n, 16), dtype uint8.
+    key, nb_keys = aes_key_search(costs, plaintexts, ciphertexts)
+
+Reference
+---------
+
+.. currentmodule:: scalib.postprocessing.keysearch
+
+.. autosummary::
+    :toctree:
+    :nosignatures:
+    :recursive:
+
+    aes_key_search
+"""
+
+import numpy as np
+
+from scalib import _scalib_ext
+from scalib.config import get_config
+import scalib.utils
+
+
+def aes_key_search(costs, plaintexts, ciphertexts, max_nb_keys=2**20):
+    r"""Search an AES-128 or AES-256 key by enumerating the keys by
+    increasing cost, and testing them against plaintext/ciphertext pairs.
+
+    Parameters
+    ----------
+    costs : array_like, f64
+        Cost for each value of the key bytes (e.g. negative log
+        probabilities). Array must be of shape `(16,256)` for AES-128 or
+        `(32,256)` for AES-256.
+    plaintexts : array_like, uint8
+        Known plaintext blocks, of shape `(n,16)` with `n >= 1`.
+    ciphertexts : array_like, uint8
+        Ciphertext blocks of the plaintexts, of shape `(n,16)`.
+    max_nb_keys : int, default: 2**20
+        Maximum number of keys to test. The memory used by the enumeration
+        grows linearly with the number of keys: about 80 bytes per key for
+        AES-128 (at most twice as much), e.g. about 80 MB for ``2**20`` keys.
+
+    Returns
+    -------
+    (key, nb_keys): (array_like or None, int)
+
+            - **key** is the recovered key (array of 16 or 32 bytes), or
+              ``None`` if no tested key matches.
+            - **nb_keys** is the number of keys tested: the rank of the key if
+              it was found.
+    """
+    plaintexts = np.asarray(plaintexts, dtype=np.uint8)
+    ciphertexts = np.asarray(ciphertexts, dtype=np.uint8)
+    with scalib.utils.interruptible():
+        key, nb_keys = _scalib_ext.aes_key_search(
+            costs,
+            plaintexts.tolist(),
+            ciphertexts.tolist(),
+            max_nb_keys,
+            get_config(),
+        )
+    if key is not None:
+        key = np.array(key, dtype=np.uint8)
+    return key, nb_keys
@@ -10,6 +10,10 @@ lower-level `rank_nbin` function.
 That function gives direct, lower-level control to the core algorithm, allowing
 to specify the number of bins in the histograms (whereas `rank_accuracy` tunes
 this parameter automatically).
+The `rank_accuracy_bounds` function handles the case where the costs are only
+known to lie within bounds (e.g. from
+`scalib.modeling.RLDAClusteredModel.bounded_prs`), and returns a rank interval
+that holds for any costs within those bounds.
 
 Examples
 --------
@@ -38,6 +42,7 @@ Reference
 
     rank_accuracy
     rank_nbin
+    rank_accuracy_bounds
 
 Notes
 -----
@@ -81,6 +86,8 @@ def rank_nbin(costs, key, nbins, method="hist"):
         * "hist": using histograms (default).
         * "naive": enumerate possible keys (very slow).
         * "histbignum": using NTL library, allows better precision.
+        * "histbigint": exact bin counts with arbitrary-precision integers,
+          without requiring NTL.
 
     Returns
     -------
@@ -119,6 +126,8 @@ def rank_accuracy(costs, key, acc_bit=1.0, method="hist", max_nb_bin=2**26):
 
         * "hist": using histograms (default).
         * "ntl": using NTL library, allows better precision.
+        * "histbigint": exact bin counts with arbitrary-precision integers,
+          without requiring NTL.
     max_nb_bin : int, default: 2**26
         Maximum number of bins to use.
         This fixes an upper bound on the computational cost of the algorithm
@@ -144,6 +153,64 @@ def rank_accuracy(costs, key, acc_bit=1.0, method="hist", max_nb_bin=2**26):
         )
 
 
+def rank_accuracy_bounds(
+    costs_lower, costs_upper, key, acc_bit=1.0, method="hist", max_nb_bin=2**26
+):
+    r"""Estimate the rank of the full key when the costs of the sub-keys are
+    only known to lie within bounds.
+
+    The result holds for any costs `c` such that `costs_lower <= c <=
+    costs_upper`. The rank is lowest when the sub-keys of the correct key
+    take their lower costs and all the other values take their upper costs,
+    and highest in the opposite case: these two extreme ranks are estimated
+    with `rank_accuracy`.
+
+    Parameters
+    ----------
+    costs_lower : array_like, f64
+        Lower bound on the cost for each of the sub-keys. Array must be of
+        shape `(ns,nc)` where `ns` is the number of sub-keys, `nc` the
+        possible values of each sub-keys. When the costs are negative log
+        probabilities, this is the negative log of the upper bound on the
+        probabilities.
+    costs_upper : array_like, f64
+        Upper bound on the cost for each of the sub-keys, same shape as
+        `costs_lower`.
+    key : array_like, int
+        Correct full key split in sub-keys. Array must be of shape `(ns,)`.
+    acc_bit : f64, default: 1.0
+        Expected log2 accuracy for each of the underlying rank estimations
+        (see `rank_accuracy`). The width of the resulting interval also
+        reflects the distance between the bounds, hence may be larger.
+    method : string
+        Method used to estimate the rank (see `rank_accuracy`).
+    max_nb_bin : int, default: 2**26
+        Maximum number of bins to use (see `rank_accuracy`).
+
+    Returns
+    -------
+    (rmin, r, rmax): (float, float, float)
+
+            - **rmin** is a lower bound for the key rank, for any costs within
+              the bounds.
+            - **r** is the estimated key rank for the mid-point costs
+              `(costs_lower+costs_upper)/2`.
+            - **rmax** is an upper bound for the key rank, for any costs within
+              the bounds.
+    """
+    with scalib.utils.interruptible():
+        return _scalib_ext.rank_bounds_accuracy(
+            costs_lower,
+            costs_upper,
+            key,
+            2.0**acc_bit,
+            _choose_merge_value(costs_lower),
+            method,
+            max_nb_bin,
+            get_config(),
+        )
+
+
 def _choose_merge_value(costs):
     """The merge parameter is the number of sub-keys to merge in a
     brute-force manner before computing histograms. Merging may improve
@@ -0,0 +1,279 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use rayon::prelude::*;
+
+use crate::rank::RankProblem;
+use crate::RankError;
+
+/// Number of keys that are enumerated at once, then tested in parallel.
+const BATCH_SIZE: usize = 1 << 12;
+
+/// Test of a candidate key, e.g. against known plaintext/ciphertext pairs.
+pub trait KeyVerifier: Sync {
+    /// Returns true if key (given as the value of each of its subkeys) is the right key.
+    fn verify(&self, key: &[usize]) -> bool;
+}
+
+impl<F: Fn(&[usize]) -> bool + Sync> KeyVerifier for F {
+    fn verify(&self, key: &[usize]) -> bool {
+        self(key)
+    }
+}
+
+/// Enumeration of all the keys, by increasing cost (i.e. decreasing probability).
+///
+/// This is a best-first enumeration: each key is identified by the ranks of its subkeys, and
+/// keys are generated as a tree such that the cost of a child is at least the cost of its
+/// parent. Each key has at most 3 children, hence after enumerating n keys, at most 2n + 1
+/// candidates are stored, each taking about 40 bytes plus 2 bytes per subkey. In practice, there
+/// is about one candidate per enumerated key (about 80 bytes per key for AES-128).
+#[derive(Debug, Clone)]
+pub struct KeyEnumerator {
+    /// For each subkey, its values by increasing cost.
+    values: Vec<Vec<usize>>,
+    /// For each subkey, its costs, sorted.
+    costs: Vec<Vec<f64>>,
+    /// Position in the key of each subkey (the subkeys are sorted by increasing cost difference
+    /// between their two best values).
+    positions: Vec<usize>,
+    heap: BinaryHeap<Candidate>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Candidate {
+    cost: f64,
+    /// Index in the sorted costs for each subkey.
+    idx: Box<[u16]>,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    // Reversed ordering to get a min-heap, with deterministic tie breaking.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.idx.cmp(&self.idx))
+    }
+}
+
+impl KeyEnumerator {
+    /// costs[i][j] is the cost of value j for subkey i (e.g. negative log-probability).
+    pub fn new(costs: &[Vec<f64>]) -> Result<Self, RankError> {
+        let problem = RankProblem::new(costs, vec![0; costs.len()])?;
+        if problem.costs.iter().any(|sc| sc.len() > 1 << 16) {
+            return Err("Subkeys cannot have more than 2^16 values.".into());
+        }
+        let values: Vec<Vec<usize>> = problem
+            .costs
+            .iter()
+            .map(|sc| {
+                let mut values: Vec<usize> = (0..sc.len()).collect();
+                values.sort_by(|a, b| sc[*a].total_cmp(&sc[*b]));
+                values
+            })
+            .collect();
+        let costs: Vec<Vec<f64>> = problem
+            .costs
+            .iter()
+            .zip(values.iter())
+            .map(|(sc, v)| v.iter().map(|j| sc[*j]).collect())
+            .collect();
+        // Subkeys with a single value come last, they are never advanced.
+        let first_step = |sc: &Vec<f64>| {
+            if sc.len() > 1 {
+                sc[1] - sc[0]
+            } else {
+                f64::INFINITY
+            }
+        };
+        let mut positions: Vec<usize> = (0..costs.len()).collect();
+        positions.sort_by(|a, b| first_step(&costs[*a]).total_cmp(&first_step(&costs[*b])));
+        let values = positions.iter().map(|i| values[*i].clone()).collect();
+        let costs: Vec<Vec<f64>> = positions.iter().map(|i| costs[*i].clone()).collect();
+        let first = Candidate {
+            cost: costs.iter().map(|sc| sc[0]).sum(),
+            idx: vec![0; costs.len()].into_boxed_slice(),
+        };
+        Ok(Self {
+            values,
+            costs,
+            positions,
+            heap: BinaryHeap::from(vec![first]),
+        })
+    }
+
+    fn push_child(&mut self, parent: &Candidate, changes: &[(usize, u16)]) {
+        let mut child = parent.clone();
+        for (j, i) in changes {
+            child.cost += self.costs[*j][*i as usize] - self.costs[*j][child.idx[*j] as usize];
+            child.idx[*j] = *i;
+        }
+        self.heap.push(child);
+    }
+}
+
+impl Iterator for KeyEnumerator {
+    type Item = Vec<usize>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let candidate = self.heap.pop()?;
+        let idx = &candidate.idx;
+        // The subkeys up to the last advanced one (the last non-zero index) can be advanced:
+        // - the last advanced one can be incremented,
+        // - the next one can be set to 1 (extend),
+        // - if the last advanced one is at 1, it can be moved to the next one (shift).
+        // This gives each key a unique parent, whose cost is not larger since the costs of each
+        // subkey are sorted, and the subkeys are sorted by the cost of their first increment.
+        let last = idx.iter().rposition(|i| *i != 0);
+        let next = last.map_or(0, |j| j + 1);
+        if let Some(j) = last {
+            if (idx[j] as usize) + 1 < self.costs[j].len() {
+                self.push_child(&candidate, &[(j, idx[j] + 1)]);
+            }
+        }
+        if next < idx.len() && self.costs[next].len() > 1 {
+            self.push_child(&candidate, &[(next, 1)]);
+            if let Some(j) = last.filter(|j| idx[*j] == 1) {
+                self.push_child(&candidate, &[(j, 0), (next, 1)]);
+            }
+        }
+        let mut key = vec![0; idx.len()];
+        for ((i, v), p) in idx
+            .iter()
+            .zip(self.values.iter())
+            .zip(self.positions.iter())
+        {
+            key[*p] = v[*i as usize];
+        }
+        Some(key)
+    }
+}
+
+/// Result of a key search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySearch {
+    /// The first key accepted by the verifier, if any.
+    pub key: Option<Vec<usize>>,
+    /// Number of keys enumerated: rank of the key if it was found, otherwise the number of keys
+    /// tested before exhausting the budget or the key space.
+    pub nb_keys: u64,
+}
+
+/// Enumerate keys by decreasing probability and test them with the verifier, stopping at the
+/// first match or after max_nb_keys keys.
+///
+/// The keys are tested in parallel (with rayon), while the result is the same as for a
+/// sequential search.
+pub fn enumerate_and_test(
+    costs: &[Vec<f64>],
+    verifier: &impl KeyVerifier,
+    max_nb_keys: u64,
+) -> Result<KeySearch, RankError> {
+    let mut enumerator = KeyEnumerator::new(costs)?;
+    let mut nb_keys = 0;
+    while nb_keys < max_nb_keys {
+        let batch_size = (max_nb_keys - nb_keys).min(BATCH_SIZE as u64) as usize;
+        let batch: Vec<Vec<usize>> = enumerator.by_ref().take(batch_size).collect();
+        if let Some(pos) = batch.par_iter().position_first(|k| verifier.verify(k)) {
+            return Ok(KeySearch {
+                key: Some(batch[pos].clone()),
+                nb_keys: nb_keys + pos as u64 + 1,
+            });
+        }
+        nb_keys += batch.len() as u64;
+        if batch.len() < batch_size {
+            break;
+        }
+    }
+    Ok(KeySearch { key: None, nb_keys })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enumeration_order() {
+        let costs: Vec<Vec<f64>> = (0..3)
+            .map(|i| (0..(3 + i)).map(|j| ((j * 5 + i * 3) % 7) as f64).collect())
+            .collect();
+        let keys: Vec<Vec<usize>> = KeyEnumerator::new(&costs).unwrap().collect();
+        assert_eq!(keys.len(), 3 * 4 * 5);
+        let mut prev_rank = 0.0;
+        for key in keys.iter() {
+            let rank = RankProblem::new(costs.clone(), key.clone())
+                .unwrap()
+                .naive_rank();
+            assert!(rank >= prev_rank);
+            prev_rank = rank;
+        }
+        let mut sorted = keys.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), keys.len());
+    }
+
+    #[test]
+    fn test_enumeration_candidates() {
+        // Subkeys with a single value, and with increments not sorted by subkey.
+        let costs = vec![
+            vec![0.5, 0.0, 2.0],
+            vec![1.0],
+            vec![0.0, 0.1, 0.3, 0.2],
+            vec![3.0],
+            vec![0.0, 1.5],
+        ];
+        let mut enumerator = KeyEnumerator::new(&costs).unwrap();
+        let mut keys = Vec::new();
+        while let Some(key) = enumerator.next() {
+            keys.push(key);
+            assert!(enumerator.heap.len() <= 2 * keys.len() + 1);
+        }
+        let cost = |k: &Vec<usize>| k.iter().zip(costs.iter()).map(|(j, c)| c[*j]).sum::<f64>();
+        let mut expected: Vec<Vec<usize>> = (0..3 * 4 * 2)
+            .map(|x| vec![x % 3, 0, (x / 3) % 4, 0, x / 12])
+            .collect();
+        expected.sort_by(|a, b| cost(a).total_cmp(&cost(b)));
+        assert_eq!(
+            keys.iter().map(cost).collect::<Vec<_>>(),
+            expected.iter().map(cost).collect::<Vec<_>>()
+        );
+        keys.sort();
+        expected.sort();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_enumerate_and_test() {
+        let costs: Vec<Vec<f64>> = (0..4)
+            .map(|i| {
+                (0..16)
+                    .map(|j| ((j * 7 + i * 5) % 16) as f64 * 0.3)
+                    .collect()
+            })
+            .collect();
+        let target = vec![3, 1, 4, 1];
+        let rank = KeyEnumerator::new(&costs)
+            .unwrap()
+            .position(|k| k == target)
+            .unwrap() as u64
+            + 1;
+        let verifier = |k: &[usize]| k == target.as_slice();
+        let res = enumerate_and_test(&costs, &verifier, 1 << 20).unwrap();
+        assert_eq!(res.key, Some(target.clone()));
+        assert_eq!(res.nb_keys, rank);
+        let res = enumerate_and_test(&costs, &verifier, rank - 1).unwrap();
+        assert_eq!(res.key, None);
+        assert_eq!(res.nb_keys, rank - 1);
+        let res = enumerate_and_test(&costs, &|_: &[usize]| false, 1 << 20).unwrap();
+        assert_eq!(res.nb_keys, 1 << 16);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::histogram::Histogram;
+use crate::rank::RankProblem;
+use crate::{RankError, RankEstimation};
+
+/// Guessing work of an adversary that enumerates keys by decreasing probability.
+///
+/// The costs of the ranking problem are interpreted as negative natural log-probabilities (up to
+/// an additive constant for each subkey), and the subkeys are independent: the probability of a
+/// key is proportional to exp(-sum_i costs[i][key[i]]).
+///
+/// The key space is represented by bins of keys with (approximately) the same cost. Each key
+/// has a true probability that is within a factor `proba_margin` of the nominal probability of
+/// its bin, which gives the bounds on all the results.
+#[derive(Debug, Clone)]
+pub struct GuessingWork {
+    /// Number of keys in each bin, by increasing cost.
+    counts: Vec<f64>,
+    /// Nominal probability of each key in each bin (not normalized).
+    probas: Vec<f64>,
+    /// Sum of the nominal probabilities of all keys.
+    total_proba: f64,
+    /// Multiplicative bound on the error of the nominal probabilities.
+    proba_margin: f64,
+    /// Shannon entropy of the key, in bits.
+    entropy: f64,
+}
+
+impl GuessingWork {
+    /// Exact computation through enumeration of all the keys.
+    pub(crate) fn naive(problem: &RankProblem) -> Self {
+        let merged = problem.merge(problem.costs.len());
+        let mut costs = merged.costs[0].clone();
+        costs.sort_unstable_by(|a, b| a.partial_cmp(b).expect("No NaN"));
+        Self::from_bins(problem, vec![1.0; costs.len()], costs, 0.0)
+    }
+    /// Approximate computation with histograms of merged_problem, which must be a merge of
+    /// problem.
+    pub(crate) fn hist<H: Histogram>(
+        problem: &RankProblem,
+        merged_problem: &RankProblem,
+        nb_bins: usize,
+    ) -> Result<Self, RankError> {
+        let (hist, bin_size): (H, _) = merged_problem.build_full_histogram(nb_bins)?;
+        let counts = hist.coefs_f64();
+        let costs = (0..counts.len()).map(|i| i as f64 * bin_size).collect();
+        // Rounding the cost of each subkey to its bin shifts the cost by at most half a bin.
+        let cost_margin = (merged_problem.costs.len() as f64) / 2.0 * bin_size;
+        Ok(Self::from_bins(problem, counts, costs, cost_margin))
+    }
+    fn from_bins(
+        problem: &RankProblem,
+        counts: Vec<f64>,
+        costs: Vec<f64>,
+        cost_margin: f64,
+    ) -> Self {
+        // Normalization and entropy are computed exactly, subkey by subkey.
+        let (log_norm, entropy) = problem
+            .costs
+            .iter()
+            .map(|sc| {
+                let norm: f64 = sc.iter().map(|c| (-c).exp()).sum();
+                let log_norm = norm.ln();
+                let entropy: f64 = sc.iter().map(|c| (-c).exp() / norm * (c + log_norm)).sum();
+                (log_norm, entropy)
+            })
+            .fold((0.0, 0.0), |(ln1, h1), (ln2, h2)| (ln1 + ln2, h1 + h2));
+        let probas: Vec<f64> = costs.iter().map(|c| (-c - log_norm).exp()).collect();
+        let total_proba = counts.iter().zip(probas.iter()).map(|(n, p)| n * p).sum();
+        Self {
+            counts,
+            probas,
+            total_proba,
+            proba_margin: cost_margin.exp(),
+            entropy: entropy / std::f64::consts::LN_2,
+        }
+    }
+    /// Shannon entropy of the full key (in bits).
+    pub fn entropy(&self) -> f64 {
+        self.entropy
+    }
+    /// Total number of keys.
+    pub fn nb_keys(&self) -> f64 {
+        self.counts.iter().sum()
+    }
+    /// Expected number of keys to test before finding the right one (a.k.a. guessing entropy),
+    /// that is, the expected rank of the key under the posterior distribution.
+    pub fn expected_rank(&self) -> RankEstimation {
+        // E[rank] = 1 + sum_{k=1}^{nb_keys} (1 - P[rank <= k])
+        estimation(
+            1.0 + self.sum_complement(self.proba_margin),
+            1.0 + self.sum_complement(1.0 / self.total_proba),
+            1.0 + self.sum_complement(1.0 / self.proba_margin),
+        )
+    }
+    /// Probability of success when testing the nb_keys most likely keys.
+    pub fn success_proba(&self, nb_keys: f64) -> RankEstimation {
+        let mass = self.nominal_mass(nb_keys.floor());
+        estimation(
+            (mass / self.proba_margin).min(1.0),
+            (mass / self.total_proba).min(1.0),
+            (mass * self.proba_margin).min(1.0),
+        )
+    }
+    /// Number of keys that must be tested (by decreasing probability) to reach the given
+    /// success probability.
+    pub fn keys_needed(&self, success_proba: f64) -> Result<RankEstimation, RankError> {
+        if !(0.0..=1.0).contains(&success_proba) {
+            Err("Success probability must be in [0, 1].")?;
+        }
+        Ok(estimation(
+            self.keys_for_mass(success_proba / self.proba_margin),
+            self.keys_for_mass(success_proba * self.total_proba),
+            self.keys_for_mass(success_proba * self.proba_margin),
+        ))
+    }
+    /// Success probability as a function of the number of keys tested, evaluated at the
+    /// boundary of every non-empty bin.
+    pub fn tradeoff_curve(&self) -> Vec<(f64, RankEstimation)> {
+        let mut nb_keys = 0.0;
+        self.counts
+            .iter()
+            .filter(|n| **n != 0.0)
+            .map(|n| {
+                nb_keys += n;
+                (nb_keys, self.success_proba(nb_keys))
+            })
+            .collect()
+    }
+    /// Sum of the nominal probabilities of the nb_keys most likely keys.
+    fn nominal_mass(&self, nb_keys: f64) -> f64 {
+        let mut cum_keys = 0.0;
+        let mut cum_mass = 0.0;
+        for (n, p) in self.counts.iter().zip(self.probas.iter()) {
+            if nb_keys <= cum_keys + n {
+                return cum_mass + (nb_keys - cum_keys).max(0.0) * p;
+            }
+            cum_keys += n;
+            cum_mass += n * p;
+        }
+        cum_mass
+    }
+    /// Minimum number of keys such that their nominal probabilities sum to at least mass.
+    fn keys_for_mass(&self, mass: f64) -> f64 {
+        let mut cum_keys = 0.0;
+        let mut cum_mass = 0.0;
+        if mass <= 0.0 {
+            return 0.0;
+        }
+        for (n, p) in self.counts.iter().zip(self.probas.iter()) {
+            if *n != 0.0 && mass <= cum_mass + n * p {
+                return cum_keys + ((mass - cum_mass) / p).ceil().clamp(1.0, *n);
+            }
+            cum_keys += n;
+            cum_mass += n * p;
+        }
+        cum_keys
+    }
+    /// sum_{k=1}^{nb_keys} max(0, 1 - scale * nominal_mass(k))
+    fn sum_complement(&self, scale: f64) -> f64 {
+        let mut cum_mass = 0.0;
+        let mut res = 0.0;
+        for (n, p) in self.counts.iter().zip(self.probas.iter()) {
+            // Terms in this bin are rem - scale * p * j for j in 1..=n.
+            let rem = 1.0 - scale * cum_mass;
+            if rem <= 0.0 {
+                break;
+            }
+            let nb_pos = if *p > 0.0 {
+                (rem / (scale * p)).floor().min(*n)
+            } else {
+                *n
+            };
+            res += nb_pos * rem - scale * p * nb_pos * (nb_pos + 1.0) / 2.0;
+            cum_mass += n * p;
+        }
+        res
+    }
+}
+
+/// Build a RankEstimation, accounting for rounding errors in the estimate: when the bounds are
+/// tight, the estimate (computed differently) may fall just outside.
+fn estimation(min: f64, est: f64, max: f64) -> RankEstimation {
+    RankEstimation::new(min, est.max(min).min(max), max)
+}
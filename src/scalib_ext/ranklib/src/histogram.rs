@@ -137,3 +137,137 @@ impl Drop for BigNumHist {
         }
     }
 }
+
+/// Histogram with exact, arbitrary-precision bin counts, in pure Rust.
+///
+/// Convolution uses Kronecker substitution: each histogram is packed in a single big integer
+/// (one fixed-width slot per bin), such that the integer product contains the convolution.
+#[cfg(feature = "bigint")]
+#[derive(Clone, Debug)]
+pub struct BigIntHist {
+    state: Vec<num_bigint::BigUint>,
+    // Sum of all bin counts, gives an upper bound on the value of any bin.
+    total: num_bigint::BigUint,
+}
+
+#[cfg(feature = "bigint")]
+impl Histogram for BigIntHist {
+    fn new(size: usize) -> Self {
+        Self {
+            state: vec![num_bigint::BigUint::default(); size],
+            total: num_bigint::BigUint::default(),
+        }
+    }
+    fn convolve(&self, other: &Self) -> Self {
+        assert_eq!(self.state.len(), other.state.len());
+        // Every coefficient of the product is at most the product of the totals, hence fits in
+        // slot_bits bits.
+        let total = &self.total * &other.total;
+        let slot_bits = std::cmp::max(total.bits(), 1);
+        let product = bigint_pack(&self.state, slot_bits) * bigint_pack(&other.state, slot_bits);
+        let state = bigint_unpack(&product, self.state.len(), slot_bits);
+        // Truncation may have dropped some bins.
+        let total = state.iter().sum();
+        Self { state, total }
+    }
+    fn coefs_f64(&self) -> Vec<f64> {
+        use num_traits::ToPrimitive;
+        self.state
+            .iter()
+            .map(|x| x.to_f64().unwrap_or(f64::INFINITY))
+            .collect()
+    }
+    fn from_elems(size: usize, iter: impl Iterator<Item = usize>) -> Self {
+        let mut counts = vec![0u64; size];
+        for elem in iter {
+            if elem < size {
+                counts[elem] += 1;
+            }
+        }
+        let state: Vec<num_bigint::BigUint> = counts.into_iter().map(Into::into).collect();
+        let total = state.iter().sum();
+        Self { state, total }
+    }
+}
+
+/// Pack coefs in a big integer, coefs[i] being stored at bits [i*slot_bits, (i+1)*slot_bits[.
+/// All coefs must be smaller than 2**slot_bits.
+#[cfg(feature = "bigint")]
+fn bigint_pack(coefs: &[num_bigint::BigUint], slot_bits: u64) -> num_bigint::BigUint {
+    let total_bits = coefs.len() as u64 * slot_bits;
+    let mut digits = vec![0u32; (total_bits / 32) as usize + 2];
+    for (i, coef) in coefs.iter().enumerate() {
+        debug_assert!(coef.bits() <= slot_bits);
+        let offset = i as u64 * slot_bits;
+        for (j, d) in coef.iter_u32_digits().enumerate() {
+            let pos = offset + 32 * j as u64;
+            let (word, shift) = ((pos / 32) as usize, (pos % 32) as u32);
+            digits[word] |= d << shift;
+            if shift != 0 {
+                digits[word + 1] |= d >> (32 - shift);
+            }
+        }
+    }
+    num_bigint::BigUint::new(digits)
+}
+
+/// Inverse of bigint_pack, returns the first n coefs.
+#[cfg(feature = "bigint")]
+fn bigint_unpack(x: &num_bigint::BigUint, n: usize, slot_bits: u64) -> Vec<num_bigint::BigUint> {
+    let digits = x.to_u32_digits();
+    let digit = |i: usize| digits.get(i).copied().unwrap_or(0);
+    (0..n)
+        .map(|i| {
+            let offset = i as u64 * slot_bits;
+            let slot = (0..slot_bits)
+                .step_by(32)
+                .map(|slot_pos| {
+                    let pos = offset + slot_pos;
+                    let (word, shift) = ((pos / 32) as usize, (pos % 32) as u32);
+                    let mut d = digit(word) >> shift;
+                    if shift != 0 {
+                        d |= digit(word + 1) << (32 - shift);
+                    }
+                    let rem_bits = slot_bits - slot_pos;
+                    if rem_bits < 32 {
+                        d &= (1 << rem_bits) - 1;
+                    }
+                    d
+                })
+                .collect();
+            num_bigint::BigUint::new(slot)
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "bigint"))]
+mod tests {
+    use super::{BigIntHist, F64Hist, Histogram};
+
+    #[test]
+    fn test_bigint_hist_vs_f64() {
+        let elems = [0, 1, 1, 3, 4, 4, 4, 7, 9];
+        let h_f64 = F64Hist::from_elems(8, elems.iter().copied());
+        let h_big = BigIntHist::from_elems(8, elems.iter().copied());
+        assert_eq!(h_f64.coefs_f64(), h_big.coefs_f64());
+        let h_f64 = h_f64.convolve(&h_f64).convolve(&h_f64);
+        let h_big = h_big.convolve(&h_big).convolve(&h_big);
+        assert_eq!(h_f64.coefs_f64(), h_big.coefs_f64());
+    }
+
+    #[test]
+    fn test_bigint_hist_exact() {
+        // (1+X)^n has binomial coefficients, which do not fit in a u64 nor in a f64 mantissa.
+        let n = 120;
+        let base = BigIntHist::from_elems(n + 1, [0, 1].into_iter());
+        let mut hist = base.clone();
+        for _ in 1..n {
+            hist = hist.convolve(&base);
+        }
+        let mut binom = num_bigint::BigUint::from(1u32);
+        for (k, coef) in hist.state.iter().enumerate() {
+            assert_eq!(coef, &binom, "k: {}", k);
+            binom = binom * (n - k) / (k + 1);
+        }
+    }
+}
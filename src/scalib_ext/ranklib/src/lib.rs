@@ -1,5 +1,12 @@
+mod enumeration;
+mod guessing;
 mod histogram;
 mod rank;
+mod verifier;
+
+pub use enumeration::{enumerate_and_test, KeyEnumerator, KeySearch, KeyVerifier};
+pub use guessing::GuessingWork;
+pub use verifier::AesVerifier;
 
 #[derive(Debug)]
 pub struct RankError {
@@ -51,6 +58,8 @@ pub enum RankingMethod {
     Hist,
     #[cfg(feature = "ntl")]
     HistBigNum,
+    #[cfg(feature = "bigint")]
+    HistBigInt,
 }
 
 impl RankingMethod {
@@ -60,14 +69,7 @@ impl RankingMethod {
         nb_bin: usize,
         merge: Option<usize>,
     ) -> Result<RankEstimation, RankError> {
-        let merged_problem = if let Some(merge) = merge {
-            if merge < 1 || merge > problem.costs.len() {
-                Err("Merge value not supported.")?;
-            }
-            problem.merge(merge)
-        } else {
-            problem.auto_merge(1 << 16)
-        };
+        let merged_problem = merge_problem(problem, merge)?;
         match self {
             RankingMethod::Naive => {
                 let rank = problem.naive_rank();
@@ -80,6 +82,8 @@ impl RankingMethod {
             RankingMethod::Hist => merged_problem.rank_hist::<histogram::F64Hist>(nb_bin),
             #[cfg(feature = "ntl")]
             RankingMethod::HistBigNum => merged_problem.rank_hist::<histogram::BigNumHist>(nb_bin),
+            #[cfg(feature = "bigint")]
+            RankingMethod::HistBigInt => merged_problem.rank_hist::<histogram::BigIntHist>(nb_bin),
         }
     }
     pub fn rank_nbin(
@@ -121,6 +125,149 @@ impl RankingMethod {
         // we can have.
         return self.rank_inner(&problem, max_nb_bin, merge);
     }
+    /// Rank interval of the key when the cost of each sub-key value is only known to lie between
+    /// costs_lower and costs_upper (e.g. from bounds on the probabilities).
+    ///
+    /// The returned min (resp. max) is a lower (resp. upper) bound on the rank for any costs
+    /// within the bounds, while est is the rank for the mid-point costs.
+    pub fn rank_bounds_nbin(
+        &self,
+        costs_lower: &[Vec<f64>],
+        costs_upper: &[Vec<f64>],
+        key: &[usize],
+        nb_bin: usize,
+        merge: Option<usize>,
+    ) -> Result<RankEstimation, RankError> {
+        let [best, mid, worst] = bounded_costs(costs_lower, costs_upper, key)?;
+        Ok(bounds_estimation(
+            self.rank_nbin(&best, key, nb_bin, merge)?,
+            self.rank_nbin(&mid, key, nb_bin, merge)?,
+            self.rank_nbin(&worst, key, nb_bin, merge)?,
+        ))
+    }
+    /// Same as rank_bounds_nbin, where each of the underlying rank estimations is computed as in
+    /// rank_accuracy.
+    ///
+    /// The accuracy target applies to each estimation: the ratio max/min of the result also
+    /// contains the uncertainty on the costs, which cannot be reduced.
+    pub fn rank_bounds_accuracy(
+        &self,
+        costs_lower: &[Vec<f64>],
+        costs_upper: &[Vec<f64>],
+        key: &[usize],
+        acc: f64,
+        merge: Option<usize>,
+        max_nb_bin: usize,
+    ) -> Result<RankEstimation, RankError> {
+        let [best, mid, worst] = bounded_costs(costs_lower, costs_upper, key)?;
+        Ok(bounds_estimation(
+            self.rank_accuracy(&best, key, acc, merge, max_nb_bin)?,
+            self.rank_accuracy(&mid, key, acc, merge, max_nb_bin)?,
+            self.rank_accuracy(&worst, key, acc, merge, max_nb_bin)?,
+        ))
+    }
+    /// Guessing work and success probability of key enumeration, for the posterior distribution
+    /// given by the costs (negative natural log-probabilities).
+    pub fn guessing_work(
+        &self,
+        costs: &[Vec<f64>],
+        nb_bin: usize,
+        merge: Option<usize>,
+    ) -> Result<GuessingWork, RankError> {
+        let problem = rank::RankProblem::new(costs, vec![0; costs.len()])?;
+        let merged_problem = merge_problem(&problem, merge)?;
+        match self {
+            RankingMethod::Naive => Ok(GuessingWork::naive(&problem)),
+            #[cfg(feature = "hellib")]
+            RankingMethod::Hellib => Err("Guessing work is not supported by hellib.")?,
+            RankingMethod::Hist => {
+                GuessingWork::hist::<histogram::F64Hist>(&problem, &merged_problem, nb_bin)
+            }
+            #[cfg(feature = "ntl")]
+            RankingMethod::HistBigNum => {
+                GuessingWork::hist::<histogram::BigNumHist>(&problem, &merged_problem, nb_bin)
+            }
+            #[cfg(feature = "bigint")]
+            RankingMethod::HistBigInt => {
+                GuessingWork::hist::<histogram::BigIntHist>(&problem, &merged_problem, nb_bin)
+            }
+        }
+    }
+}
+
+fn merge_problem(
+    problem: &rank::RankProblem,
+    merge: Option<usize>,
+) -> Result<rank::RankProblem, RankError> {
+    if let Some(merge) = merge {
+        if merge < 1 || merge > problem.costs.len() {
+            Err("Merge value not supported.")?;
+        }
+        Ok(problem.merge(merge))
+    } else {
+        Ok(problem.auto_merge(1 << 16))
+    }
+}
+
+/// Extreme cost tables for ranking under cost uncertainty: [best, mid, worst].
+///
+/// The rank of the key is minimal when its sub-keys take their lower costs and all the other
+/// values take their upper costs (best case), and maximal in the opposite case (worst case).
+/// Since each comparison between two keys only involves the sub-keys where they differ, these
+/// two assignments give the exact extreme ranks.
+fn bounded_costs(
+    costs_lower: &[Vec<f64>],
+    costs_upper: &[Vec<f64>],
+    key: &[usize],
+) -> Result<[Vec<Vec<f64>>; 3], RankError> {
+    if costs_lower.len() != costs_upper.len()
+        || costs_lower
+            .iter()
+            .zip(costs_upper.iter())
+            .any(|(l, u)| l.len() != u.len())
+    {
+        Err("Lower and upper costs do not have the same shape.")?;
+    }
+    if costs_lower
+        .iter()
+        .flatten()
+        .zip(costs_upper.iter().flatten())
+        .any(|(l, u)| l > u)
+    {
+        Err("Lower costs must not be larger than upper costs.")?;
+    }
+    if costs_lower.len() != key.len() || key.iter().zip(costs_lower).any(|(k, c)| *k >= c.len()) {
+        Err("Key does not match costs.")?;
+    }
+    let select = |key_lower: bool| -> Vec<Vec<f64>> {
+        costs_lower
+            .iter()
+            .zip(costs_upper.iter())
+            .zip(key.iter())
+            .map(|((l, u), k)| {
+                let (c_key, c_other) = if key_lower { (l, u) } else { (u, l) };
+                let mut c = c_other.clone();
+                c[*k] = c_key[*k];
+                c
+            })
+            .collect()
+    };
+    let mid = costs_lower
+        .iter()
+        .zip(costs_upper.iter())
+        .map(|(l, u)| l.iter().zip(u.iter()).map(|(l, u)| (l + u) / 2.0).collect())
+        .collect();
+    Ok([select(true), mid, select(false)])
+}
+
+/// Combine the rank estimations of the cost tables from bounded_costs.
+fn bounds_estimation(
+    best: RankEstimation,
+    mid: RankEstimation,
+    worst: RankEstimation,
+) -> RankEstimation {
+    let est = mid.est.max(best.min).min(worst.max);
+    RankEstimation::new(best.min, est, worst.max)
 }
 
 #[cfg(feature = "hellib")]
@@ -185,6 +332,8 @@ pub mod tests {
         RankingMethod::Hist,
         #[cfg(feature = "ntl")]
         RankingMethod::HistBigNum,
+        #[cfg(feature = "bigint")]
+        RankingMethod::HistBigInt,
     ];
     #[test]
     fn test_rank_hellib_corpus() {
@@ -268,6 +417,129 @@ pub mod tests {
             }
         }
     }
+    #[test]
+    fn test_guessing_work() {
+        let costs: Vec<Vec<f64>> = (0..3)
+            .map(|i| {
+                (0..(4 + i))
+                    .map(|j| ((j * 7 + i * 3) % 5) as f64 * 0.7)
+                    .collect()
+            })
+            .collect();
+        // Reference by enumeration of all keys.
+        let mut probas: Vec<f64> = costs
+            .iter()
+            .multi_cartesian_product()
+            .map(|c| (-c.into_iter().sum::<f64>()).exp())
+            .collect();
+        let norm: f64 = probas.iter().sum();
+        probas.iter_mut().for_each(|p| *p /= norm);
+        probas.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        let ref_entropy: f64 = probas.iter().map(|p| -p * p.log2()).sum();
+        let ref_expected_rank: f64 = probas
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i + 1) as f64 * p)
+            .sum();
+        let ref_success_3: f64 = probas[..3].iter().sum();
+        let ref_keys_half = probas
+            .iter()
+            .scan(0.0, |acc, p| {
+                *acc += p;
+                Some(*acc)
+            })
+            .position(|m| m >= 0.5)
+            .unwrap() as f64
+            + 1.0;
+        let approx_eq = |a: f64, b: f64| (a - b).abs() <= 1e-9 * a.abs().max(1.0);
+        let gw = RankingMethod::Naive.guessing_work(&costs, 0, None).unwrap();
+        assert_eq!(gw.nb_keys(), probas.len() as f64);
+        assert!(approx_eq(gw.entropy(), ref_entropy));
+        assert!(approx_eq(gw.expected_rank().est, ref_expected_rank));
+        assert!(approx_eq(gw.success_proba(3.0).est, ref_success_3));
+        assert_eq!(gw.keys_needed(0.5).unwrap().est, ref_keys_half);
+        for method in RANKING_METHODS {
+            #[cfg(feature = "hellib")]
+            if *method == RankingMethod::Hellib {
+                continue;
+            }
+            for nb_bin in [16, 256, 4096] {
+                let gw = method.guessing_work(&costs, nb_bin, Some(1)).unwrap();
+                let contains = |e: RankEstimation, x: f64| {
+                    e.min - 1e-9 <= x && x <= e.max + 1e-9 && e.min <= e.est && e.est <= e.max
+                };
+                println!("method: {:?}, nb_bin: {}", method, nb_bin);
+                assert!(approx_eq(gw.entropy(), ref_entropy));
+                assert_eq!(gw.nb_keys(), probas.len() as f64);
+                assert!(contains(gw.expected_rank(), ref_expected_rank));
+                assert!(contains(gw.success_proba(3.0), ref_success_3));
+                assert!(contains(gw.keys_needed(0.5).unwrap(), ref_keys_half));
+                for (nb_keys, success) in gw.tradeoff_curve() {
+                    let ref_success: f64 = probas[..nb_keys as usize].iter().sum();
+                    assert!(contains(success, ref_success));
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_rank_bounds() {
+        let costs_lower: Vec<Vec<f64>> = (0..3)
+            .map(|i| (0..4).map(|j| ((j * 5 + i * 3) % 7) as f64 * 0.5).collect())
+            .collect();
+        let costs_upper: Vec<Vec<f64>> = costs_lower
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                c.iter()
+                    .enumerate()
+                    .map(|(j, c)| c + ((i + j) % 3) as f64 * 0.3)
+                    .collect()
+            })
+            .collect();
+        let key = [2, 1, 1];
+        // Reference: rank for random costs within the bounds.
+        let ref_ranks: Vec<f64> = (0..50)
+            .map(|s| {
+                let costs: Vec<Vec<f64>> = costs_lower
+                    .iter()
+                    .zip(costs_upper.iter())
+                    .enumerate()
+                    .map(|(i, (l, u))| {
+                        l.iter()
+                            .zip(u.iter())
+                            .enumerate()
+                            .map(|(j, (l, u))| {
+                                let t = ((s * 31 + i * 7 + j * 13) % 11) as f64 / 10.0;
+                                l + t * (u - l)
+                            })
+                            .collect()
+                    })
+                    .collect();
+                RankProblem::new(costs, key).unwrap().naive_rank()
+            })
+            .collect();
+        let exact = RankingMethod::Naive
+            .rank_bounds_nbin(&costs_lower, &costs_upper, &key, 0, None)
+            .unwrap();
+        assert!(exact.min < exact.max, "{exact:?}");
+        assert!(ref_ranks.iter().all(|r| exact.contains(*r)));
+        for method in RANKING_METHODS {
+            #[cfg(feature = "hellib")]
+            if *method == RankingMethod::Hellib {
+                continue;
+            }
+            let rank_est = method
+                .rank_bounds_accuracy(&costs_lower, &costs_upper, &key, 1.1, Some(1), 1 << 12)
+                .unwrap();
+            assert!(rank_est.min <= exact.min && exact.max <= rank_est.max);
+        }
+        assert!(RankingMethod::Naive
+            .rank_bounds_nbin(&costs_upper, &costs_lower, &key, 0, None)
+            .is_err());
+        assert!(RankingMethod::Naive
+            .rank_bounds_nbin(&costs_lower, &costs_upper[..2], &key, 0, None)
+            .is_err());
+    }
     pub fn rank2_vs_naive(
         costs: &[Vec<f64>],
         key: &[usize],
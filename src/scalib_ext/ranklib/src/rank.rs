@@ -175,8 +175,38 @@ impl RankProblem {
     /// Return the histogram and its bin size.
     fn build_histogram<H: Histogram>(&self, nb_bins: usize) -> Result<(H, f64), RankError> {
         let bin_size = self.bin_size(nb_bins)?;
-        let hist = self
+        Ok((self.histogram_with_bin_size(nb_bins, bin_size), bin_size))
+    }
+    /// Create a convolved histogram with nb_bins bins that covers the full key space.
+    /// Return the histogram and its bin size.
+    pub fn build_full_histogram<H: Histogram>(
+        &self,
+        nb_bins: usize,
+    ) -> Result<(H, f64), RankError> {
+        if !(1..=MAX_NB_BINS).contains(&nb_bins) {
+            Err("Bin count out of limits.")?;
+        }
+        // Same margin as in bin_size, but for the maximum total cost instead of the key cost.
+        let nb_subkeys = self.costs.len();
+        let margin = (nb_subkeys / 2) + (nb_subkeys & 0x1);
+        let effective_nb_bins = nb_bins.checked_sub(margin + 1).ok_or("nb_bins too small")?;
+        if effective_nb_bins == 0 {
+            Err("nb_bins too small")?;
+        }
+        let max_cost: f64 = self
             .costs
+            .iter()
+            .map(|sc| sc.iter().copied().fold(0.0, f64::max))
+            .sum();
+        let bin_size = if max_cost > 0.0 {
+            max_cost / (effective_nb_bins as f64)
+        } else {
+            1.0
+        };
+        Ok((self.histogram_with_bin_size(nb_bins, bin_size), bin_size))
+    }
+    fn histogram_with_bin_size<H: Histogram>(&self, nb_bins: usize, bin_size: f64) -> H {
+        self.costs
             .iter()
             .map(|costs| {
                 H::from_elems(
@@ -191,8 +221,7 @@ impl RankProblem {
             .fold(None, |acc: Option<H>, hist| {
                 acc.map(|x| x.convolve(&hist)).or(Some(hist))
             })
-            .expect("Some subkey");
-        return Ok((hist, bin_size));
+            .expect("Some subkey")
     }
     /// Get the exact rank through brute-force cost computation for all keys.
     pub fn naive_rank(&self) -> f64 {
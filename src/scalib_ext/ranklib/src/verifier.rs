@@ -0,0 +1,113 @@
+use aes::cipher::{consts::U16, generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256};
+
+use crate::enumeration::KeyVerifier;
+use crate::RankError;
+
+/// AES-128 or AES-256 key verification against known plaintext/ciphertext pairs.
+///
+/// The subkeys are the bytes of the key (16 or 32 subkeys, with values in 0..256).
+#[derive(Debug, Clone)]
+pub struct AesVerifier {
+    key_len: usize,
+    pairs: Vec<([u8; 16], [u8; 16])>,
+}
+
+impl AesVerifier {
+    /// key_len is the key length in bytes (16 for AES-128, 32 for AES-256), and pairs are
+    /// (plaintext, ciphertext) blocks, at least one of which is needed.
+    pub fn new(key_len: usize, pairs: Vec<([u8; 16], [u8; 16])>) -> Result<Self, RankError> {
+        if key_len != 16 && key_len != 32 {
+            Err("AES key length must be 16 or 32 bytes.")?;
+        }
+        if pairs.is_empty() {
+            Err("At least one plaintext/ciphertext pair is needed.")?;
+        }
+        Ok(Self { key_len, pairs })
+    }
+    fn check(&self, cipher: impl BlockEncrypt<BlockSize = U16>) -> bool {
+        self.pairs.iter().all(|(p, c)| {
+            let mut block = GenericArray::from(*p);
+            cipher.encrypt_block(&mut block);
+            block.as_slice() == c
+        })
+    }
+}
+
+impl KeyVerifier for AesVerifier {
+    fn verify(&self, key: &[usize]) -> bool {
+        if key.len() != self.key_len || key.iter().any(|k| *k > u8::MAX as usize) {
+            return false;
+        }
+        let key: Vec<u8> = key.iter().map(|k| *k as u8).collect();
+        if self.key_len == 16 {
+            self.check(Aes128::new(GenericArray::from_slice(&key)))
+        } else {
+            self.check(Aes256::new(GenericArray::from_slice(&key)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enumeration::enumerate_and_test;
+
+    fn hex(s: &str) -> [u8; 16] {
+        let mut res = [0; 16];
+        for (i, r) in res.iter_mut().enumerate() {
+            *r = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        res
+    }
+
+    #[test]
+    fn test_aes_fips197() {
+        let pt = hex("00112233445566778899aabbccddeeff");
+        let v128 =
+            AesVerifier::new(16, vec![(pt, hex("69c4e0d86a7b0430d8cdb78070b4c55a"))]).unwrap();
+        let v256 =
+            AesVerifier::new(32, vec![(pt, hex("8ea2b7ca516745bfeafc49904b496089"))]).unwrap();
+        let key: Vec<usize> = (0..32).collect();
+        assert!(v128.verify(&key[..16]));
+        assert!(!v128.verify(&key[1..17]));
+        assert!(v256.verify(&key));
+        assert!(!v256.verify(&key[..16]));
+        assert!(AesVerifier::new(24, vec![(pt, pt)]).is_err());
+        assert!(AesVerifier::new(16, vec![]).is_err());
+    }
+
+    #[test]
+    fn test_aes_key_recovery() {
+        let key: Vec<usize> = (0..16).map(|i| (i * 37 + 11) % 256).collect();
+        let key_bytes: Vec<u8> = key.iter().map(|k| *k as u8).collect();
+        let cipher = Aes128::new(GenericArray::from_slice(&key_bytes));
+        let pairs: Vec<_> = (0..2)
+            .map(|i| {
+                let pt = [i as u8; 16];
+                let mut ct = GenericArray::from(pt);
+                cipher.encrypt_block(&mut ct);
+                (pt, ct.into())
+            })
+            .collect();
+        let verifier = AesVerifier::new(16, pairs).unwrap();
+        // The right value is the second most likely one for two of the subkeys.
+        let costs: Vec<Vec<f64>> = key
+            .iter()
+            .enumerate()
+            .map(|(i, k)| {
+                (0..256)
+                    .map(|j| match (j == *k, i % 8 == 0, j == (k + 1) % 256) {
+                        (true, false, _) => 0.0,
+                        (true, true, _) => 1.0,
+                        (false, true, true) => 0.5,
+                        _ => 10.0,
+                    })
+                    .collect()
+            })
+            .collect();
+        let res = enumerate_and_test(&costs, &verifier, 100).unwrap();
+        assert_eq!(res.key, Some(key));
+        assert_eq!(res.nb_keys, 4);
+    }
+}
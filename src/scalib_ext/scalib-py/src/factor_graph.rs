@@ -4,7 +4,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use bincode::{deserialize, serialize};
-use numpy::{PyArray, PyArray1, PyArray2};
+use numpy::{PyArray, PyArray1, PyArray2, PyReadonlyArray2};
 use pyo3::exceptions::{PyKeyError, PyTypeError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyTuple};
@@ -56,7 +56,35 @@ impl FactorGraph {
             })
         }
     }
-//__getstate__方法用于序列化，序列化是指将对象状态转换为可存储或传输的格式的过程
+    #[staticmethod]
+    pub fn from_netlist(
+        netlist: &str,
+        format: &str,
+        single: Vec<String>,
+        public: Vec<String>,
+        probes: Vec<String>,
+    ) -> PyResult<Self> {
+        let config = sasca::NetlistConfig {
+            single,
+            public,
+            probes,
+        };
+        let fg = match format {
+            "blif" => sasca::import_blif(netlist, &config),
+            "verilog" => sasca::import_verilog(netlist, &config),
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown netlist format {}.",
+                    format
+                )))
+            }
+        }
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Self {
+            inner: Some(Arc::new(fg)),
+        })
+    }
+    //__getstate__方法用于序列化，序列化是指将对象状态转换为可存储或传输的格式的过程
     pub fn __getstate__(&self, py: Python) -> PyResult<PyObject> {
         let to_ser: Option<&sasca::FactorGraph> = self.inner.as_deref();
         Ok(PyBytes::new(py, &serialize(&to_ser).unwrap()).to_object(py))
@@ -91,6 +119,63 @@ impl FactorGraph {
     pub fn factor_names(&self) -> Vec<&str> {
         self.get_inner().factor_names().collect()
     }
+    pub fn simplify(&self, keep: Vec<&str>) -> PyResult<Self> {
+        let keep = keep
+            .into_iter()
+            .map(|v| {
+                self.get_inner()
+                    .get_varid(v)
+                    .map_err(|e| PyValueError::new_err(e.to_string()))
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(Self {
+            inner: Some(Arc::new(self.get_inner().simplify(&keep))),
+        })
+    }
+    pub fn eliminated(&self) -> HashMap<&str, Option<(&str, bool)>> {
+        let inner = self.get_inner();
+        inner
+            .eliminated()
+            .map(|(name, e)| {
+                let alias = match e {
+                    sasca::Elimination::Alias { var, neg } => Some((inner.var_name(var), neg)),
+                    sasca::Elimination::Removed => None,
+                };
+                (name, alias)
+            })
+            .collect()
+    }
+    pub fn to_description(&self) -> String {
+        self.get_inner().to_description()
+    }
+    pub fn to_dot(&self) -> String {
+        self.get_inner().to_dot()
+    }
+    pub fn to_graphml(&self) -> String {
+        self.get_inner().to_graphml()
+    }
+    pub fn analyze(&self, py: Python, nmulti: u32) -> PyObject {
+        let analysis = self.get_inner().analyze(nmulti);
+        let names = |cycles: Vec<Vec<sasca::VarId>>| -> Vec<Vec<&str>> {
+            cycles
+                .into_iter()
+                .map(|c| {
+                    c.into_iter()
+                        .map(|v| self.get_inner().var_name(v))
+                        .collect()
+                })
+                .collect()
+        };
+        (
+            analysis.cyclic,
+            names(analysis.cycles),
+            names(analysis.multi_cycles),
+            analysis.treewidth,
+            analysis.edge_memory,
+            analysis.var_memory,
+        )
+            .into_py(py)
+    }
     pub fn factor_scope<'s>(&'s self, factor: &str) -> PyResult<Vec<&'s str>> {
         let factor_id = self.get_factor(factor)?;
         Ok(self
@@ -208,22 +293,160 @@ impl BPState {
     pub fn is_cyclic(&self) -> bool {
         self.get_inner().is_cyclic()
     }
+    pub fn set_max_product(&mut self, max_product: bool) {
+        self.get_inner_mut().set_mode(if max_product {
+            sasca::BPMode::MaxProduct
+        } else {
+            sasca::BPMode::SumProduct
+        });
+    }
+    pub fn set_log_domain(&mut self, log_domain: bool) {
+        self.get_inner_mut().set_log_domain(log_domain);
+    }
+    pub fn get_state_log(&self, py: Python, var: &str) -> PyResult<PyObject> {
+        Ok(match self.get_inner().get_state_log(self.get_var(var)?) {
+            Some(d) => PyArray1::from_array(py, &d.value().row(0)).into_py(py),
+            None => py.None(),
+        })
+    }
+    pub fn map_assignment(&self, py: Python) -> PyResult<HashMap<String, PyObject>> {
+        let inner = self.get_inner();
+        let assignment = inner.map_assignment();
+        Ok(inner
+            .get_graph()
+            .vars()
+            .map(|(v, vn)| (vn.to_owned(), pub2py(py, &assignment[v])))
+            .collect())
+    }
     //先验概率
+    pub fn entropy(&self, py: Python, var: &str) -> PyResult<PyObject> {
+        let var_id = self.get_var(var)?;
+        let entropy = self.get_inner().entropy(var_id);
+        Ok(per_exec2py(
+            py,
+            &entropy,
+            self.get_inner().get_graph().var_multi(var_id),
+        ))
+    }
+    pub fn argmax(&self, py: Python, var: &str) -> PyResult<PyObject> {
+        Ok(pub2py(py, &self.get_inner().argmax(self.get_var(var)?)))
+    }
+    pub fn probability(&self, py: Python, var: &str, value: PyObject) -> PyResult<PyObject> {
+        let var_id = self.get_var(var)?;
+        let bp = self.get_inner();
+        let value = obj2pub(py, value, bp.get_graph().var_multi(var_id))?;
+        let proba = bp
+            .probability(var_id, &value)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(per_exec2py(py, &proba, bp.get_graph().var_multi(var_id)))
+    }
+    pub fn set_trace(
+        &mut self,
+        py: Python,
+        vars: Vec<&str>,
+        values: HashMap<&str, PyObject>,
+    ) -> PyResult<()> {
+        let mut trace_vars = Vec::with_capacity(vars.len());
+        for var in vars {
+            let var_id = self.get_var(var)?;
+            let multi = self.get_inner().get_graph().var_multi(var_id);
+            let value = values
+                .get(var)
+                .map(|v| obj2pub(py, v.clone_ref(py), multi))
+                .transpose()?;
+            trace_vars.push((var_id, value));
+        }
+        self.get_inner_mut()
+            .set_trace(trace_vars)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+    /// For each traced variable, the entropy, argmax and probability (None if there is no
+    /// reference value) at each iteration.
+    pub fn get_trace(&self, py: Python) -> HashMap<&str, (PyObject, PyObject, PyObject)> {
+        let bp = self.get_inner();
+        let graph = bp.get_graph();
+        bp.trace_vars()
+            .iter()
+            .enumerate()
+            .map(|(i, (var, value))| {
+                let multi = graph.var_multi(*var);
+                let steps = bp.trace().iter().map(|step| &step[i]);
+                let entropy = rows2py(py, multi, steps.clone().map(|s| s.entropy.clone()));
+                let argmax = rows2py(
+                    py,
+                    multi,
+                    steps.clone().map(|s| s.argmax.as_slice().to_vec()),
+                );
+                let probability = if value.is_some() {
+                    rows2py(py, multi, steps.map(|s| s.probability.clone().unwrap()))
+                } else {
+                    py.None()
+                };
+                (graph.var_name(*var), (entropy, argmax, probability))
+            })
+            .collect()
+    }
     pub fn set_evidence(&mut self, py: Python, var: &str, distr: PyObject) -> PyResult<()> {
         let var_id = self.get_var(var)?;
-        let bp = self.get_inner_mut();//返回inner 字段的可变引用 &mut sasca::BPState
+        let bp = self.get_inner_mut(); //返回inner 字段的可变引用 &mut sasca::BPState
         let distr = obj2distr(py, distr, bp.get_graph().var_multi(var_id))?;
         bp.set_evidence(var_id, distr)
             .map_err(|e| PyTypeError::new_err(e.to_string()))?;
         Ok(())
     }
+    /// For each (var, model, pois, model var), the evidence of var is given by model (an LDA or
+    /// a RLDA, with model var) on the pois of the traces.
+    pub fn set_evidence_from_models(
+        &mut self,
+        py: Python,
+        traces: PyReadonlyArray2<i16>,
+        models: Vec<(&str, &PyAny, Vec<usize>, usize)>,
+        config: crate::ConfigWrapper,
+    ) -> PyResult<()> {
+        let models = models
+            .into_iter()
+            .map(|(var, model, pois, v)| {
+                let model = if let Ok(lda) = model.extract::<PyRef<crate::lda::LDA>>() {
+                    ModelRef::Lda(lda)
+                } else if let Ok(rlda) = model.extract::<PyRef<crate::rlda::RLDA>>() {
+                    ModelRef::Rlda(rlda, v)
+                } else {
+                    return Err(PyTypeError::new_err("The model must be an LDA or a RLDA."));
+                };
+                Ok((self.get_var(var)?, model, pois))
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        let sources = models
+            .iter()
+            .map(|(var, model, pois)| sasca::EvidenceSource {
+                var: *var,
+                model: match model {
+                    ModelRef::Lda(lda) => sasca::EvidenceModel::Lda(&lda.inner),
+                    ModelRef::Rlda(rlda, v) => {
+                        sasca::EvidenceModel::Rlda(rlda.inner.as_ref().unwrap(), *v)
+                    }
+                },
+                pois: pois.clone(),
+            })
+            .collect::<Vec<_>>();
+        let traces = traces.as_array();
+        config.on_worker(py, |_| {
+            self.get_inner_mut()
+                .set_evidence_from_models(traces, &sources)
+                .map_err(|e| PyValueError::new_err(e.to_string()))
+        })
+    }
     pub fn drop_evidence(&mut self, var: &str) -> PyResult<()> {
         let var_id = self.get_var(var)?;
         self.get_inner_mut().drop_evidence(var_id);
         Ok(())
     }
     pub fn get_state(&self, py: Python, var: &str) -> PyResult<PyObject> {
-        distr2py(py, self.get_inner().get_state(self.get_var(var)?))
+        let state = self
+            .get_inner()
+            .get_state_by_name(var)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        distr2py(py, &state)
     }
     pub fn set_state(&mut self, py: Python, var: &str, distr: PyObject) -> PyResult<()> {
         let var_id = self.get_var(var)?;
@@ -345,6 +568,58 @@ impl BPState {
                 .propagate_loopy_step(n_steps, clear_beliefs);
         });
     }
+    pub fn propagate_loopy(
+        &mut self,
+        py: Python,
+        n_steps: u32,
+        config: crate::ConfigWrapper,
+        clear_beliefs: bool,
+        damping: f64,
+        schedule: &str,
+        tolerance: Option<f64>,
+    ) -> PyResult<u32> {
+        let schedule = match schedule {
+            "flooding" => sasca::BPSchedule::Flooding,
+            "sequential" => sasca::BPSchedule::Sequential,
+            "sorted_sweep" => sasca::BPSchedule::SortedSweep,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown schedule {}.",
+                    schedule
+                )))
+            }
+        };
+        let loopy_config = sasca::LoopyConfig {
+            damping,
+            tolerance,
+            schedule,
+        };
+        config.on_worker(py, |_| {
+            self.get_inner_mut()
+                .propagate_loopy(n_steps, clear_beliefs, &loopy_config)
+                .map_err(|e| PyValueError::new_err(e.to_string()))
+        })
+    }
+    pub fn rank_accuracy(
+        &self,
+        py: Python,
+        key_vars: Vec<&str>,
+        key: Vec<sasca::ClassVal>,
+        acc: f64,
+        method: &str,
+        max_nb_bin: usize,
+        config: crate::ConfigWrapper,
+    ) -> PyResult<(f64, f64, f64)> {
+        let method =
+            crate::ranking::str2method(method).map_err(|e| PyValueError::new_err(e.to_owned()))?;
+        config.on_worker(py, |_| {
+            let res = self
+                .get_inner()
+                .rank_accuracy(&key_vars, &key, method, acc, None, max_nb_bin)
+                .map_err(|e| PyValueError::new_err(e.to_string()))?;
+            Ok((res.min, res.est, res.max))
+        })
+    }
     pub fn graph(&self) -> FactorGraph {
         FactorGraph {
             inner: Some(self.get_inner().get_graph().clone()),
@@ -366,6 +641,59 @@ impl BPState {
                 .map_err(|e| PyValueError::new_err(e.to_string()))
         })
     }
+    pub fn extend(
+        &mut self,
+        py: Python,
+        nmulti: u32,
+        public_values: PyObject,
+        evidence: HashMap<&str, PyObject>,
+    ) -> PyResult<()> {
+        let graph = self.get_inner().get_graph().clone();
+        let pub_values = pyobj2pubs(py, public_values, graph.public_multi())?;
+        let evidence = evidence
+            .into_iter()
+            .map(|(var, distr)| {
+                let var_id = self.get_var(var)?;
+                Ok((var_id, obj2distr(py, distr, graph.var_multi(var_id))?))
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        self.get_inner_mut()
+            .extend(nmulti, pub_values, evidence)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+    pub fn propagate_incremental(
+        &mut self,
+        py: Python,
+        config: crate::ConfigWrapper,
+    ) -> PyResult<()> {
+        config.on_worker(py, |_| {
+            self.get_inner_mut()
+                .propagate_incremental()
+                .map_err(|e| PyValueError::new_err(e.to_string()))
+        })
+    }
+    pub fn exact_cost(&self) -> (usize, usize, usize) {
+        let cost = self.get_inner().exact_cost();
+        (cost.max_clique_size, cost.total_size, cost.width)
+    }
+    pub fn propagate_exact(
+        &mut self,
+        py: Python,
+        max_clique_size: usize,
+        config: crate::ConfigWrapper,
+    ) -> PyResult<()> {
+        config.on_worker(py, |_| {
+            self.get_inner_mut()
+                .propagate_exact(max_clique_size)
+                .map(|_| ())
+                .map_err(|e| PyValueError::new_err(e.to_string()))
+        })
+    }
+}
+
+enum ModelRef<'py> {
+    Lda(PyRef<'py, crate::lda::LDA>),
+    Rlda(PyRef<'py, crate::rlda::RLDA>, usize),
 }
 
 fn obj2distr(py: Python, distr: PyObject, multi: bool) -> PyResult<sasca::Distribution> {
@@ -401,6 +729,42 @@ fn obj2pub(py: Python, obj: PyObject, multi: bool) -> PyResult<sasca::PublicValu
     })
 }
 
+/// Value of each execution, as a float for SINGLE vars.
+fn per_exec2py(py: Python, x: &[f64], multi: bool) -> PyObject {
+    if multi {
+        PyArray1::from_slice(py, x).into_py(py)
+    } else {
+        x[0].into_py(py)
+    }
+}
+
+fn pub2py(py: Python, value: &sasca::PublicValue) -> PyObject {
+    match value {
+        sasca::PublicValue::Single(x) => x.into_py(py),
+        sasca::PublicValue::Multi(x) => PyArray1::from_slice(py, x).into_py(py),
+    }
+}
+
+/// Stack the per-execution values of each iteration in an array of shape (n_iter, nexec), or
+/// (n_iter,) for SINGLE vars.
+fn rows2py<T: numpy::Element + Copy>(
+    py: Python,
+    multi: bool,
+    rows: impl Iterator<Item = Vec<T>>,
+) -> PyObject {
+    let rows: Vec<Vec<T>> = rows.collect();
+    if multi {
+        let ncols = rows.first().map_or(0, |r| r.len());
+        let flat: Vec<T> = rows.iter().flatten().copied().collect();
+        ndarray::Array2::from_shape_vec((rows.len(), ncols), flat)
+            .map(|a| PyArray2::from_owned_array(py, a).into_py(py))
+            .unwrap()
+    } else {
+        let col: Vec<T> = rows.iter().map(|r| r[0]).collect();
+        PyArray1::from_vec(py, col).into_py(py)
+    }
+}
+
 fn distr2py(py: Python, distr: &sasca::Distribution) -> PyResult<PyObject> {
     if let Some(d) = distr.value() {
         if distr.multi() {
@@ -113,7 +113,7 @@ impl LdaAcc {
 
 #[pyclass]
 pub(crate) struct LDA {
-    inner: scalib::lda::LDA,
+    pub inner: scalib::lda::LDA,
 }
 #[pymethods]
 impl LDA {
@@ -128,6 +128,8 @@ fn _scalib_ext(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<thread_pool::ThreadPool>()?;
     m.add_function(wrap_pyfunction!(ranking::rank_accuracy, m)?)?;
     m.add_function(wrap_pyfunction!(ranking::rank_nbin, m)?)?;
+    m.add_function(wrap_pyfunction!(ranking::rank_bounds_accuracy, m)?)?;
+    m.add_function(wrap_pyfunction!(ranking::aes_key_search, m)?)?;
     m.add_function(wrap_pyfunction!(belief_propagation::run_bp, m)?)?;
     m.add_function(wrap_pyfunction!(partial_cp, m)?)?;
     m.add_function(wrap_pyfunction!(usable_parallelism, m)?)?;
@@ -1,3 +1,4 @@
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::{pyfunction, PyResult, Python};
 
 #[pyfunction]
@@ -47,7 +48,62 @@ pub fn rank_nbin(
     })
 }
 
-fn str2method(s: &str) -> Result<ranklib::RankingMethod, &str> {
+#[pyfunction]
+#[pyo3(signature = (costs_lower, costs_upper, key, acc, merge, method, max_nb_bin, config))]
+pub fn rank_bounds_accuracy(
+    py: Python,
+    costs_lower: Vec<Vec<f64>>,
+    costs_upper: Vec<Vec<f64>>,
+    key: Vec<usize>,
+    acc: f64,
+    merge: Option<usize>,
+    method: String,
+    max_nb_bin: usize,
+    config: crate::ConfigWrapper,
+) -> PyResult<(f64, f64, f64)> {
+    config.on_worker(py, |_| {
+        let res = str2method(&method).map_err(|s| PyValueError::new_err(s.to_owned()))?;
+        let res = res
+            .rank_bounds_accuracy(&costs_lower, &costs_upper, &key, acc, merge, max_nb_bin)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok((res.min, res.est, res.max))
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (costs, plaintexts, ciphertexts, max_nb_keys, config))]
+pub fn aes_key_search(
+    py: Python,
+    costs: Vec<Vec<f64>>,
+    plaintexts: Vec<Vec<u8>>,
+    ciphertexts: Vec<Vec<u8>>,
+    max_nb_keys: u64,
+    config: crate::ConfigWrapper,
+) -> PyResult<(Option<Vec<usize>>, u64)> {
+    let block = |b: Vec<u8>| -> PyResult<[u8; 16]> {
+        b.try_into()
+            .map_err(|_| PyValueError::new_err("AES blocks must be 16 bytes long."))
+    };
+    if plaintexts.len() != ciphertexts.len() {
+        return Err(PyValueError::new_err(
+            "There must be as many plaintexts as ciphertexts.",
+        ));
+    }
+    let pairs = plaintexts
+        .into_iter()
+        .zip(ciphertexts)
+        .map(|(p, c)| Ok((block(p)?, block(c)?)))
+        .collect::<PyResult<Vec<_>>>()?;
+    let verifier = ranklib::AesVerifier::new(costs.len(), pairs)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    config.on_worker(py, |_| {
+        let res = ranklib::enumerate_and_test(&costs, &verifier, max_nb_keys)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok((res.key, res.nb_keys))
+    })
+}
+
+pub(crate) fn str2method(s: &str) -> Result<ranklib::RankingMethod, &str> {
     match s {
         "naive" => Ok(ranklib::RankingMethod::Naive),
         "hist" => Ok(ranklib::RankingMethod::Hist),
@@ -55,6 +111,10 @@ fn str2method(s: &str) -> Result<ranklib::RankingMethod, &str> {
         "histbignum" => Ok(ranklib::RankingMethod::HistBigNum),
         #[cfg(not(feature = "ntl"))]
         "histbignum" => Err("Ranking method 'histbignum' is not supported. Compile scalib_ext with ntl feature enabled."),
+        #[cfg(feature = "bigint")]
+        "histbigint" => Ok(ranklib::RankingMethod::HistBigInt),
+        #[cfg(not(feature = "bigint"))]
+        "histbigint" => Err("Ranking method 'histbigint' is not supported. Compile scalib_ext with bigint feature enabled."),
         #[cfg(feature = "hellib")]
         "hellib" => Ok(ranklib::RankingMethod::Hellib),
         #[cfg(not(feature = "hellib"))]
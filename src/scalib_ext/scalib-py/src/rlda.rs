@@ -11,7 +11,7 @@ use std::sync::Arc;
 
 #[pyclass(module = "scalib._scalib_ext")]
 pub(crate) struct RLDA {
-    inner: Option<scalib::rlda::RLDA>,
+    pub inner: Option<scalib::rlda::RLDA>,
 }
 #[pymethods]
 impl RLDA {
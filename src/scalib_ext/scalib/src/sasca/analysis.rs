@@ -0,0 +1,145 @@
+//! Structural analysis of a factor graph, to catch bad models before running BP on them.
+
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+
+use super::bp_compute::Proba;
+use super::exact::JunctionTree;
+use super::factor_graph::VarId;
+use super::FactorGraph;
+
+/// Report on the structure of a factor graph and on the cost of running BP on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphAnalysis {
+    /// Number of executions the report is made for.
+    pub nmulti: u32,
+    /// BP is loopy (same as BPState::is_cyclic).
+    pub cyclic: bool,
+    /// Cycles of one execution: one cycle for each edge that is not in a spanning forest of the
+    /// graph (i.e., a cycle basis), given as the variables along the cycle.
+    pub cycles: Vec<Vec<VarId>>,
+    /// Cycles through several executions (only if nmulti > 1): path between two SINGLE
+    /// variables linked by the MULTI part of the graph, which the same path in another
+    /// execution closes.
+    pub multi_cycles: Vec<Vec<VarId>>,
+    /// Upper bound on the treewidth of the graph (with all the executions), from the
+    /// elimination order of exact inference (same as ExactCost::width).
+    pub treewidth: usize,
+    /// Bytes of the beliefs on the edges of a BPState (belief_from_var and belief_to_var).
+    pub edge_memory: usize,
+    /// Bytes of the evidence and of the current distributions of the variables of a BPState.
+    pub var_memory: usize,
+}
+
+/// Spanning forest of the graph, from a BFS.
+struct Forest {
+    parent: Vec<Option<NodeIndex>>,
+    depth: Vec<usize>,
+}
+
+impl Forest {
+    fn new(graph: &FactorGraph) -> Self {
+        let n = graph.petgraph.node_count();
+        let mut parent = vec![None; n];
+        let mut depth = vec![0; n];
+        let mut seen = vec![false; n];
+        for root in graph.petgraph.node_indices() {
+            if seen[root.index()] {
+                continue;
+            }
+            seen[root.index()] = true;
+            let mut component = vec![root];
+            let mut next = 0;
+            while let Some(node) = component.get(next).copied() {
+                next += 1;
+                for nbr in graph.petgraph.neighbors(node) {
+                    if !seen[nbr.index()] {
+                        seen[nbr.index()] = true;
+                        parent[nbr.index()] = Some(node);
+                        depth[nbr.index()] = depth[node.index()] + 1;
+                        component.push(nbr);
+                    }
+                }
+            }
+        }
+        Self { parent, depth }
+    }
+
+    fn is_tree_edge(&self, a: NodeIndex, b: NodeIndex) -> bool {
+        self.parent[a.index()] == Some(b) || self.parent[b.index()] == Some(a)
+    }
+
+    /// Path from a to b in the forest (a and b must be in the same tree).
+    fn path(&self, mut a: NodeIndex, mut b: NodeIndex) -> Vec<NodeIndex> {
+        let mut from_a = Vec::new();
+        let mut from_b = Vec::new();
+        while a != b {
+            if self.depth[a.index()] >= self.depth[b.index()] {
+                from_a.push(a);
+                a = self.parent[a.index()].unwrap();
+            } else {
+                from_b.push(b);
+                b = self.parent[b.index()].unwrap();
+            }
+        }
+        from_a.push(a);
+        from_a.extend(from_b.into_iter().rev());
+        from_a
+    }
+}
+
+impl FactorGraph {
+    /// Analyze the graph for nmulti executions.
+    pub fn analyze(&self, nmulti: u32) -> GraphAnalysis {
+        let forest = Forest::new(self);
+        let path_vars = |a, b| -> Vec<VarId> {
+            forest
+                .path(a, b)
+                .into_iter()
+                .filter_map(|n| self.petgraph[n].var())
+                .collect()
+        };
+        let cycles = self
+            .petgraph
+            .edge_references()
+            .filter(|e| !forest.is_tree_edge(e.source(), e.target()))
+            .map(|e| path_vars(e.source(), e.target()))
+            .collect();
+        let multi_cycles = if nmulti > 1 {
+            self.multi_components_singles()
+                .into_iter()
+                .flat_map(|singles| {
+                    let node = |var: VarId| self.var_graph_ids[var];
+                    let first = singles[0];
+                    singles[1..]
+                        .iter()
+                        .map(|var| path_vars(node(first), node(*var)))
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        let distr_bytes = |multi: bool, nc: usize| {
+            let n = if multi { nmulti as usize } else { 1 };
+            n.saturating_mul(nc)
+                .saturating_mul(2 * std::mem::size_of::<Proba>())
+        };
+        GraphAnalysis {
+            nmulti,
+            cyclic: self.is_cyclic(nmulti > 1),
+            cycles,
+            multi_cycles,
+            treewidth: JunctionTree::new(self, nmulti as usize).cost(self).width,
+            edge_memory: self
+                .edges
+                .iter()
+                .map(|e| distr_bytes(self.factor_multi(e.factor), self.var_nc(e.var)))
+                .fold(0, usize::saturating_add),
+            var_memory: self
+                .range_vars()
+                .map(|v| distr_bytes(self.var_multi(v), self.var_nc(v)))
+                .fold(0, usize::saturating_add),
+        }
+    }
+}
@@ -1,13 +1,22 @@
 //1、BPState 方法：其中propagate_factor 和 propagate_var: 分别针对因子和变量进行信念传播；2、因子函数 (factor_gen_and, factor_xor, factor_not, 等):这些函数用于在因子图中的不同因子类型上执行信念传播计算。
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use itertools::Itertools;
+use rayon::prelude::*;
 use thiserror::Error;
 
+use super::exact::JunctionTree;
 use super::factor_graph::{
-    EdgeId, EdgeSlice, EdgeVec, Factor, FactorId, FactorKind, FactorVec, Node, Table, VarId, VarVec,
+    EdgeId, EdgeSlice, EdgeVec, Factor, FactorId, FactorKind, FactorVec, Field, Node, Table, VarId,
+    VarVec,
+};
+use super::max_product::factor_max_product;
+use super::{
+    ClassVal, Distribution, Elimination, ExactCost, FGError, FactorGraph, LogDistribution,
+    PublicValue,
 };
-use super::{Distribution, FactorGraph, PublicValue};
 
 // TODO improvements
 // - use a pool for Distribution allocations (can be a simple Vec storing them), to avoid frequent
@@ -77,6 +86,20 @@ pub struct BPState {
     cyclic: bool,
     // fft plans
     plans: FftPlans,
+    // fft plans for GF multiplication (in the log domain, of size nc-1)
+    gf_plans: Option<FftPlans>,
+    mode: BPMode,
+    // compute the products for SINGLE vars in the log domain
+    log_domain: bool,
+    // current log-domain proba for each SINGLE var (if computed in the log domain)
+    var_state_log: VarVec<Option<LogDistribution>>,
+    // vars (with their reference value) summarized after each loopy BP iteration
+    trace_vars: Vec<(VarId, Option<PublicValue>)>,
+    trace: Vec<Vec<VarSummary>>,
+    // executions whose messages are in the incremental products (see propagate_incremental)
+    incremental_rows: u32,
+    // for each edge of a SINGLE var, product of its messages over these executions
+    incremental_products: HashMap<EdgeId, LogDistribution>,
 }
 
 #[derive(Debug, Clone, Error)]
@@ -91,6 +114,85 @@ pub enum BPError {
     DistributionLayout(Vec<usize>, Vec<isize>),
     #[error("Cannot run acyclic BP on a cyclic graph.")]
     NotAcyclic,
+    #[error("Damping must be in [0, 1), got {0}.")]
+    InvalidDamping(f64),
+    #[error("Exact inference needs a clique of {0} entries, more than the budget of {1}.")]
+    ExactTooLarge(usize, usize),
+    #[error("Wrong value {0} for a variable with {1} classes.")]
+    WrongValue(ClassVal, usize),
+    #[error("Wrong number of values: got {0}, expected {1}.")]
+    WrongValueCount(usize, usize),
+    #[error("Wrong number of public values: got {0}, expected {1}.")]
+    WrongPublicCount(usize, usize),
+    #[error("Wrong value for public {0}: {1}.")]
+    WrongPublic(String, &'static str),
+    #[error("Wrong number of traces: got {0}, expected {1}.")]
+    WrongTraceCount(usize, u32),
+    #[error("Wrong number of samples for the model: got {0}, expected {1}.")]
+    WrongPoiCount(usize, usize),
+    #[error("Sample {0} is out of the traces, which have {1} samples.")]
+    WrongPoi(usize, usize),
+    #[error("Wrong model variable {0}, the model has {1} variables.")]
+    WrongModelVar(usize, usize),
+}
+
+/// Order of the message updates in loopy BP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BPSchedule {
+    /// Update all the factors, then all the variables.
+    Flooding,
+    /// Update the factors one by one, each one being followed by the update of its variables.
+    Sequential,
+    /// Same as Sequential, but the factors are sorted by decreasing change of their messages at
+    /// the previous iteration (the order is fixed for a whole iteration, and every factor is
+    /// updated once per iteration).
+    SortedSweep,
+}
+
+/// Kind of the factor to var messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum BPMode {
+    /// Marginal distributions (sum-product).
+    #[default]
+    SumProduct,
+    /// Max-marginals (max-product), whose argmax is the maximum a posteriori assignment.
+    ///
+    /// The factor messages are max-convolutions, computed directly in O(nc^2) per pair of
+    /// operands (there is no equivalent of the WHT/FFT used for XOR, ADD and GF multiplication
+    /// factors in sum-product mode), which is much slower for large nc.
+    MaxProduct,
+}
+
+/// Configuration of loopy BP.
+#[derive(Debug, Clone)]
+pub struct LoopyConfig {
+    /// The new factor to var messages are (1-damping)*new + damping*previous.
+    pub damping: f64,
+    /// Stop when the maximum change in a factor to var message during an iteration is at most
+    /// tolerance.
+    pub tolerance: Option<f64>,
+    pub schedule: BPSchedule,
+}
+
+/// Summary of the state of a variable (for each execution if the variable is MULTI).
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct VarSummary {
+    /// Shannon entropy, in bits.
+    pub entropy: Vec<f64>,
+    /// Most likely value.
+    pub argmax: PublicValue,
+    /// Probability of the reference value, if one is given.
+    pub probability: Option<Vec<f64>>,
+}
+
+impl Default for LoopyConfig {
+    fn default() -> Self {
+        Self {
+            damping: 0.0,
+            tolerance: None,
+            schedule: BPSchedule::Flooding,
+        }
+    }
 }
 
 impl BPState {
@@ -104,19 +206,24 @@ impl BPState {
         let var_state: VarVec<_> = graph
             .vars
             .values()
-            .map(|v| Distribution::new(v.multi, graph.nc, nmulti))
+            .map(|v| Distribution::new(v.multi, v.nc, nmulti))
             .collect();
         let beliefs: EdgeVec<_> = graph
             .edges
             .iter()
-            .map(|e| Distribution::new(graph.factor(e.factor).multi, graph.nc, nmulti))
+            .map(|e| {
+                let nc = graph.var_nc(e.var);
+                Distribution::new(graph.factor(e.factor).multi, nc, nmulti)
+            })
             //.multi 表示是否为多模态分布，如果为 true，则表示这是一个多模态分布，否则为单模态。graph.nc 表示分布的类别数量或类别数。nmulti 表示分布的数量，即有多少个不同的模态。
             //AES NC 256 nmulti=攻击能量迹数量
             //初始化为均匀分布，具体见bp_compute：Distribution定义
             .collect();
+        let var_state_log = VarVec::from_vec(vec![None; graph.vars.len()]);
         let pub_reduced = graph.reduce_pub(&public_values);
         let cyclic = graph.is_cyclic(nmulti > 1);
         let plans = FftPlans::new(graph.nc);
+        let gf_plans = graph.field.as_ref().map(|_| FftPlans::new(graph.nc - 1));
         Self {//这个 Self 用于作为构造函数的返回值
             evidence: var_state.clone(),//克隆值不会互相影响
             belief_from_var: beliefs.clone(),
@@ -128,49 +235,335 @@ impl BPState {
             pub_reduced,
             cyclic,
             plans,
+            gf_plans,
+            mode: BPMode::SumProduct,
+            log_domain: false,
+            var_state_log,
+            trace_vars: vec![],
+            trace: vec![],
+            incremental_rows: 0,
+            incremental_products: HashMap::new(),
         }
     }
     pub fn is_cyclic(&self) -> bool {
         self.cyclic
     }
+    pub fn mode(&self) -> BPMode {
+        self.mode
+    }
+    /// Change the kind of the factor to var messages (does not reset the current beliefs).
+    pub fn set_mode(&mut self, mode: BPMode) {
+        self.mode = mode;
+        self.reset_incremental();
+    }
+    pub fn log_domain(&self) -> bool {
+        self.log_domain
+    }
+    /// Compute the products of the beliefs and evidence of SINGLE vars in the log domain.
+    /// This avoids underflows and keeps the posteriors exact when many traces are combined
+    /// into a single var. The beliefs sent to the factors are still in the linear domain.
+    pub fn set_log_domain(&mut self, log_domain: bool) {
+        self.log_domain = log_domain;
+        if !log_domain {
+            self.var_state_log.iter_mut().for_each(|s| *s = None);
+        }
+    }
     pub fn get_graph(&self) -> &std::sync::Arc<FactorGraph> {
         &self.graph
     }
-    fn check_distribution(&self, distr: &Distribution, multi: bool) -> Result<(), BPError> {
+    fn check_distribution(
+        &self,
+        distr: &Distribution,
+        multi: bool,
+        nc: usize,
+    ) -> Result<(), BPError> {
+        Self::check_shape(distr, multi, nc, self.nmulti)
+    }
+    fn check_shape(
+        distr: &Distribution,
+        multi: bool,
+        nc: usize,
+        nmulti: u32,
+    ) -> Result<(), BPError> {
         if distr.multi() != multi {
             Err(BPError::WrongDistributionKind(
                 if distr.multi() { "multi" } else { "single" },
                 if multi { "multi" } else { "single" },
             ))
-        } else if distr.shape().1 != self.graph.nc {
-            Err(BPError::WrongDistributionNc(distr.shape().1, self.graph.nc))
-        } else if distr.multi() && self.nmulti as usize != distr.shape().0 {
-            Err(BPError::WrongDistributionNmulti(
-                distr.shape().0,
-                self.nmulti,
-            ))
+        } else if distr.shape().1 != nc {
+            Err(BPError::WrongDistributionNc(distr.shape().1, nc))
+        } else if distr.multi() && nmulti as usize != distr.shape().0 {
+            Err(BPError::WrongDistributionNmulti(distr.shape().0, nmulti))
         } else {
             Ok(())
         }
     }
     pub fn set_evidence(&mut self, var: VarId, evidence: Distribution) -> Result<(), BPError> {
-        self.check_distribution(&evidence, self.graph.var_multi(var))?;
+        self.check_distribution(&evidence, self.graph.var_multi(var), self.graph.var_nc(var))?;
         self.evidence[var] = evidence;
+        if self.graph.var_multi(var) {
+            self.reset_incremental();
+        }
         Ok(())
     }
     pub fn drop_evidence(&mut self, var: VarId) {
         self.evidence[var] = self.evidence[var].as_uniform();//重置为均匀分布
+        if self.graph.var_multi(var) {
+            self.reset_incremental();
+        }
     }
     pub fn get_state(&self, var: VarId) -> &Distribution {
         &self.var_state[var]
     }
+    /// State of a variable given by name, which may have been replaced by an alias by
+    /// FactorGraph::simplify.
+    pub fn get_state_by_name(&self, var: &str) -> Result<Cow<'_, Distribution>, FGError> {
+        if let Some(Elimination::Alias { var, neg }) = self.graph.elimination(var) {
+            let mut state = self.var_state[var].clone();
+            if neg {
+                state.not();
+            }
+            Ok(Cow::Owned(state))
+        } else {
+            Ok(Cow::Borrowed(&self.var_state[self.graph.get_varid(var)?]))
+        }
+    }
+    /// Normalized log-domain state of a SINGLE var, if it was last computed in the log domain.
+    pub fn get_state_log(&self, var: VarId) -> Option<&LogDistribution> {
+        self.var_state_log[var].as_ref()
+    }
     pub fn set_state(&mut self, var: VarId, state: Distribution) -> Result<(), BPError> {
-        self.check_distribution(&state, self.graph.var_multi(var))?;
+        self.check_distribution(&state, self.graph.var_multi(var), self.graph.var_nc(var))?;
         self.var_state[var] = state;
+        self.var_state_log[var] = None;
         Ok(())
     }
     pub fn drop_state(&mut self, var: VarId) {
         self.var_state[var] = self.var_state[var].as_uniform();
+        self.var_state_log[var] = None;
+    }
+    /// Most likely value of every var according to its current state.
+    /// In max-product mode, after BP has converged, this is the MAP assignment.
+    pub fn map_assignment(&self) -> VarVec<PublicValue> {
+        self.var_state.iter().map(|d| d.argmax()).collect()
+    }
+    /// Entropy (in bits) of the current state of var.
+    pub fn entropy(&self, var: VarId) -> Vec<f64> {
+        self.var_state[var].entropy()
+    }
+    /// Most likely value of var according to its current state.
+    pub fn argmax(&self, var: VarId) -> PublicValue {
+        self.var_state[var].argmax()
+    }
+    /// Probability of value according to the current state of var.
+    pub fn probability(&self, var: VarId, value: &PublicValue) -> Result<Vec<f64>, BPError> {
+        self.check_value(var, value)?;
+        Ok(self.var_state[var].probability(value))
+    }
+    /// Entropy, argmax and probability of value (if any) of var.
+    pub fn summary(&self, var: VarId, value: Option<&PublicValue>) -> Result<VarSummary, BPError> {
+        Ok(VarSummary {
+            entropy: self.entropy(var),
+            argmax: self.argmax(var),
+            probability: value.map(|x| self.probability(var, x)).transpose()?,
+        })
+    }
+    fn check_value(&self, var: VarId, value: &PublicValue) -> Result<(), BPError> {
+        let n = self.var_state[var].shape().0;
+        if let PublicValue::Multi(x) = value {
+            if x.len() != n {
+                return Err(BPError::WrongValueCount(x.len(), n));
+            }
+        }
+        let nc = self.graph.var_nc(var);
+        match value.as_slice().iter().find(|x| **x as usize >= nc) {
+            Some(x) => Err(BPError::WrongValue(*x, nc)),
+            None => Ok(()),
+        }
+    }
+    /// Record the summary of vars (with the probability of their reference value, if any) after
+    /// each iteration of loopy BP. This clears the trace, and an empty vars stops the recording.
+    pub fn set_trace(&mut self, vars: Vec<(VarId, Option<PublicValue>)>) -> Result<(), BPError> {
+        for (var, value) in vars.iter() {
+            if let Some(value) = value {
+                self.check_value(*var, value)?;
+            }
+        }
+        self.trace_vars = vars;
+        self.trace.clear();
+        Ok(())
+    }
+    /// Summaries recorded after each iteration of loopy BP, in the order of the vars of
+    /// set_trace.
+    pub fn trace(&self) -> &[Vec<VarSummary>] {
+        &self.trace
+    }
+    /// Vars (and their reference value) of the trace.
+    pub fn trace_vars(&self) -> &[(VarId, Option<PublicValue>)] {
+        &self.trace_vars
+    }
+    fn record_trace(&mut self) {
+        if !self.trace_vars.is_empty() {
+            let summaries = self
+                .trace_vars
+                .iter()
+                .map(|(var, value)| VarSummary {
+                    entropy: self.entropy(*var),
+                    argmax: self.argmax(*var),
+                    probability: value.as_ref().map(|x| self.var_state[*var].probability(x)),
+                })
+                .collect();
+            self.trace.push(summaries);
+        }
+    }
+    pub fn nmulti(&self) -> u32 {
+        self.nmulti
+    }
+    /// Add nmulti executions, with the values of the publics for them (SINGLE publics cannot
+    /// change) and the evidence of some MULTI vars for them (uniform for the other vars).
+    /// The beliefs of the new executions are uniform. This stops the trace.
+    pub fn extend(
+        &mut self,
+        nmulti: u32,
+        public_values: Vec<PublicValue>,
+        evidence: Vec<(VarId, Distribution)>,
+    ) -> Result<(), BPError> {
+        let graph = self.graph.clone();
+        if public_values.len() != graph.publics.len() {
+            return Err(BPError::WrongPublicCount(
+                public_values.len(),
+                graph.publics.len(),
+            ));
+        }
+        for ((name, public), (old, new)) in graph
+            .publics
+            .iter()
+            .zip(self.public_values.iter().zip(public_values.iter()))
+        {
+            match (public.multi, new) {
+                (true, PublicValue::Multi(x)) if x.len() == nmulti as usize => {}
+                (false, PublicValue::Single(_)) if new == old => {}
+                (true, _) => {
+                    return Err(BPError::WrongPublic(
+                        name.clone(),
+                        "expected one value for each new execution",
+                    ))
+                }
+                (false, _) => {
+                    return Err(BPError::WrongPublic(
+                        name.clone(),
+                        "a SINGLE public cannot change",
+                    ))
+                }
+            }
+        }
+        for (var, distr) in evidence.iter() {
+            if !graph.var_multi(*var) {
+                return Err(BPError::WrongDistributionKind("multi", "single"));
+            }
+            Self::check_shape(distr, true, graph.var_nc(*var), nmulti)?;
+        }
+        let evidence: HashMap<VarId, Distribution> = evidence.into_iter().collect();
+        for var in graph.range_vars().filter(|v| graph.var_multi(*v)) {
+            let uniform = Distribution::new_multi(graph.var_nc(var), nmulti);
+            self.evidence[var].append(evidence.get(&var).unwrap_or(&uniform));
+            self.var_state[var].append(&uniform);
+        }
+        for (e, edge) in graph.edges.iter_enumerated() {
+            if graph.factor_multi(edge.factor) {
+                let uniform = Distribution::new_multi(graph.var_nc(edge.var), nmulti);
+                self.belief_from_var[e].append(&uniform);
+                self.belief_to_var[e].append(&uniform);
+            }
+        }
+        for (old, new) in self.public_values.iter_mut().zip(public_values) {
+            if let (PublicValue::Multi(old), PublicValue::Multi(new)) = (old, new) {
+                old.extend(new);
+            }
+        }
+        self.nmulti += nmulti;
+        self.pub_reduced = graph.reduce_pub(self.public_values.as_raw_slice());
+        self.cyclic = graph.is_cyclic(self.nmulti > 1);
+        self.trace_vars.clear();
+        self.trace.clear();
+        Ok(())
+    }
+    /// Update the states of the SINGLE vars with the messages of the executions added (by
+    /// extend) since the previous call, as propagate_acyclic would do with all the
+    /// executions. The products of the messages of the previous executions are cached, hence
+    /// only the new executions are propagated (all of them at the first call, and after a
+    /// change of mode or of the evidence of a MULTI var).
+    /// The graph must be acyclic for any number of executions, and the states of the MULTI
+    /// vars are not updated.
+    pub fn propagate_incremental(&mut self) -> Result<(), BPError> {
+        if self.graph.is_cyclic(true) {
+            return Err(BPError::NotAcyclic);
+        }
+        let graph = self.graph.clone();
+        let start = self.incremental_rows as usize;
+        let public_values = |start: usize| {
+            self.public_values
+                .iter()
+                .map(|p| match p {
+                    PublicValue::Multi(x) => PublicValue::Multi(x[start..].to_vec()),
+                    PublicValue::Single(_) => p.clone(),
+                })
+                .collect()
+        };
+        let single_vars = graph
+            .range_vars()
+            .filter(|v| !graph.var_multi(*v))
+            .collect::<Vec<_>>();
+        // Since the graph is acyclic for any number of executions, the MULTI factors of a
+        // SINGLE var only lead to MULTI vars: their messages only depend on the evidence of
+        // the new executions.
+        let mut new_rows = BPState::new(
+            graph.clone(),
+            self.nmulti - self.incremental_rows,
+            public_values(start),
+        );
+        new_rows.mode = self.mode;
+        for var in graph.range_vars().filter(|v| graph.var_multi(*v)) {
+            new_rows.evidence[var] = self.evidence[var].rows_from(start);
+        }
+        for var in single_vars.iter() {
+            new_rows.propagate_acyclic(*var, false, false)?;
+            for (factor, e) in graph.var(*var).edges.iter() {
+                if graph.factor_multi(*factor) {
+                    let uniform = Distribution::new_single(graph.var_nc(*var));
+                    self.incremental_products
+                        .entry(*e)
+                        .or_insert_with(|| LogDistribution::from_distribution(&uniform))
+                        .multiply(&new_rows.belief_to_var[*e]);
+                }
+            }
+        }
+        // The SINGLE factors depend on all the executions: they are propagated with the
+        // products of the MULTI messages as evidence, over no execution.
+        let mut singles = BPState::new(graph.clone(), 0, public_values(self.nmulti as usize));
+        singles.mode = self.mode;
+        singles.log_domain = self.log_domain;
+        for var in single_vars.iter() {
+            let mut prior = LogDistribution::from_distribution(&self.evidence[*var]);
+            for e in graph.var(*var).edges.values() {
+                if let Some(product) = self.incremental_products.get(e) {
+                    prior.multiply_log(product);
+                }
+            }
+            prior.normalize();
+            singles.evidence[*var] = prior.to_distribution();
+        }
+        for var in single_vars {
+            singles.propagate_acyclic(var, false, false)?;
+            self.var_state[var] = singles.var_state[var].clone();
+            self.var_state_log[var] = singles.var_state_log[var].clone();
+        }
+        self.incremental_rows = self.nmulti;
+        Ok(())
+    }
+    fn reset_incremental(&mut self) {
+        self.incremental_rows = 0;
+        self.incremental_products.clear();
     }
     pub fn get_belief_to_var(&self, edge: EdgeId) -> &Distribution {
         &self.belief_to_var[edge]
@@ -183,12 +576,14 @@ impl BPState {
         edge: EdgeId,
         belief: Distribution,
     ) -> Result<(), BPError> {
-        self.check_distribution(&belief, self.graph.edge_multi(edge))?;
+        let nc = self.graph.var_nc(self.graph.edges[edge].var);
+        self.check_distribution(&belief, self.graph.edge_multi(edge), nc)?;
         self.belief_from_var[edge] = belief;
         Ok(())
     }
     pub fn set_belief_to_var(&mut self, edge: EdgeId, belief: Distribution) -> Result<(), BPError> {
-        self.check_distribution(&belief, self.graph.edge_multi(edge))?;
+        let nc = self.graph.var_nc(self.graph.edges[edge].var);
+        self.check_distribution(&belief, self.graph.edge_multi(edge), nc)?;
         self.belief_to_var[edge] = belief;
         Ok(())
     }
@@ -260,6 +655,36 @@ impl BPState {
         }
         self.var_state[var_id] = var_state;
     }
+    /// Same as propagate_var_t_single, with the products computed in the log domain.
+    fn propagate_var_t_single_log(
+        &mut self,
+        var_id: VarId,
+        to_edges: Vec<EdgeId>,
+        other_edges: Vec<EdgeId>,
+        clear_evidence: bool,
+        clear_beliefs: bool,
+    ) {
+        let evidence = self.evidence[var_id].take_or_clone(clear_evidence);
+        let mut base = LogDistribution::from_distribution(&evidence);
+        for e in other_edges {
+            base.multiply(&self.belief_to_var[e]);
+            if clear_beliefs {
+                self.belief_to_var[e].reset();
+            }
+        }
+        let beliefs: Vec<_> = to_edges.iter().map(|e| &self.belief_to_var[*e]).collect();
+        let (mut var_state, new_beliefs) =
+            super::bp_compute::log_belief_reciprocal_product(base, beliefs.as_slice());
+        for (e, d) in to_edges.iter().zip(new_beliefs) {
+            self.belief_from_var[*e] = d;
+            if clear_beliefs {
+                self.belief_to_var[*e].reset();
+            }
+        }
+        var_state.normalize();
+        self.var_state[var_id] = var_state.to_distribution();
+        self.var_state_log[var_id] = Some(var_state);
+    }
    //因子向父变量(一个或多个)传播信息
     pub fn propagate_factor(&mut self, factor_id: FactorId, dest: &[VarId], clear_incoming: bool) {
         let factor = self.graph.factor(factor_id);
@@ -267,22 +692,44 @@ impl BPState {
         for d in dest {
             self.belief_to_var[factor.edges[d]].reset();//针对目标变量集合 dest，重置与每个目标变量关联的信念，以准备进行下一轮的信念传播
         }
+        let mut belief_from_var = std::mem::take(&mut self.belief_from_var);
+        let messages = self.factor_messages(factor_id, dest, &mut belief_from_var, clear_incoming);
+        self.belief_from_var = belief_from_var;
+        for (distr, dest) in messages.into_iter().zip(dest.iter()) {
+            self.belief_to_var[factor.edges[dest]] = distr;
+        }
+    }
+    /// Messages from a factor to the dest vars, computed from belief_from_var (instead of
+    /// self.belief_from_var, such that factors can be processed concurrently).
+    fn factor_messages(
+        &self,
+        factor_id: FactorId,
+        dest: &[VarId],
+        belief_from_var: &mut EdgeSlice<Distribution>,
+        clear_incoming: bool,
+    ) -> Vec<Distribution> {
+        let factor = self.graph.factor(factor_id);
         // Use a macro to call very similar functions in match arms.
         // Needed because of anonymous return types of these functions.
         macro_rules! prop_factor {
             //macro_rules! 是Rust中用于定义宏的关键字
             //这是宏 prop_factor 的调用，其中 $f 是传递给宏的函数名参数
             ($f:ident, $($arg:expr),*) => {
-                {
-                    let it = $f(factor, &mut self.belief_from_var, dest, clear_incoming, $($arg,)*);
-                    for (mut distr, dest) in it.zip(dest.iter()) {
-                        //同时迭代（遍历）it dest的元素
+                $f(factor, belief_from_var, dest, clear_incoming, $($arg,)*)
+                    .map(|mut distr| {
                         distr.regularize();
-                        self.belief_to_var[factor.edges[dest]] = distr;
-                    }
-                }
+                        distr
+                    })
+                    .collect()
             };
         }
+        if self.mode == BPMode::MaxProduct {
+            return prop_factor!(
+                factor_max_product,
+                &self.pub_reduced[factor_id],
+                &self.graph
+            );
+        }
         //match 表达式根据 factor.kind 的不同值选择不同的分支来执行不同类型的因子传播操作。每个分支使用 prop_factor 宏来调用相应的传播函数，并传递了相应的参数。
         match &factor.kind {
             FactorKind::AND { .. } => {
@@ -290,8 +737,21 @@ impl BPState {
             }
             FactorKind::XOR => prop_factor!(factor_xor, &self.pub_reduced[factor_id]),
             FactorKind::NOT => prop_factor!(factor_not, (self.graph.nc - 1) as u32),
-            FactorKind::ADD => prop_factor!(factor_add, &self.pub_reduced[factor_id], &self.plans),
+            FactorKind::ADD { coefs } => {
+                prop_factor!(factor_add, coefs, &self.pub_reduced[factor_id], &self.plans)
+            }
             FactorKind::MUL => prop_factor!(factor_mul, &self.pub_reduced[factor_id]),
+            FactorKind::GFMUL => prop_factor!(
+                factor_gfmul,
+                &self.pub_reduced[factor_id],
+                self.graph.field.as_ref().unwrap(),
+                self.gf_plans.as_ref().unwrap()
+            ),
+            FactorKind::LOOKUP { table } if self.graph.tables[*table].ninputs == 2 => {
+                prop_factor!(factor_lookup2, &self.graph.tables[*table])
+            }
+            FactorKind::BITS => prop_factor!(factor_bits,),
+            FactorKind::HW => prop_factor!(factor_hw, &self.pub_reduced[factor_id]),
             FactorKind::LOOKUP { table } => {
                 prop_factor!(factor_lookup, &self.graph.tables[*table])
                 //使用宏 prop_factor! 来调用不同的传播函数
@@ -304,6 +764,47 @@ impl BPState {
         let dest: Vec<_> = self.graph.factor(factor).edges.keys().cloned().collect();
         self.propagate_factor(factor, dest.as_slice(), false);
     }
+    /// Same as propagate_factor_all for every factor, with the factors processed in parallel.
+    pub fn propagate_all_factors(&mut self) {
+        let graph = self.graph.clone();
+        // Pre-erase to have buffers available in cache allocator.
+        for belief in self.belief_to_var.iter_mut() {
+            belief.reset();
+        }
+        // Move the incoming beliefs of each factor out of belief_from_var, which becomes uniform.
+        let mut belief_from_var = std::mem::take(&mut self.belief_from_var);
+        let incoming: Vec<(FactorId, Vec<Distribution>)> = graph
+            .range_factors()
+            .map(|f| {
+                let edges = graph.factor(f).edges.values();
+                (f, edges.map(|e| belief_from_var[*e].reset()).collect())
+            })
+            .collect();
+        let results: Vec<(Vec<Distribution>, Vec<Distribution>)> = incoming
+            .into_par_iter()
+            .map_init(
+                || belief_from_var.clone(),
+                |local, (factor_id, incoming)| {
+                    let factor = graph.factor(factor_id);
+                    for (e, d) in factor.edges.values().zip(incoming) {
+                        local[*e] = d;
+                    }
+                    let dest: Vec<_> = factor.edges.keys().cloned().collect();
+                    let messages = self.factor_messages(factor_id, &dest, local, false);
+                    let incoming = factor.edges.values().map(|e| local[*e].reset()).collect();
+                    (messages, incoming)
+                },
+            )
+            .collect();
+        for (factor_id, (messages, incoming)) in graph.range_factors().zip(results) {
+            let edges = graph.factor(factor_id).edges.values();
+            for ((e, m), d) in edges.zip(messages).zip(incoming) {
+                self.belief_to_var[*e] = m;
+                belief_from_var[*e] = d;
+            }
+        }
+        self.belief_from_var = belief_from_var;
+    }
     pub fn propagate_var(&mut self, var_id: VarId, clear_beliefs: bool) {
         let clear_evidence = false;
         self.propagate_var_to(
@@ -350,6 +851,14 @@ impl BPState {
                 clear_evidence,
                 clear_beliefs,
             );
+        } else if self.log_domain {
+            self.propagate_var_t_single_log(
+                var_id,
+                to_edges,
+                other_edges,
+                clear_evidence,
+                clear_beliefs,
+            );
         } else {
             self.propagate_var_t_single(
                 var_id,
@@ -366,12 +875,78 @@ impl BPState {
         }
     }
     pub fn propagate_loopy_step(&mut self, n_steps: u32, clear_beliefs: bool) {
-        for _ in 0..n_steps {
-            for factor_id in self.graph.range_factors() {
-                self.propagate_factor_all(factor_id);
+        self.propagate_loopy(n_steps, clear_beliefs, &LoopyConfig::default())
+            .expect("Default config is valid.");
+    }
+    /// Run at most n_steps iterations of loopy BP, and return the number of iterations done.
+    /// With the Sequential and SortedSweep schedules, beliefs are only cleared at the end.
+    pub fn propagate_loopy(
+        &mut self,
+        n_steps: u32,
+        clear_beliefs: bool,
+        config: &LoopyConfig,
+    ) -> Result<u32, BPError> {
+        if !(0.0..1.0).contains(&config.damping) {
+            return Err(BPError::InvalidDamping(config.damping));
+        }
+        let flooding = config.schedule == BPSchedule::Flooding;
+        let graph = self.graph.clone();
+        // Messages of the previous iteration, only kept when needed.
+        let mut prev = (config.damping != 0.0
+            || config.tolerance.is_some()
+            || config.schedule == BPSchedule::SortedSweep)
+            .then(|| self.belief_to_var.clone());
+        // Change of the messages of each factor at the previous iteration.
+        let mut changes = FactorVec::from_vec(vec![f64::INFINITY; graph.factors.len()]);
+        let mut order: Vec<FactorId> = graph.range_factors().collect();
+        for step in 0..n_steps {
+            if config.schedule == BPSchedule::SortedSweep {
+                order.sort_by(|a, b| changes[*b].total_cmp(&changes[*a]));
+            }
+            let mut max_change: f64 = 0.0;
+            if flooding {
+                self.propagate_all_factors();
+            }
+            for factor_id in order.iter() {
+                if !flooding {
+                    self.propagate_factor_all(*factor_id);
+                }
+                let factor = graph.factor(*factor_id);
+                if let Some(prev) = prev.as_mut() {
+                    let mut change: f64 = 0.0;
+                    for e in factor.edges.values() {
+                        if config.damping != 0.0 {
+                            self.belief_to_var[*e].damp(&prev[*e], config.damping);
+                        }
+                        change = change.max(self.belief_to_var[*e].max_diff(&prev[*e]));
+                        prev[*e] = self.belief_to_var[*e].clone();
+                    }
+                    changes[*factor_id] = change;
+                    max_change = max_change.max(change);
+                }
+                if !flooding {
+                    for var_id in factor.edges.keys() {
+                        self.propagate_var(*var_id, false);
+                    }
+                }
+            }
+            if flooding {
+                self.propagate_all_vars(clear_beliefs);
+            }
+            self.record_trace();
+            if matches!(config.tolerance, Some(tol) if max_change <= tol) {
+                return Ok(self.end_loopy(step + 1, clear_beliefs && !flooding));
             }
-            self.propagate_all_vars(clear_beliefs);
         }
+        Ok(self.end_loopy(n_steps, clear_beliefs && !flooding))
+    }
+    fn end_loopy(&mut self, n_steps: u32, clear_beliefs: bool) -> u32 {
+        if clear_beliefs {
+            for belief in self.belief_to_var.iter_mut() {
+                belief.reset();
+            }
+        }
+        n_steps
     }
     pub fn propagate_acyclic(//无环传播算法，输入目标变量
         &mut self,
@@ -401,6 +976,35 @@ impl BPState {
         }
         Ok(())
     }
+    /// Cost of exact inference with propagate_exact.
+    pub fn exact_cost(&self) -> ExactCost {
+        JunctionTree::new(&self.graph, self.nmulti as usize).cost(&self.graph)
+    }
+    /// Compute the exact distributions of all the variables (or their max-marginals in
+    /// max-product mode) given the evidence, also for cyclic graphs, using a junction tree.
+    /// Fails without computing anything if a clique table has more than max_clique_size
+    /// entries.
+    pub fn propagate_exact(&mut self, max_clique_size: usize) -> Result<ExactCost, BPError> {
+        let tree = JunctionTree::new(&self.graph, self.nmulti as usize);
+        let cost = tree.cost(&self.graph);
+        if cost.max_clique_size > max_clique_size {
+            return Err(BPError::ExactTooLarge(
+                cost.max_clique_size,
+                max_clique_size,
+            ));
+        }
+        let states = tree.marginals(
+            &self.graph,
+            &self.evidence,
+            &self.pub_reduced,
+            self.mode == BPMode::MaxProduct,
+        );
+        for (var, state) in states.into_iter_enumerated() {
+            self.var_state[var] = state;
+            self.var_state_log[var] = None;
+        }
+        Ok(cost)
+    }
 }
 
 fn factor_gen_and<'a>(
@@ -627,15 +1231,45 @@ fn factor_not<'a>(
     )
 }
 
-// TODO handle subtraction too (actually, we can re-write it as an addition by moving terms around).
+/// Table of x -> coef*x mod nc.
+fn mul_table(coef: usize, nc: usize) -> Vec<ClassVal> {
+    (0..nc).map(|x| ((x * coef) % nc) as ClassVal).collect()
+}
+
+/// Message to a variable of an ADD factor whose coefficient is (-1)^negated * scale, where acc
+/// is the distribution of -(-1)^negated times the sum of the other (non-public) terms.
+fn add_msg(
+    mut acc: Distribution,
+    pub_red: &PublicValue,
+    negated: bool,
+    scale: usize,
+) -> Distribution {
+    acc.add_cst(pub_red, !negated);
+    if scale != 1 {
+        acc = acc.map_table_inv(&mul_table(scale, acc.shape().1));
+    }
+    acc.regularize();
+    acc
+}
+
 fn factor_add<'a>(
     factor: &'a Factor,
     belief_from_var: &'a mut EdgeSlice<Distribution>,
     dest: &'a [VarId],
     clear_incoming: bool,
+    coefs: &[ClassVal],
     pub_red: &PublicValue,
     plans: &FftPlans,
 ) -> impl Iterator<Item = Distribution> + 'a {
+    let nc = plans.size;
+    // Each coefficient is (-1)^negated_vars[i] * scales[i], where the negation is handled in
+    // the FFT domain and the scaling (if not 1) by mapping the distributions.
+    let negated_vars: Vec<bool> = coefs.iter().map(|c| (*c as usize) > nc / 2).collect();
+    let scales: Vec<usize> = coefs
+        .iter()
+        .zip(negated_vars.iter())
+        .map(|(c, neg)| if *neg { nc - *c as usize } else { *c as usize })
+        .collect();
     // Special case for single-input ADD
     if factor.edges.len() == 2 {
         return dest
@@ -643,15 +1277,29 @@ fn factor_add<'a>(
             .map(|var| {
                 let i = factor.edges.get_index_of(var).unwrap();
                 let mut distr = belief_from_var[factor.edges[1 - i]].take_or_clone(clear_incoming);
-                distr.add_cst(pub_red, i != 0);
+                if scales[1 - i] != 1 {
+                    distr = distr.map_table(&mul_table(scales[1 - i], nc));
+                }
+                if negated_vars[1 - i] == negated_vars[i] {
+                    distr.negate();
+                }
+                distr.add_cst(pub_red, !negated_vars[i]);
+                if scales[i] != 1 {
+                    distr = distr.map_table_inv(&mul_table(scales[i], nc));
+                }
                 distr
             })
             .collect::<Vec<_>>()
             .into_iter();
     }
+    // Distributions of scales[i]*x_i, when scales[i] is not 1.
+    let scaled: Vec<Option<Distribution>> = factor
+        .edges
+        .values()
+        .zip(scales.iter())
+        .map(|(e, s)| (*s != 1).then(|| belief_from_var[*e].map_table(&mul_table(*s, nc))))
+        .collect();
     let mut taken_dest = vec![false; factor.edges.len()];
-    let mut negated_vars = vec![false; factor.edges.len()];
-    negated_vars[0] = true;
     for dest in dest {
         taken_dest[factor.edges.get_index_of(dest).unwrap()] = true;
     }
@@ -660,13 +1308,14 @@ fn factor_add<'a>(
         .iter()
         .zip(taken_dest.iter())
         .zip(negated_vars.iter())
-        .filter(|(((_, e), _), _)| !belief_from_var[**e].is_full());
+        .zip(scaled.iter())
+        .filter(|((((_, e), _), _), s)| !s.as_ref().unwrap_or(&belief_from_var[**e]).is_full())
+        .map(|(x, _)| x);
     let uniform_op = uniform_iter.next();
     let uniform_template = belief_from_var[factor.edges[0]].as_uniform();
-    let (nmulti, nc) = uniform_template.shape();
+    let nmulti = uniform_template.shape().0;
     let mut fft_tmp = ndarray::Array2::zeros((nmulti, nc / 2 + 1));
-    let mut acc_fft = ndarray::Array2::zeros((nmulti, nc / 2 + 1));
-    let mut acc_fft_init = false;
+    let mut acc_fft = ndarray::Array2::ones((nmulti, nc / 2 + 1));
     let mut fft_scratch = plans.r2c.make_scratch_vec();
     let mut fft_input_scratch = plans.r2c.make_input_vec();
     if let Some((((v_dest, e_dest), t), dest_negated)) = uniform_op {
@@ -675,51 +1324,57 @@ fn factor_add<'a>(
             // all dest messages are uniform.
             reset_incoming(factor, belief_from_var, &taken_dest, clear_incoming);
             return vec![uniform_template; dest.len()].into_iter();
-        } else {
-            // Single uniform op, only compute for that one.
-            for (e, negated_var) in factor.edges.values().zip(negated_vars.iter()) {
-                if e != e_dest {
-                    let negate = !(dest_negated ^ negated_var);
-                    belief_from_var[*e].fft_to(
-                        fft_input_scratch.as_mut_slice(),
-                        fft_tmp.view_mut(),
-                        fft_scratch.as_mut_slice(),
-                        plans,
-                        negate,
-                    );
-                    if acc_fft_init {
-                        acc_fft *= &fft_tmp;
-                    } else {
-                        acc_fft.assign(&fft_tmp);
-                        acc_fft_init = true;
-                    }
-                }
-                if clear_incoming {
-                    belief_from_var[*e].reset();
-                }
+        }
+        let dest_negated = *dest_negated;
+        let idx = factor.edges.get_index_of(v_dest).unwrap();
+        // Single uniform op, only compute for that one.
+        for ((e, negated_var), s) in factor
+            .edges
+            .values()
+            .zip(negated_vars.iter())
+            .zip(scaled.iter())
+        {
+            if e != e_dest {
+                let negate = !(dest_negated ^ negated_var);
+                s.as_ref().unwrap_or(&belief_from_var[*e]).fft_to(
+                    fft_input_scratch.as_mut_slice(),
+                    fft_tmp.view_mut(),
+                    fft_scratch.as_mut_slice(),
+                    plans,
+                    negate,
+                );
+                acc_fft *= &fft_tmp;
             }
         }
-        let mut acc = uniform_template.clone();
+        let mut res = vec![uniform_template.clone(); dest.len()];
+        if clear_incoming {
+            for e in factor.edges.values() {
+                belief_from_var[*e].reset();
+            }
+        }
+        let mut acc = uniform_template;
         let mut fft_scratch = plans.c2r.make_scratch_vec();
         acc.ifft(acc_fft.view_mut(), fft_scratch.as_mut_slice(), plans, false);
-        acc.regularize();
-        let mut res = vec![uniform_template; dest.len()];
-        res[dest.iter().position(|v| v == v_dest).unwrap()] = acc;
-        return res.into_iter();
+        res[dest.iter().position(|v| v == v_dest).unwrap()] =
+            add_msg(acc, pub_red, negated_vars[idx], scales[idx]);
+        res.into_iter()
     } else {
         // Here we have to actually compute.
         // Simply make the product if FFT domain
         // We do take the product of all factors then divide because some factors could be zero.
         let mut dest_fft = Vec::with_capacity(dest.len());
-        for ((e, taken), negated_var) in factor
+        for ((((j, e), taken), negated_var), s) in factor
             .edges
             .values()
+            .enumerate()
             .zip(taken_dest.iter())
             .zip(negated_vars.iter())
+            .zip(scaled.iter())
         {
+            let distr = s.as_ref().unwrap_or(&belief_from_var[*e]);
             if *taken {
                 let mut fft_e = ndarray::Array2::zeros((nmulti, nc / 2 + 1));
-                belief_from_var[*e].fft_to(
+                distr.fft_to(
                     fft_input_scratch.as_mut_slice(),
                     fft_e.view_mut(),
                     fft_scratch.as_mut_slice(),
@@ -727,24 +1382,20 @@ fn factor_add<'a>(
                     *negated_var,
                 );
 
-                dest_fft.push(fft_e);
+                dest_fft.push((j, fft_e));
             } else {
-                belief_from_var[*e].fft_to(
+                distr.fft_to(
                     fft_input_scratch.as_mut_slice(),
                     fft_tmp.view_mut(),
                     fft_scratch.as_mut_slice(),
                     plans,
                     *negated_var,
                 );
-
-                if acc_fft_init {
-                    acc_fft *= &fft_tmp;
-                } else {
-                    acc_fft.assign(&fft_tmp);
-                    acc_fft_init = true;
-                }
+                acc_fft *= &fft_tmp;
             }
-            if clear_incoming {
+        }
+        if clear_incoming {
+            for e in factor.edges.values() {
                 belief_from_var[*e].reset();
             }
         }
@@ -752,21 +1403,15 @@ fn factor_add<'a>(
         // This could be done in O(l) instead of O(l^2) where l=dest.len() by
         // better caching product computations.
         let mut fft_scratch = plans.c2r.make_scratch_vec();
-        return (0..dest.len())
-            .map(move |i| {
-                let mut res = if acc_fft_init {
-                    acc_fft.clone()
-                } else {
-                    ndarray::Array2::ones(acc_fft.raw_dim())
-                };
-
-                for (j, fft_op) in dest_fft.iter().enumerate() {
-                    if j != i {
+        dest.iter()
+            .map(move |var| {
+                let idx = factor.edges.get_index_of(var).unwrap();
+                let mut res = acc_fft.clone();
+                for (j, fft_op) in dest_fft.iter() {
+                    if *j != idx {
                         res *= fft_op;
                     }
                 }
-                let idx = factor.edges.get_index_of(&dest[i]).unwrap();
-
                 let mut acc = uniform_template.clone();
                 acc.ifft(
                     res.view_mut(),
@@ -774,14 +1419,12 @@ fn factor_add<'a>(
                     plans,
                     !negated_vars[idx],
                 );
-                acc.regularize();
-                acc
+                add_msg(acc, pub_red, negated_vars[idx], scales[idx])
             })
             .collect::<Vec<_>>()
-            .into_iter();
+            .into_iter()
     }
 }
-
 fn factor_mul<'a>(
     factor: &'a Factor,
     belief_from_var: &'a mut EdgeSlice<Distribution>,
@@ -825,6 +1468,94 @@ fn factor_mul<'a>(
     })
 }
 
+fn factor_gfmul<'a>(
+    factor: &'a Factor,
+    belief_from_var: &'a mut EdgeSlice<Distribution>,
+    dest: &'a [VarId],
+    clear_incoming: bool,
+    pub_red: &PublicValue,
+    field: &Field,
+    plans: &FftPlans,
+) -> impl Iterator<Item = Distribution> + 'a {
+    // Multiplication of non-zero values is an addition of their logarithms modulo nc-1, which we
+    // compute as a convolution in the FFT domain. Zero is absorbing, its probability is computed
+    // separately.
+    // The result of the multiplication is always a variable (checked when building the graph).
+    let uniform_template = belief_from_var[factor.edges[0]].as_uniform();
+    let (nmulti, nc) = uniform_template.shape();
+    let mut fft_scratch = plans.r2c.make_scratch_vec();
+    let mut fft_input_scratch = plans.r2c.make_input_vec();
+    let mut to_log = |distr: &Distribution| {
+        let mut fft = ndarray::Array2::zeros((nmulti, (nc - 1) / 2 + 1));
+        let mut zero = ndarray::Array1::zeros(nmulti);
+        distr.gf_log_fft_to(
+            field,
+            fft_input_scratch.as_mut_slice(),
+            fft.view_mut(),
+            zero.view_mut(),
+            fft_scratch.as_mut_slice(),
+            plans,
+        );
+        // Keep the probability of non-zero instead of zero, as it is the one that gets
+        // multiplied.
+        (fft, zero.mapv_into(|z| 1.0 - z))
+    };
+    let cst_log = to_log(&uniform_template.new_constant(pub_red));
+    let logs = factor
+        .edges
+        .values()
+        .map(|e| to_log(&belief_from_var[*e].take_or_clone(clear_incoming)))
+        .collect::<Vec<_>>();
+    // This could be done in O(l) instead of O(l^2) where l=dest.len() by
+    // better caching product computations.
+    let mut fft_scratch = plans.c2r.make_scratch_vec();
+    dest.iter()
+        .map(|var| {
+            let i = factor.edges.get_index_of(var).unwrap();
+            let (mut fft, mut non_zero) = cst_log.clone();
+            for (_, (fft_op, non_zero_op)) in
+                logs.iter().enumerate().skip(1).filter(|(j, _)| *j != i)
+            {
+                fft *= fft_op;
+                non_zero *= non_zero_op;
+            }
+            let mut res = uniform_template.clone();
+            if i == 0 {
+                // dest is the result
+                let zero = non_zero.mapv(|nz| 1.0 - nz);
+                let offset = ndarray::Array1::zeros(nmulti);
+                res.gf_log_ifft(
+                    field,
+                    fft.view_mut(),
+                    zero.view(),
+                    offset.view(),
+                    fft_scratch.as_mut_slice(),
+                    plans,
+                );
+            } else {
+                // dest is an operand: correlation of the result with the product of the other
+                // operands, and when one of these is zero, the result is zero whatever dest is.
+                let (fft_res, non_zero_res) = &logs[0];
+                fft.mapv_inplace(|x| x.conj());
+                fft *= fft_res;
+                let zero_res = non_zero_res.mapv(|nz| 1.0 - nz);
+                let offset = &zero_res * &non_zero.mapv(|nz| 1.0 - nz);
+                res.gf_log_ifft(
+                    field,
+                    fft.view_mut(),
+                    zero_res.view(),
+                    offset.view(),
+                    fft_scratch.as_mut_slice(),
+                    plans,
+                );
+            }
+            res.regularize();
+            res
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
 fn factor_lookup<'a>(
     factor: &'a Factor,
     belief_from_var: &'a mut EdgeSlice<Distribution>,
@@ -849,3 +1580,103 @@ fn factor_lookup<'a>(
         res
     })
 }
+
+fn factor_lookup2<'a>(
+    factor: &'a Factor,
+    belief_from_var: &'a mut EdgeSlice<Distribution>,
+    dest: &'a [VarId],
+    clear_incoming: bool,
+    table: &'a Table,
+) -> impl Iterator<Item = Distribution> + 'a {
+    // we know that there is no constant involved
+    assert_eq!(factor.edges.len(), 3);
+    let mut dest_iter = dest.iter();
+    std::iter::from_fn(move || {
+        if let Some(var) = dest_iter.next() {
+            let i = factor.edges.get_index_of(var).unwrap();
+            let values = table.values.as_slice();
+            let d = |j: usize| &belief_from_var[factor.edges[j]];
+            Some(match i {
+                0 => d(1).map_table2(d(2), values),
+                1 => d(0).map_table2_inv(d(2), values, false),
+                _ => d(0).map_table2_inv(d(1), values, true),
+            })
+        } else {
+            if clear_incoming {
+                for e in factor.edges.values() {
+                    belief_from_var[*e].reset();
+                }
+            }
+            None
+        }
+    })
+}
+
+fn factor_bits<'a>(
+    factor: &'a Factor,
+    belief_from_var: &'a mut EdgeSlice<Distribution>,
+    dest: &'a [VarId],
+    clear_incoming: bool,
+) -> impl Iterator<Item = Distribution> + 'a {
+    let mut dest_iter = dest.iter();
+    std::iter::from_fn(move || {
+        if let Some(var) = dest_iter.next() {
+            let i = factor.edges.get_index_of(var).unwrap();
+            let distrs: Vec<_> = factor
+                .edges
+                .values()
+                .map(|e| &belief_from_var[*e])
+                .collect();
+            Some(Distribution::bits_msg(distrs.as_slice(), i, false))
+        } else {
+            if clear_incoming {
+                for e in factor.edges.values() {
+                    belief_from_var[*e].reset();
+                }
+            }
+            None
+        }
+    })
+}
+
+fn factor_hw<'a>(
+    factor: &'a Factor,
+    belief_from_var: &'a mut EdgeSlice<Distribution>,
+    dest: &'a [VarId],
+    clear_incoming: bool,
+    pub_red: &'a PublicValue,
+) -> impl Iterator<Item = Distribution> + 'a {
+    // The result is HW(x ^ pub_red) or HW(x ^ y ^ pub_red).
+    let mut dest_iter = dest.iter();
+    std::iter::from_fn(move || {
+        if let Some(var) = dest_iter.next() {
+            let i = factor.edges.get_index_of(var).unwrap();
+            let d = |j: usize| &belief_from_var[factor.edges[j]];
+            let two_ops = factor.edges.len() == 3;
+            Some(if i == 0 {
+                let mut x = if two_ops {
+                    d(1).xor(d(2))
+                } else {
+                    d(1).clone()
+                };
+                x.xor_cst(pub_red);
+                x.map_hw(d(0).shape().1, false)
+            } else {
+                let mut x = d(0).map_hw_inv(d(i).shape().1);
+                x.xor_cst(pub_red);
+                if two_ops {
+                    x.xor(d(3 - i))
+                } else {
+                    x
+                }
+            })
+        } else {
+            if clear_incoming {
+                for e in factor.edges.values() {
+                    belief_from_var[*e].reset();
+                }
+            }
+            None
+        }
+    })
+}
@@ -1,16 +1,21 @@
 use std::ops::MulAssign;
 
 use super::belief_propagation::{BPError, FftPlans};
-use super::factor_graph::PublicValue;
+use super::factor_graph::{Field, PublicValue};
 use super::ClassVal;
-use ndarray::{azip, s, ArrayViewMut2};
+use ndarray::{azip, s, ArrayView1, ArrayViewMut1, ArrayViewMut2};
+use rayon::prelude::*;
 use realfft::num_complex::Complex;
 
-type Proba = f64;
+pub(super) type Proba = f64;
 
 /// The minimum non-zero probability (to avoid denormalization, etc.)
 const MIN_PROBA: Proba = 1e-40;
 
+/// Minimum number of probabilities in a distribution for row-wise operations to be run in
+/// parallel over the executions.
+const PAR_MIN_SIZE: usize = 1 << 14;
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 enum DistrRepr {
     Uniform,
@@ -256,6 +261,37 @@ impl Distribution {
         }
     }
 
+    /// Executions from start on (for MULTI distributions).
+    pub fn rows_from(&self, start: usize) -> Self {
+        assert!(self.multi && start <= self.shape.0);
+        Self {
+            multi: true,
+            shape: (self.shape.0 - start, self.shape.1),
+            value: match &self.value {
+                DistrRepr::Uniform => DistrRepr::Uniform,
+                DistrRepr::Full(v) => DistrRepr::Full(v.slice(s![start.., ..]).to_owned()),
+            },
+        }
+    }
+    /// Append the executions of other (both must be MULTI, with the same number of classes).
+    pub fn append(&mut self, other: &Distribution) {
+        assert!(self.multi && other.multi);
+        assert_eq!(self.shape.1, other.shape.1);
+        let shape = (self.shape.0 + other.shape.0, self.shape.1);
+        if self.is_full() || other.is_full() {
+            let mut other = other.clone();
+            self.ensure_full();
+            other.ensure_full();
+            let (DistrRepr::Full(v), DistrRepr::Full(w)) = (&self.value, &other.value) else {
+                unreachable!()
+            };
+            self.value = DistrRepr::Full(
+                ndarray::concatenate(ndarray::Axis(0), &[v.view(), w.view()])
+                    .expect("Same number of classes."),
+            );
+        }
+        self.shape = shape;
+    }
     pub fn multiply_to_single(&mut self, other: &Distribution) {
         assert!(!self.multi);
         if let DistrRepr::Full(sv) = &other.value {
@@ -277,6 +313,12 @@ impl Distribution {
             xor_cst_slice(d.as_slice_mut().unwrap(), inv_cst);
         });
     }
+    /// Distribution of -x mod nc.
+    pub fn negate(&mut self) {
+        self.for_each_ignore(|mut d, _| {
+            negate_slice_distr(d.as_slice_mut().unwrap());
+        });
+    }
     pub fn wht(&mut self) {
         self.for_each_error(|mut d, _| {
             slice_wht(d.as_slice_mut().unwrap());
@@ -305,16 +347,34 @@ impl Distribution {
         plans: &FftPlans,
         negated: bool,
     ) {
+        let fft_row = |input_scratch: &mut [f64],
+                       fft_scratch: &mut [Complex<f64>],
+                       distr: ArrayView1<f64>,
+                       mut dest: ArrayViewMut1<Complex<f64>>| {
+            input_scratch.copy_from_slice(distr.as_slice().unwrap());
+            if negated {
+                negate_slice_distr(input_scratch);
+            }
+            plans
+                .r2c
+                .process_with_scratch(input_scratch, dest.as_slice_mut().unwrap(), fft_scratch)
+                .unwrap();
+        };
         if let DistrRepr::Full(v) = &self.value {
-            for (distr, mut dest) in v.outer_iter().zip(dest.outer_iter_mut()) {
-                input_scratch.copy_from_slice(distr.as_slice().unwrap());
-                if negated {
-                    negate_slice_distr(input_scratch);
+            if v.len() >= PAR_MIN_SIZE {
+                v.axis_iter(ndarray::Axis(0))
+                    .into_par_iter()
+                    .zip(dest.axis_iter_mut(ndarray::Axis(0)).into_par_iter())
+                    .for_each_init(
+                        || (plans.r2c.make_input_vec(), plans.r2c.make_scratch_vec()),
+                        |(input_scratch, fft_scratch), (distr, dest)| {
+                            fft_row(input_scratch, fft_scratch, distr, dest)
+                        },
+                    );
+            } else {
+                for (distr, dest) in v.outer_iter().zip(dest.outer_iter_mut()) {
+                    fft_row(input_scratch, fft_scratch, distr, dest);
                 }
-                plans
-                    .r2c
-                    .process_with_scratch(input_scratch, dest.as_slice_mut().unwrap(), fft_scratch)
-                    .unwrap();
             }
         }
     }
@@ -325,9 +385,9 @@ impl Distribution {
         plans: &FftPlans,
         negated: bool,
     ) {
-        self.ensure_full();
-        let mut v = self.value_mut().unwrap();
-        for (mut dest, mut input) in v.outer_iter_mut().zip(input.outer_iter_mut()) {
+        let ifft_row = |fft_scratch: &mut [Complex<f64>],
+                        mut dest: ArrayViewMut1<f64>,
+                        mut input: ArrayViewMut1<Complex<f64>>| {
             plans
                 .c2r
                 .process_with_scratch(
@@ -339,6 +399,133 @@ impl Distribution {
             if negated {
                 negate_slice_distr(dest.as_slice_mut().unwrap());
             }
+        };
+        self.ensure_full();
+        let mut v = self.value_mut().unwrap();
+        if v.len() >= PAR_MIN_SIZE {
+            v.axis_iter_mut(ndarray::Axis(0))
+                .into_par_iter()
+                .zip(input.axis_iter_mut(ndarray::Axis(0)).into_par_iter())
+                .for_each_init(
+                    || plans.c2r.make_scratch_vec(),
+                    |fft_scratch, (dest, input)| ifft_row(fft_scratch, dest, input),
+                );
+        } else {
+            for (dest, input) in v.outer_iter_mut().zip(input.outer_iter_mut()) {
+                ifft_row(fft_scratch, dest, input);
+            }
+        }
+    }
+    /// FFT of the distribution of the logarithm of the (non-zero) values, and probability of
+    /// zero, for the normalized distribution.
+    pub(super) fn gf_log_fft_to(
+        &self,
+        field: &Field,
+        input_scratch: &mut [f64],
+        mut dest: ArrayViewMut2<Complex<f64>>,
+        mut zero: ArrayViewMut1<f64>,
+        fft_scratch: &mut [Complex<f64>],
+        plans: &FftPlans,
+    ) {
+        let nc = self.shape.1;
+        let log_fft_row = |input_scratch: &mut [f64],
+                           fft_scratch: &mut [Complex<f64>],
+                           i: usize,
+                           mut dest: ArrayViewMut1<Complex<f64>>,
+                           zero: &mut f64| {
+            if let DistrRepr::Full(v) = &self.value {
+                let distr = v.row(i);
+                // Clamped such that a row of zeros does not give NaNs.
+                let norm = 1.0 / distr.sum().max(Proba::MIN_POSITIVE);
+                *zero = distr[0] * norm;
+                for (x, e) in input_scratch.iter_mut().zip(field.exp.iter()) {
+                    *x = distr[*e as usize] * norm;
+                }
+            } else {
+                *zero = 1.0 / (nc as f64);
+                input_scratch.fill(1.0 / (nc as f64));
+            }
+            plans
+                .r2c
+                .process_with_scratch(input_scratch, dest.as_slice_mut().unwrap(), fft_scratch)
+                .unwrap();
+        };
+        let zero = zero.as_slice_mut().unwrap();
+        if self.shape.0 * nc >= PAR_MIN_SIZE {
+            dest.axis_iter_mut(ndarray::Axis(0))
+                .into_par_iter()
+                .zip(zero.par_iter_mut())
+                .enumerate()
+                .for_each_init(
+                    || (plans.r2c.make_input_vec(), plans.r2c.make_scratch_vec()),
+                    |(input_scratch, fft_scratch), (i, (dest, zero))| {
+                        log_fft_row(input_scratch, fft_scratch, i, dest, zero)
+                    },
+                );
+        } else {
+            for (i, (dest, zero)) in dest.outer_iter_mut().zip(zero.iter_mut()).enumerate() {
+                log_fft_row(input_scratch, fft_scratch, i, dest, zero);
+            }
+        }
+    }
+    /// Inverse of gf_log_fft_to, where offset is added to the probability of every non-zero
+    /// value.
+    pub(super) fn gf_log_ifft(
+        &mut self,
+        field: &Field,
+        mut input: ArrayViewMut2<Complex<f64>>,
+        zero: ArrayView1<f64>,
+        offset: ArrayView1<f64>,
+        fft_scratch: &mut [Complex<f64>],
+        plans: &FftPlans,
+    ) {
+        let scale = 1.0 / ((self.shape.1 - 1) as f64);
+        let log_ifft_row = |output_scratch: &mut [f64],
+                            fft_scratch: &mut [Complex<f64>],
+                            mut dest: ArrayViewMut1<f64>,
+                            mut input: ArrayViewMut1<Complex<f64>>,
+                            zero: f64,
+                            offset: f64| {
+            plans
+                .c2r
+                .process_with_scratch(input.as_slice_mut().unwrap(), output_scratch, fft_scratch)
+                .unwrap();
+            dest[0] = zero;
+            for (x, e) in output_scratch.iter().zip(field.exp.iter()) {
+                dest[*e as usize] = offset + x * scale;
+            }
+        };
+        self.ensure_full();
+        let mut v = self.value_mut().unwrap();
+        if v.len() >= PAR_MIN_SIZE {
+            v.axis_iter_mut(ndarray::Axis(0))
+                .into_par_iter()
+                .zip(input.axis_iter_mut(ndarray::Axis(0)).into_par_iter())
+                .zip(zero.as_slice().unwrap().par_iter())
+                .zip(offset.as_slice().unwrap().par_iter())
+                .for_each_init(
+                    || (plans.c2r.make_output_vec(), plans.c2r.make_scratch_vec()),
+                    |(output_scratch, fft_scratch), (((dest, input), zero), offset)| {
+                        log_ifft_row(output_scratch, fft_scratch, dest, input, *zero, *offset)
+                    },
+                );
+        } else {
+            let mut output_scratch = plans.c2r.make_output_vec();
+            for (((dest, input), zero), offset) in v
+                .outer_iter_mut()
+                .zip(input.outer_iter_mut())
+                .zip(zero.iter())
+                .zip(offset.iter())
+            {
+                log_ifft_row(
+                    &mut output_scratch,
+                    fft_scratch,
+                    dest,
+                    input,
+                    *zero,
+                    *offset,
+                );
+            }
         }
     }
     pub fn is_full(&self) -> bool {
@@ -406,6 +593,283 @@ impl Distribution {
             }
         }
     }
+    /// The multi distribution among self and other (if any), which gives the shape of their
+    /// combination.
+    fn broadcast<'a>(&'a self, other: &'a Self) -> &'a Self {
+        if self.multi || !other.multi {
+            self
+        } else {
+            other
+        }
+    }
+    /// Probability of value i in row k, broadcasting single distributions.
+    fn proba(&self, k: usize, i: usize) -> Proba {
+        match &self.value {
+            DistrRepr::Full(v) => v[(if self.multi { k } else { 0 }, i)],
+            DistrRepr::Uniform => 1.0 / (self.shape.1 as Proba),
+        }
+    }
+    /// Distribution of table[a*nc+b], where self is the distribution of a and other is the
+    /// distribution of b.
+    pub fn map_table2(&self, other: &Self, table: &[ClassVal]) -> Self {
+        let mut res = self.broadcast(other).full_zeros();
+        let nc = self.shape.1;
+        res.for_each_ignore(|mut res, k| {
+            for i1 in 0..nc {
+                let p1 = self.proba(k, i1);
+                for i2 in 0..nc {
+                    res[table[i1 * nc + i2] as usize] += p1 * other.proba(k, i2);
+                }
+            }
+        });
+        res
+    }
+    /// Message to an input of table[a*nc+b], where self is the distribution of the output and
+    /// other is the distribution of the other input.
+    /// If transpose is false, the message is for a, otherwise it is for b.
+    pub fn map_table2_inv(&self, other: &Self, table: &[ClassVal], transpose: bool) -> Self {
+        if !self.is_full() {
+            return self.broadcast(other).as_uniform();
+        }
+        let mut res = self.broadcast(other).full_zeros();
+        let nc = self.shape.1;
+        res.for_each_ignore(|mut res, k| {
+            for i1 in 0..nc {
+                for i2 in 0..nc {
+                    let (a, b) = if transpose { (i2, i1) } else { (i1, i2) };
+                    res[i1] += self.proba(k, table[a * nc + b] as usize) * other.proba(k, i2);
+                }
+            }
+        });
+        res
+    }
+    /// Message to operand dest of x = BITS(p_1, ..., p_n), where distrs are the distributions
+    /// of x, p_1, ..., p_n (p_1 being the least significant part of x in mixed radix).
+    /// The marginalization is a maximum instead of a sum if max_product is set.
+    pub fn bits_msg(distrs: &[&Self], dest: usize, max_product: bool) -> Self {
+        let others: Vec<usize> = (0..distrs.len())
+            .filter(|i| *i != dest && distrs[*i].is_full())
+            .collect();
+        if others.is_empty() {
+            return distrs[dest].as_uniform();
+        }
+        let ncs: Vec<usize> = distrs[1..].iter().map(|d| d.shape.1).collect();
+        let mut res = distrs[dest].full_zeros();
+        res.for_each_ignore(|mut res, k| {
+            // digits[j] is the value of p_{j+1} for x.
+            let mut digits = vec![0; ncs.len()];
+            for x in 0..distrs[0].shape.1 {
+                let p: Proba = others
+                    .iter()
+                    .map(|i| {
+                        let v = if *i == 0 { x } else { digits[*i - 1] };
+                        distrs[*i].proba(k, v)
+                    })
+                    .product();
+                accumulate(
+                    &mut res[if dest == 0 { x } else { digits[dest - 1] }],
+                    p,
+                    max_product,
+                );
+                for (d, nc) in digits.iter_mut().zip(ncs.iter()) {
+                    *d += 1;
+                    if *d < *nc {
+                        break;
+                    }
+                    *d = 0;
+                }
+            }
+        });
+        res
+    }
+    /// Distribution of the Hamming weight of self, with values in 0..nc (or max-marginal if
+    /// max_product is set).
+    pub fn map_hw(&self, nc: usize, max_product: bool) -> Self {
+        let mut res = Self {
+            multi: self.multi,
+            shape: (self.shape.0, nc),
+            value: DistrRepr::Full(ndarray::Array2::zeros((self.shape.0, nc))),
+        };
+        res.for_each_ignore(|mut res, k| {
+            for x in 0..self.shape.1 {
+                accumulate(
+                    &mut res[x.count_ones() as usize],
+                    self.proba(k, x),
+                    max_product,
+                );
+            }
+        });
+        res
+    }
+    /// Message to x with values in 0..nc, where self is the distribution of the Hamming weight
+    /// of x.
+    pub fn map_hw_inv(&self, nc: usize) -> Self {
+        let value = if let DistrRepr::Full(v) = &self.value {
+            DistrRepr::Full(ndarray::Array2::from_shape_fn(
+                (self.shape.0, nc),
+                |(k, x)| v[(k, x.count_ones() as usize)],
+            ))
+        } else {
+            DistrRepr::Uniform
+        };
+        Self {
+            multi: self.multi,
+            shape: (self.shape.0, nc),
+            value,
+        }
+    }
+    /// Max-marginal of table[x], where self is the distribution of x.
+    /// This only takes maxima of the probabilities, hence it cannot underflow.
+    pub fn map_table_max(&self, table: &[ClassVal]) -> Self {
+        let mut res = self.full_zeros();
+        res.for_each_ignore(|mut res, k| {
+            for (i, t) in table.iter().enumerate() {
+                accumulate(&mut res[*t as usize], self.proba(k, i), true);
+            }
+        });
+        res
+    }
+    /// Log-probabilities of execution k.
+    fn ln_probas(&self, k: usize) -> Vec<Proba> {
+        (0..self.shape.1).map(|x| self.proba(k, x).ln()).collect()
+    }
+    /// Max-marginal of op(x, y), where self is the distribution of x and other is the
+    /// distribution of y. The result is normalized.
+    /// The products are computed in the log domain (max-plus), to avoid underflows.
+    pub fn max_op(&self, other: &Self, op: impl Fn(usize, usize) -> usize + Sync) -> Self {
+        let mut res = self.broadcast(other).full_zeros();
+        res.for_each_ignore(|mut res, k| {
+            let (p, q) = (self.ln_probas(k), other.ln_probas(k));
+            res.fill(Proba::NEG_INFINITY);
+            for (x, p) in p.iter().enumerate() {
+                for (y, q) in q.iter().enumerate() {
+                    accumulate(&mut res[op(x, y)], p + q, true);
+                }
+            }
+            exp_max_normalized(res);
+        });
+        res.normalize();
+        res
+    }
+    /// Max-product message to x for the relation z = op(x, y), where self is the distribution of
+    /// z and other is the distribution of y. The result is normalized.
+    /// The products are computed in the log domain (max-plus), to avoid underflows.
+    pub fn max_op_inv(&self, other: &Self, op: impl Fn(usize, usize) -> usize + Sync) -> Self {
+        if !self.is_full() {
+            return self.broadcast(other).as_uniform();
+        }
+        let mut res = self.broadcast(other).full_zeros();
+        res.for_each_ignore(|mut res, k| {
+            let (p, q) = (self.ln_probas(k), other.ln_probas(k));
+            for (x, r) in res.iter_mut().enumerate() {
+                *r = Proba::NEG_INFINITY;
+                for (y, q) in q.iter().enumerate() {
+                    accumulate(r, p[op(x, y)] + q, true);
+                }
+            }
+            exp_max_normalized(res);
+        });
+        res.normalize();
+        res
+    }
+    /// Most likely value (for each execution if multi, the first one in case of tie).
+    pub fn argmax(&self) -> PublicValue {
+        let argmax = |d: ArrayView1<Proba>| {
+            d.iter()
+                .enumerate()
+                .fold((0, Proba::NEG_INFINITY), |(i, m), (j, p)| {
+                    if *p > m {
+                        (j, *p)
+                    } else {
+                        (i, m)
+                    }
+                })
+                .0 as ClassVal
+        };
+        let values: Vec<ClassVal> = if let Some(v) = self.value() {
+            v.outer_iter().map(argmax).collect()
+        } else {
+            vec![0; self.shape.0]
+        };
+        if self.multi {
+            PublicValue::Multi(values)
+        } else {
+            PublicValue::Single(values[0])
+        }
+    }
+    /// Shannon entropy in bits (for each execution if multi).
+    pub fn entropy(&self) -> Vec<f64> {
+        if let Some(v) = self.value() {
+            v.outer_iter()
+                .map(|d| {
+                    let sum = d.sum();
+                    -d.iter()
+                        .filter(|p| **p > 0.0)
+                        .map(|p| (p / sum) * (p / sum).log2())
+                        .sum::<f64>()
+                })
+                .collect()
+        } else {
+            vec![(self.shape.1 as f64).log2(); self.shape.0]
+        }
+    }
+    /// Normalized probability of value (for each execution if multi).
+    pub fn probability(&self, value: &PublicValue) -> Vec<f64> {
+        (0..self.shape.0)
+            .map(|i| {
+                let x = value.get(i) as usize;
+                if let Some(v) = self.value() {
+                    let d = v.row(i);
+                    d[x] / d.sum()
+                } else {
+                    1.0 / (self.shape.1 as f64)
+                }
+            })
+            .collect()
+    }
+    /// Distribution of the XOR of self and other.
+    pub fn xor(&self, other: &Self) -> Self {
+        if !self.is_full() || !other.is_full() {
+            return self.broadcast(other).as_uniform();
+        }
+        let mut res = self.clone();
+        let mut other = other.clone();
+        res.wht();
+        other.wht();
+        res.multiply(Some(&other).into_iter());
+        res.wht();
+        res.regularize();
+        res
+    }
+    fn normalized_full(&self) -> Self {
+        let mut res = self.clone();
+        res.ensure_full();
+        res.normalize();
+        res
+    }
+    /// Damped update: self becomes (1-damping)*self + damping*old, after normalization.
+    pub fn damp(&mut self, old: &Self, damping: f64) {
+        if !self.is_full() && !old.is_full() {
+            return;
+        }
+        *self = self.normalized_full();
+        let old = old.normalized_full();
+        azip!((x in self.value_mut().unwrap(), o in old.value().unwrap()) {
+            *x = (1.0 - damping) * *x + damping * *o;
+        });
+    }
+    /// Maximum absolute difference between the probabilities of self and other (after
+    /// normalization).
+    pub fn max_diff(&self, other: &Self) -> f64 {
+        if !self.is_full() && !other.is_full() {
+            return 0.0;
+        }
+        let (a, b) = (self.normalized_full(), other.normalized_full());
+        let (a, b) = (a.value().unwrap(), b.value().unwrap());
+        a.iter()
+            .zip(b.iter())
+            .fold(0.0, |acc, (x, y)| f64::max(acc, (x - y).abs()))
+    }
     /// Normalize sum to one, and make values not too small
     pub fn normalize(&mut self) {
         self.for_each_ignore(|mut d, _| {
@@ -471,7 +935,7 @@ impl Distribution {
         let mut res = self.full_zeros();
         let nc = self.shape().1;
         let u = 1.0f64 / (nc as f64);
-        for (k, mut res) in res.value_mut().unwrap().outer_iter_mut().enumerate() {
+        res.for_each_ignore(|mut res, k| {
             for i1 in 0..nc {
                 for i2 in 0..nc {
                     let o = (((i1 * i2) as u32) % (nc as u32)) as usize;
@@ -479,7 +943,7 @@ impl Distribution {
                         * other.value().map(|d| d[(k, i2)]).unwrap_or(u);
                 }
             }
-        }
+        });
         res
     }
     pub fn op_multiply_factor(&self, other: &Self) -> Self {
@@ -535,14 +999,21 @@ impl Distribution {
         }
         res
     }
-    pub fn for_each<F, G>(&mut self, mut f: F, default: G)
+    pub fn for_each<F, G>(&mut self, f: F, default: G)
     where
-        F: FnMut(ndarray::ArrayViewMut1<f64>, usize),
+        F: Fn(ndarray::ArrayViewMut1<f64>, usize) + Sync + Send,
         G: FnOnce(&mut Self),
     {
         if let DistrRepr::Full(v) = &mut self.value {
-            for (i, d) in v.axis_iter_mut(ndarray::Axis(0)).enumerate() {
-                f(d, i);
+            if v.len() >= PAR_MIN_SIZE {
+                v.axis_iter_mut(ndarray::Axis(0))
+                    .into_par_iter()
+                    .enumerate()
+                    .for_each(|(i, d)| f(d, i));
+            } else {
+                for (i, d) in v.axis_iter_mut(ndarray::Axis(0)).enumerate() {
+                    f(d, i);
+                }
             }
         } else {
             default(self);
@@ -550,13 +1021,13 @@ impl Distribution {
     }
     pub fn for_each_ignore<F>(&mut self, f: F)
     where
-        F: FnMut(ndarray::ArrayViewMut1<f64>, usize),
+        F: Fn(ndarray::ArrayViewMut1<f64>, usize) + Sync + Send,
     {
         self.for_each(f, |_| {});
     }
     pub fn for_each_error<F>(&mut self, f: F)
     where
-        F: FnMut(ndarray::ArrayViewMut1<f64>, usize),
+        F: Fn(ndarray::ArrayViewMut1<f64>, usize) + Sync + Send,
     {
         self.for_each(f, |_| {
             unimplemented!("This function must be called on Full distributions.");
@@ -693,6 +1164,157 @@ fn inv_and_cst_slice(a: &mut [f64], cst: ClassVal) {
     }
 }
 
+/// Compute the product of all distributions in belief, and, at position i
+/// in the vector, the product of all distributions in belief except the
+/// one at position i.
+/// All are multiplied by base.
+/// Distribution stored as natural log-probabilities (not necessarily normalized).
+/// Products of many distributions are sums in this domain, hence they neither underflow nor
+/// need clamping.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LogDistribution {
+    multi: bool,
+    /// (nmulti, nc)
+    shape: (usize, usize),
+    /// A uniform distribution is all zeros.
+    value: ndarray::Array2<Proba>,
+}
+
+impl LogDistribution {
+    pub fn multi(&self) -> bool {
+        self.multi
+    }
+    pub fn shape(&self) -> (usize, usize) {
+        self.shape
+    }
+    pub fn value(&self) -> ndarray::ArrayView2<'_, Proba> {
+        self.value.view()
+    }
+    pub fn from_distribution(distr: &Distribution) -> Self {
+        let value = match &distr.value {
+            DistrRepr::Full(v) => v.mapv(Proba::ln),
+            DistrRepr::Uniform => ndarray::Array2::zeros(distr.shape),
+        };
+        Self {
+            multi: distr.multi,
+            shape: distr.shape,
+            value,
+        }
+    }
+    /// Multiply by other: execution-wise if self is MULTI, otherwise by all the executions of
+    /// other.
+    pub fn multiply(&mut self, other: &Distribution) {
+        assert_eq!(self.shape.1, other.shape.1);
+        if let DistrRepr::Full(v) = &other.value {
+            if self.multi {
+                azip!((x in &mut self.value, y in v) *x += y.ln());
+            } else {
+                let mut x = self.value.row_mut(0);
+                for y in v.outer_iter() {
+                    azip!((x in &mut x, y in &y) *x += y.ln());
+                }
+            }
+        }
+    }
+    /// Multiply by other (execution-wise, both must have the same shape).
+    pub fn multiply_log(&mut self, other: &LogDistribution) {
+        assert_eq!(self.shape, other.shape);
+        self.value += &other.value;
+    }
+    /// Normalize the probabilities to sum to one (with log-sum-exp).
+    pub fn normalize(&mut self) {
+        for mut d in self.value.outer_iter_mut() {
+            let lse = log_sum_exp(d.view());
+            d.mapv_inplace(|x| x - lse);
+        }
+    }
+    /// Normalized linear-domain distribution.
+    pub fn to_distribution(&self) -> Distribution {
+        let mut value = self.value.clone();
+        for mut d in value.outer_iter_mut() {
+            let max = d.fold(Proba::NEG_INFINITY, |m, x| m.max(*x));
+            d.mapv_inplace(|x| (x - max).exp());
+        }
+        let mut res = Distribution {
+            multi: self.multi,
+            shape: self.shape,
+            value: DistrRepr::Full(value),
+        };
+        res.normalize();
+        res
+    }
+    /// For each execution of self, the product of other and of all the other executions.
+    fn exclusive_products(&self, other: ndarray::Array1<Proba>) -> Self {
+        let n = self.shape.0;
+        let mut value = ndarray::Array2::zeros(self.shape);
+        let mut prefix = other;
+        for i in 0..n {
+            value.row_mut(i).assign(&prefix);
+            prefix += &self.value.row(i);
+        }
+        let mut suffix = ndarray::Array1::zeros(self.shape.1);
+        for i in (0..n).rev() {
+            let mut v = value.row_mut(i);
+            v += &suffix;
+            suffix += &self.value.row(i);
+        }
+        Self {
+            multi: self.multi,
+            shape: self.shape,
+            value,
+        }
+    }
+}
+
+fn log_sum_exp(d: ArrayView1<Proba>) -> Proba {
+    let max = d.fold(Proba::NEG_INFINITY, |m, x| m.max(*x));
+    if !max.is_finite() {
+        return max;
+    }
+    max + d.iter().map(|x| (x - max).exp()).sum::<Proba>().ln()
+}
+
+/// Log-domain version of belief_reciprocal_product for a SINGLE var.
+/// Returns the product of base and of all the beliefs, and for each belief the product of base
+/// and of all the other beliefs (where, for a MULTI belief, each execution excludes only
+/// itself).
+/// Only additions are used (no division), hence zero probabilities are handled exactly.
+/// The returned beliefs are converted back to the linear domain.
+pub fn log_belief_reciprocal_product(
+    base: LogDistribution,
+    beliefs: &[&Distribution],
+) -> (LogDistribution, Vec<Distribution>) {
+    assert!(!base.multi);
+    let logs: Vec<LogDistribution> = beliefs
+        .iter()
+        .map(|b| LogDistribution::from_distribution(b))
+        .collect();
+    // Product of all the executions of each belief.
+    let totals: Vec<ndarray::Array1<Proba>> = logs
+        .iter()
+        .map(|l| l.value.sum_axis(ndarray::Axis(0)))
+        .collect();
+    // prefixes[i] is the product of base and of the beliefs before i.
+    let mut prefixes = vec![base.value.row(0).to_owned()];
+    for t in totals.iter() {
+        let next = prefixes.last().unwrap() + t;
+        prefixes.push(next);
+    }
+    let mut suffix = ndarray::Array1::zeros(base.shape.1);
+    let mut res: Vec<Distribution> = Vec::with_capacity(logs.len());
+    for (i, (l, t)) in logs.iter().zip(totals.iter()).enumerate().rev() {
+        res.push(
+            l.exclusive_products(&prefixes[i] + &suffix)
+                .to_distribution(),
+        );
+        suffix += t;
+    }
+    res.reverse();
+    let mut product = base;
+    product.value.row_mut(0).assign(prefixes.last().unwrap());
+    (product, res)
+}
+
 /// Compute the product of all distributions in belief, and, at position i
 /// in the vector, the product of all distributions in belief except the
 /// one at position i.
@@ -719,3 +1341,23 @@ pub fn belief_reciprocal_product<'a>(
     }
     return (running_product, res);
 }
+
+/// Sum (or maximum if max_product) of acc and p.
+/// Replace log-probabilities by probabilities, scaled such that the maximum is one (all zeros if
+/// all the log-probabilities are -inf).
+fn exp_max_normalized(mut d: ArrayViewMut1<Proba>) {
+    let max = d.fold(Proba::NEG_INFINITY, |m, x| m.max(*x));
+    if max == Proba::NEG_INFINITY {
+        d.fill(0.0);
+    } else {
+        d.mapv_inplace(|x| (x - max).exp());
+    }
+}
+
+fn accumulate(acc: &mut Proba, p: Proba, max_product: bool) {
+    if max_product {
+        *acc = acc.max(p);
+    } else {
+        *acc += p;
+    }
+}
@@ -0,0 +1,96 @@
+//! Evidence of the MULTI vars computed from the leakage traces with template models.
+
+use ndarray::{ArrayView2, Axis};
+use rayon::prelude::*;
+
+use super::{BPError, BPState, Distribution, VarId};
+use crate::lda::LDA;
+use crate::rlda::RLDA;
+
+/// Model giving the probabilities of the values of a var from leakage samples.
+#[derive(Clone, Copy)]
+pub enum EvidenceModel<'a> {
+    Lda(&'a LDA),
+    /// Model and index of the variable in the model.
+    Rlda(&'a RLDA, usize),
+}
+
+/// Evidence of a var from a model.
+#[derive(Clone)]
+pub struct EvidenceSource<'a> {
+    pub var: VarId,
+    pub model: EvidenceModel<'a>,
+    /// Columns of the traces that are the samples of the model.
+    pub pois: Vec<usize>,
+}
+
+impl EvidenceModel<'_> {
+    fn ns(&self) -> usize {
+        match self {
+            EvidenceModel::Lda(lda) => lda.ns,
+            EvidenceModel::Rlda(rlda, _) => rlda.ns,
+        }
+    }
+    fn nc(&self) -> usize {
+        match self {
+            EvidenceModel::Lda(lda) => lda.nc,
+            EvidenceModel::Rlda(rlda, _) => 1 << rlda.nb,
+        }
+    }
+    fn predict_proba(&self, x: ArrayView2<i16>) -> ndarray::Array2<f64> {
+        match self {
+            EvidenceModel::Lda(lda) => lda.predict_proba(x),
+            EvidenceModel::Rlda(rlda, v) => rlda.predict_proba(x, *v),
+        }
+    }
+}
+
+impl BPState {
+    /// Set the evidence of MULTI vars from the leakage traces (of shape (nmulti, ns)), with
+    /// their models. The sources are checked before anything is computed, and the models are
+    /// evaluated in parallel.
+    pub fn set_evidence_from_models(
+        &mut self,
+        traces: ArrayView2<i16>,
+        sources: &[EvidenceSource<'_>],
+    ) -> Result<(), BPError> {
+        let graph = self.get_graph();
+        if traces.nrows() != self.nmulti() as usize {
+            return Err(BPError::WrongTraceCount(traces.nrows(), self.nmulti()));
+        }
+        for source in sources {
+            if !graph.var_multi(source.var) {
+                return Err(BPError::WrongDistributionKind("multi", "single"));
+            }
+            if source.model.nc() != graph.var_nc(source.var) {
+                return Err(BPError::WrongDistributionNc(
+                    source.model.nc(),
+                    graph.var_nc(source.var),
+                ));
+            }
+            if source.pois.len() != source.model.ns() {
+                return Err(BPError::WrongPoiCount(source.pois.len(), source.model.ns()));
+            }
+            if let Some(poi) = source.pois.iter().find(|poi| **poi >= traces.ncols()) {
+                return Err(BPError::WrongPoi(*poi, traces.ncols()));
+            }
+            if let EvidenceModel::Rlda(rlda, v) = source.model {
+                if v >= rlda.nv {
+                    return Err(BPError::WrongModelVar(v, rlda.nv));
+                }
+            }
+        }
+        let evidence = sources
+            .par_iter()
+            .map(|source| {
+                let x = traces.select(Axis(1), &source.pois);
+                let prs = source.model.predict_proba(x.view());
+                Distribution::from_array_multi(prs.as_standard_layout().into_owned())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (source, evidence) in sources.iter().zip(evidence) {
+            self.set_evidence(source.var, evidence)?;
+        }
+        Ok(())
+    }
+}
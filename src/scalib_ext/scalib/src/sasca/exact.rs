@@ -0,0 +1,464 @@
+//! Exact inference with a junction tree.
+//!
+//! The junction tree is derived from an elimination order of the variables, where each MULTI
+//! variable has one copy per execution. The executions only interact through the SINGLE
+//! variables, hence the MULTI variables are eliminated first, in the same order for all the
+//! executions, and the elimination order is computed on the graph with a single execution.
+//! The MULTI cliques of the executions are thus copies of each other.
+//!
+//! The clique tables are computed in the log domain, such that the products of the messages of
+//! many executions do not underflow.
+
+use std::collections::BTreeSet;
+
+use rayon::prelude::*;
+
+use super::factor_graph::{FactorId, FactorVec, VarId, VarVec};
+use super::{ClassVal, Distribution, FactorGraph, PublicValue};
+
+/// Cost of exact inference, estimated from the cliques of the junction tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExactCost {
+    /// Number of entries of the largest clique table.
+    pub max_clique_size: usize,
+    /// Total number of entries of the clique tables (for all the executions), to which the
+    /// running time is proportional.
+    pub total_size: usize,
+    /// Number of variables in the largest clique, minus one.
+    pub width: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Clique {
+    /// Eliminated variable, followed by the separator with the parent clique.
+    vars: Vec<VarId>,
+    dims: Vec<usize>,
+    /// Number of entries of the table (saturating).
+    size: usize,
+    /// Copied for every execution (i.e., the eliminated variable is MULTI).
+    multi: bool,
+    parent: Option<usize>,
+    /// Position in the parent clique of each variable of the separator.
+    parent_axes: Vec<usize>,
+    /// Position of the separator in the clique.
+    sep_axes: Vec<usize>,
+    children: Vec<usize>,
+    /// Factors assigned to the clique, with the position in the clique of each of their
+    /// variables.
+    factors: Vec<(FactorId, Vec<usize>)>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct JunctionTree {
+    nmulti: usize,
+    /// In elimination order: the MULTI cliques come first.
+    cliques: Vec<Clique>,
+    n_multi_cliques: usize,
+}
+
+impl JunctionTree {
+    pub(super) fn new(graph: &FactorGraph, nmulti: usize) -> Self {
+        // Interaction graph of the variables.
+        let mut adjacency: VarVec<BTreeSet<VarId>> = graph
+            .range_vars()
+            .map(|var| {
+                graph
+                    .petgraph
+                    .neighbors(graph.var_graph_ids[var])
+                    .flat_map(|f| graph.petgraph.neighbors(f))
+                    .filter_map(|n| graph.petgraph[n].var())
+                    .filter(|v| *v != var)
+                    .collect()
+            })
+            .collect();
+        let weight = |vars: &mut dyn Iterator<Item = &VarId>| {
+            vars.fold(1usize, |acc, v| acc.saturating_mul(graph.var_nc(*v)))
+        };
+        // With a single execution, there is no need to eliminate the MULTI variables first.
+        let phases: &[bool] = if nmulti > 1 { &[true, false] } else { &[false] };
+        let mut eliminated: VarVec<bool> = graph.range_vars().map(|_| false).collect();
+        let mut order: VarVec<usize> = graph.range_vars().map(|_| 0).collect();
+        let mut cliques = Vec::new();
+        let mut n_multi_cliques = 0;
+        for multi_phase in phases {
+            // Greedy min-fill elimination (ties broken by the size of the clique).
+            while let Some(var) = graph
+                .range_vars()
+                .filter(|v| !eliminated[*v] && (!multi_phase || graph.var_multi(*v)))
+                .min_by_key(|v| {
+                    let nbrs = &adjacency[*v];
+                    let fill = nbrs
+                        .iter()
+                        .map(|a| {
+                            nbrs.iter()
+                                .filter(|b| a < *b && !adjacency[*a].contains(b))
+                                .count()
+                        })
+                        .sum::<usize>();
+                    (fill, weight(&mut nbrs.iter().chain(Some(v))), *v)
+                })
+            {
+                let nbrs = std::mem::take(&mut adjacency[var]);
+                for a in nbrs.iter() {
+                    adjacency[*a].remove(&var);
+                    adjacency[*a].extend(nbrs.iter().filter(|b| *b != a));
+                }
+                eliminated[var] = true;
+                order[var] = cliques.len();
+                let vars: Vec<VarId> = std::iter::once(var).chain(nbrs).collect();
+                cliques.push(Clique {
+                    sep_axes: (1..vars.len()).collect(),
+                    dims: vars.iter().map(|v| graph.var_nc(*v)).collect(),
+                    size: weight(&mut vars.iter()),
+                    vars,
+                    multi: *multi_phase,
+                    parent: None,
+                    parent_axes: vec![],
+                    children: vec![],
+                    factors: vec![],
+                });
+            }
+            if *multi_phase {
+                n_multi_cliques = cliques.len();
+            }
+        }
+        // The parent is the clique of the first eliminated variable of the separator.
+        for k in 0..cliques.len() {
+            let parent = cliques[k].vars[1..].iter().map(|v| order[*v]).min();
+            if let Some(parent) = parent {
+                let parent_axes = cliques[k].vars[1..]
+                    .iter()
+                    .map(|v| cliques[parent].vars.iter().position(|x| x == v).unwrap())
+                    .collect();
+                cliques[k].parent = Some(parent);
+                cliques[k].parent_axes = parent_axes;
+                cliques[parent].children.push(k);
+            }
+        }
+        // Each factor goes to the clique of the first eliminated variable of its scope, which
+        // contains the whole scope.
+        for factor in graph.range_factors() {
+            let k = graph
+                .factor_scope(factor)
+                .map(|v| order[v])
+                .min()
+                .expect("Factor without variables.");
+            let axes = graph
+                .factor_scope(factor)
+                .map(|v| cliques[k].vars.iter().position(|x| *x == v).unwrap())
+                .collect();
+            cliques[k].factors.push((factor, axes));
+        }
+        Self {
+            nmulti,
+            cliques,
+            n_multi_cliques,
+        }
+    }
+
+    pub(super) fn cost(&self, graph: &FactorGraph) -> ExactCost {
+        let total_size = self
+            .cliques
+            .iter()
+            .map(|c| {
+                // A MULTI factor in a SINGLE clique is evaluated for all the executions.
+                if c.multi || c.factors.iter().any(|(f, _)| graph.factor_multi(*f)) {
+                    c.size.saturating_mul(self.nmulti)
+                } else {
+                    c.size
+                }
+            })
+            .fold(0usize, |acc, x| acc.saturating_add(x));
+        ExactCost {
+            max_clique_size: self.cliques.iter().map(|c| c.size).max().unwrap_or(1),
+            total_size,
+            width: self
+                .cliques
+                .iter()
+                .map(|c| c.vars.len() - 1)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Exact marginals (or max-marginals if max_product) of all the variables.
+    pub(super) fn marginals(
+        &self,
+        graph: &FactorGraph,
+        evidence: &VarVec<Distribution>,
+        pub_reduced: &FactorVec<PublicValue>,
+        max_product: bool,
+    ) -> VarVec<Distribution> {
+        let n1 = self.n_multi_cliques;
+        let n_cliques = self.cliques.len();
+        let ctx = TableContext {
+            graph,
+            evidence,
+            pub_reduced,
+            nmulti: self.nmulti,
+        };
+        // Messages to the parents, for the MULTI cliques (by execution) then the SINGLE ones.
+        let mut up_multi = vec![vec![vec![]; n1]; self.nmulti];
+        let mut up_single = vec![vec![]; n_cliques - n1];
+        up_multi.par_iter_mut().enumerate().for_each(|(n, up)| {
+            for k in 0..n1 {
+                let incoming = self.cliques[k]
+                    .children
+                    .iter()
+                    .map(|c| (up[*c].as_slice(), self.cliques[*c].parent_axes.as_slice()));
+                let table = ctx.log_table(&self.cliques[k], n, incoming);
+                up[k] = self.up_message(k, &table, max_product);
+            }
+        });
+        for k in n1..n_cliques {
+            let table = ctx.log_table(
+                &self.cliques[k],
+                0,
+                self.single_incoming(k, &up_multi, &up_single),
+            );
+            up_single[k - n1] = self.up_message(k, &table, max_product);
+        }
+        // Messages to the children, and marginals of the eliminated variables.
+        let mut down_multi = vec![vec![vec![]; n1]; self.nmulti];
+        let mut down_single = vec![vec![]; n_cliques - n1];
+        let mut marginals: VarVec<Vec<Vec<f64>>> = graph.range_vars().map(|_| vec![]).collect();
+        for k in (n1..n_cliques).rev() {
+            let clique = &self.cliques[k];
+            let down = &down_single[k - n1];
+            let incoming = self.single_incoming(k, &up_multi, &up_single).chain(
+                clique
+                    .parent
+                    .map(|_| (down.as_slice(), clique.sep_axes.as_slice())),
+            );
+            let table = ctx.log_table(clique, 0, incoming);
+            marginals[clique.vars[0]] = vec![log_marginal(&table, &clique.dims, &[0], max_product)];
+            for c in clique.children.iter() {
+                let msg = log_marginal(
+                    &table,
+                    &clique.dims,
+                    &self.cliques[*c].parent_axes,
+                    max_product,
+                );
+                if *c < n1 {
+                    for (down, up) in down_multi.iter_mut().zip(up_multi.iter()) {
+                        down[*c] = divide(msg.clone(), &up[*c]);
+                    }
+                } else {
+                    down_single[*c - n1] = divide(msg, &up_single[*c - n1]);
+                }
+            }
+        }
+        let multi_marginals: Vec<Vec<Vec<f64>>> = down_multi
+            .par_iter_mut()
+            .zip(up_multi.par_iter())
+            .enumerate()
+            .map(|(n, (down, up))| {
+                (0..n1)
+                    .rev()
+                    .map(|k| {
+                        let clique = &self.cliques[k];
+                        let incoming = clique
+                            .children
+                            .iter()
+                            .map(|c| (up[*c].as_slice(), self.cliques[*c].parent_axes.as_slice()))
+                            .chain(
+                                clique
+                                    .parent
+                                    .map(|_| (down[k].as_slice(), clique.sep_axes.as_slice())),
+                            );
+                        let table = ctx.log_table(clique, n, incoming);
+                        for c in clique.children.iter() {
+                            let msg = log_marginal(
+                                &table,
+                                &clique.dims,
+                                &self.cliques[*c].parent_axes,
+                                max_product,
+                            );
+                            down[*c] = divide(msg, &up[*c]);
+                        }
+                        log_marginal(&table, &clique.dims, &[0], max_product)
+                    })
+                    .collect()
+            })
+            .collect();
+        for (n, mut m) in multi_marginals.into_iter().enumerate() {
+            for k in 0..n1 {
+                let var = self.cliques[k].vars[0];
+                if marginals[var].is_empty() {
+                    marginals[var] = vec![vec![]; self.nmulti];
+                }
+                marginals[var][n] = m.pop().unwrap();
+            }
+        }
+        marginals
+            .into_iter()
+            .zip(graph.range_vars())
+            .map(|(m, var)| {
+                let nc = graph.var_nc(var);
+                let mut probas = ndarray::Array2::from_shape_fn((m.len(), nc), |(i, j)| m[i][j]);
+                for mut row in probas.outer_iter_mut() {
+                    let max = row.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                    row.mapv_inplace(|x| (x - max).exp());
+                    let sum = row.sum();
+                    row /= sum;
+                }
+                if graph.var_multi(var) {
+                    Distribution::from_array_multi(probas).unwrap()
+                } else {
+                    Distribution::from_array_single(probas.row(0).to_owned()).unwrap()
+                }
+            })
+            .collect()
+    }
+
+    /// Incoming messages from the children of a SINGLE clique.
+    fn single_incoming<'s>(
+        &'s self,
+        k: usize,
+        up_multi: &'s [Vec<Vec<f64>>],
+        up_single: &'s [Vec<f64>],
+    ) -> impl Iterator<Item = (&'s [f64], &'s [usize])> + 's {
+        let n1 = self.n_multi_cliques;
+        self.cliques[k].children.iter().flat_map(move |c| {
+            let axes = self.cliques[*c].parent_axes.as_slice();
+            let msgs: Vec<&[f64]> = if *c < n1 {
+                up_multi.iter().map(|up| up[*c].as_slice()).collect()
+            } else {
+                vec![up_single[*c - n1].as_slice()]
+            };
+            msgs.into_iter().map(move |msg| (msg, axes))
+        })
+    }
+
+    fn up_message(&self, k: usize, table: &[f64], max_product: bool) -> Vec<f64> {
+        let clique = &self.cliques[k];
+        if clique.parent.is_some() {
+            log_marginal(table, &clique.dims, &clique.sep_axes, max_product)
+        } else {
+            vec![]
+        }
+    }
+}
+
+struct TableContext<'a> {
+    graph: &'a FactorGraph,
+    evidence: &'a VarVec<Distribution>,
+    pub_reduced: &'a FactorVec<PublicValue>,
+    nmulti: usize,
+}
+
+impl<'a> TableContext<'a> {
+    /// Log-domain product of the evidence of the eliminated variable, the factors and the
+    /// incoming messages of a clique for execution n (ignored for SINGLE cliques).
+    fn log_table<'m>(
+        &self,
+        clique: &Clique,
+        n: usize,
+        incoming: impl Iterator<Item = (&'m [f64], &'m [usize])>,
+    ) -> Vec<f64> {
+        let mut table = vec![0.0; clique.size];
+        let var = clique.vars[0];
+        if let Some(ev) = self.evidence[var].value() {
+            let row = if self.graph.var_multi(var) { n } else { 0 };
+            let ev: Vec<f64> = ev.row(row).iter().map(|p| p.ln()).collect();
+            add_message(&mut table, &clique.dims, &[0], &ev);
+        }
+        for (msg, axes) in incoming {
+            add_message(&mut table, &clique.dims, axes, msg);
+        }
+        for (factor_id, axes) in clique.factors.iter() {
+            let factor = self.graph.factor(*factor_id);
+            let execs = if clique.multi || !factor.multi {
+                n..(n + 1)
+            } else {
+                0..self.nmulti
+            };
+            let pub_red = &self.pub_reduced[*factor_id];
+            let mut values = vec![0; axes.len()];
+            for_each_index(&clique.dims, |i, idx| {
+                if table[i] == f64::NEG_INFINITY {
+                    return;
+                }
+                for (v, a) in values.iter_mut().zip(axes.iter()) {
+                    *v = idx[*a] as ClassVal;
+                }
+                if !execs
+                    .clone()
+                    .all(|e| self.graph.factor_holds(factor, &values, pub_red.get(e)))
+                {
+                    table[i] = f64::NEG_INFINITY;
+                }
+            });
+        }
+        table
+    }
+}
+
+/// Call f(i, idx) for every entry of a row-major table, where idx is the multi-index of entry i.
+fn for_each_index(dims: &[usize], mut f: impl FnMut(usize, &[usize])) {
+    let size = dims.iter().product::<usize>();
+    let mut idx = vec![0; dims.len()];
+    for i in 0..size {
+        f(i, &idx);
+        for (x, d) in idx.iter_mut().zip(dims.iter()).rev() {
+            *x += 1;
+            if *x < *d {
+                break;
+            }
+            *x = 0;
+        }
+    }
+}
+
+/// Row-major strides of a message on the given axes of a table.
+fn msg_strides(dims: &[usize], axes: &[usize]) -> Vec<usize> {
+    let mut strides = vec![0; dims.len()];
+    let mut stride = 1;
+    for a in axes.iter().rev() {
+        strides[*a] = stride;
+        stride *= dims[*a];
+    }
+    strides
+}
+
+fn add_message(table: &mut [f64], dims: &[usize], axes: &[usize], msg: &[f64]) {
+    let strides = msg_strides(dims, axes);
+    for_each_index(dims, |i, idx| {
+        let j: usize = idx.iter().zip(strides.iter()).map(|(x, s)| x * s).sum();
+        table[i] += msg[j];
+    });
+}
+
+/// Log-domain marginal (or max-marginal) of a table on the given axes.
+fn log_marginal(table: &[f64], dims: &[usize], axes: &[usize], max_product: bool) -> Vec<f64> {
+    let size = axes.iter().map(|a| dims[*a]).product::<usize>();
+    let max = table.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return vec![f64::NEG_INFINITY; size];
+    }
+    let strides = msg_strides(dims, axes);
+    let mut res = vec![0.0f64; size];
+    for_each_index(dims, |i, idx| {
+        let j: usize = idx.iter().zip(strides.iter()).map(|(x, s)| x * s).sum();
+        let p = (table[i] - max).exp();
+        if max_product {
+            res[j] = res[j].max(p);
+        } else {
+            res[j] += p;
+        }
+    });
+    res.into_iter().map(|p| p.ln() + max).collect()
+}
+
+/// Log-domain division, where 0/0 = 0: the messages are zero only where the product of the
+/// other messages does not matter.
+fn divide(mut msg: Vec<f64>, other: &[f64]) -> Vec<f64> {
+    for (m, o) in msg.iter_mut().zip(other.iter()) {
+        if *o == f64::NEG_INFINITY {
+            *m = f64::NEG_INFINITY;
+        } else {
+            *m -= *o;
+        }
+    }
+    msg
+}
@@ -4,6 +4,7 @@ use std::fmt;
 use indexmap::IndexMap;
 use thiserror::Error;
 
+use super::fg_simplify::Elimination;
 use super::{ClassVal, NamedList};
 
 macro_rules! new_id {
@@ -34,6 +35,8 @@ pub type TableId = usize;
 pub(super) struct Var {
     pub(super) multi: bool,
     pub(super) edges: IndexMap<FactorId, EdgeId>,
+    /// Domain size.
+    pub(super) nc: usize,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -59,35 +62,52 @@ impl Factor {
     }
 }
 
+/// ADD is the modular sum sum(coefs[i]*vars[i]) + publics = 0, where the coefficient of the
+/// result is -1.
+/// BITS is the decomposition of the result in mixed radix, where the radices are the domain sizes
+/// of the operands (least significant first).
+/// HW is the Hamming weight of the XOR of the operands (i.e., the Hamming distance between the
+/// operands if there are two of them).
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 //具有类型参数 T 的枚举，类型参数默认值为 TableId
 pub(super) enum FactorKind<T = TableId> {
     AND { vars_neg: Vec<bool> },//这个枚举成员关联了一个名为 vars_neg 的字段，该字段的类型是 Vec<bool>，预留字段
     XOR,
     NOT,
-    ADD,
+    ADD { coefs: Vec<ClassVal> },
     MUL,
+    GFMUL,
     LOOKUP { table: T },
+    BITS,
+    HW,
 }
 
 impl FactorKind {
-    fn merge(&self, a: ClassVal, b: ClassVal, nc: usize) -> ClassVal {
+    fn merge(&self, a: ClassVal, b: ClassVal, nc: usize, field: Option<&Field>) -> ClassVal {
         //它根据枚举类型 FactorKind 的不同成员，执行不同的代码块
         match self {
             FactorKind::AND { vars_neg: _ } => a & b,//用 _ 来表示我们不使用vars_neg
-            FactorKind::XOR => a ^ b,
-            FactorKind::ADD => (((a as u64) + (b as u64)) % (nc as u64)) as ClassVal,
-            FactorKind::MUL => (((a as u64) + (b as u64)) % (nc as u64)) as ClassVal,
-            FactorKind::NOT | FactorKind::LOOKUP { .. } => unreachable!(),//表示在代码执行到这个点时应该是不可达的
+            FactorKind::XOR | FactorKind::HW => a ^ b,
+            FactorKind::ADD { .. } => (((a as u64) + (b as u64)) % (nc as u64)) as ClassVal,
+            FactorKind::MUL => (((a as u64) * (b as u64)) % (nc as u64)) as ClassVal,
+            FactorKind::GFMUL => field.expect("GFMUL requires a field").mul(a, b),
+            FactorKind::NOT | FactorKind::LOOKUP { .. } | FactorKind::BITS => unreachable!(),//表示在代码执行到这个点时应该是不可达的
         }
     }
     fn neutral(&self, nc: usize) -> ClassVal {
         //中性元素？
         match self {
             FactorKind::AND { vars_neg: _ } => (nc - 1) as ClassVal,
-            FactorKind::XOR | FactorKind::ADD => 0,
-            FactorKind::MUL => 1,
-            FactorKind::NOT | FactorKind::LOOKUP { .. } => unreachable!(),
+            FactorKind::XOR | FactorKind::ADD { .. } | FactorKind::HW => 0,
+            FactorKind::MUL | FactorKind::GFMUL => 1,
+            FactorKind::NOT | FactorKind::LOOKUP { .. } | FactorKind::BITS => unreachable!(),
+        }
+    }
+    /// Inverse of a public operand (bitwise NOT for AND, opposite for ADD).
+    fn inverse(&self, x: ClassVal, nc: usize) -> ClassVal {
+        match self {
+            FactorKind::ADD { .. } => ((nc as u64 - x as u64 % nc as u64) % nc as u64) as ClassVal,
+            _ => ((nc - 1) as ClassVal) ^ x,
         }
     }
 }
@@ -108,7 +128,69 @@ pub(super) struct Public {
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub(super) struct Table {
+    // For 2-input tables, T[a, b] is values[a*nc+b].
     pub(super) values: Vec<ClassVal>,
+    pub(super) ninputs: usize,
+}
+
+/// Binary finite field GF(nc), defined by its reduction polynomial.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(super) struct Field {
+    pub(super) poly: u64,
+    /// exp[i] = g^i for i in 0..nc-1, where g generates the multiplicative group.
+    pub(super) exp: Vec<ClassVal>,
+    /// Inverse of exp (log[0] is meaningless).
+    pub(super) log: Vec<u32>,
+}
+
+impl Field {
+    /// Returns None if poly is not an irreducible polynomial of degree log2(nc).
+    pub(super) fn new(poly: u64, nc: usize) -> Option<Self> {
+        if !nc.is_power_of_two() || nc < 2 || poly >> nc.trailing_zeros() != 1 {
+            return None;
+        }
+        // The polynomial is irreducible iff the multiplicative group of the quotient ring is
+        // cyclic of order nc-1, we thus look for a generator.
+        (1..nc as u64).find_map(|g| {
+            let mut exp = Vec::with_capacity(nc - 1);
+            let mut log = vec![0; nc];
+            let mut x = 1;
+            for i in 0..(nc - 1) {
+                if i != 0 && x == 1 {
+                    return None;
+                }
+                exp.push(x as ClassVal);
+                log[x as usize] = i as u32;
+                x = gf_mul_poly(x, g, poly);
+            }
+            (x == 1).then_some(Self { poly, exp, log })
+        })
+    }
+    pub(super) fn mul(&self, a: ClassVal, b: ClassVal) -> ClassVal {
+        if a == 0 || b == 0 {
+            0
+        } else {
+            let l = (self.log[a as usize] + self.log[b as usize]) as usize % self.exp.len();
+            self.exp[l]
+        }
+    }
+}
+
+/// Carry-less multiplication of a and b, reduced modulo poly.
+fn gf_mul_poly(mut a: u64, mut b: u64, poly: u64) -> u64 {
+    let deg = 63 - poly.leading_zeros();
+    let mut res = 0;
+    while b != 0 {
+        if b & 1 == 1 {
+            res ^= a;
+        }
+        b >>= 1;
+        a <<= 1;
+        if (a >> deg) & 1 == 1 {
+            a ^= poly;
+        }
+    }
+    res
 }
 
 #[derive(Debug, Copy, Clone, serde::Serialize, serde::Deserialize)]
@@ -125,9 +207,12 @@ pub struct FactorGraph {
     pub(super) edges: EdgeVec<Edge>,
     pub(super) publics: NamedList<Public>,
     pub(super) tables: NamedList<Table>,
+    pub(super) field: Option<Field>,
     pub(super) petgraph: petgraph::Graph<Node, EdgeId, petgraph::Undirected>,//表示了一个无向图，其中节点类型为 Node，边的标识符类型为 EdgeId
     pub(super) var_graph_ids: VarVec<petgraph::graph::NodeIndex>,
     pub(super) factor_graph_ids: FactorVec<petgraph::graph::NodeIndex>,
+    /// Variables removed by simplify.
+    pub(super) eliminated: NamedList<Elimination>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -160,6 +245,23 @@ impl PublicValue {
             PublicValue::Multi(x) => PublicValue::Multi(x.iter().cloned().map(f).collect()),
         }
     }
+    pub fn map2(&self, other: &Self, f: impl Fn(ClassVal, ClassVal) -> ClassVal) -> Self {
+        match (self, other) {
+            (PublicValue::Single(x), PublicValue::Single(y)) => PublicValue::Single(f(*x, *y)),
+            (PublicValue::Multi(x), _) => PublicValue::Multi(
+                x.iter()
+                    .zip(other.iter(x.len()))
+                    .map(|(x, y)| f(*x, y))
+                    .collect(),
+            ),
+            (_, PublicValue::Multi(y)) => PublicValue::Multi(
+                self.iter(y.len())
+                    .zip(y.iter())
+                    .map(|(x, y)| f(x, *y))
+                    .collect(),
+            ),
+        }
+    }
 }
 impl fmt::Display for PublicValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -174,6 +276,8 @@ impl fmt::Display for PublicValue {
 pub enum FGError {
     #[error("No variable named {0}.")]
     NoVar(String),
+    #[error("Variable {0} was removed by graph simplification.")]
+    Eliminated(String),
     #[error("No factor named {0}.")]
     NoFactor(String),
     #[error("No edge between variable {var} and factor {factor}.")]
@@ -204,7 +308,13 @@ impl FactorGraph {
         self.vars
             .get_index_of(var)
             .map(VarId::from_idx)
-            .ok_or_else(|| FGError::NoVar(var.to_owned()))
+            .ok_or_else(|| {
+                if self.eliminated.contains_key(var) {
+                    FGError::Eliminated(var.to_owned())
+                } else {
+                    FGError::NoVar(var.to_owned())
+                }
+            })
     }
     //var_multi 和 factor_multi 和 edge_multi: 检查变量、因子或边是否是多值的
     pub(super) fn var(&self, var: VarId) -> &Var {
@@ -213,6 +323,9 @@ impl FactorGraph {
     pub fn var_multi(&self, var: VarId) -> bool {
         self.var(var).multi
     }
+    pub fn var_nc(&self, var: VarId) -> usize {
+        self.var(var).nc
+    }
     pub fn factor_multi(&self, factor: FactorId) -> bool {
         self.factor(factor).multi
     }
@@ -264,10 +377,13 @@ impl FactorGraph {
         assert_eq!(var_assignments.len(), self.vars.len());
         let reduced_pub = self.reduce_pub(public_values.as_slice());
         for ((factor_name, factor), cst) in self.factors.iter().zip(reduced_pub) {
-            let expected_res = factor
-                .res_id()
-                .map(|v_id| &var_assignments[v_id])
-                .unwrap_or(&cst);
+            let zero = PublicValue::Single(0);
+            let expected_res = match factor.res_id() {
+                Some(v_id) => &var_assignments[v_id],
+                // A public result of a sum is included in cst.
+                None if matches!(factor.kind, FactorKind::ADD { .. }) => &zero,
+                None => &cst,
+            };
             let skip_res = if factor.has_res { 1 } else { 0 };
             let mut ops = factor
                 .edges
@@ -280,16 +396,51 @@ impl FactorGraph {
                     ops.zip(vars_neg.iter().cloned())
                         .chain(std::iter::once((&cst, false))),
                 ),
-                FactorKind::XOR | FactorKind::ADD | FactorKind::MUL => self.merge_pubs(
+                FactorKind::ADD { coefs } => {
+                    let nc = self.nc as u64;
+                    let terms: Vec<PublicValue> = ops
+                        .zip(coefs.iter().skip(skip_res))
+                        .map(|(x, c)| x.map(|x| ((x as u64 * *c as u64) % nc) as ClassVal))
+                        .collect();
+                    self.merge_pubs(
+                        &factor.kind,
+                        terms
+                            .iter()
+                            .map(|t| (t, false))
+                            .chain(std::iter::once((&cst, false))),
+                    )
+                }
+                FactorKind::XOR | FactorKind::MUL | FactorKind::GFMUL => self.merge_pubs(
                     &factor.kind,
                     ops.zip(std::iter::repeat(false))
                         .chain(std::iter::once((&cst, false))),
                 ),
+                FactorKind::HW => self
+                    .merge_pubs(
+                        &factor.kind,
+                        ops.zip(std::iter::repeat(false))
+                            .chain(std::iter::once((&cst, false))),
+                    )
+                    .map(ClassVal::count_ones),
                 FactorKind::NOT => ops.next().unwrap().map(|x| self.not(x)),
-                FactorKind::LOOKUP { table } => ops
-                    .next()
-                    .unwrap()
-                    .map(|x| self.tables[*table].values[x as usize]),
+                FactorKind::LOOKUP { table } => {
+                    let values = &self.tables[*table].values;
+                    let x = ops.next().unwrap();
+                    if let Some(y) = ops.next() {
+                        x.map2(y, |x, y| values[x as usize * self.nc + y as usize])
+                    } else {
+                        x.map(|x| values[x as usize])
+                    }
+                }
+                FactorKind::BITS => {
+                    let mut weight = 1;
+                    let mut res = PublicValue::Single(0);
+                    for (v_id, x) in factor.edges.keys().skip(1).zip(ops) {
+                        res = res.map2(x, |r, x| r + x * weight);
+                        weight *= self.var(*v_id).nc as ClassVal;
+                    }
+                    res
+                }
             };
             if &res != expected_res {
                 return Err(FGError::CheckFail(
@@ -301,13 +452,78 @@ impl FactorGraph {
         }
         Ok(())
     }
+    /// Is the constraint of the factor satisfied by the values of its variables (in the order of
+    /// its edges), where cst is the reduced public value of the factor (see reduce_pub) for the
+    /// execution.
+    pub(super) fn factor_holds(&self, factor: &Factor, values: &[ClassVal], cst: ClassVal) -> bool {
+        let skip_res = usize::from(factor.has_res);
+        // Result of the operation, and the fold of the operands with the constant (a constant
+        // is an operand if the result is a variable, and the result otherwise).
+        let res = || if factor.has_res { values[0] } else { cst };
+        let fold_ops = |ops: &mut dyn Iterator<Item = ClassVal>| {
+            let init = if factor.has_res {
+                cst
+            } else {
+                factor.kind.neutral(self.nc)
+            };
+            ops.fold(init, |acc, x| {
+                factor.kind.merge(acc, x, self.nc, self.field.as_ref())
+            })
+        };
+        match &factor.kind {
+            FactorKind::AND { vars_neg } => {
+                let x = |i: usize| {
+                    if vars_neg[i] {
+                        self.not(values[i])
+                    } else {
+                        values[i]
+                    }
+                };
+                let res = if factor.has_res { x(0) } else { cst };
+                fold_ops(&mut (skip_res..values.len()).map(x)) == res
+            }
+            FactorKind::XOR | FactorKind::MUL | FactorKind::GFMUL => {
+                fold_ops(&mut values[skip_res..].iter().cloned()) == res()
+            }
+            FactorKind::HW => {
+                fold_ops(&mut values[skip_res..].iter().cloned()).count_ones() == res()
+            }
+            FactorKind::ADD { coefs } => {
+                let nc = self.nc as u64;
+                let sum = values
+                    .iter()
+                    .zip(coefs.iter())
+                    .fold(cst as u64, |acc, (x, c)| (acc + *x as u64 * *c as u64) % nc);
+                sum == 0
+            }
+            FactorKind::NOT => values[0] == self.not(values[1]),
+            FactorKind::LOOKUP { table } => {
+                let values_t = &self.tables[*table].values;
+                let idx = values[1..]
+                    .iter()
+                    .fold(0, |acc, x| acc * self.nc + *x as usize);
+                values[0] == values_t[idx]
+            }
+            FactorKind::BITS => {
+                let mut weight = 1;
+                let mut res = 0;
+                for (v_id, x) in factor.edges.keys().skip(1).zip(values[1..].iter()) {
+                    res += x * weight;
+                    weight *= self.var(*v_id).nc as ClassVal;
+                }
+                values[0] == res
+            }
+        }
+    }
     pub(super) fn reduce_pub(&self, public_values: &[PublicValue]) -> FactorVec<PublicValue> {
         self.factors
             .values()
             .map(|factor| {
                 match &factor.kind {
                     // Not used
-                    FactorKind::NOT | FactorKind::LOOKUP { .. } => PublicValue::Single(0),
+                    FactorKind::NOT | FactorKind::LOOKUP { .. } | FactorKind::BITS => {
+                        PublicValue::Single(0)
+                    }
                     _ => self.merge_pubs(
                         &factor.kind,
                         factor
@@ -330,7 +546,16 @@ impl FactorGraph {
     ) -> PublicValue {
         let merge_inner = |p1: PublicValue, (p2, nv2): (&PublicValue, bool)| {
             let f = |x: ClassVal, y: ClassVal| {
-                factor_kind.merge(x, if nv2 { self.not(y) } else { y }, self.nc)
+                factor_kind.merge(
+                    x,
+                    if nv2 {
+                        factor_kind.inverse(y, self.nc)
+                    } else {
+                        y
+                    },
+                    self.nc,
+                    self.field.as_ref(),
+                )
             };
             match (p1, p2) {
                 (PublicValue::Single(c1), PublicValue::Single(c2)) => {
@@ -364,24 +589,47 @@ impl FactorGraph {
             return true;
         }
         if multi_exec {
-            return petgraph::algo::kosaraju_scc(&self.petgraph)
-                .into_iter()
-                .any(|scc| {
-                    scc.into_iter()
-                        .filter(|n| match self.petgraph[*n] {
-                            Node::Var(var_id) => {
-                                !self.vars.get_index(var_id.index()).unwrap().1.multi
-                            }
-                            Node::Factor(_) => false,
-                        })
-                        .count()
-                        > 1
-                });
+            // The MULTI part of the graph is repeated for each execution, which closes a
+            // cycle if it links several SINGLE vars.
+            return self
+                .multi_components_singles()
+                .iter()
+                .any(|singles| singles.len() > 1);
         } else {
             return false;
         }
     }
 
+    /// For each connected component of the MULTI vars and factors, the SINGLE vars that are
+    /// adjacent to it.
+    pub(super) fn multi_components_singles(&self) -> Vec<Vec<VarId>> {
+        use petgraph::visit::EdgeRef;
+        let is_multi = |n: petgraph::graph::NodeIndex| match self.petgraph[n] {
+            Node::Var(var) => self.var_multi(var),
+            Node::Factor(factor) => self.factor_multi(factor),
+        };
+        let mut components = petgraph::unionfind::UnionFind::new(self.petgraph.node_count());
+        for e in self.petgraph.edge_references() {
+            if is_multi(e.source()) && is_multi(e.target()) {
+                components.union(e.source().index(), e.target().index());
+            }
+        }
+        let mut singles: std::collections::BTreeMap<usize, Vec<VarId>> = Default::default();
+        for e in self.petgraph.edge_references() {
+            for (m, s) in [(e.source(), e.target()), (e.target(), e.source())] {
+                if let (true, Node::Var(var)) = (is_multi(m), self.petgraph[s]) {
+                    if !self.var_multi(var) {
+                        let entry = singles.entry(components.find(m.index())).or_default();
+                        if !entry.contains(&var) {
+                            entry.push(var);
+                        }
+                    }
+                }
+            }
+        }
+        singles.into_values().collect()
+    }
+
     pub(super) fn propagation_order(&self, var: VarId) -> Vec<(Node, Option<Node>)> {
         let mut propagations = vec![(Node::Var(var), None)];
         petgraph::visit::depth_first_search(&self.petgraph, [self.var_graph_ids[var]], |event| {
@@ -3,6 +3,7 @@ use super::factor_graph::{FactorId, FactorVec, Node, VarVec};
 use super::fg_parser;
 use super::{ClassVal, NamedList, VarId};
 use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
 #[derive(Debug, Clone, Error)]
@@ -21,8 +22,12 @@ pub enum GraphBuildError {
     RepeatedOperand(String, String),
     #[error("Constants appears as both result and operand in factor {0}.")]
     CstOpRes(String),
-    #[error("Wrong length {1} for table {0}.")]
+    #[error("Wrong length {1} for table {0} (must be NC or NC*NC).")]
     TableSize(String, usize),
+    #[error("Table {0} has {1} inputs, but is used with {2} in factor {3}.")]
+    TableArity(String, usize, usize, String),
+    #[error("Public {1} is not allowed in lookup factor {0}.")]
+    LookupPublic(String, String),
     #[error("Wrong value {1} for table {0}.")]
     TableValue(String, ClassVal),
     #[error("Value of table {0} not given.")]
@@ -31,12 +36,80 @@ pub enum GraphBuildError {
     MultipleNc,
     #[error("NC not given.")]
     NoNc,
+    #[error("FIELD given more than once.")]
+    MultipleField,
+    #[error("FIELD polynomial {0:#x} is not an irreducible polynomial of degree log2(NC).")]
+    InvalidField(u64),
+    #[error("GF multiplication in factor {0} requires a FIELD declaration.")]
+    NoField(String),
+    #[error("Result of GF multiplication in factor {0} must be a variable.")]
+    GfMulPubRes(String),
+    #[error("Coefficient of {1} is zero modulo NC in factor {0}.")]
+    ZeroCoef(String, String),
+    #[error("Public {1} must have a coefficient 1 or -1 in factor {0}.")]
+    PubCoef(String, String),
+    #[error("Wrong NC {1} for variable {0} (must be at least 2).")]
+    VarNc(String, u64),
+    #[error("Variable {1} has NC {2} in factor {0}, expected {3}.")]
+    FactorVarNc(String, String, usize, usize),
+    #[error("Public {1} is not allowed in BITS factor {0}.")]
+    BitsPublic(String, String),
+    #[error("Product of the NCs of the parts in BITS factor {0} is {1}, expected {2}.")]
+    BitsSize(String, usize, usize),
+    #[error("Result of HW/HD factor {0} cannot be a public.")]
+    HwPubRes(String),
+    #[error("HW/HD factor {0} must have a variable operand.")]
+    HwPubOperands(String),
+    #[error("Variable {1} has NC {2} in HW/HD factor {0}, must be at least {3}.")]
+    HwNc(String, String, usize, usize),
+    #[error("HW/HD factor {0} requires a power of two NC, got {1}.")]
+    HwNcPow2(String, usize),
+    #[error("GRAPH {0} declared multiple times.")]
+    MultipleGraphDecl(String),
+    #[error("GRAPH {0} not declared.")]
+    UnknownGraph(String),
+    #[error("Parameter {1} appears multiple times in GRAPH {0}.")]
+    RepeatedParam(String, String),
+    #[error("GRAPH {0} has no matching END.")]
+    UnclosedGraph(String),
+    #[error("END without matching GRAPH.")]
+    UnmatchedEnd,
+    #[error("NC, FIELD, TABLE and GRAPH statements are not allowed in the body of GRAPH {0}.")]
+    GraphStatement(String),
+    #[error("GRAPH {0} is instantiated recursively.")]
+    RecursiveGraph(String),
+    #[error("GRAPH {0} has {1} parameters, but is instantiated with {2} arguments.")]
+    GraphArity(String, usize, usize),
+    #[error("Parameter {1} of GRAPH {0} is an array of length {2}, the argument must be a range of that length.")]
+    GraphArgLen(String, String, u64),
+    #[error("Ranges given for the scalar parameters of GRAPH {0} have different lengths.")]
+    BroadcastLen(String),
+    #[error("Range {0} is empty.")]
+    EmptyRange(String),
+    #[error("Parameter {1} of GRAPH {0} is used with a wrong index.")]
+    ParamIndex(String, String),
+    #[error("Line {0}: {1}")]
+    AtLine(usize, Box<GraphBuildError>),
+    #[error("In GRAPH instance {0}, line {1}: {2}")]
+    InGraph(String, usize, Box<GraphBuildError>),
+    #[error("Netlist line {0}: {1}")]
+    Netlist(usize, String),
+    #[error("Wire or bus {0} not found in the netlist.")]
+    UnknownWire(String),
+    #[error("Wire {0} is driven by multiple gates in the netlist (line {1}).")]
+    MultipleDrivers(String, usize),
+    #[error("Bits of bus {0} are not numbered contiguously from 0.")]
+    BusBits(String),
+    #[error("Bus {0} has {1} bits, at most {2} are supported.")]
+    BusWidth(String, usize, u32),
     #[error("Could not parse graph description.\n{0}")]
     Parse(String),
+    #[error("Name {0} given before any property.")]
+    NoPropertyToName(String),
 }
 
 impl fg::FactorGraph {
-    fn build(nc: usize) -> Self {
+    pub(super) fn build(nc: usize) -> Self {
         Self {
             nc,
             vars: NamedList::new(),
@@ -44,9 +117,11 @@ impl fg::FactorGraph {
             edges: fg::EdgeVec::new(),
             publics: NamedList::new(),
             tables: NamedList::new(),
+            field: None,
             petgraph: petgraph::Graph::new_undirected(),
             var_graph_ids: VarVec::new(),
             factor_graph_ids: FactorVec::new(),
+            eliminated: NamedList::new(),
         }
     }
     fn check_new_var(&self, name: &String) -> Result<(), GraphBuildError> {
@@ -55,20 +130,33 @@ impl fg::FactorGraph {
         }
         Ok(())
     }
-    fn add_var(&mut self, name: String, multi: bool) -> Result<(), GraphBuildError> {
+    pub(super) fn add_var(
+        &mut self,
+        name: String,
+        multi: bool,
+        nc: Option<u64>,
+    ) -> Result<(), GraphBuildError> {
         self.check_new_var(&name)?;
+        let nc = match nc {
+            Some(nc) if nc < 2 || nc > (ClassVal::MAX as u64) => {
+                return Err(GraphBuildError::VarNc(name, nc));
+            }
+            Some(nc) => nc as usize,
+            None => self.nc,
+        };
         let (var_idx, _) = self.vars.insert_full(
             name,
             fg::Var {
                 multi,
                 edges: IndexMap::new(),
+                nc,
             },
         );
         self.var_graph_ids
             .push(self.petgraph.add_node(Node::Var(VarId::from_idx(var_idx))));
         Ok(())
     }
-    fn add_pub(&mut self, name: String, multi: bool) -> Result<(), GraphBuildError> {
+    pub(super) fn add_pub(&mut self, name: String, multi: bool) -> Result<(), GraphBuildError> {
         self.check_new_var(&name)?;
         self.publics.insert(name, fg::Public { multi });
         Ok(())
@@ -77,15 +165,19 @@ impl fg::FactorGraph {
         if self.tables.contains_key(&name) {
             return Err(GraphBuildError::MultipleTableDecl(name));
         }
-        if values.len() != self.nc {
+        let ninputs = if values.len() == self.nc {
+            1
+        } else if values.len() == self.nc * self.nc {
+            2
+        } else {
             return Err(GraphBuildError::TableSize(name, values.len()));
-        }
+        };
         for v in values.iter() {
             if (*v as usize) >= self.nc {
                 return Err(GraphBuildError::TableValue(name, *v));
             }
         }
-        self.tables.insert(name, fg::Table { values });
+        self.tables.insert(name, fg::Table { values, ninputs });
         Ok(())
     }
     fn add_factor<'a>(
@@ -105,6 +197,16 @@ impl fg::FactorGraph {
         let mut has_res = None;
         let mut is_var = Vec::new();
         for (i, var) in vars.enumerate() {
+            if let fg::FactorKind::ADD { coefs } = &kind {
+                if coefs[i] == 0 {
+                    return Err(GraphBuildError::ZeroCoef(name, var.to_owned()));
+                } else if self.publics.contains_key(var)
+                    && coefs[i] != 1
+                    && coefs[i] as usize != self.nc - 1
+                {
+                    return Err(GraphBuildError::PubCoef(name, var.to_owned()));
+                }
+            }
             if let Some((var_id, _, v)) = self.vars.get_full_mut(var) {
                 has_res = Some(has_res.unwrap_or(true));
                 is_var.push(true);
@@ -122,6 +224,12 @@ impl fg::FactorGraph {
                 });
                 multi |= v.multi;
             } else if let Some((pub_id, _, public)) = self.publics.get_full(var) {
+                if let fg::FactorKind::LOOKUP { .. } = kind {
+                    return Err(GraphBuildError::LookupPublic(name, var.to_owned()));
+                }
+                if let fg::FactorKind::BITS = kind {
+                    return Err(GraphBuildError::BitsPublic(name, var.to_owned()));
+                }
                 if has_res == Some(false) {
                     return Err(GraphBuildError::CstOpRes(name));
                 }
@@ -133,19 +241,73 @@ impl fg::FactorGraph {
                 return Err(GraphBuildError::UnknownVar(var.to_owned()));
             }
         }
-        let kind = kind
-            .map_vars_neg(|vn| {
-                vn.into_iter()
-                    .zip(is_var.into_iter())
-                    .filter(|(_, iv)| *iv)
-                    .map(|(vn, _)| vn)
-                    .collect()
-            })
-            .map_table(|t| {
-                self.tables
-                    .get_index_of(t)
-                    .ok_or_else(|| GraphBuildError::UnknownTable(t.to_owned()))
-            })?;
+        let nb_operands = is_var.len() - 1;
+        let kind = kind.filter_vars(is_var.as_slice()).map_table(|t| {
+            self.tables
+                .get_index_of(t)
+                .ok_or_else(|| GraphBuildError::UnknownTable(t.to_owned()))
+        })?;
+        if let fg::FactorKind::LOOKUP { table } = kind {
+            let ninputs = self.tables[table].ninputs;
+            if ninputs != nb_operands {
+                return Err(GraphBuildError::TableArity(
+                    self.tables.get_index(table).unwrap().0.clone(),
+                    ninputs,
+                    nb_operands,
+                    name,
+                ));
+            }
+        }
+        if let fg::FactorKind::HW = kind {
+            if has_res == Some(false) {
+                return Err(GraphBuildError::HwPubRes(name));
+            } else if edges.len() < 2 {
+                return Err(GraphBuildError::HwPubOperands(name));
+            } else if !self.nc.is_power_of_two() {
+                // Operands are bit vectors, and HD XORs them.
+                return Err(GraphBuildError::HwNcPow2(name, self.nc));
+            }
+            let (res, _) = edges.get_index(0).unwrap();
+            let nc = self.vars[res.idx()].nc;
+            let min_nc = (0..self.nc).map(|x| x.count_ones() as usize).max().unwrap() + 1;
+            if nc < min_nc {
+                return Err(GraphBuildError::HwNc(
+                    name,
+                    self.vars.get_index(res.idx()).unwrap().0.clone(),
+                    nc,
+                    min_nc,
+                ));
+            }
+        }
+        if let fg::FactorKind::BITS = kind {
+            let var_nc = |v: &VarId| self.vars[v.idx()].nc;
+            let nc_parts: usize = edges.keys().skip(1).map(var_nc).product();
+            let nc_whole = var_nc(edges.get_index(0).unwrap().0);
+            if nc_parts != nc_whole {
+                return Err(GraphBuildError::BitsSize(name, nc_parts, nc_whole));
+            }
+        } else {
+            // The result of HW has its own domain.
+            let skip_res = usize::from(matches!(kind, fg::FactorKind::HW));
+            for var_id in edges.keys().skip(skip_res) {
+                let nc = self.vars[var_id.idx()].nc;
+                if nc != self.nc {
+                    return Err(GraphBuildError::FactorVarNc(
+                        name,
+                        self.vars.get_index(var_id.idx()).unwrap().0.clone(),
+                        nc,
+                        self.nc,
+                    ));
+                }
+            }
+        }
+        if matches!(kind, fg::FactorKind::GFMUL) {
+            if self.field.is_none() {
+                return Err(GraphBuildError::NoField(name));
+            } else if has_res == Some(false) {
+                return Err(GraphBuildError::GfMulPubRes(name));
+            }
+        }
         let factor = fg::Factor {
             kind,
             multi,
@@ -158,7 +320,44 @@ impl fg::FactorGraph {
             .push(self.petgraph.add_node(Node::Factor(factor_id)));
         Ok(())
     }
-    fn add_graph_edges(&mut self) {
+    /// Add a factor whose operands are already resolved (the kind is filtered to the vars, see
+    /// filter_vars).
+    pub(super) fn push_factor(
+        &mut self,
+        name: String,
+        kind: fg::FactorKind,
+        vars: &[VarId],
+        has_res: bool,
+        publics: Vec<(fg::PublicId, bool)>,
+    ) {
+        let factor_id = FactorId::from_idx(self.factors.len());
+        let mut edges = IndexMap::new();
+        for var_id in vars {
+            let edge_id = fg::EdgeId::from_idx(self.edges.len());
+            let v = &mut self.vars[var_id.idx()];
+            v.edges.insert(factor_id, edge_id);
+            edges.insert(*var_id, edge_id);
+            self.edges.push(fg::Edge {
+                var: *var_id,
+                pos_var: v.edges.len() - 1,
+                factor: factor_id,
+                pos_factor: edges.len() - 1,
+            });
+        }
+        let multi = vars.iter().any(|v| self.vars[v.idx()].multi)
+            || publics.iter().any(|(p, _)| self.publics[*p].multi);
+        let factor = fg::Factor {
+            kind,
+            multi,
+            edges,
+            has_res,
+            publics,
+        };
+        self.factors.insert(name, factor);
+        self.factor_graph_ids
+            .push(self.petgraph.add_node(Node::Factor(factor_id)));
+    }
+    pub(super) fn add_graph_edges(&mut self) {
         for (i, e) in self.edges.iter_enumerated() {
             self.petgraph.add_edge(
                 self.var_graph_ids[e.var],
@@ -177,18 +376,29 @@ impl<T> fg::FactorKind<T> {
             fg::FactorKind::AND { vars_neg } => fg::FactorKind::AND { vars_neg },
             fg::FactorKind::XOR => fg::FactorKind::XOR,
             fg::FactorKind::NOT => fg::FactorKind::NOT,
-            fg::FactorKind::ADD => fg::FactorKind::ADD,
+            fg::FactorKind::ADD { coefs } => fg::FactorKind::ADD { coefs },
             fg::FactorKind::MUL => fg::FactorKind::MUL,
+            fg::FactorKind::GFMUL => fg::FactorKind::GFMUL,
+            fg::FactorKind::BITS => fg::FactorKind::BITS,
+            fg::FactorKind::HW => fg::FactorKind::HW,
             fg::FactorKind::LOOKUP { table } => fg::FactorKind::LOOKUP { table: f(table)? },
         })
     }
-    fn map_vars_neg<F>(self, f: F) -> Self
-    where
-        F: FnOnce(Vec<bool>) -> Vec<bool>,
-    {
+    /// Keep only the per-operand attributes of variables (i.e., drop those of publics).
+    fn filter_vars(self, is_var: &[bool]) -> Self {
+        fn filter<X>(x: Vec<X>, is_var: &[bool]) -> Vec<X> {
+            x.into_iter()
+                .zip(is_var.iter())
+                .filter(|(_, iv)| **iv)
+                .map(|(x, _)| x)
+                .collect()
+        }
         match self {
             fg::FactorKind::AND { vars_neg } => fg::FactorKind::AND {
-                vars_neg: f(vars_neg),
+                vars_neg: filter(vars_neg, is_var),
+            },
+            fg::FactorKind::ADD { coefs } => fg::FactorKind::ADD {
+                coefs: filter(coefs, is_var),
             },
             x => x,
         }
@@ -196,12 +406,14 @@ impl<T> fg::FactorKind<T> {
     fn get_neg(&self, i: usize) -> bool {
         match self {
             fg::FactorKind::AND { vars_neg } => vars_neg[i],
+            // Coefficients of publics are 1 or -1.
+            fg::FactorKind::ADD { coefs } => coefs[i] != 1,
             _ => false,
         }
     }
 }
 impl fg_parser::Expr {
-    fn as_factor_kind(&self) -> fg::FactorKind<&str> {
+    fn as_factor_kind(&self, nc: usize) -> fg::FactorKind<&str> {
         fn get_neg(vars: &Vec<fg_parser::NVar>, neg: bool) -> Vec<bool> {
             // Include the result of the operation
             std::iter::once(neg)
@@ -213,8 +425,21 @@ impl fg_parser::Expr {
             Self::Lookup { table, .. } => fg::FactorKind::LOOKUP {
                 table: table.as_str(),
             },
-            Self::Add(_) => fg::FactorKind::ADD,
+            // The sum is re-written as -res + sum(terms) = 0.
+            Self::Add(terms) => fg::FactorKind::ADD {
+                coefs: std::iter::once((nc - 1) as ClassVal)
+                    .chain(terms.iter().map(|t| {
+                        let c = t.coef % (nc as u64);
+                        (if t.neg {
+                            (nc as u64 - c) % (nc as u64)
+                        } else {
+                            c
+                        }) as ClassVal
+                    }))
+                    .collect(),
+            },
             Self::Mul(_) => fg::FactorKind::MUL,
+            Self::GfMul(_) => fg::FactorKind::GFMUL,
             Self::Xor(_) => fg::FactorKind::XOR,
             Self::And(vars) => fg::FactorKind::AND {
                 vars_neg: get_neg(vars, false),
@@ -223,30 +448,391 @@ impl fg_parser::Expr {
             Self::Or(vars) => fg::FactorKind::AND {
                 vars_neg: get_neg(vars, true),
             },
+            Self::Bits(_) => fg::FactorKind::BITS,
+            Self::Hw(_) => fg::FactorKind::HW,
+        }
+    }
+    fn vars_mut(&mut self) -> Vec<&mut fg_parser::Var> {
+        match self {
+            Self::Not(var) => vec![var],
+            Self::Lookup { vars: v, .. }
+            | Self::Xor(v)
+            | Self::Mul(v)
+            | Self::GfMul(v)
+            | Self::Bits(v)
+            | Self::Hw(v) => v.iter_mut().collect(),
+            Self::Add(v) => v.iter_mut().map(|t| &mut t.var).collect(),
+            Self::And(v) | Self::Or(v) => v.iter_mut().map(|v| &mut v.var).collect(),
         }
     }
     fn vars(&self) -> impl Iterator<Item = &str> {
         match self {
-            Self::Not(var) | Self::Lookup { var, .. } => vec![var.0.as_str()],
-            Self::Xor(v) | Self::Add(v) | Self::Mul(v) => v.iter().map(|v| v.0.as_str()).collect(),
+            Self::Not(var) => vec![var.0.as_str()],
+            Self::Lookup { vars, .. } => vars.iter().map(|v| v.0.as_str()).collect(),
+            Self::Xor(v) | Self::Mul(v) | Self::GfMul(v) | Self::Bits(v) | Self::Hw(v) => {
+                v.iter().map(|v| v.0.as_str()).collect()
+            }
+            Self::Add(v) => v.iter().map(|t| t.var.0.as_str()).collect(),
             Self::And(v) | Self::Or(v) => v.iter().map(|v| v.var.0.as_str()).collect(),
         }
         .into_iter()
     }
 }
+
+/// A statement of the expanded description, with the GRAPH instance it comes from (if any)
+/// and its line (in the template for an instance).
+type Located = (fg_parser::Statement, (Option<String>, usize));
+
+fn locate((instance, line): &(Option<String>, usize), e: GraphBuildError) -> GraphBuildError {
+    match instance {
+        Some(instance) => GraphBuildError::InGraph(instance.clone(), *line, Box::new(e)),
+        None => GraphBuildError::AtLine(*line, Box::new(e)),
+    }
+}
+
+/// Split x[i] into x and i.
+pub(super) fn split_index(name: &str) -> (&str, Option<u64>) {
+    name.strip_suffix(']')
+        .and_then(|n| n.split_once('['))
+        .and_then(|(base, idx)| Some((base, Some(idx.parse().ok()?))))
+        .unwrap_or((name, None))
+}
+
+/// Names declared by a VAR or PUB statement.
+fn decl_names(vd: &fg_parser::VarDecl) -> Vec<String> {
+    match vd.len {
+        Some(len) => (0..len).map(|i| format!("{}[{}]", vd.name.0, i)).collect(),
+        None => vec![vd.name.0.clone()],
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Template<'a> {
+    params: &'a [fg_parser::Param],
+    body: &'a [(fg_parser::Statement, usize)],
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    Var(String),
+    Array(Vec<String>),
+}
+
+/// Names of a GRAPH instance (or of the top-level description).
+#[derive(Debug)]
+struct Scope<'a> {
+    graph: &'a str,
+    /// Full name of the instance, empty at top-level. Prefix of the local names.
+    instance: String,
+    params: HashMap<&'a str, Binding>,
+    locals: HashSet<&'a str>,
+    /// Number of unlabelled instances of each GRAPH, for default labels.
+    counts: HashMap<&'a str, usize>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(graph: &'a str, instance: String, params: HashMap<&'a str, Binding>) -> Self {
+        Self {
+            graph,
+            instance,
+            params,
+            locals: HashSet::new(),
+            counts: HashMap::new(),
+        }
+    }
+    fn local(&self, name: &str) -> String {
+        format!("{}.{}", self.instance, name)
+    }
+    /// Name in the graph of the variable name used in this scope.
+    fn resolve(&self, name: &str) -> Result<String, GraphBuildError> {
+        let (base, idx) = split_index(name);
+        match (self.params.get(base), idx) {
+            (Some(Binding::Var(v)), None) => Ok(v.clone()),
+            (Some(Binding::Array(a)), Some(i)) if (i as usize) < a.len() => {
+                Ok(a[i as usize].clone())
+            }
+            (Some(_), _) => Err(GraphBuildError::ParamIndex(
+                self.graph.to_owned(),
+                base.to_owned(),
+            )),
+            (None, _) if self.locals.contains(base) => Ok(self.local(name)),
+            (None, _) => Ok(name.to_owned()),
+        }
+    }
+    fn locate(&self, line: usize, e: GraphBuildError) -> GraphBuildError {
+        if self.instance.is_empty() {
+            GraphBuildError::AtLine(line, Box::new(e))
+        } else {
+            GraphBuildError::InGraph(self.instance.clone(), line, Box::new(e))
+        }
+    }
+    /// Rename the declarations and variables of a statement of a GRAPH body.
+    fn rename(&self, s: &fg_parser::Statement) -> Result<fg_parser::Statement, GraphBuildError> {
+        let mut s = s.clone();
+        match &mut s {
+            fg_parser::Statement::VarDecl(vd) | fg_parser::Statement::PubDecl(vd) => {
+                vd.name.0 = self.local(&vd.name.0);
+            }
+            fg_parser::Statement::Property { name, dest, expr } => {
+                if let Some(name) = name {
+                    *name = self.local(name);
+                }
+                for var in std::iter::once(dest).chain(expr.vars_mut()) {
+                    var.0 = self.resolve(&var.0)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(s)
+    }
+}
+
+/// Expansion of the GRAPH instances of a description into plain statements.
+struct Expander<'a> {
+    templates: HashMap<&'a str, Template<'a>>,
+    /// Statements outside of GRAPH bodies.
+    top_level: Vec<&'a (fg_parser::Statement, usize)>,
+    /// GRAPHs being instantiated.
+    stack: Vec<&'a str>,
+    out: Vec<Located>,
+}
+
+impl<'a> Expander<'a> {
+    /// Collect the GRAPH templates and the top-level statements.
+    fn new(stmts: &'a [(fg_parser::Statement, usize)]) -> Result<Self, GraphBuildError> {
+        let mut templates = HashMap::new();
+        let mut top_level = Vec::new();
+        let mut i = 0;
+        while i < stmts.len() {
+            let (s, line) = &stmts[i];
+            let at_line = |e| GraphBuildError::AtLine(*line, Box::new(e));
+            match s {
+                fg_parser::Statement::GraphDecl { name, params } => {
+                    let len = stmts[i + 1..]
+                        .iter()
+                        .position(|(s, _)| {
+                            matches!(
+                                s,
+                                fg_parser::Statement::End | fg_parser::Statement::GraphDecl { .. }
+                            )
+                        })
+                        .filter(|j| matches!(stmts[i + 1 + j].0, fg_parser::Statement::End))
+                        .ok_or_else(|| at_line(GraphBuildError::UnclosedGraph(name.clone())))?;
+                    let body = &stmts[i + 1..i + 1 + len];
+                    for (s, line) in body {
+                        if let fg_parser::Statement::NC(_)
+                        | fg_parser::Statement::Field(_)
+                        | fg_parser::Statement::TableDecl { .. } = s
+                        {
+                            return Err(GraphBuildError::AtLine(
+                                *line,
+                                Box::new(GraphBuildError::GraphStatement(name.clone())),
+                            ));
+                        }
+                    }
+                    for (j, p) in params.iter().enumerate() {
+                        if params[..j].iter().any(|p2| p2.name == p.name) {
+                            return Err(at_line(GraphBuildError::RepeatedParam(
+                                name.clone(),
+                                p.name.clone(),
+                            )));
+                        }
+                    }
+                    if templates
+                        .insert(name.as_str(), Template { params, body })
+                        .is_some()
+                    {
+                        return Err(at_line(GraphBuildError::MultipleGraphDecl(name.clone())));
+                    }
+                    i += len + 2;
+                }
+                fg_parser::Statement::End => {
+                    return Err(at_line(GraphBuildError::UnmatchedEnd));
+                }
+                _ => {
+                    top_level.push(&stmts[i]);
+                    i += 1;
+                }
+            }
+        }
+        Ok(Self {
+            templates,
+            top_level,
+            stack: Vec::new(),
+            out: Vec::new(),
+        })
+    }
+    fn expand(stmts: &'a [(fg_parser::Statement, usize)]) -> Result<Vec<Located>, GraphBuildError> {
+        let mut expander = Self::new(stmts)?;
+        let mut scope = Scope::new("", String::new(), HashMap::new());
+        for (s, line) in std::mem::take(&mut expander.top_level) {
+            if let fg_parser::Statement::Instance { label, graph, args } = s {
+                expander.instantiate(&mut scope, label.as_ref(), graph, args, *line)?;
+            } else {
+                expander.out.push((s.clone(), (None, *line)));
+            }
+        }
+        Ok(expander.out)
+    }
+    fn instantiate(
+        &mut self,
+        caller: &mut Scope<'a>,
+        label: Option<&String>,
+        graph: &'a str,
+        args: &'a [fg_parser::Arg],
+        line: usize,
+    ) -> Result<(), GraphBuildError> {
+        let label = label.cloned().unwrap_or_else(|| {
+            let count = caller.counts.entry(graph).or_insert(0);
+            *count += 1;
+            format!("{}_{}", graph, *count - 1)
+        });
+        let scopes = self
+            .bind(caller, &label, graph, args)
+            .map_err(|e| caller.locate(line, e))?;
+        let body = self.templates[graph].body;
+        self.stack.push(graph);
+        for mut scope in scopes {
+            for (s, line) in body {
+                match s {
+                    fg_parser::Statement::Instance { label, graph, args } => {
+                        self.instantiate(&mut scope, label.as_ref(), graph, args, *line)?;
+                    }
+                    s => {
+                        let s = scope.rename(s).map_err(|e| scope.locate(*line, e))?;
+                        self.out.push((s, (Some(scope.instance.clone()), *line)));
+                    }
+                }
+            }
+        }
+        self.stack.pop();
+        Ok(())
+    }
+    /// Bind the arguments to the parameters of the instance, which yields one scope per
+    /// instance when ranges are given for scalar parameters.
+    fn bind(
+        &self,
+        caller: &Scope<'a>,
+        label: &str,
+        graph: &'a str,
+        args: &'a [fg_parser::Arg],
+    ) -> Result<Vec<Scope<'a>>, GraphBuildError> {
+        let template = self
+            .templates
+            .get(graph)
+            .ok_or_else(|| GraphBuildError::UnknownGraph(graph.to_owned()))?;
+        if self.stack.contains(&graph) {
+            return Err(GraphBuildError::RecursiveGraph(graph.to_owned()));
+        }
+        if template.params.len() != args.len() {
+            return Err(GraphBuildError::GraphArity(
+                graph.to_owned(),
+                template.params.len(),
+                args.len(),
+            ));
+        }
+        let mut n_instances = None;
+        let mut values = Vec::with_capacity(args.len());
+        for (param, arg) in template.params.iter().zip(args.iter()) {
+            let value = match arg {
+                fg_parser::Arg::Var(var) => Binding::Var(caller.resolve(&var.0)?),
+                fg_parser::Arg::Range { name, start, end } => {
+                    if start >= end {
+                        return Err(GraphBuildError::EmptyRange(format!(
+                            "{}[{}..{}]",
+                            name, start, end
+                        )));
+                    }
+                    Binding::Array(
+                        (*start..*end)
+                            .map(|i| caller.resolve(&format!("{}[{}]", name, i)))
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+            };
+            match (param.len, &value) {
+                (Some(len), Binding::Array(a)) if a.len() as u64 == len => {}
+                (Some(len), _) => {
+                    return Err(GraphBuildError::GraphArgLen(
+                        graph.to_owned(),
+                        param.name.clone(),
+                        len,
+                    ));
+                }
+                (None, Binding::Array(a)) => {
+                    if *n_instances.get_or_insert(a.len()) != a.len() {
+                        return Err(GraphBuildError::BroadcastLen(graph.to_owned()));
+                    }
+                }
+                (None, Binding::Var(_)) => {}
+            }
+            values.push(value);
+        }
+        let instance = if caller.instance.is_empty() {
+            label.to_owned()
+        } else {
+            caller.local(label)
+        };
+        let locals = template
+            .body
+            .iter()
+            .filter_map(|(s, _)| match s {
+                fg_parser::Statement::VarDecl(vd) | fg_parser::Statement::PubDecl(vd) => {
+                    Some(vd.name.0.as_str())
+                }
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let scope = |instance, j: Option<usize>| {
+            let params = template
+                .params
+                .iter()
+                .zip(values.iter())
+                .map(|(p, v)| {
+                    let v = match (p.len, v, j) {
+                        (None, Binding::Array(a), Some(j)) => Binding::Var(a[j].clone()),
+                        (_, v, _) => v.clone(),
+                    };
+                    (p.name.as_str(), v)
+                })
+                .collect();
+            let mut scope = Scope::new(graph, instance, params);
+            scope.locals = locals.clone();
+            scope
+        };
+        Ok(match n_instances {
+            // Ranges given for scalar parameters: one instance per element.
+            Some(n) => (0..n)
+                .map(|j| scope(format!("{}[{}]", instance, j), Some(j)))
+                .collect(),
+            None => vec![scope(instance, None)],
+        })
+    }
+}
+
 pub(super) fn build_graph(
-    stmts: &[fg_parser::Statement],
+    stmts: &[(fg_parser::Statement, usize)],
     mut tables: std::collections::HashMap<String, Vec<ClassVal>>,
 ) -> Result<fg::FactorGraph, GraphBuildError> {
-    let nc = get_nc(stmts)?;
+    let stmts = Expander::expand(stmts)?;
+    let nc = get_nc(&stmts)?;
     let mut graph = fg::FactorGraph::build(nc as usize);
-    for s in stmts {
+    graph.field = get_field(&stmts, graph.nc)?;
+    for (s, origin) in stmts.iter() {
         match s {
             fg_parser::Statement::VarDecl(vd) => {
-                graph.add_var(vd.name.0.clone(), vd.multi)?;
+                for name in decl_names(vd) {
+                    graph
+                        .add_var(name, vd.multi, vd.nc)
+                        .map_err(|e| locate(origin, e))?;
+                }
             }
             fg_parser::Statement::PubDecl(vd) => {
-                graph.add_pub(vd.name.0.clone(), vd.multi)?;
+                for name in decl_names(vd) {
+                    graph
+                        .add_pub(name, vd.multi)
+                        .map_err(|e| locate(origin, e))?;
+                }
             }
             fg_parser::Statement::TableDecl { name, val } => {
                 let val = if let Some(val) = val {
@@ -254,33 +840,44 @@ pub(super) fn build_graph(
                 } else if let Some(val) = tables.get_mut(name) {
                     std::mem::replace(val, Vec::new())
                 } else {
-                    return Err(GraphBuildError::MissingTableDef(name.clone()));
+                    return Err(locate(
+                        origin,
+                        GraphBuildError::MissingTableDef(name.clone()),
+                    ));
                 };
-                graph.add_table(name.clone(), val)?;
+                graph
+                    .add_table(name.clone(), val)
+                    .map_err(|e| locate(origin, e))?;
             }
             fg_parser::Statement::Invalid
             | fg_parser::Statement::Empty
             | fg_parser::Statement::Property { .. }
-            | fg_parser::Statement::NC(_) => {}
+            | fg_parser::Statement::NC(_)
+            | fg_parser::Statement::Field(_)
+            | fg_parser::Statement::GraphDecl { .. }
+            | fg_parser::Statement::End
+            | fg_parser::Statement::Instance { .. } => {}
         }
     }
     let mut anon_names = (0..).map(|i| format!("ANONYMOUS_{}", i));
-    for s in stmts {
+    for (s, origin) in stmts.iter() {
         if let fg_parser::Statement::Property { name, dest, expr } = s {
             let name = name.clone().unwrap_or_else(|| anon_names.next().unwrap());
-            graph.add_factor(
-                name,
-                expr.as_factor_kind(),
-                std::iter::once(dest.0.as_str()).chain(expr.vars()),
-            )?;
+            graph
+                .add_factor(
+                    name,
+                    expr.as_factor_kind(graph.nc),
+                    std::iter::once(dest.0.as_str()).chain(expr.vars()),
+                )
+                .map_err(|e| locate(origin, e))?;
         }
     }
     graph.add_graph_edges();
     Ok(graph)
 }
 
-fn get_nc(stmts: &[fg_parser::Statement]) -> Result<u64, GraphBuildError> {
-    let mut nc_decls = stmts.iter().filter_map(|s| {
+fn get_nc(stmts: &[Located]) -> Result<u64, GraphBuildError> {
+    let mut nc_decls = stmts.iter().filter_map(|(s, _)| {
         if let fg_parser::Statement::NC(nc) = s {
             Some(*nc)
         } else {
@@ -297,3 +894,192 @@ fn get_nc(stmts: &[fg_parser::Statement]) -> Result<u64, GraphBuildError> {
         return Err(GraphBuildError::NoNc);
     }
 }
+
+fn get_field(stmts: &[Located], nc: usize) -> Result<Option<fg::Field>, GraphBuildError> {
+    let mut field_decls = stmts.iter().filter_map(|(s, _)| {
+        if let fg_parser::Statement::Field(poly) = s {
+            Some(*poly)
+        } else {
+            None
+        }
+    });
+    if let Some(poly) = field_decls.next() {
+        if field_decls.next().is_some() {
+            Err(GraphBuildError::MultipleField)
+        } else {
+            fg::Field::new(poly, nc)
+                .map(Some)
+                .ok_or(GraphBuildError::InvalidField(poly))
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+/// Typed construction of a [`fg::FactorGraph`], equivalent to a textual description
+/// given to [`super::build_graph`], with the same validation.
+///
+/// Properties are anonymous unless named with [`FactorGraphBuilder::named`].
+#[derive(Debug, Clone)]
+pub struct FactorGraphBuilder {
+    stmts: Vec<fg_parser::Statement>,
+    /// Names given with no property to name, reported by build.
+    orphan_names: Vec<String>,
+}
+
+impl FactorGraphBuilder {
+    /// New graph where variables have nc values by default.
+    pub fn new(nc: usize) -> Self {
+        Self {
+            stmts: vec![fg_parser::Statement::NC(nc as u64)],
+            orphan_names: Vec::new(),
+        }
+    }
+    /// Reduction polynomial of GF(nc), required for GF multiplications.
+    pub fn field(mut self, poly: u64) -> Self {
+        self.stmts.push(fg_parser::Statement::Field(poly));
+        self
+    }
+    fn decl(mut self, var: bool, name: &str, multi: bool, nc: Option<usize>) -> Self {
+        let vd = fg_parser::VarDecl {
+            name: fg_parser::Var(name.to_owned()),
+            multi,
+            nc: nc.map(|nc| nc as u64),
+            len: None,
+        };
+        self.stmts.push(if var {
+            fg_parser::Statement::VarDecl(vd)
+        } else {
+            fg_parser::Statement::PubDecl(vd)
+        });
+        self
+    }
+    /// Variable with the graph NC.
+    pub fn var(self, name: &str, multi: bool) -> Self {
+        self.decl(true, name, multi, None)
+    }
+    /// Variable with its own domain size.
+    pub fn var_nc(self, name: &str, multi: bool, nc: usize) -> Self {
+        self.decl(true, name, multi, Some(nc))
+    }
+    /// Public value, given when creating a BPState.
+    pub fn public(self, name: &str, multi: bool) -> Self {
+        self.decl(false, name, multi, None)
+    }
+    /// Table with nc (single-input) or nc*nc (two-input) values.
+    pub fn table(mut self, name: &str, values: Vec<ClassVal>) -> Self {
+        self.stmts.push(fg_parser::Statement::TableDecl {
+            name: name.to_owned(),
+            val: Some(values),
+        });
+        self
+    }
+    fn property(mut self, res: &str, expr: fg_parser::Expr) -> Self {
+        self.stmts.push(fg_parser::Statement::Property {
+            name: None,
+            dest: fg_parser::Var(res.to_owned()),
+            expr,
+        });
+        self
+    }
+    /// Name the last added property.
+    ///
+    /// If the last added item is not a property, build returns an error.
+    pub fn named(mut self, name: &str) -> Self {
+        match self.stmts.last_mut() {
+            Some(fg_parser::Statement::Property { name: n, .. }) => *n = Some(name.to_owned()),
+            _ => self.orphan_names.push(name.to_owned()),
+        }
+        self
+    }
+    /// res = !x
+    pub fn not(self, res: &str, x: &str) -> Self {
+        self.property(res, fg_parser::Expr::Not(to_var(x)))
+    }
+    /// res = table[x] or res = table[x, y]
+    pub fn lookup(self, res: &str, table: &str, operands: &[&str]) -> Self {
+        let expr = fg_parser::Expr::Lookup {
+            vars: to_vars(operands),
+            table: table.to_owned(),
+        };
+        self.property(res, expr)
+    }
+    /// res = sum of coef*x mod nc, for all (coef, x) in terms.
+    pub fn add(self, res: &str, terms: &[(i64, &str)]) -> Self {
+        let terms = terms
+            .iter()
+            .map(|(coef, x)| fg_parser::Term {
+                var: to_var(x),
+                neg: *coef < 0,
+                coef: coef.unsigned_abs(),
+            })
+            .collect();
+        self.property(res, fg_parser::Expr::Add(terms))
+    }
+    /// res = product of operands mod nc
+    pub fn mul(self, res: &str, operands: &[&str]) -> Self {
+        self.property(res, fg_parser::Expr::Mul(to_vars(operands)))
+    }
+    /// res = product of operands in GF(nc)
+    pub fn gf_mul(self, res: &str, operands: &[&str]) -> Self {
+        self.property(res, fg_parser::Expr::GfMul(to_vars(operands)))
+    }
+    /// res = bitwise XOR of the operands
+    pub fn xor(self, res: &str, operands: &[&str]) -> Self {
+        self.property(res, fg_parser::Expr::Xor(to_vars(operands)))
+    }
+    /// Bitwise AND of the operands, each operand (x, neg) is negated if neg.
+    pub fn and(self, res: &str, operands: &[(&str, bool)]) -> Self {
+        self.property(res, fg_parser::Expr::And(to_nvars(operands)))
+    }
+    /// Bitwise OR of the operands, each operand (x, neg) is negated if neg.
+    pub fn or(self, res: &str, operands: &[(&str, bool)]) -> Self {
+        self.property(res, fg_parser::Expr::Or(to_nvars(operands)))
+    }
+    /// Decomposition of x into parts, least significant first.
+    pub fn bits(self, parts: &[&str], x: &str) -> Self {
+        self.property(x, fg_parser::Expr::Bits(to_vars(parts)))
+    }
+    /// Hamming weight of x.
+    pub fn hw(self, res: &str, x: &str) -> Self {
+        self.property(res, fg_parser::Expr::Hw(vec![to_var(x)]))
+    }
+    /// Hamming distance between x and y.
+    pub fn hd(self, res: &str, x: &str, y: &str) -> Self {
+        self.property(res, fg_parser::Expr::Hw(vec![to_var(x), to_var(y)]))
+    }
+    /// Build the graph, with the same checks as for a textual description.
+    pub fn build(&self) -> Result<fg::FactorGraph, GraphBuildError> {
+        if let Some(name) = self.orphan_names.first() {
+            return Err(GraphBuildError::NoPropertyToName(name.clone()));
+        }
+        let stmts = self
+            .stmts
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), i + 1))
+            .collect::<Vec<_>>();
+        // There are no lines to report.
+        build_graph(&stmts, HashMap::new()).map_err(|e| match e {
+            GraphBuildError::AtLine(_, e) => *e,
+            e => e,
+        })
+    }
+}
+
+fn to_var(x: &str) -> fg_parser::Var {
+    fg_parser::Var(x.to_owned())
+}
+
+fn to_vars(xs: &[&str]) -> Vec<fg_parser::Var> {
+    xs.iter().map(|x| to_var(x)).collect()
+}
+
+fn to_nvars(xs: &[(&str, bool)]) -> Vec<fg_parser::NVar> {
+    xs.iter()
+        .map(|(x, neg)| fg_parser::NVar {
+            var: to_var(x),
+            neg: *neg,
+        })
+        .collect()
+}
@@ -0,0 +1,333 @@
+//! Export of factor graphs: textual description (that can be parsed again), DOT and GraphML.
+
+use super::factor_graph::{Factor, FactorGraph, FactorKind};
+use super::ClassVal;
+use std::fmt::Write;
+
+/// Result or operand of a factor.
+#[derive(Debug, Clone, Copy)]
+struct Operand<'a> {
+    name: &'a str,
+    public: bool,
+    /// Negation (AND factors).
+    neg: bool,
+    /// Coefficient (ADD factors).
+    coef: ClassVal,
+}
+
+/// Name in a graph description, quoted (with escaped `"` and `\`) if it is not an identifier.
+fn desc_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_ident = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false)
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_ident {
+        name.to_owned()
+    } else {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn multi_str(multi: bool) -> &'static str {
+    if multi {
+        "MULTI"
+    } else {
+        "SINGLE"
+    }
+}
+
+impl FactorGraph {
+    /// Result and operands of factor.
+    fn factor_operands(&self, factor: &Factor) -> (Operand<'_>, Vec<Operand<'_>>) {
+        let vars = factor.edges.keys().enumerate().map(|(i, v)| Operand {
+            name: self.vars.get_index(v.idx()).unwrap().0.as_str(),
+            public: false,
+            neg: match &factor.kind {
+                FactorKind::AND { vars_neg } => vars_neg[i],
+                _ => false,
+            },
+            coef: match &factor.kind {
+                FactorKind::ADD { coefs } => coefs[i],
+                _ => 1,
+            },
+        });
+        let publics = factor.publics.iter().map(|(p, neg)| Operand {
+            name: self.publics.get_index(*p).unwrap().0.as_str(),
+            public: true,
+            neg: *neg,
+            coef: if *neg { (self.nc - 1) as ClassVal } else { 1 },
+        });
+        let mut operands = vars.chain(publics).collect::<Vec<_>>();
+        // Without a variable result, the result is the first public.
+        let res_idx = if factor.has_res {
+            0
+        } else {
+            factor.edges.len()
+        };
+        let res = operands.remove(res_idx);
+        (res, operands)
+    }
+    /// Short description of the kind of a factor.
+    fn factor_op(&self, factor: &Factor) -> String {
+        match &factor.kind {
+            FactorKind::AND { .. } => "AND".to_owned(),
+            FactorKind::XOR => "XOR".to_owned(),
+            FactorKind::NOT => "NOT".to_owned(),
+            FactorKind::ADD { .. } => "ADD".to_owned(),
+            FactorKind::MUL => "MUL".to_owned(),
+            FactorKind::GFMUL => "GFMUL".to_owned(),
+            FactorKind::LOOKUP { table } => {
+                format!("LOOKUP {}", self.tables.get_index(*table).unwrap().0)
+            }
+            FactorKind::BITS => "BITS".to_owned(),
+            FactorKind::HW => "HW".to_owned(),
+        }
+    }
+    /// Right-hand side of the PROPERTY statement of a factor.
+    fn factor_expr(&self, factor: &Factor, res: &Operand, operands: &[Operand]) -> String {
+        let names = |sep: &str| {
+            operands
+                .iter()
+                .map(|o| desc_name(o.name))
+                .collect::<Vec<_>>()
+                .join(sep)
+        };
+        let single = || match operands {
+            [o] => Some(desc_name(o.name)),
+            _ => None,
+        };
+        match &factor.kind {
+            FactorKind::AND { .. } => {
+                // res ^ res.neg = AND(x ^ x.neg), for AND gates that is
+                // res = x & !y, and for OR gates (res.neg = true), res = !x | y.
+                let lit = |o: &Operand| {
+                    let neg = o.neg ^ res.neg;
+                    format!("{}{}", if neg { "!" } else { "" }, desc_name(o.name))
+                };
+                let lits = operands.iter().map(lit).collect::<Vec<_>>();
+                lits.join(if res.neg { " | " } else { " & " })
+            }
+            FactorKind::XOR => single().unwrap_or_else(|| names(" ^ ")),
+            FactorKind::MUL => single().unwrap_or_else(|| names(" * ")),
+            FactorKind::GFMUL => single().unwrap_or_else(|| names(" . ")),
+            FactorKind::NOT => format!("!{}", names("")),
+            FactorKind::LOOKUP { table } => format!(
+                "{}[{}]",
+                desc_name(self.tables.get_index(*table).unwrap().0),
+                names(", ")
+            ),
+            FactorKind::ADD { .. } => {
+                let mut expr = String::new();
+                for (i, o) in operands.iter().enumerate() {
+                    let (neg, coef) = if o.public {
+                        (o.neg, 1)
+                    } else {
+                        (false, o.coef)
+                    };
+                    expr.push_str(match (i, neg) {
+                        (0, false) => "",
+                        (0, true) => "-",
+                        (_, false) => " + ",
+                        (_, true) => " - ",
+                    });
+                    // A single term needs an explicit coefficient.
+                    if coef != 1 || (operands.len() == 1 && !neg) {
+                        write!(expr, "{}*", coef).unwrap();
+                    }
+                    expr.push_str(&desc_name(o.name));
+                }
+                expr
+            }
+            FactorKind::HW if operands.len() == 1 => format!("HW({})", names("")),
+            FactorKind::HW => format!("HD({})", names(", ")),
+            FactorKind::BITS => unreachable!(),
+        }
+    }
+    /// Description of the graph in the language of `build_graph`, from which an identical
+    /// graph can be built (without external tables).
+    ///
+    /// Degenerate factors with a single operand (e.g., a single-input XOR or AND) are
+    /// described as equalities or negations.
+    pub fn to_description(&self) -> String {
+        let mut desc = String::new();
+        writeln!(desc, "NC {}", self.nc).unwrap();
+        if let Some(field) = &self.field {
+            writeln!(desc, "FIELD {:#x}", field.poly).unwrap();
+        }
+        for (name, table) in self.tables.iter() {
+            let values = table
+                .values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>();
+            writeln!(desc, "TABLE {} = [{}]", desc_name(name), values.join(", ")).unwrap();
+        }
+        for (name, var) in self.vars.iter() {
+            write!(desc, "VAR {} {}", multi_str(var.multi), desc_name(name)).unwrap();
+            if var.nc != self.nc {
+                write!(desc, " NC {}", var.nc).unwrap();
+            }
+            desc.push('\n');
+        }
+        for (name, public) in self.publics.iter() {
+            writeln!(desc, "PUB {} {}", multi_str(public.multi), desc_name(name)).unwrap();
+        }
+        for (name, factor) in self.factors.iter() {
+            let (res, operands) = self.factor_operands(factor);
+            write!(desc, "PROPERTY {}: ", desc_name(name)).unwrap();
+            if let FactorKind::BITS = factor.kind {
+                let parts = operands
+                    .iter()
+                    .map(|o| desc_name(o.name))
+                    .collect::<Vec<_>>();
+                writeln!(desc, "{} = BITS({})", parts.join(", "), desc_name(res.name)).unwrap();
+            } else {
+                let expr = match (&factor.kind, operands.as_slice()) {
+                    // Single-operand AND: res = x or res = !x.
+                    (FactorKind::AND { .. }, [o]) if o.neg == res.neg => desc_name(o.name),
+                    (FactorKind::AND { .. }, [o]) => format!("!{}", desc_name(o.name)),
+                    _ => self.factor_expr(factor, &res, &operands),
+                };
+                writeln!(desc, "{} = {}", desc_name(res.name), expr).unwrap();
+            }
+        }
+        desc
+    }
+    /// DOT (graphviz) representation of the graph.
+    ///
+    /// Variables are ellipses, factors are boxes and publics are diamonds (with dashed edges).
+    /// MULTI nodes have a double border.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("graph factor_graph {\n");
+        let peripheries = |multi| if multi { 2 } else { 1 };
+        for (name, var) in self.vars.iter() {
+            let mut label = dot_escape(name);
+            if var.nc != self.nc {
+                write!(label, "\\nNC {}", var.nc).unwrap();
+            }
+            writeln!(
+                dot,
+                "  \"v:{}\" [label=\"{}\", shape=ellipse, peripheries={}];",
+                dot_escape(name),
+                label,
+                peripheries(var.multi)
+            )
+            .unwrap();
+        }
+        for (name, public) in self.publics.iter() {
+            writeln!(
+                dot,
+                "  \"p:{}\" [label=\"{}\", shape=diamond, peripheries={}];",
+                dot_escape(name),
+                dot_escape(name),
+                peripheries(public.multi)
+            )
+            .unwrap();
+        }
+        for (name, factor) in self.factors.iter() {
+            writeln!(
+                dot,
+                "  \"f:{}\" [label=\"{}\\n{}\", shape=box, peripheries={}];",
+                dot_escape(name),
+                dot_escape(name),
+                dot_escape(&self.factor_op(factor)),
+                peripheries(factor.multi)
+            )
+            .unwrap();
+        }
+        for (name, factor) in self.factors.iter() {
+            for var in factor.edges.keys() {
+                let var_name = self.vars.get_index(var.idx()).unwrap().0;
+                writeln!(
+                    dot,
+                    "  \"v:{}\" -- \"f:{}\";",
+                    dot_escape(var_name),
+                    dot_escape(name)
+                )
+                .unwrap();
+            }
+            for (public, _) in factor.publics.iter() {
+                let pub_name = self.publics.get_index(*public).unwrap().0;
+                writeln!(
+                    dot,
+                    "  \"p:{}\" -- \"f:{}\" [style=dashed];",
+                    dot_escape(pub_name),
+                    dot_escape(name)
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+    /// GraphML representation of the graph.
+    ///
+    /// Nodes have a `kind` (var, public or factor), a `name` and a `multi` attribute. Variables
+    /// have a `nc` attribute, and factors an `op` attribute.
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <key id=\"name\" for=\"node\" attr.name=\"name\" attr.type=\"string\"/>\n",
+            "  <key id=\"multi\" for=\"node\" attr.name=\"multi\" attr.type=\"boolean\"/>\n",
+            "  <key id=\"nc\" for=\"node\" attr.name=\"nc\" attr.type=\"long\"/>\n",
+            "  <key id=\"op\" for=\"node\" attr.name=\"op\" attr.type=\"string\"/>\n",
+            "  <graph id=\"factor_graph\" edgedefault=\"undirected\">\n",
+        ));
+        let mut node = |id: String, kind: &str, name: &str, multi: bool, extra: &str| {
+            writeln!(
+                xml,
+                concat!(
+                    "    <node id=\"{}\"><data key=\"kind\">{}</data>",
+                    "<data key=\"name\">{}</data><data key=\"multi\">{}</data>{}</node>"
+                ),
+                id,
+                kind,
+                xml_escape(name),
+                multi,
+                extra
+            )
+            .unwrap();
+        };
+        for (i, (name, var)) in self.vars.iter().enumerate() {
+            let nc = format!("<data key=\"nc\">{}</data>", var.nc);
+            node(format!("v{}", i), "var", name, var.multi, &nc);
+        }
+        for (i, (name, public)) in self.publics.iter().enumerate() {
+            node(format!("p{}", i), "public", name, public.multi, "");
+        }
+        for (i, (name, factor)) in self.factors.iter().enumerate() {
+            let op = format!(
+                "<data key=\"op\">{}</data>",
+                xml_escape(&self.factor_op(factor))
+            );
+            node(format!("f{}", i), "factor", name, factor.multi, &op);
+        }
+        for (i, factor) in self.factors.values().enumerate() {
+            let sources = factor
+                .edges
+                .keys()
+                .map(|v| format!("v{}", v.idx()))
+                .chain(factor.publics.iter().map(|(p, _)| format!("p{}", p)));
+            for source in sources {
+                writeln!(xml, "    <edge source=\"{}\" target=\"f{}\"/>", source, i).unwrap();
+            }
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+}
@@ -17,21 +17,60 @@ impl NVar {
     }
 }
 
+/// Term of a modular sum: (-1)^neg * coef * var.
+#[derive(Debug, Clone)]
+pub(super) struct Term {
+    pub(super) var: Var,
+    pub(super) neg: bool,
+    pub(super) coef: u64,
+}
+
 #[derive(Debug, Clone)]
 pub(super) enum Expr {
     Not(Var),
-    Lookup { var: Var, table: String },
-    Add(Vec<Var>),
+    Lookup {
+        vars: Vec<Var>,
+        table: String,
+    },
+    Add(Vec<Term>),
     Mul(Vec<Var>),
+    GfMul(Vec<Var>),
     Xor(Vec<Var>),
     And(Vec<NVar>),
     Or(Vec<NVar>),
+    /// Parts of the result, least significant first.
+    Bits(Vec<Var>),
+    /// Hamming weight of the operand, or Hamming distance between the two operands.
+    Hw(Vec<Var>),
 }
 
 #[derive(Debug, Clone)]
 pub(super) struct VarDecl {
     pub(super) name: Var,
     pub(super) multi: bool,
+    /// Domain size, if not the graph NC.
+    pub(super) nc: Option<u64>,
+    /// Number of elements, if this declares the array name[0], ..., name[len-1].
+    pub(super) len: Option<u64>,
+}
+
+/// Parameter of a GRAPH template, which is an array of len variables if len is given.
+#[derive(Debug, Clone)]
+pub(super) struct Param {
+    pub(super) name: String,
+    pub(super) len: Option<u64>,
+}
+
+/// Argument of a GRAPH instantiation.
+#[derive(Debug, Clone)]
+pub(super) enum Arg {
+    Var(Var),
+    /// Variables name[start], ..., name[end-1].
+    Range {
+        name: String,
+        start: u64,
+        end: u64,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -44,21 +83,74 @@ pub(super) enum Statement {
         expr: Expr,
     },
     NC(u64),
+    Field(u64),
     VarDecl(VarDecl),
     PubDecl(VarDecl),
     TableDecl {
         name: String,
         val: Option<Vec<ClassVal>>,
     },
+    /// Start of the body of a GRAPH template, which ends at the next END.
+    GraphDecl {
+        name: String,
+        params: Vec<Param>,
+    },
+    End,
+    Instance {
+        label: Option<String>,
+        graph: String,
+        args: Vec<Arg>,
+    },
+}
+
+/// Integer in the given radix, with a parse error if it does not fit in a T.
+fn integer<T: TryFrom<u64> + Default>(
+    radix: u32,
+) -> impl Parser<char, T, Error = Simple<char>> + Copy {
+    text::int(radix).validate(move |x: String, span, emit| {
+        u64::from_str_radix(&x, radix)
+            .ok()
+            .and_then(|x| T::try_from(x).ok())
+            .unwrap_or_else(|| {
+                emit(Simple::custom(span, format!("Integer {} is too large.", x)));
+                T::default()
+            })
+    })
 }
 
-fn parser() -> impl Parser<char, Vec<Statement>, Error = Simple<char>> {
+fn parser() -> impl Parser<char, Vec<(Statement, usize)>, Error = Simple<char>> {
     let pad = just(' ').or(just('\t')).repeated();
     let space = pad.at_least(1);
     let op = |c| just(c).delimited_by(pad, pad);
     let ident = pad.ignore_then(text::ident()).then_ignore(pad);
+    // Names that are not identifiers are quoted, e.g. "r0.x[3]", with \" and \\ escapes.
+    let quoted = pad
+        .ignore_then(
+            just('\\')
+                .ignore_then(just('"').or(just('\\')))
+                .or(filter(|c| !matches!(*c, '"' | '\\' | '\n' | '\r')))
+                .repeated()
+                .at_least(1)
+                .collect::<String>()
+                .delimited_by(just('"'), just('"')),
+        )
+        .then_ignore(pad);
+    let name = ident.or(quoted);
     let kw = |s| text::keyword::<_, _, Simple<char>>(s).delimited_by(pad, space);
-    let var = ident.map(|s| Var(s));
+    let int = || integer::<u64>(10);
+    let index = just('[').ignore_then(int()).then_ignore(just(']'));
+    // Indexed variables are named x[i].
+    let var = ident
+        .then(index.or_not())
+        .map(|(s, idx)| match idx {
+            Some(idx) => Var(format!("{}[{}]", s, idx)),
+            None => Var(s),
+        })
+        .or(quoted.map(Var));
+    // Declared name, with optional array length.
+    let decl_name = ident
+        .then(index.then_ignore(pad).or_not())
+        .or(quoted.map(|s| (s, None)));
     let not_var = || op('!').ignore_then(var);
     let nvar = || {
         var.map(|v| NVar::new(v, false))
@@ -66,50 +158,168 @@ fn parser() -> impl Parser<char, Vec<Statement>, Error = Simple<char>> {
     };
     let op_nexpr = |c, f| nvar().separated_by(op(c)).at_least(2).map(f);
     let op_expr = |c, f| var.separated_by(op(c)).at_least(2).map(f);
-    let expr = ident
-        .then(var.delimited_by(op('['), op(']')))
-        .map(|(table, var)| Expr::Lookup { table, var })
+    // Sum terms are [k *] var, where the coefficient k is an integer.
+    let coef = || pad.ignore_then(int()).then_ignore(op('*'));
+    let term = || coef().or_not().then(var);
+    let more_terms = || {
+        op('+')
+            .to(false)
+            .or(op('-').to(true))
+            .then(term())
+            .repeated()
+    };
+    // A sum starts with a minus sign or a coefficient, or has at least two terms.
+    let sum = op('-')
+        .to(true)
+        .then(term())
+        .then(more_terms())
+        .or(empty()
+            .to(false)
+            .then(coef().map(Some).then(var))
+            .then(more_terms()))
+        .or(empty()
+            .to(false)
+            .then(term())
+            .then(more_terms().at_least(1)))
+        .map(|((neg, first), others)| {
+            Expr::Add(
+                std::iter::once((neg, first))
+                    .chain(others)
+                    .map(|(neg, (coef, var))| Term {
+                        var,
+                        neg,
+                        coef: coef.unwrap_or(1),
+                    })
+                    .collect(),
+            )
+        });
+    // HW(x) and HD(x, y), with n operands.
+    let hw = |name, n| {
+        pad.ignore_then(text::keyword(name))
+            .ignore_then(
+                var.separated_by(op(','))
+                    .exactly(n)
+                    .delimited_by(op('('), op(')')),
+            )
+            .map(Expr::Hw)
+    };
+    let lookup = name
+        .then(
+            var.separated_by(op(','))
+                .at_least(1)
+                .at_most(2)
+                .delimited_by(op('['), op(']')),
+        )
+        .map(|(table, vars)| Expr::Lookup { table, vars });
+    let expr = hw("HW", 1)
+        .or(hw("HD", 2))
+        .or(lookup)
         .or(op_expr('^', Expr::Xor as fn(_) -> _))
         .or(op_nexpr('&', Expr::And as fn(_) -> _))
         .or(op_nexpr('|', Expr::Or as fn(_) -> _))
-        .or(op_expr('+', Expr::Add as fn(_) -> _))
+        .or(sum)
         .or(op_expr('*', Expr::Mul as fn(_) -> _))
-        .or(not_var().map(|v| Expr::Not(v)));
+        .or(op_expr('.', Expr::GfMul as fn(_) -> _))
+        .or(not_var().map(|v| Expr::Not(v)))
+        // Equality, as a single-operand XOR.
+        .or(var.map(|v| Expr::Xor(vec![v])));
     let prop = kw("PROPERTY")
-        .ignore_then(ident.then_ignore(op(':')).or_not())
+        .ignore_then(name.then_ignore(op(':')).or_not())
         .then(var)
         .then_ignore(op('='))
         .then(expr)
         .map(|((name, dest), expr)| Statement::Property { name, dest, expr });
-    let nc = kw("NC")
-        .ignore_then(text::int(10))
-        .map(|nc: String| Statement::NC(nc.parse().unwrap()));
+    // The whole variable is the destination of the factor.
+    let prop_bits = kw("PROPERTY")
+        .ignore_then(name.then_ignore(op(':')).or_not())
+        .then(var.separated_by(op(',')).at_least(1))
+        .then_ignore(op('='))
+        .then_ignore(pad.then(text::keyword("BITS")))
+        .then(var.delimited_by(op('('), op(')')))
+        .map(|((name, parts), dest)| Statement::Property {
+            name,
+            dest,
+            expr: Expr::Bits(parts),
+        });
+    let nc = kw("NC").ignore_then(int()).map(Statement::NC);
+    let poly = just("0x").ignore_then(integer(16)).or(int());
+    let field = kw("FIELD").ignore_then(poly).map(Statement::Field);
     let comment = op('#').then_ignore(filter(|c| *c != '\n' && *c != '\r').repeated());
     let var_decl = kw("VAR")
         .ignore_then(kw("SINGLE").to(false).or(kw("MULTI").to(true)))
-        .then(var)
-        .map(|(multi, name)| Statement::VarDecl(VarDecl { name, multi }));
+        .then(decl_name)
+        .then(kw("NC").ignore_then(int()).then_ignore(pad).or_not())
+        .map(|((multi, (name, len)), nc)| {
+            Statement::VarDecl(VarDecl {
+                name: Var(name),
+                multi,
+                nc,
+                len,
+            })
+        });
     let pub_decl = kw("PUB")
         .ignore_then(kw("SINGLE").to(false).or(kw("MULTI").to(true)))
-        .then(var)
-        .map(|(multi, name)| Statement::PubDecl(VarDecl { name, multi }));
-    let table_val = text::int(10)
-        .map(|x: String| x.parse().unwrap())
+        .then(decl_name)
+        .map(|(multi, (name, len))| {
+            Statement::PubDecl(VarDecl {
+                name: Var(name),
+                multi,
+                nc: None,
+                len,
+            })
+        });
+    let table_val = integer::<ClassVal>(10)
         .separated_by(op(','))
         .allow_trailing()
         .delimited_by(op('['), op(']'));
     let table = kw("TABLE")
-        .ignore_then(ident)
+        .ignore_then(name)
         .then(op('=').ignore_then(table_val).or_not())
         .map(|(name, val)| Statement::TableDecl { name, val });
-    let graph = prop
+    let graph_decl = kw("GRAPH")
+        .ignore_then(ident)
+        .then(
+            decl_name
+                .map(|(name, len)| Param { name, len })
+                .separated_by(op(','))
+                .delimited_by(op('('), op(')')),
+        )
+        .map(|(name, params)| Statement::GraphDecl { name, params });
+    let end_graph = pad
+        .ignore_then(text::keyword("END"))
+        .then_ignore(pad)
+        .to(Statement::End);
+    // Arguments are variables or ranges of indexed variables x[start..end].
+    let arg = ident
+        .then(
+            int()
+                .then_ignore(just("..").padded_by(pad))
+                .then(int())
+                .delimited_by(op('['), op(']')),
+        )
+        .map(|(name, (start, end))| Arg::Range { name, start, end })
+        .or(var.map(Arg::Var));
+    let instance = ident
+        .then_ignore(op(':'))
+        .or_not()
+        .then(ident)
+        .then(arg.separated_by(op(',')).delimited_by(op('('), op(')')))
+        .map(|((label, graph), args)| Statement::Instance { label, graph, args });
+    // BITS first, otherwise `x = BITS` is parsed as an equality.
+    let graph = prop_bits
+        .or(prop)
         .or(nc)
+        .or(field)
         .or(var_decl)
         .or(pub_decl)
         .or(table)
+        .or(graph_decl)
+        .or(end_graph)
+        .or(instance)
         .or(pad.at_least(0).to(Statement::Empty))
         .then_ignore(comment.or_not())
         .recover_with(skip_until(['\n', '\r'], |_| Statement::Invalid))
+        .map_with_span(|s, span: std::ops::Range<usize>| (s, span.start))
         .separated_by(text::newline())
         .allow_leading()
         .allow_trailing()
@@ -117,9 +327,10 @@ fn parser() -> impl Parser<char, Vec<Statement>, Error = Simple<char>> {
     graph
 }
 
-/// Parse the factor graph description in src, and returns the statements if no
-/// error, otherwise the error is a locale-encoded error message.
-pub(super) fn parse(src: &str) -> Result<Vec<Statement>, Vec<u8>> {
+/// Parse the factor graph description in src, and returns the statements with their
+/// (1-based) line number if no error, otherwise the error is a locale-encoded error
+/// message.
+pub(super) fn parse(src: &str) -> Result<Vec<(Statement, usize)>, Vec<u8>> {
     let (graph, errs) = parser().parse_recovery_verbose(src);
     let err = !errs.is_empty();
     let mut err_str = Vec::new();
@@ -166,6 +377,18 @@ pub(super) fn parse(src: &str) -> Result<Vec<Statement>, Vec<u8>> {
     if err {
         return Err(err_str);
     } else {
-        return Ok(graph.unwrap());
+        // Spans are in chars, convert them to line numbers.
+        let mut stmts = graph.unwrap();
+        let mut chars = src.chars().enumerate().peekable();
+        let mut line = 1;
+        for (_, pos) in stmts.iter_mut() {
+            while let Some((_, c)) = chars.next_if(|(i, _)| i < pos) {
+                if c == '\n' {
+                    line += 1;
+                }
+            }
+            *pos = line;
+        }
+        return Ok(stmts);
     }
 }
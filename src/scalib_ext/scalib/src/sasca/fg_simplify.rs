@@ -0,0 +1,472 @@
+//! Simplification of factor graphs.
+//!
+//! The passes remove variables that are not kept (i.e., that have no evidence and whose
+//! distribution is not needed), while preserving the joint distribution of the other ones:
+//! - a NOT or equality factor is folded into the other factors of one of its variables (e.g.,
+//!   into the vars_neg of AND factors), which becomes an alias of the other one,
+//! - two cascaded XOR factors are merged,
+//! - two consecutive LOOKUP factors are composed into a single one (with a new table),
+//! - a leaf variable is removed, with its factor if the factor does not constrain the other
+//!   variables (e.g., the variable is the result of the factor).
+//!
+//! All the factors are indicator functions, hence moving a factor across executions (e.g., a
+//! MULTI factor that becomes SINGLE) does not change the distribution. However, a SINGLE
+//! variable can only be summed out if it is not constrained by the factors of several executions.
+
+use super::factor_graph::{FactorKind, PublicId, Table, TableId, VarId, VarVec};
+use super::{ClassVal, FactorGraph};
+
+/// What became of a variable removed by FactorGraph::simplify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Elimination {
+    /// The variable is equal to var (or to its bitwise NOT if neg).
+    Alias { var: VarId, neg: bool },
+    /// The variable was summed out.
+    Removed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fate {
+    Live,
+    Alias(VarId, bool),
+    Removed,
+}
+
+/// Factor being simplified (same layout as Factor, without the edges).
+#[derive(Debug, Clone)]
+struct WFactor {
+    name: String,
+    kind: FactorKind,
+    /// The result comes first if has_res.
+    vars: Vec<VarId>,
+    has_res: bool,
+    publics: Vec<(PublicId, bool)>,
+}
+
+impl WFactor {
+    fn res(&self) -> Option<VarId> {
+        self.has_res.then(|| self.vars[0])
+    }
+    fn pos(&self, var: VarId) -> usize {
+        self.vars.iter().position(|v| *v == var).unwrap()
+    }
+}
+
+struct Simplifier<'a> {
+    graph: &'a FactorGraph,
+    keep: VarVec<bool>,
+    fates: VarVec<Fate>,
+    /// Removed factors are None.
+    factors: Vec<Option<WFactor>>,
+    var_factors: VarVec<Vec<usize>>,
+    /// Composed tables, numbered after the tables of the graph.
+    tables: Vec<(String, Table)>,
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(graph: &'a FactorGraph, keep: &[VarId]) -> Self {
+        let mut keep_vars: VarVec<bool> = graph.range_vars().map(|_| false).collect();
+        for var in keep {
+            keep_vars[*var] = true;
+        }
+        let factors: Vec<_> = graph
+            .factors
+            .iter()
+            .map(|(name, f)| {
+                Some(WFactor {
+                    name: name.clone(),
+                    kind: f.kind.clone(),
+                    vars: f.edges.keys().copied().collect(),
+                    has_res: f.has_res,
+                    publics: f.publics.clone(),
+                })
+            })
+            .collect();
+        let mut var_factors: VarVec<Vec<usize>> = graph.range_vars().map(|_| vec![]).collect();
+        for (i, f) in factors.iter().enumerate() {
+            for var in f.as_ref().unwrap().vars.iter() {
+                var_factors[*var].push(i);
+            }
+        }
+        Self {
+            graph,
+            keep: keep_vars,
+            fates: graph.range_vars().map(|_| Fate::Live).collect(),
+            factors,
+            var_factors,
+            tables: vec![],
+        }
+    }
+
+    fn factor(&self, i: usize) -> &WFactor {
+        self.factors[i].as_ref().unwrap()
+    }
+
+    fn factor_multi(&self, f: &WFactor) -> bool {
+        f.vars.iter().any(|v| self.graph.var_multi(*v))
+            || f.publics.iter().any(|(p, _)| self.graph.publics[*p].multi)
+    }
+
+    fn table(&self, table: TableId) -> &[ClassVal] {
+        if let Some((_, t)) = self.graph.tables.get_index(table) {
+            &t.values
+        } else {
+            &self.tables[table - self.graph.tables.len()].1.values
+        }
+    }
+
+    fn remove_factor(&mut self, i: usize) -> WFactor {
+        let f = self.factors[i].take().unwrap();
+        for var in f.vars.iter() {
+            self.var_factors[*var].retain(|g| *g != i);
+        }
+        f
+    }
+
+    fn insert_factor(&mut self, i: usize, f: WFactor) {
+        for var in f.vars.iter() {
+            self.var_factors[*var].push(i);
+        }
+        self.factors[i] = Some(f);
+    }
+
+    /// The two factors of var, if it has exactly two factors.
+    fn factor_pair(&self, var: VarId) -> Option<(usize, usize)> {
+        if let [f, g] = self.var_factors[var].as_slice() {
+            Some((*f, *g))
+        } else {
+            None
+        }
+    }
+
+    fn run(&mut self) {
+        loop {
+            let mut changed = false;
+            for i in 0..self.factors.len() {
+                if self.factors[i].is_some() {
+                    changed |= self.fold_alias(i);
+                }
+            }
+            for var in self.graph.range_vars() {
+                if !self.keep[var] && self.fates[var] == Fate::Live {
+                    changed |=
+                        self.remove_leaf(var) || self.merge_xor(var) || self.compose_lookup(var);
+                }
+            }
+            if !changed {
+                return;
+            }
+        }
+    }
+
+    /// Fold the NOT or equality factor i into the other factors of one of its variables.
+    fn fold_alias(&mut self, i: usize) -> bool {
+        let f = self.factor(i);
+        let neg = match f.kind {
+            FactorKind::NOT => true,
+            FactorKind::XOR => false,
+            _ => return false,
+        };
+        if f.vars.len() != 2 || !f.publics.is_empty() {
+            return false;
+        }
+        for (var, alias) in [(f.vars[0], f.vars[1]), (f.vars[1], f.vars[0])] {
+            // A SINGLE var equal to a MULTI one forces it to be the same in all the executions,
+            // and a MULTI var cannot be the alias of a SINGLE one (its distribution has one row
+            // per execution).
+            if self.keep[var] || self.graph.var_multi(alias) != self.graph.var_multi(var) {
+                continue;
+            }
+            let others: Vec<usize> = self.var_factors[var]
+                .iter()
+                .copied()
+                .filter(|g| *g != i)
+                .collect();
+            let foldable = others.iter().all(|g| {
+                let g = self.factor(*g);
+                !g.vars.contains(&alias)
+                    && (!neg
+                        || matches!(g.kind, FactorKind::AND { .. })
+                        || (matches!(g.kind, FactorKind::NOT)
+                            && g.vars.len() == 2
+                            && g.publics.is_empty()))
+            });
+            if !foldable {
+                continue;
+            }
+            self.remove_factor(i);
+            for g in others {
+                let mut factor = self.remove_factor(g);
+                let pos = factor.pos(var);
+                factor.vars[pos] = alias;
+                if neg {
+                    match &mut factor.kind {
+                        FactorKind::AND { vars_neg } => vars_neg[pos] = !vars_neg[pos],
+                        // The NOT of a NOT is an equality.
+                        kind => *kind = FactorKind::XOR,
+                    }
+                }
+                self.insert_factor(g, factor);
+            }
+            self.fates[var] = Fate::Alias(alias, neg);
+            return true;
+        }
+        false
+    }
+
+    /// Remove var if it has no factor, or if its only factor does not constrain the other
+    /// variables when var is summed out.
+    fn remove_leaf(&mut self, var: VarId) -> bool {
+        match self.var_factors[var].as_slice() {
+            [] => {}
+            [i] => {
+                let i = *i;
+                let f = self.factor(i);
+                let pos = f.pos(var);
+                let free = f.res() == Some(var)
+                    || match &f.kind {
+                        FactorKind::XOR | FactorKind::NOT => true,
+                        FactorKind::ADD { coefs } => gcd(coefs[pos] as usize, self.graph.nc) == 1,
+                        _ => false,
+                    };
+                if !free || (!self.graph.var_multi(var) && self.factor_multi(f)) {
+                    return false;
+                }
+                self.remove_factor(i);
+            }
+            _ => return false,
+        }
+        self.fates[var] = Fate::Removed;
+        true
+    }
+
+    /// Merge the two XOR factors of var into one.
+    fn merge_xor(&mut self, var: VarId) -> bool {
+        let Some((fi, gi)) = self.factor_pair(var) else {
+            return false;
+        };
+        // Keep the name of the factor that uses var as an operand.
+        let (fi, gi) = if self.factor(gi).res() == Some(var) {
+            (gi, fi)
+        } else {
+            (fi, gi)
+        };
+        let (f, g) = (self.factor(fi), self.factor(gi));
+        if !matches!(f.kind, FactorKind::XOR)
+            || !matches!(g.kind, FactorKind::XOR)
+            || (!self.graph.var_multi(var) && self.factor_multi(f) && self.factor_multi(g))
+        {
+            return false;
+        }
+        let f_vars = f.vars.iter().filter(|v| **v != var);
+        if f_vars.clone().any(|v| g.vars.contains(v))
+            || f.publics.iter().any(|p| g.publics.contains(p))
+        {
+            return false;
+        }
+        // Since XOR is symmetric, any variable can be the result.
+        let vars: Vec<VarId> = g
+            .vars
+            .iter()
+            .filter(|v| **v != var)
+            .chain(f_vars)
+            .copied()
+            .collect();
+        let publics: Vec<_> = g.publics.iter().chain(f.publics.iter()).copied().collect();
+        if vars.is_empty() || vars.len() + publics.len() < 2 {
+            return false;
+        }
+        let merged = WFactor {
+            name: g.name.clone(),
+            kind: FactorKind::XOR,
+            vars,
+            has_res: true,
+            publics,
+        };
+        self.remove_factor(fi);
+        self.remove_factor(gi);
+        self.insert_factor(gi, merged);
+        self.fates[var] = Fate::Removed;
+        true
+    }
+
+    /// Compose the LOOKUP factor that computes var with the LOOKUP factor that uses it, if the
+    /// composed table has at most two inputs.
+    fn compose_lookup(&mut self, var: VarId) -> bool {
+        let Some((fi, gi)) = self.factor_pair(var) else {
+            return false;
+        };
+        let (fi, gi) = if self.factor(gi).res() == Some(var) {
+            (gi, fi)
+        } else {
+            (fi, gi)
+        };
+        let (f, g) = (self.factor(fi), self.factor(gi));
+        let (FactorKind::LOOKUP { table: f_table }, FactorKind::LOOKUP { table: g_table }) =
+            (&f.kind, &g.kind)
+        else {
+            return false;
+        };
+        if f.res() != Some(var)
+            || g.res() == Some(var)
+            || (!self.graph.var_multi(var) && self.factor_multi(f))
+        {
+            return false;
+        }
+        // Position of the inputs of f in the inputs of the composed table.
+        let k = g.pos(var) - 1;
+        let f_in = &f.vars[1..];
+        let inputs: Vec<VarId> = g.vars[1..=k]
+            .iter()
+            .chain(f_in.iter())
+            .chain(g.vars[k + 2..].iter())
+            .copied()
+            .collect();
+        if inputs.len() > 2
+            || inputs.contains(&g.vars[0])
+            || (inputs.len() == 2 && inputs[0] == inputs[1])
+        {
+            return false;
+        }
+        let nc = self.graph.nc;
+        let (tf, tg) = (self.table(*f_table), self.table(*g_table));
+        let index =
+            |digits: &mut dyn Iterator<Item = &usize>| digits.fold(0, |acc, d| acc * nc + d);
+        let values: Vec<ClassVal> = (0..nc.pow(inputs.len() as u32))
+            .map(|x| {
+                let digits = if inputs.len() == 2 {
+                    vec![x / nc, x % nc]
+                } else {
+                    vec![x]
+                };
+                let t = tf[index(&mut digits[k..k + f_in.len()].iter())] as usize;
+                tg[index(
+                    &mut digits[..k]
+                        .iter()
+                        .chain(Some(&t))
+                        .chain(digits[k + f_in.len()..].iter()),
+                )]
+            })
+            .collect();
+        let composed = format!(
+            "{}.{}",
+            self.table_name(*g_table),
+            self.table_name(*f_table)
+        );
+        let name = (1..)
+            .map(|i| {
+                if i == 1 {
+                    composed.clone()
+                } else {
+                    format!("{}_{}", composed, i)
+                }
+            })
+            .find(|n| !self.graph.tables.contains_key(n) && self.tables.iter().all(|(t, _)| t != n))
+            .unwrap();
+        let table = self.graph.tables.len() + self.tables.len();
+        let ninputs = inputs.len();
+        let composed = WFactor {
+            name: g.name.clone(),
+            kind: FactorKind::LOOKUP { table },
+            vars: std::iter::once(g.vars[0]).chain(inputs).collect(),
+            has_res: true,
+            publics: vec![],
+        };
+        self.tables.push((name, Table { values, ninputs }));
+        self.remove_factor(fi);
+        self.remove_factor(gi);
+        self.insert_factor(gi, composed);
+        self.fates[var] = Fate::Removed;
+        true
+    }
+
+    fn table_name(&self, table: TableId) -> &str {
+        if let Some((name, _)) = self.graph.tables.get_index(table) {
+            name
+        } else {
+            &self.tables[table - self.graph.tables.len()].0
+        }
+    }
+
+    /// Build the simplified graph.
+    fn finish(self) -> FactorGraph {
+        let graph = self.graph;
+        let mut res = FactorGraph::build(graph.nc);
+        res.field = graph.field.clone();
+        res.publics = graph.publics.clone();
+        res.tables = graph.tables.clone();
+        res.tables.extend(self.tables);
+        let mut new_ids: VarVec<Option<VarId>> = graph.range_vars().map(|_| None).collect();
+        for (var, (name, v)) in graph.range_vars().zip(graph.vars.iter()) {
+            if self.fates[var] == Fate::Live {
+                res.add_var(name.clone(), v.multi, Some(v.nc as u64))
+                    .expect("Valid variable.");
+                new_ids[var] = Some(VarId::from_idx(res.vars.len() - 1));
+            }
+        }
+        for f in self.factors.into_iter().flatten() {
+            let vars: Vec<VarId> = f.vars.iter().map(|v| new_ids[*v].unwrap()).collect();
+            res.push_factor(f.name, f.kind, &vars, f.has_res, f.publics);
+        }
+        res.add_graph_edges();
+        let resolve = |mut var: VarId, mut neg: bool| loop {
+            match self.fates[var] {
+                Fate::Live => {
+                    return Elimination::Alias {
+                        var: new_ids[var].unwrap(),
+                        neg,
+                    }
+                }
+                Fate::Alias(alias, alias_neg) => {
+                    var = alias;
+                    neg ^= alias_neg;
+                }
+                Fate::Removed => return Elimination::Removed,
+            }
+        };
+        // Variables removed by a previous simplification.
+        for (name, elimination) in graph.eliminated.iter() {
+            let elimination = match elimination {
+                Elimination::Alias { var, neg } => resolve(*var, *neg),
+                Elimination::Removed => Elimination::Removed,
+            };
+            res.eliminated.insert(name.clone(), elimination);
+        }
+        for (var, name) in graph.vars() {
+            if self.fates[var] != Fate::Live {
+                res.eliminated.insert(name.to_owned(), resolve(var, false));
+            }
+        }
+        res
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl FactorGraph {
+    /// Simplified graph, from which the variables that are not in keep may be removed.
+    ///
+    /// The joint distribution of the remaining variables is preserved if there is no evidence
+    /// on the removed variables: keep must contain all the variables that will have evidence or
+    /// whose distribution is needed. The distribution of a variable that became an alias can
+    /// still be obtained with BPState::get_state_by_name.
+    pub fn simplify(&self, keep: &[VarId]) -> FactorGraph {
+        let mut simplifier = Simplifier::new(self, keep);
+        simplifier.run();
+        simplifier.finish()
+    }
+    /// What became of var in simplify (None if var was not removed).
+    pub fn elimination(&self, var: &str) -> Option<Elimination> {
+        self.eliminated.get(var).copied()
+    }
+    /// Variables removed by simplify.
+    pub fn eliminated(&self) -> impl Iterator<Item = (&str, Elimination)> {
+        self.eliminated.iter().map(|(n, e)| (n.as_str(), *e))
+    }
+}
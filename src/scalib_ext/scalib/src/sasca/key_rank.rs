@@ -0,0 +1,95 @@
+use thiserror::Error;
+
+use super::factor_graph::FGError;
+use super::{BPState, ClassVal, VarId};
+
+/// Smallest probability taken into account when converting distributions to costs, to keep costs
+/// finite.
+const MIN_PROBA_COST: f64 = f64::MIN_POSITIVE;
+
+#[derive(Debug, Clone, Error)]
+pub enum KeyRankError {
+    #[error(transparent)]
+    Graph(#[from] FGError),
+    #[error("Variable {0} is MULTI, only SINGLE variables can be part of a key.")]
+    MultiVar(String),
+    #[error("Got {1} key values for {0} key variables.")]
+    KeyLength(usize, usize),
+    #[error("Value {1} of key variable {0} is out of range (nc = {2}).")]
+    KeyValue(String, ClassVal, usize),
+    #[error("{0}")]
+    Rank(String),
+}
+
+impl From<ranklib::RankError> for KeyRankError {
+    fn from(e: ranklib::RankError) -> Self {
+        Self::Rank(e.to_string())
+    }
+}
+
+impl BPState {
+    /// Costs (negative natural log-probabilities) of every value of the key_vars, taken from
+    /// their current state.
+    /// A variable with a uniform state has all costs equal to zero.
+    /// The log-domain state is used for the variables that have one (see
+    /// BPState::set_log_domain), otherwise the probabilities are clamped to MIN_PROBA_COST.
+    pub fn key_costs(&self, key_vars: &[VarId]) -> Result<Vec<Vec<f64>>, KeyRankError> {
+        let graph = self.get_graph();
+        key_vars
+            .iter()
+            .map(|var| {
+                if graph.var_multi(*var) {
+                    return Err(KeyRankError::MultiVar(graph.var_name(*var).to_owned()));
+                }
+                let state = self.get_state(*var);
+                Ok(if let Some(log_state) = self.get_state_log(*var) {
+                    log_state.value().row(0).iter().map(|x| -x).collect()
+                } else if let Some(distr) = state.value() {
+                    let distr = distr.row(0);
+                    let norm = distr.sum();
+                    distr
+                        .iter()
+                        .map(|p| -(p / norm).max(MIN_PROBA_COST).ln())
+                        .collect()
+                } else {
+                    vec![0.0; graph.var_nc(*var)]
+                })
+            })
+            .collect()
+    }
+    /// Rank of the key, whose sub-keys are the key_vars (SINGLE variables), based on their
+    /// current state (see ranklib::RankingMethod::rank_accuracy for the other parameters).
+    pub fn rank_accuracy(
+        &self,
+        key_vars: &[&str],
+        key: &[ClassVal],
+        method: ranklib::RankingMethod,
+        acc: f64,
+        merge: Option<usize>,
+        max_nb_bin: usize,
+    ) -> Result<ranklib::RankEstimation, KeyRankError> {
+        if key_vars.len() != key.len() {
+            return Err(KeyRankError::KeyLength(key_vars.len(), key.len()));
+        }
+        let graph = self.get_graph();
+        let var_ids = key_vars
+            .iter()
+            .map(|v| graph.get_varid(v))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = key_vars
+            .iter()
+            .zip(var_ids.iter())
+            .zip(key.iter())
+            .map(|((v, v_id), k)| {
+                let nc = graph.var_nc(*v_id);
+                if (*k as usize) < nc {
+                    Ok(*k as usize)
+                } else {
+                    Err(KeyRankError::KeyValue((*v).to_owned(), *k, nc))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let costs = self.key_costs(&var_ids)?;
+        Ok(method.rank_accuracy(&costs, &key, acc, merge, max_nb_bin)?)
+    }
+}
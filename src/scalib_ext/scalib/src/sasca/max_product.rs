@@ -0,0 +1,239 @@
+//! Factor to variable messages for max-product belief propagation.
+//!
+//! The variable updates are the same as for sum-product BP, only the factor messages differ: the
+//! marginalization over the other operands is a maximum instead of a sum. There is no fast
+//! transform (such as the FFT or WHT) for max-convolutions, hence these are computed directly,
+//! in O(nc^2) per pair of operands.
+
+use super::factor_graph::{EdgeSlice, Factor, FactorKind, VarId};
+use super::{ClassVal, Distribution, FactorGraph, PublicValue};
+
+/// Factor of the form res = op(t_1(x_1), ..., t_n(x_n), cst), where op is associative and
+/// commutative, and the t_i are tables.
+struct OpFactor<'a, F> {
+    op: F,
+    neutral: ClassVal,
+    /// Table applied to each var (identity if None), including the result.
+    tables: Vec<Option<Vec<ClassVal>>>,
+    /// The first var is the result, otherwise the result is target.
+    res_var: bool,
+    target: Option<PublicValue>,
+    /// Constant operand.
+    cst: Option<&'a PublicValue>,
+}
+
+impl<'a, F: Fn(usize, usize) -> usize + Sync> OpFactor<'a, F> {
+    fn messages(
+        &self,
+        factor: &Factor,
+        belief_from_var: &EdgeSlice<Distribution>,
+        dest: &[VarId],
+    ) -> Vec<Distribution> {
+        let d = |i: usize| &belief_from_var[factor.edges[i]];
+        let template = d(0).as_uniform();
+        let first_op = usize::from(self.res_var);
+        let apply = |i: usize, distr: &Distribution| match &self.tables[i] {
+            Some(t) => distr.map_table_max(t),
+            None => distr.clone(),
+        };
+        let pullback = |i: usize, distr: Distribution| match &self.tables[i] {
+            Some(t) => distr.map_table_inv(t),
+            None => distr,
+        };
+        let terms: Vec<Distribution> = (first_op..factor.edges.len())
+            .map(|i| apply(i, d(i)))
+            .collect();
+        let cst = self.cst.map(|c| template.new_constant(c));
+        // Max-marginal of the combination of the operands, except the skipped one.
+        let combine = |skip: Option<usize>| {
+            terms
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| Some(*j) != skip)
+                .map(|(_, t)| t)
+                .chain(cst.iter())
+                .fold(None, |acc: Option<Distribution>, t| {
+                    Some(match acc {
+                        Some(acc) => acc.max_op(t, &self.op),
+                        None => t.clone(),
+                    })
+                })
+                .unwrap_or_else(|| template.new_constant(&PublicValue::Single(self.neutral)))
+        };
+        let res = if self.res_var {
+            apply(0, d(0))
+        } else {
+            template.new_constant(self.target.as_ref().unwrap())
+        };
+        dest.iter()
+            .map(|var| {
+                let i = factor.edges.get_index_of(var).unwrap();
+                if self.res_var && i == 0 {
+                    pullback(0, combine(None))
+                } else {
+                    let others = combine(Some(i - first_op));
+                    pullback(i, res.max_op_inv(&others, &self.op))
+                }
+            })
+            .collect()
+    }
+}
+
+/// Max-product messages for any factor kind.
+pub(super) fn factor_max_product<'a>(
+    factor: &'a Factor,
+    belief_from_var: &'a mut EdgeSlice<Distribution>,
+    dest: &'a [VarId],
+    clear_incoming: bool,
+    pub_red: &'a PublicValue,
+    graph: &'a FactorGraph,
+) -> impl Iterator<Item = Distribution> + 'a {
+    let nc = graph.nc;
+    let tables = &graph.tables;
+    let not_table = || Some((0..nc).map(|x| ((nc - 1) ^ x) as ClassVal).collect());
+    // For a factor with a result var and a constant operand (or a constant result).
+    let (res_var, target, cst) = if factor.has_res {
+        (true, None, Some(pub_red))
+    } else {
+        (false, Some(pub_red.clone()), None)
+    };
+    let identities = || vec![None; factor.edges.len()];
+    let res = match &factor.kind {
+        FactorKind::XOR => OpFactor {
+            op: |a, b| a ^ b,
+            neutral: 0,
+            tables: identities(),
+            res_var,
+            target,
+            cst,
+        }
+        .messages(factor, belief_from_var, dest),
+        FactorKind::AND { vars_neg } => OpFactor {
+            op: |a, b| a & b,
+            neutral: (nc - 1) as ClassVal,
+            tables: vars_neg
+                .iter()
+                .map(|neg| if *neg { not_table() } else { None })
+                .collect(),
+            res_var,
+            target,
+            cst,
+        }
+        .messages(factor, belief_from_var, dest),
+        FactorKind::MUL => OpFactor {
+            op: |a, b| a * b % nc,
+            neutral: 1,
+            tables: identities(),
+            res_var,
+            target,
+            cst,
+        }
+        .messages(factor, belief_from_var, dest),
+        FactorKind::GFMUL => {
+            let field = graph.field.as_ref().unwrap();
+            OpFactor {
+                op: |a, b| field.mul(a as ClassVal, b as ClassVal) as usize,
+                neutral: 1,
+                tables: identities(),
+                res_var,
+                target,
+                cst,
+            }
+            .messages(factor, belief_from_var, dest)
+        }
+        // The sum of all the terms (including the result) is zero.
+        FactorKind::ADD { coefs } => OpFactor {
+            op: |a, b| (a + b) % nc,
+            neutral: 0,
+            tables: coefs
+                .iter()
+                .map(|c| {
+                    Some(
+                        (0..nc)
+                            .map(|x| (x * *c as usize % nc) as ClassVal)
+                            .collect(),
+                    )
+                })
+                .collect(),
+            res_var: false,
+            target: Some(PublicValue::Single(0)),
+            cst: Some(pub_red),
+        }
+        .messages(factor, belief_from_var, dest),
+        FactorKind::NOT => OpFactor {
+            op: |a, b| a ^ b,
+            neutral: 0,
+            tables: vec![None, not_table()],
+            res_var: true,
+            target: None,
+            cst: None,
+        }
+        .messages(factor, belief_from_var, dest),
+        FactorKind::LOOKUP { table } if tables[*table].ninputs == 1 => OpFactor {
+            op: |a, b| a ^ b,
+            neutral: 0,
+            tables: vec![None, Some(tables[*table].values.clone())],
+            res_var: true,
+            target: None,
+            cst: None,
+        }
+        .messages(factor, belief_from_var, dest),
+        FactorKind::LOOKUP { table } => {
+            let values = &tables[*table].values;
+            let d = |j: usize| &belief_from_var[factor.edges[j]];
+            dest.iter()
+                .map(|var| match factor.edges.get_index_of(var).unwrap() {
+                    0 => d(1).max_op(d(2), |a, b| values[a * nc + b] as usize),
+                    1 => d(0).max_op_inv(d(2), |a, b| values[a * nc + b] as usize),
+                    _ => d(0).max_op_inv(d(1), |b, a| values[a * nc + b] as usize),
+                })
+                .collect()
+        }
+        FactorKind::BITS => {
+            let distrs: Vec<_> = factor
+                .edges
+                .values()
+                .map(|e| &belief_from_var[*e])
+                .collect();
+            dest.iter()
+                .map(|var| {
+                    let i = factor.edges.get_index_of(var).unwrap();
+                    Distribution::bits_msg(distrs.as_slice(), i, true)
+                })
+                .collect()
+        }
+        FactorKind::HW => {
+            let d = |j: usize| &belief_from_var[factor.edges[j]];
+            let xor = |a: usize, b: usize| a ^ b;
+            let two_ops = factor.edges.len() == 3;
+            dest.iter()
+                .map(|var| {
+                    let i = factor.edges.get_index_of(var).unwrap();
+                    if i == 0 {
+                        let mut x = if two_ops {
+                            d(1).max_op(d(2), xor)
+                        } else {
+                            d(1).clone()
+                        };
+                        x.xor_cst(pub_red);
+                        x.map_hw(d(0).shape().1, true)
+                    } else {
+                        let mut x = d(0).map_hw_inv(d(i).shape().1);
+                        x.xor_cst(pub_red);
+                        if two_ops {
+                            x.max_op(d(3 - i), xor)
+                        } else {
+                            x
+                        }
+                    }
+                })
+                .collect()
+        }
+    };
+    if clear_incoming {
+        for e in factor.edges.values() {
+            belief_from_var[*e].reset();
+        }
+    }
+    res.into_iter()
+}
@@ -1,17 +1,31 @@
+mod analysis;
 mod belief_propagation;
 mod bp_compute;
+mod evidence;
+mod exact;
 mod factor_graph;
 mod fg_build;
+mod fg_export;
 mod fg_parser;
+mod fg_simplify;
+mod key_rank;
+mod max_product;
+mod netlist;
 
-pub use bp_compute::Distribution;
+pub use bp_compute::{Distribution, LogDistribution};
 
 pub type ClassVal = u32;
 type NamedList<T> = indexmap::IndexMap<String, T>;
 
-pub use belief_propagation::{BPError, BPState};
-pub use factor_graph::{EdgeId, FactorGraph, FactorId, PublicValue, VarId};
-pub use fg_build::GraphBuildError;
+pub use analysis::GraphAnalysis;
+pub use belief_propagation::{BPError, BPMode, BPSchedule, BPState, LoopyConfig, VarSummary};
+pub use evidence::{EvidenceModel, EvidenceSource};
+pub use exact::ExactCost;
+pub use factor_graph::{EdgeId, FGError, FactorGraph, FactorId, PublicValue, VarId};
+pub use fg_build::{FactorGraphBuilder, GraphBuildError};
+pub use fg_simplify::Elimination;
+pub use key_rank::KeyRankError;
+pub use netlist::{import_blif, import_verilog, NetlistConfig};
 
 pub fn build_graph(
     description: &str,
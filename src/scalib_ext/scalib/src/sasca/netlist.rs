@@ -0,0 +1,685 @@
+//! Import of gate-level netlists (BLIF, or a structural Verilog subset) as factor graphs over
+//! bits (NC 2).
+//!
+//! Every wire is a variable (or a public), and every gate is a AND, OR or XOR factor, with
+//! negated operands and result for NOT, NAND, NOR and XNOR gates.
+//! Indexed wires x[i] (bits of the bus x) can additionally be grouped into a wider variable x.
+
+use super::fg_build::{split_index, FactorGraphBuilder, GraphBuildError};
+use super::{ClassVal, FactorGraph};
+use indexmap::{IndexMap, IndexSet};
+use std::collections::HashMap;
+
+/// Maximum number of inputs of a BLIF logic cover.
+const MAX_COVER_INPUTS: usize = 16;
+
+/// Kind of the variables of a netlist graph, identified by wire or bus names.
+#[derive(Debug, Clone, Default)]
+pub struct NetlistConfig {
+    /// SINGLE wires (e.g., the key). Other undriven wires are MULTI, and the output of a gate
+    /// is MULTI if any of its inputs is MULTI.
+    pub single: Vec<String>,
+    /// Public wires (e.g., the plaintext).
+    pub public: Vec<String>,
+    /// Probed (or leaking) buses, each is grouped in a variable with NC 2^width (in addition
+    /// to its bits), on which evidence can be set.
+    pub probes: Vec<String>,
+}
+
+/// Read a BLIF netlist (a single model made of `.names` logic covers).
+pub fn import_blif(src: &str, config: &NetlistConfig) -> Result<FactorGraph, GraphBuildError> {
+    parse_blif(src)?.to_graph(config)
+}
+
+/// Read a structural Verilog netlist: a single module with `input`, `output` and `wire`
+/// declarations, primitive gates (`and`, `nand`, `or`, `nor`, `xor`, `xnor`, `not`, `buf`) and
+/// `assign` statements of the form `y = a & ~b & c`, `y = ~(a ^ b)`, etc.
+pub fn import_verilog(src: &str, config: &NetlistConfig) -> Result<FactorGraph, GraphBuildError> {
+    parse_verilog(src)?.to_graph(config)
+}
+
+fn netlist_error(line: usize, msg: impl Into<String>) -> GraphBuildError {
+    GraphBuildError::Netlist(line, msg.into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GateKind {
+    And,
+    Or,
+    Xor,
+}
+
+#[derive(Debug, Clone)]
+struct Gate {
+    name: Option<String>,
+    kind: GateKind,
+    /// Output wire, which is negated if out_neg.
+    out: String,
+    out_neg: bool,
+    /// Input wires and their negation (always false for XOR).
+    inputs: Vec<(String, bool)>,
+    line: usize,
+}
+
+impl Gate {
+    fn new(
+        kind: GateKind,
+        out: String,
+        mut out_neg: bool,
+        mut inputs: Vec<(String, bool)>,
+        line: usize,
+    ) -> Self {
+        if kind == GateKind::Xor {
+            for (_, neg) in inputs.iter_mut() {
+                out_neg ^= *neg;
+                *neg = false;
+            }
+        }
+        Self {
+            name: None,
+            kind,
+            out,
+            out_neg,
+            inputs,
+            line,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Netlist {
+    /// All the wires, in declaration order.
+    wires: IndexSet<String>,
+    gates: Vec<Gate>,
+}
+
+impl Netlist {
+    fn to_graph(&self, config: &NetlistConfig) -> Result<FactorGraph, GraphBuildError> {
+        let mut buses: IndexMap<&str, Vec<(u64, &str)>> = IndexMap::new();
+        for wire in self.wires.iter() {
+            if let (bus, Some(idx)) = split_index(wire) {
+                buses.entry(bus).or_default().push((idx, wire));
+            }
+        }
+        for name in config
+            .single
+            .iter()
+            .chain(config.public.iter())
+            .chain(config.probes.iter())
+        {
+            if !self.wires.contains(name) && !buses.contains_key(name.as_str()) {
+                return Err(GraphBuildError::UnknownWire(name.clone()));
+            }
+        }
+        let selected = |names: &[String], wire: &str| {
+            let bus = split_index(wire).0;
+            names.iter().any(|n| n == wire || n == bus)
+        };
+        let mut drivers = HashMap::new();
+        for gate in self.gates.iter() {
+            if drivers.insert(gate.out.as_str(), gate).is_some() {
+                return Err(GraphBuildError::MultipleDrivers(
+                    gate.out.clone(),
+                    gate.line,
+                ));
+            }
+        }
+        // Undriven wires are MULTI unless SINGLE, then propagate to gate outputs.
+        let mut multi = self
+            .wires
+            .iter()
+            .map(|w| {
+                let m = !drivers.contains_key(w.as_str()) && !selected(&config.single, w);
+                (w.as_str(), m)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for gate in self.gates.iter() {
+                if !multi[gate.out.as_str()] && gate.inputs.iter().any(|(w, _)| multi[w.as_str()]) {
+                    multi.insert(gate.out.as_str(), true);
+                    changed = true;
+                }
+            }
+        }
+        let mut builder = FactorGraphBuilder::new(2);
+        for wire in self.wires.iter() {
+            builder = if selected(&config.public, wire) {
+                builder.public(wire, multi[wire.as_str()])
+            } else {
+                builder.var(wire, multi[wire.as_str()])
+            };
+        }
+        for gate in self.gates.iter() {
+            builder = add_gate(builder, gate, multi[gate.out.as_str()]);
+        }
+        for probe in config.probes.iter() {
+            if let Some(bits) = buses.get(probe.as_str()) {
+                let mut bits = bits.clone();
+                bits.sort_unstable();
+                if bits
+                    .iter()
+                    .enumerate()
+                    .any(|(i, (idx, _))| i as u64 != *idx)
+                {
+                    return Err(GraphBuildError::BusBits(probe.clone()));
+                }
+                if bits.len() > ClassVal::BITS as usize {
+                    return Err(GraphBuildError::BusWidth(
+                        probe.clone(),
+                        bits.len(),
+                        ClassVal::BITS,
+                    ));
+                }
+                let parts = bits.iter().map(|(_, w)| *w).collect::<Vec<_>>();
+                let bus_multi = parts.iter().any(|w| multi[w]);
+                builder = builder
+                    .var_nc(probe, bus_multi, 1 << parts.len())
+                    .bits(&parts, probe);
+            }
+        }
+        builder.build()
+    }
+}
+
+fn add_gate(builder: FactorGraphBuilder, gate: &Gate, multi: bool) -> FactorGraphBuilder {
+    let out = gate.out.as_str();
+    let inputs = gate
+        .inputs
+        .iter()
+        .map(|(w, neg)| (w.as_str(), *neg))
+        .collect::<Vec<_>>();
+    let negated = inputs.iter().map(|(w, neg)| (*w, !neg)).collect::<Vec<_>>();
+    let xor_inputs = inputs.iter().map(|(w, _)| *w).collect::<Vec<_>>();
+    let builder = match (gate.kind, gate.out_neg) {
+        (GateKind::And, false) => builder.and(out, &inputs),
+        // De Morgan: !(a & b) = !a | !b, and !(a | b) = !a & !b.
+        (GateKind::And, true) => builder.or(out, &negated),
+        (GateKind::Or, false) => builder.or(out, &inputs),
+        (GateKind::Or, true) => builder.and(out, &negated),
+        (GateKind::Xor, false) => builder.xor(out, &xor_inputs),
+        (GateKind::Xor, true) if xor_inputs.len() == 1 => builder.not(out, xor_inputs[0]),
+        // XNOR, through an intermediate XOR.
+        (GateKind::Xor, true) => {
+            let inner = format!("{}__xnor", out);
+            builder
+                .var(&inner, multi)
+                .xor(&inner, &xor_inputs)
+                .not(out, &inner)
+        }
+    };
+    match &gate.name {
+        Some(name) => builder.named(name),
+        None => builder,
+    }
+}
+
+/// BLIF logic cover: inputs and output wires, rows of input patterns and output bit.
+#[derive(Debug)]
+struct Cover {
+    wires: Vec<String>,
+    rows: Vec<(String, bool)>,
+    line: usize,
+}
+
+/// Gate computed by a BLIF logic cover.
+fn cover_gate(cover: Cover) -> Result<Gate, GraphBuildError> {
+    let Cover {
+        mut wires,
+        rows,
+        line,
+    } = cover;
+    let out = wires.pop().unwrap();
+    let n = wires.len();
+    if n > MAX_COVER_INPUTS {
+        return Err(netlist_error(line, "too many inputs in logic cover."));
+    }
+    let out_bit = rows.first().map(|(_, b)| *b).unwrap_or(true);
+    if rows.iter().any(|(_, b)| *b != out_bit) {
+        return Err(netlist_error(line, "mixed output values in logic cover."));
+    }
+    let f = (0..(1usize << n))
+        .map(|m| {
+            let matched = rows.iter().any(|(pattern, _)| {
+                pattern.chars().enumerate().all(|(i, c)| match c {
+                    '-' => true,
+                    c => (c == '1') == ((m >> i) & 1 == 1),
+                })
+            });
+            matched == out_bit
+        })
+        .collect::<Vec<_>>();
+    let ones = (0..f.len()).filter(|m| f[*m]).collect::<Vec<_>>();
+    let zeros = (0..f.len()).filter(|m| !f[*m]).collect::<Vec<_>>();
+    let bit = |m: usize, i: usize| (m >> i) & 1 == 1;
+    let inputs = |neg: &dyn Fn(usize) -> bool| {
+        wires
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (w.clone(), neg(i)))
+            .collect::<Vec<_>>()
+    };
+    if n == 0 || ones.is_empty() || zeros.is_empty() {
+        Err(netlist_error(line, "constant wires are not supported."))
+    } else if (0..f.len()).all(|m| f[m] == ((m.count_ones() & 1 == 1) ^ f[0])) {
+        Ok(Gate::new(
+            GateKind::Xor,
+            out,
+            f[0],
+            inputs(&|_| false),
+            line,
+        ))
+    } else if ones.len() == 1 {
+        let neg = |i| !bit(ones[0], i);
+        Ok(Gate::new(GateKind::And, out, false, inputs(&neg), line))
+    } else if zeros.len() == 1 {
+        let neg = |i| bit(zeros[0], i);
+        Ok(Gate::new(GateKind::Or, out, false, inputs(&neg), line))
+    } else {
+        Err(netlist_error(
+            line,
+            "logic cover is not a (negated) AND, OR or XOR.",
+        ))
+    }
+}
+
+fn parse_blif(src: &str) -> Result<Netlist, GraphBuildError> {
+    // Logical lines (joining continuations), with the line number where they start.
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut continued = false;
+    for (i, l) in src.lines().enumerate() {
+        let l = l.split('#').next().unwrap();
+        let (l, cont) = match l.trim_end().strip_suffix('\\') {
+            Some(l) => (l, true),
+            None => (l, false),
+        };
+        if continued {
+            let last = lines.last_mut().unwrap();
+            last.1.push(' ');
+            last.1.push_str(l);
+        } else {
+            lines.push((i + 1, l.to_owned()));
+        }
+        continued = cont;
+    }
+    let mut netlist = Netlist::default();
+    let mut cover: Option<Cover> = None;
+    for (line, l) in lines.iter() {
+        let tokens = l.split_whitespace().collect::<Vec<_>>();
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+        if !first.starts_with('.') {
+            let Cover { wires, rows, .. } = cover
+                .as_mut()
+                .ok_or_else(|| netlist_error(*line, "cover row outside of .names."))?;
+            let (pattern, out) = match tokens.as_slice() {
+                [out] if wires.len() == 1 => ("", *out),
+                [pattern, out] if pattern.len() == wires.len() - 1 => (*pattern, *out),
+                _ => return Err(netlist_error(*line, "invalid cover row.")),
+            };
+            if !pattern.chars().all(|c| matches!(c, '0' | '1' | '-')) || !matches!(out, "0" | "1") {
+                return Err(netlist_error(*line, "invalid cover row."));
+            }
+            rows.push((pattern.to_owned(), out == "1"));
+            continue;
+        }
+        if let Some(cover) = cover.take() {
+            netlist.gates.push(cover_gate(cover)?);
+        }
+        match *first {
+            ".model" => {}
+            ".inputs" | ".outputs" => {
+                netlist
+                    .wires
+                    .extend(tokens[1..].iter().map(|w| w.to_string()));
+            }
+            ".names" if tokens.len() >= 2 => {
+                let wires = tokens[1..]
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>();
+                netlist.wires.extend(wires.iter().cloned());
+                cover = Some(Cover {
+                    wires,
+                    rows: Vec::new(),
+                    line: *line,
+                });
+            }
+            ".end" => break,
+            directive => {
+                return Err(netlist_error(
+                    *line,
+                    format!("unsupported directive {}.", directive),
+                ));
+            }
+        }
+    }
+    if let Some(cover) = cover.take() {
+        netlist.gates.push(cover_gate(cover)?);
+    }
+    Ok(netlist)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Num(u64),
+    /// Sized constant, e.g. 1'b0.
+    Const,
+    Sym(char),
+}
+
+/// Tokens of a Verilog source, with their line.
+fn tokenize_verilog(src: &str) -> Result<Vec<(Token, usize)>, GraphBuildError> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|c| *c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            prev = c;
+                        }
+                        None => return Err(netlist_error(line, "unterminated comment.")),
+                    }
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == '\\' => {
+                let mut ident = if c == '\\' {
+                    String::new()
+                } else {
+                    c.to_string()
+                };
+                // Escaped identifiers end at whitespace.
+                while let Some(c) = chars.next_if(|x| {
+                    if c == '\\' {
+                        !x.is_whitespace()
+                    } else {
+                        x.is_ascii_alphanumeric() || *x == '_' || *x == '$'
+                    }
+                }) {
+                    ident.push(c);
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            c if c.is_ascii_digit() => {
+                let mut num = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                    num.push(c);
+                }
+                if chars.next_if_eq(&'\'').is_some() {
+                    while chars.next_if(|c| c.is_ascii_alphanumeric()).is_some() {}
+                    tokens.push((Token::Const, line));
+                } else {
+                    let num = num
+                        .parse()
+                        .map_err(|_| netlist_error(line, "invalid number."))?;
+                    tokens.push((Token::Num(num), line));
+                }
+            }
+            '(' | ')' | '[' | ']' | ':' | ';' | ',' | '=' | '~' | '&' | '|' | '^' => {
+                tokens.push((Token::Sym(c), line));
+            }
+            c => return Err(netlist_error(line, format!("unexpected character {}.", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct VerilogParser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    line: usize,
+    netlist: Netlist,
+    /// Declared buses, with their width.
+    buses: HashMap<String, u64>,
+}
+
+impl VerilogParser {
+    fn error(&self, msg: impl Into<String>) -> GraphBuildError {
+        netlist_error(self.line, msg)
+    }
+    fn peek(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(t, _)| t)
+    }
+    fn next(&mut self) -> Result<Token, GraphBuildError> {
+        let (token, line) = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| self.error("unexpected end of file."))?;
+        self.pos += 1;
+        self.line = line;
+        Ok(token)
+    }
+    fn eat(&mut self, sym: char) -> bool {
+        if self.peek(0) == Some(&Token::Sym(sym)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, sym: char) -> Result<(), GraphBuildError> {
+        match self.next()? {
+            Token::Sym(c) if c == sym => Ok(()),
+            _ => Err(self.error(format!("expected {}.", sym))),
+        }
+    }
+    fn ident(&mut self) -> Result<String, GraphBuildError> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            Token::Const => Err(self.error("constant wires are not supported.")),
+            _ => Err(self.error("expected an identifier.")),
+        }
+    }
+    fn num(&mut self) -> Result<u64, GraphBuildError> {
+        match self.next()? {
+            Token::Num(num) => Ok(num),
+            _ => Err(self.error("expected a number.")),
+        }
+    }
+    /// Declaration of names, with optional range [msb:lsb].
+    fn declaration(&mut self, end: char) -> Result<(), GraphBuildError> {
+        if matches!(self.peek(0), Some(Token::Ident(k)) if k == "wire") {
+            self.next()?;
+        }
+        let range = if self.eat('[') {
+            let msb = self.num()?;
+            self.expect(':')?;
+            let lsb = self.num()?;
+            self.expect(']')?;
+            Some((msb.min(lsb), msb.max(lsb)))
+        } else {
+            None
+        };
+        loop {
+            let name = self.ident()?;
+            match range {
+                Some((lo, hi)) => {
+                    for i in lo..=hi {
+                        self.netlist.wires.insert(format!("{}[{}]", name, i));
+                    }
+                    self.buses.insert(name, hi - lo + 1);
+                }
+                None => {
+                    self.netlist.wires.insert(name);
+                }
+            }
+            // In a module header, a comma can be followed by another declaration.
+            if self.peek(0) == Some(&Token::Sym(end)) {
+                return Ok(());
+            }
+            self.expect(',')?;
+            if matches!(self.peek(0), Some(Token::Ident(k)) if is_direction(k)) {
+                return Ok(());
+            }
+        }
+    }
+    /// A wire (x or x[i]), implicitly declared if needed.
+    fn signal(&mut self) -> Result<String, GraphBuildError> {
+        let name = self.ident()?;
+        if self.eat('[') {
+            let idx = self.num()?;
+            self.expect(']')?;
+            let wire = format!("{}[{}]", name, idx);
+            if !self.netlist.wires.contains(&wire) {
+                return Err(self.error(format!("wire {} is not declared.", wire)));
+            }
+            Ok(wire)
+        } else if self.buses.contains_key(&name) {
+            Err(self.error(format!("bus {} must be indexed.", name)))
+        } else {
+            self.netlist.wires.insert(name.clone());
+            Ok(name)
+        }
+    }
+    fn literal(&mut self) -> Result<(String, bool), GraphBuildError> {
+        let neg = self.eat('~');
+        Ok((self.signal()?, neg))
+    }
+    /// Right-hand side of an assign statement.
+    fn assign_expr(&mut self, out: String) -> Result<Gate, GraphBuildError> {
+        let line = self.line;
+        let mut out_neg = false;
+        let paren = if self.eat('(') {
+            true
+        } else if self.peek(0) == Some(&Token::Sym('~')) && self.peek(1) == Some(&Token::Sym('(')) {
+            self.pos += 2;
+            out_neg = true;
+            true
+        } else {
+            false
+        };
+        let mut inputs = vec![self.literal()?];
+        let mut kind = None;
+        loop {
+            let op_kind = match self.peek(0) {
+                Some(Token::Sym('&')) => GateKind::And,
+                Some(Token::Sym('|')) => GateKind::Or,
+                Some(Token::Sym('^')) => GateKind::Xor,
+                _ => break,
+            };
+            self.next()?;
+            if *kind.get_or_insert(op_kind) != op_kind {
+                return Err(self.error("operators cannot be mixed in an assign."));
+            }
+            inputs.push(self.literal()?);
+        }
+        if paren {
+            self.expect(')')?;
+        }
+        Ok(Gate::new(
+            kind.unwrap_or(GateKind::Xor),
+            out,
+            out_neg,
+            inputs,
+            line,
+        ))
+    }
+    /// Gate primitive, with terminals (out, in...).
+    fn primitive(&mut self, prim: &str) -> Result<Gate, GraphBuildError> {
+        let line = self.line;
+        let name = match self.peek(0) {
+            Some(Token::Ident(_)) => Some(self.ident()?),
+            _ => None,
+        };
+        self.expect('(')?;
+        let out = self.signal()?;
+        let mut inputs = Vec::new();
+        while self.eat(',') {
+            inputs.push((self.signal()?, false));
+        }
+        self.expect(')')?;
+        let (kind, out_neg) = match prim {
+            "and" => (GateKind::And, false),
+            "nand" => (GateKind::And, true),
+            "or" => (GateKind::Or, false),
+            "nor" => (GateKind::Or, true),
+            "xor" | "buf" => (GateKind::Xor, false),
+            _ => (GateKind::Xor, true),
+        };
+        let unary = matches!(prim, "not" | "buf");
+        if inputs.is_empty() || (unary && inputs.len() != 1) {
+            return Err(self.error(format!("wrong number of terminals for {}.", prim)));
+        }
+        let mut gate = Gate::new(kind, out, out_neg, inputs, line);
+        gate.name = name;
+        Ok(gate)
+    }
+    fn module(&mut self) -> Result<(), GraphBuildError> {
+        if self.ident()? != "module" {
+            return Err(self.error("expected module."));
+        }
+        self.ident()?;
+        if self.eat('(') && !self.eat(')') {
+            loop {
+                match self.peek(0) {
+                    Some(Token::Ident(k)) if is_direction(k) => {
+                        self.next()?;
+                        self.declaration(')')?;
+                    }
+                    // Non-ANSI port, declared in the module body.
+                    _ => {
+                        self.ident()?;
+                        if self.peek(0) != Some(&Token::Sym(')')) {
+                            self.expect(',')?;
+                        }
+                    }
+                }
+                if self.eat(')') {
+                    break;
+                }
+            }
+        }
+        self.expect(';')?;
+        loop {
+            let kw = self.ident()?;
+            match kw.as_str() {
+                "endmodule" => return Ok(()),
+                k if is_direction(k) || k == "wire" => self.declaration(';')?,
+                "and" | "nand" | "or" | "nor" | "xor" | "xnor" | "not" | "buf" => {
+                    let gate = self.primitive(&kw)?;
+                    self.netlist.gates.push(gate);
+                }
+                "assign" => {
+                    let out = self.signal()?;
+                    self.expect('=')?;
+                    let gate = self.assign_expr(out)?;
+                    self.netlist.gates.push(gate);
+                }
+                k => return Err(self.error(format!("unsupported statement {}.", k))),
+            }
+            self.expect(';')?;
+        }
+    }
+}
+
+fn is_direction(k: &str) -> bool {
+    matches!(k, "input" | "output" | "inout")
+}
+
+fn parse_verilog(src: &str) -> Result<Netlist, GraphBuildError> {
+    let mut parser = VerilogParser {
+        tokens: tokenize_verilog(src)?,
+        pos: 0,
+        line: 1,
+        netlist: Netlist::default(),
+        buses: HashMap::new(),
+    };
+    parser.module()?;
+    Ok(parser.netlist)
+}
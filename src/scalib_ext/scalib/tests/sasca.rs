@@ -0,0 +1,2234 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ndarray::Array1;
+use scalib::sasca::{
+    self, BPMode, BPState, Distribution, GraphBuildError, KeyRankError, PublicValue,
+};
+
+fn build_graph(description: &str) -> Arc<sasca::FactorGraph> {
+    Arc::new(sasca::build_graph(description, HashMap::new()).unwrap())
+}
+
+/// Build the graph, with the build errors stripped of their line.
+fn build_unlocated(
+    description: &str,
+    tables: HashMap<String, Vec<sasca::ClassVal>>,
+) -> Result<sasca::FactorGraph, GraphBuildError> {
+    sasca::build_graph(description, tables).map_err(|e| match e {
+        GraphBuildError::AtLine(_, e) => *e,
+        e => e,
+    })
+}
+
+#[test]
+fn rank_key_from_bp() {
+    let graph = build_graph(
+        "
+        NC 4
+        VAR SINGLE k0
+        VAR SINGLE k1
+        VAR MULTI x
+        PUB MULTI p
+        PROPERTY x = k0 ^ p
+        ",
+    );
+    let mut bp = BPState::new(graph, 2, vec![PublicValue::Multi(vec![0, 1])]);
+    let k0 = Array1::from_vec(vec![0.1, 0.6, 0.2, 0.1]);
+    let k1 = Array1::from_vec(vec![4.0, 3.0, 2.0, 1.0]);
+    bp.set_state(
+        bp.get_graph().get_varid("k0").unwrap(),
+        Distribution::from_array_single(k0).unwrap(),
+    )
+    .unwrap();
+    bp.set_state(
+        bp.get_graph().get_varid("k1").unwrap(),
+        Distribution::from_array_single(k1).unwrap(),
+    )
+    .unwrap();
+    let rank = |key: &[sasca::ClassVal]| {
+        bp.rank_accuracy(
+            &["k0", "k1"],
+            key,
+            ranklib::RankingMethod::Naive,
+            1.0,
+            None,
+            1 << 10,
+        )
+    };
+    assert_eq!(rank(&[1, 0]).unwrap().est, 1.0);
+    assert_eq!(rank(&[1, 1]).unwrap().est, 2.0);
+    assert_eq!(rank(&[1, 2]).unwrap().est, 3.0);
+    assert_eq!(rank(&[2, 0]).unwrap().est, 4.0);
+    assert!(matches!(
+        bp.rank_accuracy(
+            &["k0", "x"],
+            &[0, 0],
+            ranklib::RankingMethod::Hist,
+            1.0,
+            None,
+            1 << 10
+        ),
+        Err(KeyRankError::MultiVar(_))
+    ));
+    assert!(matches!(rank(&[0, 4]), Err(KeyRankError::KeyValue(..))));
+    assert!(matches!(rank(&[0]), Err(KeyRankError::KeyLength(2, 1))));
+}
+
+fn gf16_mul(a: usize, b: usize) -> usize {
+    // Reduction polynomial x^4 + x + 1.
+    let mut res = 0;
+    for i in 0..4 {
+        if (b >> i) & 1 == 1 {
+            res ^= a << i;
+        }
+    }
+    for i in (4..8).rev() {
+        if (res >> i) & 1 == 1 {
+            res ^= 0x13 << (i - 4);
+        }
+    }
+    res
+}
+
+#[test]
+fn gfmul_bp() {
+    let nc = 16;
+    let graph = build_graph(
+        "
+        NC 16
+        FIELD 0x13
+        VAR MULTI y
+        VAR MULTI a
+        VAR SINGLE b
+        PUB MULTI c
+        PROPERTY y = a . b . c
+        ",
+    );
+    let c: Vec<sasca::ClassVal> = vec![7, 1];
+    let nmulti = c.len();
+    let distr = |seed: usize| {
+        ndarray::Array2::from_shape_fn((nmulti, nc), |(i, j)| {
+            ((i * 13 + j * 7 + seed * 5) % 11 + 1) as f64
+        })
+    };
+    let (ev_y, ev_a) = (distr(0), distr(1));
+    // Brute-force marginals (uniform prior on b).
+    let mut ref_y = ndarray::Array2::<f64>::zeros((nmulti, nc));
+    let mut ref_a = ndarray::Array2::<f64>::zeros((nmulti, nc));
+    let mut ref_b = ndarray::Array1::<f64>::zeros(nc);
+    for b in 0..nc {
+        // For each trace, weight of each (a, y) given b.
+        let weights: Vec<Vec<(usize, usize, f64)>> = (0..nmulti)
+            .map(|n| {
+                (0..nc)
+                    .map(|a| {
+                        let y = gf16_mul(gf16_mul(a, b), c[n] as usize);
+                        (a, y, ev_a[(n, a)] * ev_y[(n, y)])
+                    })
+                    .collect()
+            })
+            .collect();
+        let totals: Vec<f64> = weights
+            .iter()
+            .map(|w| w.iter().map(|x| x.2).sum())
+            .collect();
+        let total: f64 = totals.iter().product();
+        ref_b[b] += total;
+        for n in 0..nmulti {
+            for (a, y, w) in weights[n].iter() {
+                let p = w * total / totals[n];
+                ref_a[(n, *a)] += p;
+                ref_y[(n, *y)] += p;
+            }
+        }
+    }
+    let mut bp = BPState::new(graph, nmulti as u32, vec![PublicValue::Multi(c)]);
+    let var = |bp: &BPState, name: &str| bp.get_graph().get_varid(name).unwrap();
+    for (name, ev) in [("y", ev_y), ("a", ev_a)] {
+        let v = var(&bp, name);
+        bp.set_evidence(v, Distribution::from_array_multi(ev).unwrap())
+            .unwrap();
+    }
+    let check = |res: ndarray::ArrayView2<f64>, reference: ndarray::ArrayView2<f64>| {
+        for (r, e) in res.outer_iter().zip(reference.outer_iter()) {
+            let (sr, se) = (r.sum(), e.sum());
+            for (r, e) in r.iter().zip(e.iter()) {
+                assert!((r / sr - e / se).abs() < 1e-9);
+            }
+        }
+    };
+    // The graph is a tree once unrolled over the traces, hence loopy BP converges to the exact
+    // marginals.
+    bp.propagate_all_vars(false);
+    bp.propagate_loopy_step(3, false);
+    for (name, reference) in [
+        ("y", ref_y.view()),
+        ("a", ref_a.view()),
+        ("b", ref_b.view().insert_axis(ndarray::Axis(0))),
+    ] {
+        let v = var(&bp, name);
+        check(bp.get_state(v).value().unwrap(), reference);
+    }
+}
+
+#[test]
+fn gfmul_zero_message() {
+    let graph =
+        build_graph("NC 16\nFIELD 0x13\nVAR MULTI y\nVAR MULTI a\nVAR MULTI b\nPROPERTY y = a . b");
+    let nmulti = 2;
+    let mut bp = BPState::new(graph.clone(), nmulti as u32, vec![]);
+    let factor = graph.range_factors().next().unwrap();
+    let edge = |name: &str| graph.edge(graph.get_varid(name).unwrap(), factor).unwrap();
+    let distr = |seed: usize| {
+        ndarray::Array2::from_shape_fn((nmulti, 16), |(i, j)| {
+            ((i * 3 + j * 5 + seed) % 7 + 1) as f64
+        })
+    };
+    // The message from a is zero for the second execution.
+    let mut msg_a = distr(0);
+    msg_a.row_mut(1).fill(0.0);
+    bp.set_belief_from_var(edge("a"), Distribution::from_array_multi(msg_a).unwrap())
+        .unwrap();
+    bp.set_belief_from_var(edge("y"), Distribution::from_array_multi(distr(1)).unwrap())
+        .unwrap();
+    let b = graph.get_varid("b").unwrap();
+    bp.propagate_factor(factor, &[b], false);
+    let msg_b = bp.get_belief_to_var(edge("b")).value().unwrap();
+    assert!(msg_b.iter().all(|p| p.is_finite()));
+}
+
+#[test]
+fn gfmul_build_errors() {
+    let build = |desc: &str| build_unlocated(desc, HashMap::new());
+    assert!(matches!(
+        build("NC 16\nVAR SINGLE x\nVAR SINGLE y\nVAR SINGLE z\nPROPERTY z = x . y"),
+        Err(GraphBuildError::NoField(_))
+    ));
+    assert!(matches!(
+        build("NC 16\nFIELD 0x11\nVAR SINGLE x\nVAR SINGLE y\nVAR SINGLE z\nPROPERTY z = x . y"),
+        Err(GraphBuildError::InvalidField(0x11))
+    ));
+    assert!(matches!(
+        build("NC 16\nFIELD 0x10000000000000000\nVAR SINGLE x"),
+        Err(GraphBuildError::Parse(_))
+    ));
+    assert!(matches!(
+        build("NC 16\nFIELD 19\nVAR SINGLE x\nVAR SINGLE y\nPUB SINGLE p\nPROPERTY p = x . y"),
+        Err(GraphBuildError::GfMulPubRes(_))
+    ));
+    assert!(build(
+        "NC 256\nFIELD 0x11b\nVAR SINGLE x\nVAR SINGLE y\nVAR SINGLE z\nPROPERTY z = x . y"
+    )
+    .is_ok());
+}
+
+#[test]
+fn lookup2_bp() {
+    let nc = 8;
+    let table: Vec<sasca::ClassVal> = (0..nc * nc)
+        .map(|i| {
+            ((i / nc) * 3 + (i % nc) * (i % nc) + 1) as sasca::ClassVal % nc as sasca::ClassVal
+        })
+        .collect();
+    let graph = sasca::build_graph(
+        "
+        NC 8
+        TABLE t
+        VAR MULTI y
+        VAR MULTI a
+        VAR SINGLE b
+        PROPERTY y = t[a, b]
+        ",
+        HashMap::from([("t".to_owned(), table.clone())]),
+    )
+    .unwrap();
+    let graph = Arc::new(graph);
+    let nmulti = 2;
+    let ev_y =
+        ndarray::Array2::from_shape_fn((nmulti, nc), |(i, j)| ((i * 5 + j * 3) % 7 + 1) as f64);
+    let ev_a =
+        ndarray::Array2::from_shape_fn((nmulti, nc), |(i, j)| ((i * 3 + j * 5) % 9 + 1) as f64);
+    // Brute-force marginals (uniform prior on b).
+    let mut ref_y = ndarray::Array2::<f64>::zeros((nmulti, nc));
+    let mut ref_a = ndarray::Array2::<f64>::zeros((nmulti, nc));
+    let mut ref_b = ndarray::Array1::<f64>::zeros(nc);
+    for b in 0..nc {
+        let weights: Vec<Vec<(usize, usize, f64)>> = (0..nmulti)
+            .map(|n| {
+                (0..nc)
+                    .map(|a| {
+                        let y = table[a * nc + b] as usize;
+                        (a, y, ev_a[(n, a)] * ev_y[(n, y)])
+                    })
+                    .collect()
+            })
+            .collect();
+        let totals: Vec<f64> = weights
+            .iter()
+            .map(|w| w.iter().map(|x| x.2).sum())
+            .collect();
+        let total: f64 = totals.iter().product();
+        ref_b[b] += total;
+        for n in 0..nmulti {
+            for (a, y, w) in weights[n].iter() {
+                let p = w * total / totals[n];
+                ref_a[(n, *a)] += p;
+                ref_y[(n, *y)] += p;
+            }
+        }
+    }
+    let mut bp = BPState::new(graph, nmulti as u32, vec![]);
+    let var = |bp: &BPState, name: &str| bp.get_graph().get_varid(name).unwrap();
+    for (name, ev) in [("y", ev_y), ("a", ev_a)] {
+        let v = var(&bp, name);
+        bp.set_evidence(v, Distribution::from_array_multi(ev).unwrap())
+            .unwrap();
+    }
+    bp.propagate_all_vars(false);
+    bp.propagate_loopy_step(3, false);
+    for (name, reference) in [
+        ("y", ref_y.view()),
+        ("a", ref_a.view()),
+        ("b", ref_b.view().insert_axis(ndarray::Axis(0))),
+    ] {
+        let v = var(&bp, name);
+        let res = bp.get_state(v).value().unwrap().to_owned();
+        for (r, e) in res.outer_iter().zip(reference.outer_iter()) {
+            let (sr, se) = (r.sum(), e.sum());
+            for (r, e) in r.iter().zip(e.iter()) {
+                assert!((r / sr - e / se).abs() < 1e-9);
+            }
+        }
+    }
+}
+
+#[test]
+fn lookup2_build_errors() {
+    let tables = || {
+        HashMap::from([
+            ("t1".to_owned(), vec![0; 4]),
+            ("t2".to_owned(), vec![0; 16]),
+        ])
+    };
+    let build = |desc: &str| build_unlocated(desc, tables());
+    let decl = "NC 4\nTABLE t1\nTABLE t2\nVAR SINGLE x\nVAR SINGLE y\nVAR SINGLE z\nPUB SINGLE p\n";
+    assert!(build(&format!("{}PROPERTY z = t2[x, y]", decl)).is_ok());
+    assert!(build(&format!("{}PROPERTY z = t1[x]", decl)).is_ok());
+    assert!(matches!(
+        build(&format!("{}PROPERTY z = t1[x, y]", decl)),
+        Err(GraphBuildError::TableArity(_, 1, 2, _))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY z = t2[x]", decl)),
+        Err(GraphBuildError::TableArity(_, 2, 1, _))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY z = t2[x, p]", decl)),
+        Err(GraphBuildError::LookupPublic(_, _))
+    ));
+    assert!(matches!(
+        sasca::build_graph(
+            "NC 4\nTABLE t = [0, 1, 2]\nVAR SINGLE x\nVAR SINGLE y\nPROPERTY y = t[x]",
+            HashMap::new()
+        ),
+        Err(GraphBuildError::AtLine(2, e)) if matches!(*e, GraphBuildError::TableSize(_, 3))
+    ));
+}
+
+/// Compare the BP marginals with brute-force ones for a graph made of a single factor over
+/// MULTI vars (nmulti = 2), where valid tells if an assignment of vars satisfies the factor.
+fn check_single_factor_bp(
+    description: &str,
+    nc: usize,
+    vars: &[&str],
+    publics: Vec<PublicValue>,
+    valid: impl Fn(&[usize]) -> bool,
+) {
+    let ncs = vec![nc; vars.len()];
+    check_single_factor_bp_ncs(description, &ncs, vars, publics, valid);
+}
+
+/// Same as check_single_factor_bp, with a domain size ncs[i] for vars[i].
+fn check_single_factor_bp_ncs(
+    description: &str,
+    ncs: &[usize],
+    vars: &[&str],
+    publics: Vec<PublicValue>,
+    valid: impl Fn(&[usize]) -> bool,
+) {
+    let graph = build_graph(description);
+    check_single_factor_graph(graph, ncs, vars, publics, valid, BPMode::SumProduct);
+}
+
+/// Same as check_single_factor_bp_ncs, for a built graph and marginals (or max-marginals)
+/// according to mode.
+fn check_single_factor_graph(
+    graph: Arc<sasca::FactorGraph>,
+    ncs: &[usize],
+    vars: &[&str],
+    publics: Vec<PublicValue>,
+    valid: impl Fn(&[usize]) -> bool,
+    mode: BPMode,
+) {
+    let nmulti = 2;
+    let evidence: Vec<ndarray::Array2<f64>> = ncs
+        .iter()
+        .enumerate()
+        .map(|(v, nc)| {
+            ndarray::Array2::from_shape_fn((nmulti, *nc), |(i, j)| {
+                ((i * 7 + j * 5 + v * 3) % 13 + 1) as f64
+            })
+        })
+        .collect();
+    let mut reference: Vec<_> = ncs
+        .iter()
+        .map(|nc| ndarray::Array2::<f64>::zeros((nmulti, *nc)))
+        .collect();
+    for n in 0..nmulti {
+        for x in 0..ncs.iter().product() {
+            let assignment: Vec<usize> = (0..vars.len())
+                .map(|v| (x / ncs[..v].iter().product::<usize>()) % ncs[v])
+                .collect();
+            if valid(&assignment) {
+                let p: f64 = assignment
+                    .iter()
+                    .zip(evidence.iter())
+                    .map(|(a, ev)| ev[(n, *a)])
+                    .product();
+                for (a, r) in assignment.iter().zip(reference.iter_mut()) {
+                    match mode {
+                        BPMode::SumProduct => r[(n, *a)] += p,
+                        BPMode::MaxProduct => r[(n, *a)] = r[(n, *a)].max(p),
+                    }
+                }
+            }
+        }
+    }
+    // BP is exact on a single factor, check it as well as exact inference.
+    for exact in [false, true] {
+        let mut bp = BPState::new(graph.clone(), nmulti as u32, publics.clone());
+        bp.set_mode(mode);
+        for (name, ev) in vars.iter().zip(evidence.iter()) {
+            let v = bp.get_graph().get_varid(name).unwrap();
+            bp.set_evidence(v, Distribution::from_array_multi(ev.clone()).unwrap())
+                .unwrap();
+        }
+        if exact {
+            bp.propagate_exact(1 << 20).unwrap();
+        } else {
+            bp.propagate_all_vars(false);
+            bp.propagate_loopy_step(2, false);
+        }
+        for (name, reference) in vars.iter().zip(reference.iter()) {
+            let v = bp.get_graph().get_varid(name).unwrap();
+            let res = bp.get_state(v).value().unwrap().to_owned();
+            for (r, e) in res.outer_iter().zip(reference.outer_iter()) {
+                let (sr, se) = (r.sum(), e.sum());
+                for (r, e) in r.iter().zip(e.iter()) {
+                    assert!(
+                        (r / sr - e / se).abs() < 1e-9,
+                        "{} (exact: {}): {} {}",
+                        name,
+                        exact,
+                        r / sr,
+                        e / se
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn add_sub_bp() {
+    let decl = "VAR MULTI y\nVAR MULTI a\nVAR MULTI b\nPUB SINGLE p\n";
+    let p = 5;
+    for nc in [7, 8] {
+        let graph = |prop: &str| format!("NC {}\n{}PROPERTY {}", nc, decl, prop);
+        let publics = || vec![PublicValue::Single(p as sasca::ClassVal)];
+        check_single_factor_bp(&graph("y = a - b"), nc, &["y", "a", "b"], publics(), |x| {
+            x[0] == (x[1] + nc - x[2]) % nc
+        });
+        check_single_factor_bp(&graph("y = -a"), nc, &["y", "a"], publics(), |x| {
+            x[0] == (nc - x[1]) % nc
+        });
+        check_single_factor_bp(&graph("y = -a - p"), nc, &["y", "a"], publics(), |x| {
+            x[0] == (2 * nc - x[1] - p) % nc
+        });
+        check_single_factor_bp(&graph("y = 3*a"), nc, &["y", "a"], publics(), |x| {
+            x[0] == (3 * x[1]) % nc
+        });
+        check_single_factor_bp(&graph("y = 2 * a - p"), nc, &["y", "a"], publics(), |x| {
+            x[0] == (2 * x[1] + nc - p) % nc
+        });
+        check_single_factor_bp(&graph("y = 2*a"), nc, &["y", "a"], publics(), |x| {
+            x[0] == (2 * x[1]) % nc
+        });
+        check_single_factor_bp(
+            &graph("y = -a + 2*b - p"),
+            nc,
+            &["y", "a", "b"],
+            publics(),
+            |x| x[0] == (nc - x[1] + 2 * x[2] + nc - p) % nc,
+        );
+        check_single_factor_bp(&graph("p = a - 3*b"), nc, &["a", "b"], publics(), |x| {
+            (x[0] + 3 * (nc - x[1])) % nc == p
+        });
+    }
+}
+
+#[test]
+fn mul_pub_bp() {
+    let decl = "VAR MULTI y\nVAR MULTI a\nPUB SINGLE p\nPUB SINGLE q\n";
+    let (p, q) = (5, 3);
+    for nc in [7, 8] {
+        let graph = |prop: &str| format!("NC {}\n{}PROPERTY {}", nc, decl, prop);
+        let publics = || {
+            vec![
+                PublicValue::Single(p as sasca::ClassVal),
+                PublicValue::Single(q as sasca::ClassVal),
+            ]
+        };
+        check_single_factor_bp(&graph("y = a * p"), nc, &["y", "a"], publics(), |x| {
+            x[0] == (x[1] * p) % nc
+        });
+        check_single_factor_bp(&graph("y = a * p * q"), nc, &["y", "a"], publics(), |x| {
+            x[0] == (x[1] * p * q) % nc
+        });
+    }
+}
+
+#[test]
+fn add_sub_build_errors() {
+    let build = |desc: &str| build_unlocated(desc, HashMap::new());
+    let decl = "NC 7\nVAR SINGLE x\nVAR SINGLE y\nVAR SINGLE z\nPUB SINGLE p\n";
+    assert!(build(&format!("{}PROPERTY z = x - y + p", decl)).is_ok());
+    assert!(build(&format!("{}PROPERTY z = -x", decl)).is_ok());
+    assert!(build(&format!("{}PROPERTY z = 6 * x - 1*y", decl)).is_ok());
+    assert!(matches!(
+        build(&format!("{}PROPERTY z = x + 7*y", decl)),
+        Err(GraphBuildError::ZeroCoef(_, _))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY z = x + 2*p", decl)),
+        Err(GraphBuildError::PubCoef(_, _))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY z = x +", decl)),
+        Err(GraphBuildError::Parse(_))
+    ));
+    // Out of range integers are parse errors.
+    assert!(matches!(
+        build(&format!("{}PROPERTY z = 99999999999999999999*x + y", decl)),
+        Err(GraphBuildError::Parse(_))
+    ));
+    assert!(matches!(
+        build("NC 99999999999999999999\nVAR SINGLE x"),
+        Err(GraphBuildError::Parse(_))
+    ));
+}
+
+#[test]
+fn bits_bp() {
+    let decl = "NC 2\nVAR MULTI x NC 24\nVAR MULTI l NC 4\nVAR MULTI m NC 3\nVAR MULTI b\n";
+    check_single_factor_bp_ncs(
+        &format!("{}PROPERTY l, m, b = BITS(x)", decl),
+        &[24, 4, 3, 2],
+        &["x", "l", "m", "b"],
+        vec![],
+        |x| x[0] == x[1] + 4 * x[2] + 12 * x[3],
+    );
+    let decl = "NC 2\nVAR MULTI x NC 8\nVAR MULTI b0\nVAR MULTI b1\nVAR MULTI b2\n";
+    check_single_factor_bp_ncs(
+        &format!("{}PROPERTY b0, b1, b2 = BITS(x)", decl),
+        &[8, 2, 2, 2],
+        &["x", "b0", "b1", "b2"],
+        vec![],
+        |x| x[0] == x[1] + 2 * x[2] + 4 * x[3],
+    );
+}
+
+#[test]
+fn bits_build_errors() {
+    let build = |desc: &str| build_unlocated(desc, HashMap::new());
+    let decl = "NC 4\nVAR SINGLE x NC 16\nVAR SINGLE l\nVAR SINGLE h\nVAR SINGLE y\nPUB SINGLE p\n";
+    assert!(build(&format!("{}PROPERTY l, h = BITS(x)", decl)).is_ok());
+    assert!(build(&format!("{}PROPERTY y = l ^ h", decl)).is_ok());
+    assert!(matches!(
+        build(&format!("{}PROPERTY l = BITS(x)", decl)),
+        Err(GraphBuildError::BitsSize(_, 4, 16))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY l, p = BITS(x)", decl)),
+        Err(GraphBuildError::BitsPublic(_, _))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY y = x ^ l", decl)),
+        Err(GraphBuildError::FactorVarNc(_, _, 16, 4))
+    ));
+    assert!(matches!(
+        build("NC 4\nVAR SINGLE x NC 1"),
+        Err(GraphBuildError::VarNc(_, 1))
+    ));
+}
+
+#[test]
+fn hw_bp() {
+    let decl = "NC 8\nVAR MULTI h NC 4\nVAR MULTI x\nVAR MULTI y\nPUB SINGLE p\n";
+    let graph = |prop: &str| format!("{}PROPERTY {}", decl, prop);
+    let p = 5;
+    let publics = || vec![PublicValue::Single(p as sasca::ClassVal)];
+    let ncs = [4, 8, 8];
+    check_single_factor_bp_ncs(&graph("h = HW(x)"), &ncs, &["h", "x"], publics(), |x| {
+        x[0] == x[1].count_ones() as usize
+    });
+    check_single_factor_bp_ncs(
+        &graph("h = HD(x, y)"),
+        &ncs,
+        &["h", "x", "y"],
+        publics(),
+        |x| x[0] == (x[1] ^ x[2]).count_ones() as usize,
+    );
+    check_single_factor_bp_ncs(&graph("h = HD(p, x)"), &ncs, &["h", "x"], publics(), |x| {
+        x[0] == (x[1] ^ p).count_ones() as usize
+    });
+}
+
+#[test]
+fn hw_build_errors() {
+    let build = |desc: &str| build_unlocated(desc, HashMap::new());
+    let decl =
+        "NC 16\nVAR SINGLE h NC 5\nVAR SINGLE s NC 4\nVAR SINGLE x\nVAR SINGLE y\nPUB SINGLE p\n";
+    assert!(build(&format!("{}PROPERTY h = HD(x, y)", decl)).is_ok());
+    assert!(build(&format!("{}PROPERTY y = HW(x)", decl)).is_ok());
+    assert!(matches!(
+        build(&format!("{}PROPERTY s = HW(x)", decl)),
+        Err(GraphBuildError::HwNc(_, _, 4, 5))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY p = HW(x)", decl)),
+        Err(GraphBuildError::HwPubRes(_))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY h = HW(p)", decl)),
+        Err(GraphBuildError::HwPubOperands(_))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY h = HW(s)", decl)),
+        Err(GraphBuildError::FactorVarNc(_, _, 4, 16))
+    ));
+    assert!(matches!(
+        build(&format!("{}PROPERTY h = HD(x)", decl)),
+        Err(GraphBuildError::Parse(_))
+    ));
+    assert!(matches!(
+        build("NC 6\nVAR SINGLE h NC 4\nVAR SINGLE x\nPROPERTY h = HW(x)"),
+        Err(GraphBuildError::HwNcPow2(_, 6))
+    ));
+}
+
+/// Run loopy BP with the given config from the evidence on each var, and return the number of
+/// iterations and the resulting var states.
+fn run_loopy(
+    description: &str,
+    config: &sasca::LoopyConfig,
+    n_steps: u32,
+) -> (u32, Vec<ndarray::Array2<f64>>) {
+    let mut bp = BPState::new(build_graph(description), 1, vec![]);
+    let vars: Vec<_> = bp.get_graph().range_vars().collect();
+    for (i, v) in vars.iter().enumerate() {
+        let ev = Array1::from_shape_fn(4, |j| ((i * 5 + j * 3) % 7 + 1) as f64);
+        bp.set_evidence(*v, Distribution::from_array_single(ev).unwrap())
+            .unwrap();
+    }
+    bp.propagate_all_vars(false);
+    let n = bp.propagate_loopy(n_steps, true, config).unwrap();
+    let states = vars
+        .iter()
+        .map(|v| {
+            let s = bp.get_state(*v).value().unwrap();
+            &s / s.sum()
+        })
+        .collect();
+    (n, states)
+}
+
+#[test]
+fn loopy_schedules() {
+    let decl = "NC 4\nVAR SINGLE a\nVAR SINGLE b\nVAR SINGLE c\nVAR SINGLE d\nVAR SINGLE e\n";
+    let tree = format!("{}PROPERTY c = a ^ b\nPROPERTY d = c & e", decl);
+    let cyclic = format!(
+        "{}PROPERTY c = a ^ b\nPROPERTY d = a & b\nPROPERTY e = c + d",
+        decl
+    );
+    for graph in [tree, cyclic] {
+        let (_, reference) = run_loopy(&graph, &sasca::LoopyConfig::default(), 100);
+        for schedule in [
+            sasca::BPSchedule::Flooding,
+            sasca::BPSchedule::Sequential,
+            sasca::BPSchedule::SortedSweep,
+        ] {
+            for damping in [0.0, 0.4] {
+                let config = sasca::LoopyConfig {
+                    damping,
+                    tolerance: Some(1e-12),
+                    schedule,
+                };
+                let (n, states) = run_loopy(&graph, &config, 100);
+                assert!(n < 100, "{:?} {} {}", schedule, damping, n);
+                for (s, r) in states.iter().zip(reference.iter()) {
+                    assert!(s.iter().zip(r.iter()).all(|(s, r)| (s - r).abs() < 1e-9));
+                }
+            }
+        }
+    }
+    let config = sasca::LoopyConfig {
+        damping: 1.0,
+        ..Default::default()
+    };
+    let mut bp = BPState::new(build_graph("NC 2\nVAR SINGLE x"), 1, vec![]);
+    assert!(matches!(
+        bp.propagate_loopy(1, false, &config),
+        Err(sasca::BPError::InvalidDamping(_))
+    ));
+}
+
+#[test]
+fn max_product_bp() {
+    let check =
+        |description: &str, ncs: &[usize], vars: &[&str], valid: &dyn Fn(&[usize]) -> bool| {
+            let table: Vec<sasca::ClassVal> = (0..64).map(|i| (i * 5 + i / 8 + 3) % 8).collect();
+            let tables = HashMap::from([
+                ("s".to_owned(), table[..8].to_vec()),
+                ("t".to_owned(), table),
+            ]);
+            let graph = Arc::new(sasca::build_graph(description, tables).unwrap());
+            let publics = vec![PublicValue::Single(5)];
+            check_single_factor_graph(graph, ncs, vars, publics, valid, BPMode::MaxProduct);
+        };
+    let decl =
+        "NC 8\nFIELD 0xb\nTABLE s\nTABLE t\nVAR MULTI y\nVAR MULTI a\nVAR MULTI b\nPUB SINGLE p\n";
+    let graph = |prop: &str| format!("{}PROPERTY {}", decl, prop);
+    let t = |i: usize| (i * 5 + i / 8 + 3) % 8;
+    let gf8_mul = |a: usize, b: usize| {
+        (0..3).fold(0, |acc, i| {
+            if (b >> i) & 1 == 1 {
+                let mut x = a << i;
+                for j in (3..5).rev() {
+                    if (x >> j) & 1 == 1 {
+                        x ^= 0xb << (j - 3);
+                    }
+                }
+                acc ^ x
+            } else {
+                acc
+            }
+        })
+    };
+    let yab = [8, 8, 8];
+    let names = ["y", "a", "b"];
+    check(&graph("y = a ^ b ^ p"), &yab, &names, &|x| {
+        x[0] == x[1] ^ x[2] ^ 5
+    });
+    check(&graph("p = a ^ b"), &yab[1..], &names[1..], &|x| {
+        x[0] ^ x[1] == 5
+    });
+    check(&graph("y = a & !b"), &yab, &names, &|x| {
+        x[0] == x[1] & !x[2] & 7
+    });
+    check(&graph("y = a * b"), &yab, &names, &|x| {
+        x[0] == x[1] * x[2] % 8
+    });
+    check(&graph("y = a . b"), &yab, &names, &|x| {
+        x[0] == gf8_mul(x[1], x[2])
+    });
+    check(&graph("y = -a + 2*b - p"), &yab, &names, &|x| {
+        x[0] == (16 - x[1] + 2 * x[2] - 5) % 8
+    });
+    check(&graph("y = !a"), &yab[..2], &names[..2], &|x| {
+        x[0] == 7 - x[1]
+    });
+    check(&graph("y = s[a]"), &yab[..2], &names[..2], &|x| {
+        x[0] == t(x[1])
+    });
+    check(&graph("y = t[a, b]"), &yab, &names, &|x| {
+        x[0] == t(x[1] * 8 + x[2])
+    });
+    let decl = "NC 2\nVAR MULTI x NC 12\nVAR MULTI l NC 4\nVAR MULTI h NC 3\nPUB SINGLE p\n";
+    check(
+        &format!("{}PROPERTY l, h = BITS(x)", decl),
+        &[12, 4, 3],
+        &["x", "l", "h"],
+        &|x| x[0] == x[1] + 4 * x[2],
+    );
+    let decl = "NC 8\nVAR MULTI h NC 4\nVAR MULTI a\nVAR MULTI b\nPUB SINGLE p\n";
+    check(
+        &format!("{}PROPERTY h = HD(a, b)", decl),
+        &[4, 8, 8],
+        &["h", "a", "b"],
+        &|x| x[0] == (x[1] ^ x[2]).count_ones() as usize,
+    );
+    check(
+        &format!("{}PROPERTY h = HD(p, a)", decl),
+        &[4, 8],
+        &["h", "a"],
+        &|x| x[0] == (x[1] ^ 5).count_ones() as usize,
+    );
+}
+
+#[test]
+fn max_product_map_assignment() {
+    let nc = 4;
+    let graph = build_graph(
+        "NC 4\nVAR SINGLE a\nVAR SINGLE b\nVAR SINGLE c\nVAR SINGLE d\nVAR SINGLE e
+        PROPERTY c = a ^ b\nPROPERTY d = c + e",
+    );
+    let evidence: Vec<Array1<f64>> = (0..5)
+        .map(|v| Array1::from_shape_fn(nc, |j| ((v * 7 + j * j * 3 + j) % 11 + 1) as f64))
+        .collect();
+    // Brute-force MAP assignment.
+    let (best, _) = (0..nc.pow(5))
+        .map(|x| (0..5).map(|v| (x >> (2 * v)) % nc).collect::<Vec<_>>())
+        .filter(|x| x[2] == x[0] ^ x[1] && x[3] == (x[2] + x[4]) % nc)
+        .map(|x| {
+            let p: f64 = x.iter().zip(evidence.iter()).map(|(x, e)| e[*x]).product();
+            (x, p)
+        })
+        .fold(
+            (vec![], 0.0),
+            |(bx, bp), (x, p)| if p > bp { (x, p) } else { (bx, bp) },
+        );
+    let mut bp = BPState::new(graph, 1, vec![]);
+    bp.set_mode(BPMode::MaxProduct);
+    assert_eq!(bp.mode(), BPMode::MaxProduct);
+    let vars: Vec<_> = bp.get_graph().range_vars().collect();
+    for (v, ev) in vars.iter().zip(evidence) {
+        bp.set_evidence(*v, Distribution::from_array_single(ev).unwrap())
+            .unwrap();
+    }
+    // Loopy BP is exact on a tree after enough iterations.
+    bp.propagate_all_vars(false);
+    bp.propagate_loopy_step(5, false);
+    let map = bp.map_assignment();
+    for (v, b) in vars.iter().zip(best.iter()) {
+        assert_eq!(map[*v], PublicValue::Single(*b as sasca::ClassVal));
+    }
+    bp.get_graph().sanity_check(vec![], map).unwrap();
+}
+
+#[test]
+fn max_product_underflow() {
+    // The products of these probabilities underflow in the linear domain.
+    let tiny = |scale: f64, v: [f64; 4]| {
+        Distribution::from_array_single(Array1::from_iter(v.iter().map(|x| x * scale))).unwrap()
+    };
+    let (x, y, z) = (
+        [1.0, 3.0, 2.0, 0.0],
+        [2.0, 1.0, 0.0, 5.0],
+        [4.0, 1.0, 3.0, 2.0],
+    );
+    let op = |a: usize, b: usize| (a + 3 * b) % 4;
+    let direct = |scale: f64| tiny(scale, x).max_op(&tiny(scale, y), op);
+    let inv = |scale: f64| tiny(scale, z).max_op_inv(&tiny(scale, y), op);
+    for (res, reference) in [(direct(1e-170), direct(1.0)), (inv(1e-170), inv(1.0))] {
+        let (res, reference) = (res.value().unwrap(), reference.value().unwrap());
+        assert!(res.iter().all(|p| p.is_finite()));
+        assert!(res
+            .iter()
+            .zip(reference.iter())
+            .all(|(p, q)| (p - q).abs() < 1e-12));
+    }
+}
+
+#[test]
+fn log_domain_bp() {
+    let nc = 16;
+    let nmulti = 2000;
+    let key = 5;
+    let graph = build_graph("NC 16\nVAR SINGLE k\nVAR MULTI y\nPUB MULTI p\nPROPERTY y = k ^ p");
+    let p: Vec<sasca::ClassVal> = (0..nmulti)
+        .map(|i| (i * 7 % nc) as sasca::ClassVal)
+        .collect();
+    let ev_y = ndarray::Array2::from_shape_fn((nmulti, nc), |(i, j)| {
+        if j == key ^ p[i] as usize {
+            0.5
+        } else {
+            ((i + j) % 3 + 1) as f64 / 60.0
+        }
+    });
+    let ev_k = Array1::from_shape_fn(nc, |j| (j % 4 + 1) as f64);
+    // Exact log-posterior of k.
+    let mut reference: Vec<f64> = (0..nc)
+        .map(|k| {
+            ev_k[k].ln()
+                + (0..nmulti)
+                    .map(|i| ev_y[(i, k ^ p[i] as usize)].ln())
+                    .sum::<f64>()
+        })
+        .collect();
+    let max = reference.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let lse = max + reference.iter().map(|x| (x - max).exp()).sum::<f64>().ln();
+    reference.iter_mut().for_each(|x| *x -= lse);
+    // The posterior of most values underflows in the linear domain.
+    assert!(
+        reference
+            .iter()
+            .filter(|x| **x < f64::MIN_POSITIVE.ln())
+            .count()
+            > 1
+    );
+    let run = |log_domain: bool| {
+        let mut bp = BPState::new(
+            graph.clone(),
+            nmulti as u32,
+            vec![PublicValue::Multi(p.clone())],
+        );
+        bp.set_log_domain(log_domain);
+        let k = bp.get_graph().get_varid("k").unwrap();
+        let y = bp.get_graph().get_varid("y").unwrap();
+        bp.set_evidence(y, Distribution::from_array_multi(ev_y.clone()).unwrap())
+            .unwrap();
+        if log_domain {
+            bp.set_evidence(k, Distribution::from_array_single(ev_k.clone()).unwrap())
+                .unwrap();
+        }
+        bp.propagate_all_vars(false);
+        bp.propagate_loopy_step(1, false);
+        bp
+    };
+    let bp = run(true);
+    let k = bp.get_graph().get_varid("k").unwrap();
+    let state = bp.get_state_log(k).unwrap().value().row(0).to_owned();
+    for (s, r) in state.iter().zip(reference.iter()) {
+        assert!((s - r).abs() < 1e-6 * r.abs().max(1.0), "{} {}", s, r);
+    }
+    let costs = bp.key_costs(&[k]).unwrap();
+    for (c, r) in costs[0].iter().zip(reference.iter()) {
+        assert!((c + r).abs() < 1e-6 * r.abs().max(1.0), "{} {}", c, r);
+    }
+    let linear = bp.get_state(k).value().unwrap().row(0).to_owned();
+    assert!((linear[key] - reference[key].exp()).abs() < 1e-9);
+    // Without the log domain, the costs are clamped.
+    let bp = run(false);
+    assert!(bp.get_state_log(k).is_none());
+    let costs = bp.key_costs(&[k]).unwrap();
+    assert!(costs[0].iter().all(|c| *c <= -f64::MIN_POSITIVE.ln()));
+}
+
+#[test]
+fn log_domain_loopy() {
+    let decl = "NC 4\nVAR SINGLE a\nVAR SINGLE b\nVAR SINGLE c\nVAR SINGLE d\nVAR SINGLE e\n";
+    let graph = build_graph(&format!(
+        "{}PROPERTY c = a ^ b\nPROPERTY d = a & b\nPROPERTY e = c + d",
+        decl
+    ));
+    let run = |log_domain: bool| {
+        let mut bp = BPState::new(graph.clone(), 1, vec![]);
+        bp.set_log_domain(log_domain);
+        let vars: Vec<_> = bp.get_graph().range_vars().collect();
+        for (i, v) in vars.iter().enumerate() {
+            let ev = Array1::from_shape_fn(4, |j| ((i * 5 + j * 3) % 7 + 1) as f64);
+            bp.set_evidence(*v, Distribution::from_array_single(ev).unwrap())
+                .unwrap();
+        }
+        bp.propagate_all_vars(false);
+        bp.propagate_loopy_step(10, false);
+        vars.iter()
+            .map(|v| bp.get_state(*v).value().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    for (s, r) in run(true).iter().zip(run(false).iter()) {
+        assert!(s.iter().zip(r.iter()).all(|(s, r)| (s - r).abs() < 1e-9));
+    }
+}
+
+#[test]
+fn parallel_bp_deterministic() {
+    // Large enough for the operations on the MULTI distributions to run in parallel.
+    let nmulti = 1024;
+    let description = "NC 16\nFIELD 0x13\nVAR MULTI a\nVAR MULTI b\nVAR MULTI c\nVAR MULTI d
+        VAR MULTI e\nVAR MULTI f\nPROPERTY c = a ^ b\nPROPERTY d = a + c\nPROPERTY e = c & d
+        PROPERTY b = d * e\nPROPERTY f = a . e";
+    let graph = build_graph(description);
+    let evidence = |n: usize| {
+        (0..6)
+            .map(|v| {
+                ndarray::Array2::from_shape_fn((n, 16), |(i, j)| {
+                    ((i * 7 + j * 5 + v * 3) % 13 + 1) as f64
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let run = |n: usize, parallel: bool, mode: BPMode| {
+        let mut bp = BPState::new(graph.clone(), n as u32, vec![]);
+        bp.set_mode(mode);
+        let vars: Vec<_> = bp.get_graph().range_vars().collect();
+        for (v, ev) in vars.iter().zip(evidence(n)) {
+            bp.set_evidence(*v, Distribution::from_array_multi(ev).unwrap())
+                .unwrap();
+        }
+        bp.propagate_all_vars(false);
+        if parallel {
+            bp.propagate_loopy_step(3, false);
+        } else {
+            let factors: Vec<_> = bp.get_graph().range_factors().collect();
+            for _ in 0..3 {
+                for f in factors.iter() {
+                    bp.propagate_factor_all(*f);
+                }
+                bp.propagate_all_vars(false);
+            }
+        }
+        vars.iter()
+            .map(|v| bp.get_state(*v).value().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    for mode in [BPMode::SumProduct, BPMode::MaxProduct] {
+        let reference = run(nmulti, false, mode);
+        assert_eq!(run(nmulti, true, mode), reference);
+        assert_eq!(run(nmulti, true, mode), reference);
+        // The executions are independent, the parallel computations give the same results as
+        // with few executions.
+        for (s, r) in run(4, true, mode).iter().zip(reference.iter()) {
+            let r = r.slice(ndarray::s![..4, ..]);
+            assert!(s.iter().zip(r.iter()).all(|(s, r)| (s - r).abs() < 1e-12));
+        }
+    }
+}
+
+/// Marginals (or max-marginals) of the variables of a graph by enumerating all the assignments,
+/// for execution 0 of the MULTI variables.
+fn brute_force_marginals(
+    graph: &sasca::FactorGraph,
+    nmulti: usize,
+    publics: Vec<PublicValue>,
+    evidence: &[ndarray::Array2<f64>],
+    max_product: bool,
+) -> Vec<Array1<f64>> {
+    let vars: Vec<_> = graph.range_vars().collect();
+    let copies: Vec<usize> = vars
+        .iter()
+        .map(|v| if graph.var_multi(*v) { nmulti } else { 1 })
+        .collect();
+    let n_values: usize = vars
+        .iter()
+        .zip(copies.iter())
+        .map(|(v, c)| graph.var_nc(*v).pow(*c as u32))
+        .product();
+    let mut marginals: Vec<Array1<f64>> = vars
+        .iter()
+        .map(|v| Array1::zeros(graph.var_nc(*v)))
+        .collect();
+    for mut x in 0..n_values {
+        let assignment: Vec<Vec<usize>> = vars
+            .iter()
+            .zip(copies.iter())
+            .map(|(v, c)| {
+                (0..*c)
+                    .map(|_| {
+                        let r = x % graph.var_nc(*v);
+                        x /= graph.var_nc(*v);
+                        r
+                    })
+                    .collect()
+            })
+            .collect();
+        let var_assignments = vars
+            .iter()
+            .zip(assignment.iter())
+            .map(|(v, a)| {
+                let a: Vec<sasca::ClassVal> = a.iter().map(|x| *x as sasca::ClassVal).collect();
+                if graph.var_multi(*v) {
+                    PublicValue::Multi(a)
+                } else {
+                    PublicValue::Single(a[0])
+                }
+            })
+            .collect();
+        if graph
+            .sanity_check(publics.clone(), var_assignments)
+            .is_err()
+        {
+            continue;
+        }
+        let p: f64 = assignment
+            .iter()
+            .zip(evidence.iter())
+            .map(|(a, ev)| {
+                a.iter()
+                    .enumerate()
+                    .map(|(n, x)| ev[(n, *x)])
+                    .product::<f64>()
+            })
+            .product();
+        for (m, a) in marginals.iter_mut().zip(assignment.iter()) {
+            if max_product {
+                m[a[0]] = m[a[0]].max(p);
+            } else {
+                m[a[0]] += p;
+            }
+        }
+    }
+    for m in marginals.iter_mut() {
+        *m /= m.sum();
+    }
+    marginals
+}
+
+#[test]
+fn exact_inference_cyclic() {
+    let nmulti = 2;
+    let graph = build_graph(
+        "NC 4\nTABLE t = [2, 0, 3, 1]\nVAR SINGLE k\nVAR MULTI x\nVAR MULTI y\nVAR MULTI z
+        PUB MULTI p\nPROPERTY x = k ^ p\nPROPERTY y = t[x]\nPROPERTY z = y + k",
+    );
+    let publics = vec![PublicValue::Multi(vec![1, 2])];
+    let evidence: Vec<ndarray::Array2<f64>> = graph
+        .range_vars()
+        .enumerate()
+        .map(|(i, v)| {
+            let n = if graph.var_multi(v) { nmulti } else { 1 };
+            ndarray::Array2::from_shape_fn((n, 4), |(j, k)| {
+                ((i * 5 + j * 3 + k * k) % 7 + 1) as f64
+            })
+        })
+        .collect();
+    for mode in [BPMode::SumProduct, BPMode::MaxProduct] {
+        let reference = brute_force_marginals(
+            &graph,
+            nmulti,
+            publics.clone(),
+            &evidence,
+            mode == BPMode::MaxProduct,
+        );
+        let mut bp = BPState::new(graph.clone(), nmulti as u32, publics.clone());
+        assert!(bp.is_cyclic());
+        bp.set_mode(mode);
+        for (v, ev) in bp.get_graph().range_vars().zip(evidence.iter()) {
+            let ev = if bp.get_graph().var_multi(v) {
+                Distribution::from_array_multi(ev.clone()).unwrap()
+            } else {
+                Distribution::from_array_single(ev.row(0).to_owned()).unwrap()
+            };
+            bp.set_evidence(v, ev).unwrap();
+        }
+        let cost = bp.propagate_exact(1 << 10).unwrap();
+        assert_eq!(cost, bp.exact_cost());
+        let vars: Vec<_> = bp.get_graph().range_vars().collect();
+        for (v, r) in vars.iter().zip(reference.iter()) {
+            let state = bp.get_state(*v).value().unwrap();
+            assert_eq!(
+                state.shape()[0],
+                if bp.get_graph().var_multi(*v) {
+                    nmulti
+                } else {
+                    1
+                }
+            );
+            let mut state = state.row(0).to_owned();
+            state /= state.sum();
+            for (s, r) in state.iter().zip(r.iter()) {
+                assert!((s - r).abs() < 1e-10, "{:?} {:?} {:?}", mode, state, r);
+            }
+        }
+    }
+}
+
+#[test]
+fn exact_inference_budget() {
+    let graph = build_graph(
+        "NC 16\nVAR SINGLE a\nVAR SINGLE b\nVAR SINGLE c\nVAR SINGLE d
+        PROPERTY c = a ^ b\nPROPERTY d = a + c",
+    );
+    let mut bp = BPState::new(graph, 1, vec![]);
+    let cost = bp.exact_cost();
+    // The cycle a-c needs a clique of 3 variables.
+    assert_eq!(cost.width, 2);
+    assert_eq!(cost.max_clique_size, 16 * 16 * 16);
+    assert!(matches!(
+        bp.propagate_exact(cost.max_clique_size - 1),
+        Err(sasca::BPError::ExactTooLarge(s, b)) if s == cost.max_clique_size && b == s - 1
+    ));
+    let a = bp.get_graph().get_varid("a").unwrap();
+    assert!(bp.get_state(a).value().is_none());
+    assert_eq!(bp.propagate_exact(cost.max_clique_size).unwrap(), cost);
+    assert!(bp.get_state(a).value().is_some());
+}
+
+#[test]
+fn graph_analysis() {
+    let graph = build_graph(
+        "NC 16\nVAR SINGLE a\nVAR SINGLE b\nVAR SINGLE c\nVAR SINGLE d
+        PROPERTY c = a ^ b\nPROPERTY d = a + c",
+    );
+    let analysis = graph.analyze(1);
+    let var = |name| graph.get_varid(name).unwrap();
+    assert!(analysis.cyclic);
+    assert_eq!(analysis.cycles.len(), 1);
+    let mut cycle = analysis.cycles[0].clone();
+    cycle.sort();
+    assert_eq!(cycle, vec![var("a"), var("c")]);
+    assert!(analysis.multi_cycles.is_empty());
+    assert_eq!(analysis.treewidth, 2);
+    assert_eq!(analysis.edge_memory, 2 * 6 * 16 * 8);
+    assert_eq!(analysis.var_memory, 2 * 4 * 16 * 8);
+    let graph = build_graph(
+        "NC 4\nVAR SINGLE k0\nVAR SINGLE k1\nVAR MULTI x\nVAR MULTI y\nPUB MULTI p
+        PROPERTY x = k0 ^ p\nPROPERTY y = x ^ k1",
+    );
+    let var = |name| graph.get_varid(name).unwrap();
+    let analysis = graph.analyze(1);
+    assert!(!analysis.cyclic);
+    assert!(analysis.cycles.is_empty() && analysis.multi_cycles.is_empty());
+    // Scope of the second XOR.
+    assert_eq!(analysis.treewidth, 2);
+    let analysis = graph.analyze(10);
+    let bp = BPState::new(graph.clone(), 10, vec![PublicValue::Multi(vec![0; 10])]);
+    assert!(analysis.cyclic && bp.is_cyclic());
+    assert!(analysis.cycles.is_empty());
+    assert_eq!(
+        analysis.multi_cycles,
+        vec![vec![var("k0"), var("x"), var("k1")]]
+    );
+    assert_eq!(analysis.treewidth, bp.exact_cost().width);
+    // 5 MULTI edges (both factors are MULTI); 2 MULTI vars and 2 SINGLE vars.
+    assert_eq!(analysis.edge_memory, 2 * 5 * 10 * 4 * 8);
+    assert_eq!(analysis.var_memory, 2 * (2 * 10 + 2) * 4 * 8);
+}
+
+#[test]
+fn exact_inference_many_traces() {
+    let nc = 16;
+    let nmulti = 500;
+    let graph = build_graph("NC 16\nVAR SINGLE k\nVAR MULTI y\nPUB MULTI p\nPROPERTY y = k ^ p");
+    let p = PublicValue::Multi(
+        (0..nmulti)
+            .map(|i| (i * 7 % nc) as sasca::ClassVal)
+            .collect(),
+    );
+    let ev_y = ndarray::Array2::from_shape_fn((nmulti, nc), |(i, j)| {
+        if j == 5 ^ p.get(i) as usize {
+            0.5
+        } else {
+            ((i + j) % 3 + 1) as f64 / 60.0
+        }
+    });
+    let run = |exact: bool| {
+        let mut bp = BPState::new(graph.clone(), nmulti as u32, vec![p.clone()]);
+        bp.set_log_domain(true);
+        let y = bp.get_graph().get_varid("y").unwrap();
+        let k = bp.get_graph().get_varid("k").unwrap();
+        bp.set_evidence(y, Distribution::from_array_multi(ev_y.clone()).unwrap())
+            .unwrap();
+        if exact {
+            bp.propagate_exact(nc * nc).unwrap();
+        } else {
+            bp.propagate_acyclic(k, false, false).unwrap();
+            bp.propagate_acyclic(y, false, false).unwrap();
+        }
+        (
+            bp.get_state(k).value().unwrap().to_owned(),
+            bp.get_state(y).value().unwrap().to_owned(),
+        )
+    };
+    let (k_exact, y_exact) = run(true);
+    let (k_bp, y_bp) = run(false);
+    // The posterior of k is concentrated on the key.
+    assert!(k_exact[(0, 5)] > 0.999);
+    for (e, r) in k_exact
+        .iter()
+        .chain(y_exact.iter())
+        .zip(k_bp.iter().chain(y_bp.iter()))
+    {
+        assert!((e - r).abs() < 1e-9, "{} {}", e, r);
+    }
+}
+
+#[test]
+fn graph_templates() {
+    let graph = build_graph(
+        "
+        NC 4
+        TABLE sbox = [2, 0, 3, 1]
+        VAR SINGLE k[2]
+        PUB MULTI p[2]
+        VAR MULTI y[2]
+        VAR MULTI z
+        GRAPH SBOX(x, k, y)
+            VAR MULTI t
+            PROPERTY add: t = x ^ k
+            PROPERTY sb: y = sbox[t]
+        END
+        GRAPH LAYER(p[2], k[2], y[2])
+            SBOX(p[0..2], k[0..2], y[0..2])
+        END
+        r0: LAYER(p[0..2], k[0..2], y[0..2])
+        PROPERTY z = y[0] ^ y[1]
+        ",
+    );
+    let scope = |factor: &str| {
+        let factor = graph.get_factorid(factor).unwrap();
+        graph
+            .factor_scope(factor)
+            .map(|v| graph.var_name(v))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(scope("r0.SBOX_0[0].add"), ["r0.SBOX_0[0].t", "k[0]"]);
+    assert_eq!(scope("r0.SBOX_0[1].add"), ["r0.SBOX_0[1].t", "k[1]"]);
+    assert_eq!(scope("r0.SBOX_0[1].sb"), ["y[1]", "r0.SBOX_0[1].t"]);
+    assert_eq!(scope("ANONYMOUS_0"), ["z", "y[0]", "y[1]"]);
+    assert_eq!(graph.var_names().count(), 7);
+    assert!(!graph.var_multi(graph.get_varid("k[1]").unwrap()));
+    assert!(graph.var_multi(graph.get_varid("r0.SBOX_0[0].t").unwrap()));
+}
+
+#[test]
+fn graph_template_errors() {
+    let build = |desc: &str| sasca::build_graph(desc, HashMap::new());
+    let decl =
+        "NC 2\nVAR SINGLE a[4]\nVAR SINGLE b\nGRAPH G(x, y[2])\nPROPERTY x = y[0] ^ y[1]\nEND\n";
+    assert!(build(&format!("{}G(b, a[0..2])", decl)).is_ok());
+    assert!(build(&format!("{}G(a[2..4], a[0..2])", decl)).is_ok());
+    assert!(matches!(
+        build(&format!("{}H(b)", decl)),
+        Err(GraphBuildError::AtLine(7, e)) if matches!(*e, GraphBuildError::UnknownGraph(_))
+    ));
+    assert!(matches!(
+        build(&format!("{}G(b)", decl)),
+        Err(GraphBuildError::AtLine(7, e)) if matches!(*e, GraphBuildError::GraphArity(_, 2, 1))
+    ));
+    assert!(matches!(
+        build(&format!("{}G(b, a[0])", decl)),
+        Err(GraphBuildError::AtLine(7, e)) if matches!(*e, GraphBuildError::GraphArgLen(_, _, 2))
+    ));
+    assert!(matches!(
+        build("NC 2\nVAR SINGLE a[4]\nGRAPH G(x, y)\nPROPERTY x = !y\nEND\nG(a[0..3], a[1..3])"),
+        Err(GraphBuildError::AtLine(6, e)) if matches!(*e, GraphBuildError::BroadcastLen(_))
+    ));
+    assert!(matches!(
+        build(&format!("{}G(a[2..2], a[0..2])", decl)),
+        Err(GraphBuildError::AtLine(7, e)) if matches!(*e, GraphBuildError::EmptyRange(_))
+    ));
+    // Errors in the body of a GRAPH are reported at their line in the template.
+    let err = build(&format!("{}g: G(b, a[2..4])\nG(a[1], a[0..2])", decl)).unwrap_err();
+    assert!(matches!(
+        &err,
+        GraphBuildError::InGraph(instance, 5, e)
+            if instance == "G_0" && matches!(**e, GraphBuildError::RepeatedOperand(_, _))
+    ));
+    assert!(err
+        .to_string()
+        .starts_with("In GRAPH instance G_0, line 5:"));
+    assert!(matches!(
+        build("NC 2\nVAR SINGLE a\nGRAPH G(x[2])\nPROPERTY x[2] = x[0] ^ x[1]\nEND\nG(a)"),
+        Err(GraphBuildError::AtLine(6, e)) if matches!(*e, GraphBuildError::GraphArgLen(_, _, 2))
+    ));
+    assert!(matches!(
+        build("NC 2\nVAR SINGLE a[2]\nGRAPH G(x[2])\nPROPERTY x[0] = x[1] ^ x[2]\nEND\nG(a[0..2])"),
+        Err(GraphBuildError::InGraph(_, 4, e)) if matches!(*e, GraphBuildError::ParamIndex(_, _))
+    ));
+    assert!(matches!(
+        build("NC 2\nVAR SINGLE a\nGRAPH G(x)\nH(x)\nEND\nGRAPH H(x)\nG(x)\nEND\nG(a)"),
+        Err(GraphBuildError::InGraph(_, 7, e)) if matches!(*e, GraphBuildError::RecursiveGraph(_))
+    ));
+    assert!(matches!(
+        build("NC 2\nGRAPH G(x)\nNC 2\nEND"),
+        Err(GraphBuildError::AtLine(3, e)) if matches!(*e, GraphBuildError::GraphStatement(_))
+    ));
+    assert!(matches!(
+        build("NC 2\nGRAPH G(x)\nGRAPH H(x)\nEND"),
+        Err(GraphBuildError::AtLine(2, e)) if matches!(*e, GraphBuildError::UnclosedGraph(_))
+    ));
+    assert!(matches!(
+        build("NC 2\nEND"),
+        Err(GraphBuildError::AtLine(2, e)) if matches!(*e, GraphBuildError::UnmatchedEnd)
+    ));
+    assert!(matches!(
+        build("NC 2\nGRAPH G(x, x)\nEND"),
+        Err(GraphBuildError::AtLine(2, e)) if matches!(*e, GraphBuildError::RepeatedParam(_, _))
+    ));
+    assert!(matches!(
+        build("NC 2\nGRAPH G(x)\nEND\nGRAPH G(y)\nEND"),
+        Err(GraphBuildError::AtLine(4, e)) if matches!(*e, GraphBuildError::MultipleGraphDecl(_))
+    ));
+}
+
+#[test]
+fn graph_builder() {
+    let sbox: Vec<sasca::ClassVal> = vec![2, 0, 3, 1];
+    let reference = sasca::build_graph(
+        "
+        NC 4
+        TABLE sbox
+        VAR SINGLE k
+        PUB MULTI p
+        VAR MULTI x
+        VAR MULTI y
+        VAR MULTI z
+        VAR MULTI b0 NC 2
+        VAR MULTI b1 NC 2
+        VAR MULTI h NC 3
+        PROPERTY add: x = k ^ p
+        PROPERTY sb: y = sbox[x]
+        PROPERTY s: z = 3*x - y + p
+        PROPERTY a: z = x & !y
+        PROPERTY b0, b1 = BITS(y)
+        PROPERTY hw: h = HW(z)
+        ",
+        HashMap::from([("sbox".to_owned(), sbox.clone())]),
+    )
+    .unwrap();
+    let builder = sasca::FactorGraphBuilder::new(4)
+        .table("sbox", sbox)
+        .var("k", false)
+        .public("p", true)
+        .var("x", true)
+        .var("y", true)
+        .var("z", true)
+        .var_nc("b0", true, 2)
+        .var_nc("b1", true, 2)
+        .var_nc("h", true, 3)
+        .xor("x", &["k", "p"])
+        .named("add")
+        .lookup("y", "sbox", &["x"])
+        .named("sb")
+        .add("z", &[(3, "x"), (-1, "y"), (1, "p")])
+        .named("s")
+        .and("z", &[("x", false), ("y", true)])
+        .named("a")
+        .bits(&["b0", "b1"], "y");
+    let graph = builder.clone().hw("h", "z").named("hw").build().unwrap();
+    assert_eq!(
+        graph.var_names().collect::<Vec<_>>(),
+        reference.var_names().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        graph.factor_names().collect::<Vec<_>>(),
+        reference.factor_names().collect::<Vec<_>>()
+    );
+    for f in graph.range_factors() {
+        assert_eq!(
+            graph.factor_scope(f).collect::<Vec<_>>(),
+            reference.factor_scope(f).collect::<Vec<_>>()
+        );
+    }
+    // Both graphs give the same BP results.
+    let run = |graph: sasca::FactorGraph| {
+        let mut bp = BPState::new(Arc::new(graph), 2, vec![PublicValue::Multi(vec![1, 2])]);
+        let y = bp.get_graph().get_varid("y").unwrap();
+        let ev = ndarray::array![[0.1, 0.2, 0.3, 0.4], [0.4, 0.1, 0.3, 0.2]];
+        bp.set_evidence(y, Distribution::from_array_multi(ev).unwrap())
+            .unwrap();
+        bp.propagate_exact(1 << 20).unwrap();
+        let k = bp.get_graph().get_varid("k").unwrap();
+        bp.get_state(k).value().unwrap().to_owned()
+    };
+    assert_eq!(run(graph), run(reference));
+    // Errors are the same as for textual descriptions.
+    assert!(matches!(
+        builder.clone().hw("b0", "z").build(),
+        Err(GraphBuildError::HwNc(..))
+    ));
+    assert!(matches!(
+        builder.clone().xor("x", &["k", "q"]).build(),
+        Err(GraphBuildError::UnknownVar(_))
+    ));
+    assert!(matches!(
+        builder.clone().var("k", true).build(),
+        Err(GraphBuildError::MultipleVarDecl(_))
+    ));
+    assert!(matches!(
+        builder.clone().var("w", true).named("w").build(),
+        Err(GraphBuildError::NoPropertyToName(_))
+    ));
+    assert!(matches!(
+        builder.gf_mul("x", &["k", "y"]).build(),
+        Err(GraphBuildError::NoField(_))
+    ));
+}
+
+const BLIF_NETLIST: &str = "
+.model test
+.inputs k[0] k[1] p[0] p[1]
+.outputs y z w
+# x = k ^ p
+.names k[0] p[0] x[0]
+10 1
+01 1
+.names k[1] p[1] x[1]
+10 1
+01 1
+# NAND, OR, NOT and XNOR
+.names x[0] x[1] y
+11 0
+.names x[0] x[1] \\
+ z
+1- 1
+-1 1
+.names y w
+0 1
+.names x[1] p[0] t
+00 1
+11 1
+.end
+";
+
+const VERILOG_NETLIST: &str = "
+// Same circuit as BLIF_NETLIST.
+module test(input [1:0] k, input [1:0] p, output y, output z, output w);
+  wire [1:0] x;
+  wire t;
+  xor (x[0], k[0], p[0]);
+  assign x[1] = k[1] ^ p[1];
+  assign y = ~(x[0] & x[1]);
+  /* OR gate */
+  or g_or (z, x[0], x[1]);
+  not (w, y);
+  xnor (t, x[1], p[0]);
+endmodule
+";
+
+/// Posteriors of all the variables of a netlist graph with k = 2 and the plaintexts 0..4,
+/// given near-deterministic evidence on the bus x.
+fn run_netlist(graph: sasca::FactorGraph) -> HashMap<String, ndarray::Array2<f64>> {
+    let p = |bit: u32| PublicValue::Multi((0..4).map(|p| (p >> bit) & 1).collect());
+    let graph = Arc::new(graph);
+    let mut bp = BPState::new(graph.clone(), 4, vec![p(0), p(1)]);
+    let x = graph.get_varid("x").unwrap();
+    let mut ev = ndarray::Array2::from_elem((4, 4), 0.01);
+    for p in 0..4 {
+        ev[(p, 2 ^ p)] = 0.97;
+    }
+    bp.set_evidence(x, Distribution::from_array_multi(ev).unwrap())
+        .unwrap();
+    bp.propagate_exact(1 << 16).unwrap();
+    graph
+        .vars()
+        .map(|(v, name)| {
+            let distr = bp.get_state(v).value().unwrap().to_owned();
+            (name.to_owned(), distr)
+        })
+        .collect()
+}
+
+#[test]
+fn netlist_import() {
+    let config = sasca::NetlistConfig {
+        single: vec!["k".to_owned()],
+        public: vec!["p".to_owned()],
+        probes: vec!["x".to_owned()],
+    };
+    let blif = sasca::import_blif(BLIF_NETLIST, &config).unwrap();
+    let verilog = sasca::import_verilog(VERILOG_NETLIST, &config).unwrap();
+    assert!(blif.get_factorid("g_or").is_err());
+    assert!(verilog.get_factorid("g_or").is_ok());
+    for graph in [&blif, &verilog] {
+        let kind = |name: &str| {
+            let var = graph.get_varid(name).unwrap();
+            (graph.var_multi(var), graph.var_nc(var))
+        };
+        assert_eq!(kind("k[1]"), (false, 2));
+        assert_eq!(kind("x[0]"), (true, 2));
+        assert_eq!(kind("x"), (true, 4));
+        assert_eq!(kind("t__xnor"), (true, 2));
+        assert!(graph.get_varid("p[0]").is_err());
+    }
+    let blif = run_netlist(blif);
+    let verilog = run_netlist(verilog);
+    assert_eq!(blif.len(), verilog.len());
+    let argmax = |d: ndarray::ArrayView1<f64>| {
+        d.iter()
+            .enumerate()
+            .fold(
+                (0, 0.0),
+                |(i, m), (j, v)| if *v > m { (j, *v) } else { (i, m) },
+            )
+            .0 as u32
+    };
+    for (name, distr) in blif.iter() {
+        let other = &verilog[name];
+        assert!(distr
+            .iter()
+            .zip(other.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-9));
+        for (p, row) in distr.outer_iter().enumerate() {
+            let p = p as u32;
+            let x = 2 ^ p;
+            let (x0, x1) = (x & 1, x >> 1);
+            let expected = match name.as_str() {
+                "k[0]" => 0,
+                "k[1]" => 1,
+                "x" => x,
+                "x[0]" => x0,
+                "x[1]" => x1,
+                "y" => 1 - (x0 & x1),
+                "z" => x0 | x1,
+                "w" => x0 & x1,
+                "t" => 1 - (x1 ^ (p & 1)),
+                "t__xnor" => x1 ^ (p & 1),
+                _ => panic!("unexpected variable {}", name),
+            };
+            assert_eq!(argmax(row), expected, "{} {}", name, p);
+        }
+    }
+}
+
+#[test]
+fn netlist_import_errors() {
+    let config = sasca::NetlistConfig::default();
+    let blif = |src: &str| sasca::import_blif(src, &config);
+    let verilog = |src: &str| sasca::import_verilog(src, &config);
+    assert!(matches!(
+        blif(".inputs a b c\n.names a b c y\n11- 1\n1-1 1\n-11 1"),
+        Err(GraphBuildError::Netlist(2, _))
+    ));
+    assert!(matches!(
+        blif(".names y\n1"),
+        Err(GraphBuildError::Netlist(1, _))
+    ));
+    assert!(matches!(
+        blif(".inputs a b\n.names a y\n1 1\n.names b y\n0 1"),
+        Err(GraphBuildError::MultipleDrivers(_, 4))
+    ));
+    assert!(matches!(
+        blif(".inputs a\n.latch a y 0"),
+        Err(GraphBuildError::Netlist(2, _))
+    ));
+    assert!(matches!(
+        sasca::import_blif(
+            ".inputs a\n.names a y\n0 1",
+            &sasca::NetlistConfig {
+                single: vec!["b".to_owned()],
+                ..Default::default()
+            }
+        ),
+        Err(GraphBuildError::UnknownWire(_))
+    ));
+    assert!(matches!(
+        sasca::import_blif(
+            ".inputs a[0] a[2]\n.names a[0] a[2] y\n11 1",
+            &sasca::NetlistConfig {
+                probes: vec!["a".to_owned()],
+                ..Default::default()
+            }
+        ),
+        Err(GraphBuildError::BusBits(_))
+    ));
+    let wide_bus = (0..64).map(|i| format!("a[{}]", i)).collect::<Vec<_>>();
+    assert!(matches!(
+        sasca::import_blif(
+            &format!(".inputs {}\n.names a[0] y\n1 1", wide_bus.join(" ")),
+            &sasca::NetlistConfig {
+                probes: vec!["a".to_owned()],
+                ..Default::default()
+            }
+        ),
+        Err(GraphBuildError::BusWidth(_, 64, 32))
+    ));
+    assert!(matches!(
+        verilog("module m(input [1:0] a, output y);\nnot (y, a);\nendmodule"),
+        Err(GraphBuildError::Netlist(2, _))
+    ));
+    assert!(matches!(
+        verilog("module m(a, b, y);\ninput a, b;\noutput y;\nassign y = a & b | a;\nendmodule"),
+        Err(GraphBuildError::Netlist(4, _))
+    ));
+    assert!(matches!(
+        verilog("module m(a, y);\ninput a;\noutput y;\nassign y = a & 1'b1;\nendmodule"),
+        Err(GraphBuildError::Netlist(4, _))
+    ));
+    assert!(
+        verilog("module m(a, b, y);\ninput a, b;\noutput y;\nnand (y, a, b);\nendmodule").is_ok()
+    );
+}
+
+#[test]
+fn graph_export() {
+    let t2 = (0..16)
+        .map(|i| ((i * 7 + 3) % 4).to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let desc = format!(
+        "
+        NC 4
+        FIELD 0x7
+        TABLE t = [1, 3, 0, 2]
+        TABLE t2 = [{}]
+        VAR SINGLE k
+        VAR MULTI x
+        VAR MULTI y
+        VAR MULTI z
+        VAR MULTI a
+        VAR MULTI b
+        VAR MULTI c
+        VAR MULTI d
+        VAR MULTI e
+        VAR SINGLE f
+        VAR MULTI h NC 3
+        VAR MULTI b0 NC 2
+        VAR MULTI b1 NC 2
+        PUB MULTI p
+        PUB SINGLE q
+        PUB SINGLE r
+        PROPERTY x = k ^ p
+        PROPERTY y = t[x]
+        PROPERTY z = t2[x, y]
+        PROPERTY a = !x | y
+        PROPERTY b = x & !z
+        PROPERTY c = 3*k - y + p
+        PROPERTY d = x . z
+        PROPERTY e = x * k
+        PROPERTY h = HD(x, p)
+        PROPERTY b0, b1 = BITS(z)
+        PROPERTY f = k
+        PROPERTY q = 2*x - k
+        PROPERTY r = x & y
+        ",
+        t2
+    );
+    let graph = sasca::build_graph(&desc, HashMap::new()).unwrap();
+    let printed = graph.to_description();
+    assert!(printed.contains("PROPERTY ANONYMOUS_3: a = !x | y\n"));
+    assert!(printed.contains("PROPERTY ANONYMOUS_5: c = 3*k + 3*y + p\n"));
+    assert!(printed.contains("VAR MULTI h NC 3\n"));
+    let reparsed = sasca::build_graph(&printed, HashMap::new()).unwrap();
+    assert_eq!(reparsed.to_description(), printed);
+    assert_eq!(reparsed.to_dot(), graph.to_dot());
+    assert_eq!(reparsed.to_graphml(), graph.to_graphml());
+    // Both graphs are the same: same posteriors for k = 1, p = 2.
+    let run = |graph: sasca::FactorGraph| {
+        let pubs = vec![
+            PublicValue::Multi(vec![2]),
+            PublicValue::Single(1),
+            PublicValue::Single(2),
+        ];
+        let mut bp = BPState::new(Arc::new(graph), 1, pubs);
+        let k = bp.get_graph().get_varid("k").unwrap();
+        let ev_k = Array1::from_vec(vec![0.4, 0.3, 0.2, 0.1]);
+        bp.set_evidence(k, Distribution::from_array_single(ev_k).unwrap())
+            .unwrap();
+        let e = bp.get_graph().get_varid("e").unwrap();
+        let ev_e = ndarray::array![[0.1, 0.2, 0.3, 0.4]];
+        bp.set_evidence(e, Distribution::from_array_multi(ev_e).unwrap())
+            .unwrap();
+        bp.propagate_exact(1 << 16).unwrap();
+        let graph = bp.get_graph().clone();
+        graph
+            .vars()
+            .map(|(v, _)| bp.get_state(v).value().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let (res, res_reparsed) = (run(graph.clone()), run(reparsed));
+    assert!(res[0].iter().all(|x| x.is_finite()));
+    assert_eq!(res, res_reparsed);
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("graph factor_graph {\n"));
+    assert!(dot.contains("\"v:k\" [label=\"k\", shape=ellipse, peripheries=1];"));
+    assert!(dot.contains("\"v:x\" [label=\"x\", shape=ellipse, peripheries=2];"));
+    assert!(dot.contains("\"f:ANONYMOUS_1\" [label=\"ANONYMOUS_1\\nLOOKUP t\", shape=box"));
+    assert!(dot.contains("\"p:p\" -- \"f:ANONYMOUS_0\" [style=dashed];"));
+    let graphml = graph.to_graphml();
+    assert_eq!(graphml.matches("<node ").count(), 13 + 3 + 13);
+    assert_eq!(graphml.matches("<edge ").count(), 38);
+
+    // Names that are not identifiers are quoted.
+    let graph = sasca::FactorGraphBuilder::new(2)
+        .var("r0.x[3]", true)
+        .var("x[2]", true)
+        .var("y", false)
+        .public("~p", true)
+        .xor("y", &["r0.x[3]", "~p"])
+        .named("my factor")
+        .and("x[2]", &[("y", true)])
+        .var("a\"b\\c", false)
+        .build()
+        .unwrap();
+    let printed = graph.to_description();
+    assert!(printed.contains("VAR MULTI \"r0.x[3]\"\n"));
+    assert!(printed.contains("PROPERTY \"my factor\": y = \"r0.x[3]\" ^ \"~p\"\n"));
+    assert!(printed.contains("\"x[2]\" = !y\n"));
+    assert!(printed.contains("VAR SINGLE \"a\\\"b\\\\c\"\n"));
+    let reparsed = sasca::build_graph(&printed, HashMap::new()).unwrap();
+    assert_eq!(reparsed.to_description(), printed);
+}
+
+#[test]
+fn graph_simplification() {
+    let graph = build_graph(
+        "
+        NC 4
+        TABLE t1 = [1, 3, 0, 2]
+        TABLE t2 = [2, 2, 1, 0]
+        VAR SINGLE k
+        VAR MULTI s
+        VAR MULTI x
+        VAR MULTI nx
+        VAR MULTI c
+        VAR MULTI a
+        VAR MULTI u
+        VAR MULTI w
+        VAR MULTI y
+        VAR MULTI z
+        VAR MULTI l
+        PUB MULTI p
+        PUB MULTI q
+        PROPERTY s = p ^ q
+        PROPERTY x = k ^ s
+        PROPERTY nx = !x
+        PROPERTY a = nx & c
+        PROPERTY u = !a
+        PROPERTY w = !u
+        PROPERTY y = t1[x]
+        PROPERTY z = t2[y]
+        PROPERTY l = x + c
+        ",
+    );
+    let var = |name| graph.get_varid(name).unwrap();
+    let keep = ["k", "c", "w", "z"];
+    let simplified = graph.simplify(&keep.map(var));
+    let names: Vec<_> = simplified.var_names().collect();
+    assert_eq!(names, vec!["k", "x", "c", "w", "z"]);
+    let printed = simplified.to_description();
+    assert!(printed.contains("TABLE \"t2.t1\" = [2, 0, 2, 1]\n"));
+    assert!(printed.contains("PROPERTY ANONYMOUS_1: x = k ^ p ^ q\n"));
+    assert!(printed.contains("PROPERTY ANONYMOUS_3: w = !x & c\n"));
+    assert!(printed.contains("PROPERTY ANONYMOUS_7: z = \"t2.t1\"[x]\n"));
+    assert_eq!(simplified.factor_names().count(), 3);
+    let sx = simplified.get_varid("x").unwrap();
+    let sw = simplified.get_varid("w").unwrap();
+    assert_eq!(
+        simplified.elimination("nx"),
+        Some(sasca::Elimination::Alias { var: sx, neg: true })
+    );
+    assert_eq!(
+        simplified.elimination("a"),
+        Some(sasca::Elimination::Alias {
+            var: sw,
+            neg: false
+        })
+    );
+    assert_eq!(
+        simplified.elimination("u"),
+        Some(sasca::Elimination::Alias { var: sw, neg: true })
+    );
+    for name in ["s", "y", "l"] {
+        assert_eq!(
+            simplified.elimination(name),
+            Some(sasca::Elimination::Removed)
+        );
+    }
+    assert_eq!(simplified.elimination("x"), None);
+    assert!(matches!(
+        simplified.get_varid("y"),
+        Err(sasca::FGError::Eliminated(_))
+    ));
+    assert!(matches!(
+        simplified.get_varid("foo"),
+        Err(sasca::FGError::NoVar(_))
+    ));
+    let reparsed = sasca::build_graph(&printed, HashMap::new()).unwrap();
+    assert_eq!(reparsed.to_description(), printed);
+
+    // Same exact posteriors before and after simplification.
+    let nmulti = 2;
+    let run = |graph: Arc<sasca::FactorGraph>| {
+        let pubs = vec![
+            PublicValue::Multi(vec![1, 2]),
+            PublicValue::Multi(vec![3, 2]),
+        ];
+        let mut bp = BPState::new(graph, nmulti, pubs);
+        for (i, name) in keep.iter().enumerate() {
+            let v = bp.get_graph().get_varid(name).unwrap();
+            let n = if bp.get_graph().var_multi(v) {
+                nmulti as usize
+            } else {
+                1
+            };
+            let ev = ndarray::Array2::from_shape_fn((n, 4), |(j, k)| {
+                ((i * 5 + j * 3 + k * k) % 7 + 1) as f64
+            });
+            let ev = if n == 1 {
+                Distribution::from_array_single(ev.row(0).to_owned()).unwrap()
+            } else {
+                Distribution::from_array_multi(ev).unwrap()
+            };
+            bp.set_evidence(v, ev).unwrap();
+        }
+        bp.propagate_exact(1 << 16).unwrap();
+        bp
+    };
+    let (bp, bp_simplified) = (run(graph.clone()), run(Arc::new(simplified)));
+    for name in ["k", "x", "c", "w", "z", "nx", "a", "u"] {
+        let state = bp.get_state(var(name)).value().unwrap().to_owned();
+        let state_simplified = bp_simplified.get_state_by_name(name).unwrap();
+        let state_simplified = state_simplified.value().unwrap();
+        for (s, r) in state.rows().into_iter().zip(state_simplified.rows()) {
+            let (s, r) = (&s / s.sum(), &r / r.sum());
+            assert!(
+                s.iter().zip(r.iter()).all(|(s, r)| (s - r).abs() < 1e-10),
+                "{} {:?} {:?}",
+                name,
+                s,
+                r
+            );
+        }
+    }
+    assert!(matches!(
+        bp_simplified.get_state_by_name("y"),
+        Err(sasca::FGError::Eliminated(_))
+    ));
+
+    // A SINGLE variable constrained by several executions is not summed out.
+    let graph = build_graph(
+        "NC 4\nVAR SINGLE k\nVAR SINGLE t\nVAR MULTI x\nVAR SINGLE n\nPUB MULTI p
+        PROPERTY t = k ^ p\nPROPERTY x = k ^ p\nPROPERTY n = !x",
+    );
+    let simplified = graph.simplify(&[graph.get_varid("k").unwrap()]);
+    assert_eq!(
+        simplified.var_names().collect::<Vec<_>>(),
+        vec!["k", "t", "x", "n"]
+    );
+    // Unless the variables are MULTI.
+    let graph = build_graph(
+        "NC 4\nVAR SINGLE k\nVAR MULTI t\nVAR MULTI x\nVAR MULTI n\nPUB MULTI p
+        PROPERTY t = k ^ p\nPROPERTY x = k ^ p\nPROPERTY n = !x",
+    );
+    let simplified = graph.simplify(&[graph.get_varid("k").unwrap()]);
+    assert_eq!(simplified.var_names().collect::<Vec<_>>(), vec!["k"]);
+    assert_eq!(simplified.factor_names().count(), 0);
+
+    // A MULTI variable is not an alias of a SINGLE one, its state has one row per execution.
+    let graph = build_graph(
+        "NC 4\nVAR SINGLE k\nVAR MULTI x\nVAR MULTI y\nPUB MULTI p
+        PROPERTY x = !k\nPROPERTY y = x & p",
+    );
+    let keep = ["k", "y"].map(|name| graph.get_varid(name).unwrap());
+    let simplified = Arc::new(graph.simplify(&keep));
+    assert_eq!(simplified.elimination("x"), None);
+    let mut bp = BPState::new(simplified, 3, vec![PublicValue::Multi(vec![1, 2, 3])]);
+    let k = bp.get_graph().get_varid("k").unwrap();
+    let ev = Distribution::from_array_single(Array1::from(vec![1.0, 2.0, 3.0, 4.0])).unwrap();
+    bp.set_evidence(k, ev).unwrap();
+    bp.propagate_exact(1 << 16).unwrap();
+    let x = bp.get_state_by_name("x").unwrap();
+    assert_eq!(x.shape(), (3, 4));
+    for row in x.value().unwrap().rows() {
+        let row = &row / row.sum();
+        let expected = [0.4, 0.3, 0.2, 0.1];
+        assert!(row.iter().zip(expected).all(|(r, e)| (r - e).abs() < 1e-10));
+    }
+}
+
+#[test]
+fn bp_diagnostics() {
+    let graph = build_graph(
+        "NC 4\nVAR SINGLE a\nVAR SINGLE b\nVAR SINGLE c\nVAR SINGLE e\nVAR SINGLE k
+        VAR MULTI d\nPUB MULTI p
+        PROPERTY c = a ^ b\nPROPERTY e = a & b\nPROPERTY e = c + a\nPROPERTY d = k ^ p",
+    );
+    let mut bp = BPState::new(graph, 2, vec![PublicValue::Multi(vec![1, 3])]);
+    let var = |bp: &BPState, name| bp.get_graph().get_varid(name).unwrap();
+    let (a, c, d) = (var(&bp, "a"), var(&bp, "c"), var(&bp, "d"));
+    // Uniform states.
+    assert_eq!(bp.entropy(a), vec![2.0]);
+    assert_eq!(bp.entropy(d), vec![2.0, 2.0]);
+    assert_eq!(bp.argmax(d), PublicValue::Multi(vec![0, 0]));
+    assert_eq!(
+        bp.probability(d, &PublicValue::Single(1)).unwrap(),
+        vec![0.25, 0.25]
+    );
+    let ev_a = Array1::from_vec(vec![1.0, 2.0, 1.0, 0.0]);
+    bp.set_evidence(a, Distribution::from_array_single(ev_a).unwrap())
+        .unwrap();
+    let ev_d = ndarray::array![[1.0, 1.0, 6.0, 2.0], [4.0, 1.0, 2.0, 1.0]];
+    bp.set_evidence(d, Distribution::from_array_multi(ev_d).unwrap())
+        .unwrap();
+    bp.propagate_all_vars(false);
+    assert_eq!(bp.entropy(a), vec![1.5]);
+    assert_eq!(bp.argmax(a), PublicValue::Single(1));
+    let p = bp.probability(d, &PublicValue::Multi(vec![2, 0])).unwrap();
+    assert!((p[0] - 0.6).abs() < 1e-12 && (p[1] - 0.5).abs() < 1e-12);
+    assert!(matches!(
+        bp.probability(d, &PublicValue::Multi(vec![2])),
+        Err(sasca::BPError::WrongValueCount(1, 2))
+    ));
+    assert!(matches!(
+        bp.probability(a, &PublicValue::Single(4)),
+        Err(sasca::BPError::WrongValue(4, 4))
+    ));
+    assert!(bp
+        .set_trace(vec![(c, Some(PublicValue::Single(7)))])
+        .is_err());
+
+    // The trace holds the summaries after each iteration.
+    bp.set_trace(vec![(c, Some(PublicValue::Single(2))), (d, None)])
+        .unwrap();
+    for step in 1..=3 {
+        bp.propagate_loopy_step(1, false);
+        let trace = bp.trace();
+        assert_eq!(trace.len(), step);
+        let last = &trace[step - 1];
+        assert_eq!(
+            last[0],
+            bp.summary(c, Some(&PublicValue::Single(2))).unwrap()
+        );
+        assert_eq!(last[1], bp.summary(d, None).unwrap());
+        assert_eq!(last[1].probability, None);
+        assert_eq!(last[1].entropy.len(), 2);
+    }
+    let config = sasca::LoopyConfig {
+        tolerance: Some(1e-12),
+        ..Default::default()
+    };
+    let n = bp.propagate_loopy(100, false, &config).unwrap();
+    assert_eq!(bp.trace().len(), 3 + n as usize);
+    bp.set_trace(vec![]).unwrap();
+    assert!(bp.trace().is_empty());
+    bp.propagate_loopy_step(1, false);
+    assert!(bp.trace().is_empty());
+}
+
+#[test]
+fn incremental_bp() {
+    let graph = build_graph(
+        "NC 4\nTABLE t = [2, 0, 3, 1]\nVAR SINGLE k\nVAR MULTI x\nVAR MULTI y\nPUB MULTI p
+        VAR SINGLE j\nVAR MULTI z\nPUB SINGLE q
+        PROPERTY x = k ^ p\nPROPERTY y = t[x]\nPROPERTY z = j + q",
+    );
+    let var = |name| graph.get_varid(name).unwrap();
+    let (k, y, j, z) = (var("k"), var("y"), var("j"), var("z"));
+    let pubs = [1, 3, 0, 2, 2, 1];
+    let ev = |seed: f64, n: usize| {
+        let a = ndarray::Array2::from_shape_fn((n, 4), |(i, c)| {
+            1.0 + ((seed + 3.0 * i as f64 + 7.0 * c as f64) * 1.7).sin()
+        });
+        Distribution::from_array_multi(a).unwrap()
+    };
+    let ev_k = Distribution::from_array_single(Array1::from_vec(vec![0.4, 0.3, 0.2, 0.1])).unwrap();
+    // Reference: acyclic BP on all the executions.
+    let mut full = BPState::new(
+        graph.clone(),
+        6,
+        vec![PublicValue::Multi(pubs.to_vec()), PublicValue::Single(1)],
+    );
+    full.set_evidence(k, ev_k.clone()).unwrap();
+    full.set_evidence(y, ev(0.0, 6)).unwrap();
+    full.set_evidence(z, ev(1.0, 6)).unwrap();
+    full.propagate_acyclic(k, false, false).unwrap();
+    full.propagate_acyclic(j, false, false).unwrap();
+
+    // Executions added in three batches, the last one being empty.
+    let mut bp = BPState::new(
+        graph.clone(),
+        0,
+        vec![PublicValue::Multi(vec![]), PublicValue::Single(1)],
+    );
+    bp.set_evidence(k, ev_k).unwrap();
+    let (ev_y, ev_z) = (ev(0.0, 6), ev(1.0, 6));
+    let rows = |d: &Distribution, a: usize, b: usize| {
+        let v = d.value().unwrap().slice(ndarray::s![a..b, ..]).to_owned();
+        Distribution::from_array_multi(v).unwrap()
+    };
+    for (a, b) in [(0, 4), (4, 6), (6, 6)] {
+        bp.extend(
+            (b - a) as u32,
+            vec![
+                PublicValue::Multi(pubs[a..b].to_vec()),
+                PublicValue::Single(1),
+            ],
+            vec![(y, rows(&ev_y, a, b)), (z, rows(&ev_z, a, b))],
+        )
+        .unwrap();
+        bp.propagate_incremental().unwrap();
+    }
+    assert_eq!(bp.nmulti(), 6);
+    for v in [k, j] {
+        let d = full.get_state(v).value().unwrap().to_owned();
+        let d = &d / d.sum();
+        let d_inc = bp.get_state(v).value().unwrap();
+        assert!(d.abs_diff_eq(&d_inc, 1e-12), "{:?} {:?}", d, d_inc);
+    }
+    // The full state can also be propagated as usual.
+    bp.propagate_acyclic(k, false, false).unwrap();
+    let d = full.get_state(k).value().unwrap().to_owned();
+    let d_bp = bp.get_state(k).value().unwrap().to_owned();
+    assert!((&d / d.sum()).abs_diff_eq(&(&d_bp / d_bp.sum()), 1e-12));
+
+    assert!(matches!(
+        bp.extend(1, vec![PublicValue::Multi(vec![0])], vec![]),
+        Err(sasca::BPError::WrongPublicCount(1, 2))
+    ));
+    assert!(matches!(
+        bp.extend(
+            1,
+            vec![PublicValue::Multi(vec![0]), PublicValue::Single(2)],
+            vec![]
+        ),
+        Err(sasca::BPError::WrongPublic(..))
+    ));
+    assert!(matches!(
+        bp.extend(
+            2,
+            vec![PublicValue::Multi(vec![0]), PublicValue::Single(1)],
+            vec![]
+        ),
+        Err(sasca::BPError::WrongPublic(..))
+    ));
+    assert!(matches!(
+        bp.extend(
+            1,
+            vec![PublicValue::Multi(vec![0]), PublicValue::Single(1)],
+            vec![(y, ev(0.0, 2))]
+        ),
+        Err(sasca::BPError::WrongDistributionNmulti(2, 1))
+    ));
+    assert_eq!(bp.nmulti(), 6);
+
+    // SINGLE factor between two key vars, each with its own leakage and evidence.
+    let graph = build_graph(
+        "NC 4\nTABLE t = [2, 0, 3, 1]\nVAR SINGLE k\nVAR SINGLE k2\nPUB SINGLE c
+        VAR MULTI x\nVAR MULTI y\nVAR MULTI w\nPUB MULTI p
+        PROPERTY k2 = k ^ c\nPROPERTY x = k ^ p\nPROPERTY y = t[x]\nPROPERTY w = k2 + p",
+    );
+    let var = |name| graph.get_varid(name).unwrap();
+    let (k, k2, y, w) = (var("k"), var("k2"), var("y"), var("w"));
+    let pubs = [1, 3, 0, 2, 2, 1, 3];
+    let (ev_y, ev_w) = (ev(2.0, 7), ev(5.0, 7));
+    let ev_k2 =
+        Distribution::from_array_single(Array1::from_vec(vec![0.1, 0.2, 0.3, 0.4])).unwrap();
+    let mut full = BPState::new(
+        graph.clone(),
+        7,
+        vec![PublicValue::Single(2), PublicValue::Multi(pubs.to_vec())],
+    );
+    full.set_evidence(k2, ev_k2.clone()).unwrap();
+    full.set_evidence(y, ev_y.clone()).unwrap();
+    full.set_evidence(w, ev_w.clone()).unwrap();
+    full.propagate_acyclic(k, false, false).unwrap();
+    full.propagate_acyclic(k2, false, false).unwrap();
+    let mut bp = BPState::new(
+        graph.clone(),
+        0,
+        vec![PublicValue::Single(2), PublicValue::Multi(vec![])],
+    );
+    bp.set_evidence(k2, ev_k2).unwrap();
+    for (a, b) in [(0, 2), (2, 5), (5, 7)] {
+        bp.extend(
+            (b - a) as u32,
+            vec![
+                PublicValue::Single(2),
+                PublicValue::Multi(pubs[a..b].to_vec()),
+            ],
+            vec![(y, rows(&ev_y, a, b)), (w, rows(&ev_w, a, b))],
+        )
+        .unwrap();
+        bp.propagate_incremental().unwrap();
+    }
+    for v in [k, k2] {
+        let d = full.get_state(v).value().unwrap().to_owned();
+        let d = &d / d.sum();
+        let d_inc = bp.get_state(v).value().unwrap();
+        assert!(d.abs_diff_eq(&d_inc, 1e-12), "{:?} {:?}", d, d_inc);
+    }
+
+    let cyclic = build_graph("NC 4\nVAR SINGLE a\nVAR SINGLE b\nVAR MULTI x\nPROPERTY x = a ^ b");
+    let mut bp = BPState::new(cyclic, 1, vec![]);
+    assert!(matches!(
+        bp.propagate_incremental(),
+        Err(sasca::BPError::NotAcyclic)
+    ));
+}
+
+#[test]
+fn evidence_from_models() {
+    use scalib::{lda, rlda};
+    let (n, ns) = (200, 8);
+    let classes = Array1::from_shape_fn(n, |i| ((i * 7 + i / 5) % 4) as u16);
+    let traces = ndarray::Array2::from_shape_fn((n, ns), |(i, j)| {
+        (classes[i] as i16) * (j as i16 + 1) * 10 + ((i * 31 + j * 17) % 13) as i16
+    });
+    let lda = lda::LdaAcc::new(4, traces.slice(ndarray::s![.., 0..3]), classes.view(), 0)
+        .lda(2)
+        .unwrap();
+    let mut rlda = rlda::RLDA::new(2, 3, 1, 2);
+    let rlda_classes = classes.mapv(|x| x as u64).insert_axis(ndarray::Axis(0));
+    rlda.update(traces.slice(ndarray::s![.., 5..8]), rlda_classes.view(), 0);
+    rlda.solve().unwrap();
+
+    let graph = build_graph(
+        "NC 4\nVAR SINGLE k\nVAR MULTI x\nVAR MULTI y\nPUB MULTI p\nPROPERTY x = k ^ p\nPROPERTY y = x ^ p",
+    );
+    let (x, y, k) = (
+        graph.get_varid("x").unwrap(),
+        graph.get_varid("y").unwrap(),
+        graph.get_varid("k").unwrap(),
+    );
+    let mut bp = BPState::new(graph, n as u32, vec![PublicValue::Multi(vec![0; n])]);
+    let sources = vec![
+        sasca::EvidenceSource {
+            var: x,
+            model: sasca::EvidenceModel::Lda(&lda),
+            pois: vec![0, 1, 2],
+        },
+        sasca::EvidenceSource {
+            var: y,
+            model: sasca::EvidenceModel::Rlda(&rlda, 0),
+            pois: vec![5, 6, 7],
+        },
+    ];
+    bp.set_evidence_from_models(traces.view(), &sources)
+        .unwrap();
+    bp.propagate_var(x, false);
+    bp.propagate_var(y, false);
+    let expected_x = lda.predict_proba(traces.slice(ndarray::s![.., 0..3]));
+    let expected_y = rlda.predict_proba(traces.slice(ndarray::s![.., 5..8]), 0);
+    assert!(bp
+        .get_state(x)
+        .value()
+        .unwrap()
+        .abs_diff_eq(&expected_x, 1e-9));
+    assert!(bp
+        .get_state(y)
+        .value()
+        .unwrap()
+        .abs_diff_eq(&expected_y, 1e-9));
+
+    let check = |source: sasca::EvidenceSource, traces: ndarray::ArrayView2<i16>| {
+        let mut bp = bp.clone();
+        bp.set_evidence_from_models(traces, &[source]).unwrap_err()
+    };
+    let source = |var, model, pois: &[usize]| sasca::EvidenceSource {
+        var,
+        model,
+        pois: pois.to_vec(),
+    };
+    let lda_model = sasca::EvidenceModel::Lda(&lda);
+    assert!(matches!(
+        check(
+            source(x, lda_model, &[0, 1, 2]),
+            traces.slice(ndarray::s![1.., ..])
+        ),
+        sasca::BPError::WrongTraceCount(199, 200)
+    ));
+    assert!(matches!(
+        check(source(k, lda_model, &[0, 1, 2]), traces.view()),
+        sasca::BPError::WrongDistributionKind(..)
+    ));
+    assert!(matches!(
+        check(source(x, lda_model, &[0, 1]), traces.view()),
+        sasca::BPError::WrongPoiCount(2, 3)
+    ));
+    assert!(matches!(
+        check(source(x, lda_model, &[0, 1, 8]), traces.view()),
+        sasca::BPError::WrongPoi(8, 8)
+    ));
+    assert!(matches!(
+        check(
+            source(x, sasca::EvidenceModel::Rlda(&rlda, 1), &[5, 6, 7]),
+            traces.view()
+        ),
+        sasca::BPError::WrongModelVar(1, 1)
+    ));
+}
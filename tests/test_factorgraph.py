@@ -1,8 +1,10 @@
 import pytest
 from scalib.attacks import FactorGraph, BPState
+from scalib.modeling import LDAClassifier, RLDAClassifier
 import numpy as np
 import os
 import copy
+import itertools
 
 
 def normalize_distr(x):
@@ -79,6 +81,46 @@ def test_table():
     assert np.allclose(distri_y_ref, distri_y)
 
 
+def test_table_2d():
+    """
+    Test two-input Table lookup
+    """
+    nc = 8
+    n = 10
+    table = np.random.randint(0, nc, (nc, nc), dtype=np.uint32)
+    distri_x = np.random.randint(1, 2048, (n, nc))
+    distri_x = (distri_x.T / np.sum(distri_x, axis=1)).T
+    distri_y = np.random.randint(1, 2048, (n, nc))
+    distri_y = (distri_y.T / np.sum(distri_y, axis=1)).T
+
+    graph = f"""
+            PROPERTY z = table[x, y]
+            TABLE table
+            VAR MULTI x
+            VAR MULTI y
+            VAR MULTI z
+            NC {nc}
+            """
+    graph = FactorGraph(graph, {"table": table})
+    x = distri_x.argmax(axis=1)
+    y = distri_y.argmax(axis=1)
+    graph.sanity_check({}, {"x": x, "y": y, "z": table[x, y]})
+    bp_state = BPState(graph, n)
+
+    bp_state.set_evidence("x", distri_x)
+    bp_state.set_evidence("y", distri_y)
+
+    bp_state.bp_loopy(1, True)
+    distri_z = bp_state.get_distribution("z")
+
+    distri_z_ref = np.zeros(distri_x.shape)
+    for x in range(nc):
+        for y in range(nc):
+            distri_z_ref[:, table[x, y]] += distri_x[:, x] * distri_y[:, y]
+
+    assert np.allclose(distri_z_ref, distri_z)
+
+
 def test_table_non_bij():
     """
     Test non-bijective Table lookup
@@ -556,6 +598,121 @@ def test_ADD_multiple():
     assert np.allclose(distri_z_ref, distri_z)
 
 
+def test_SUB():
+    """
+    Test modular subtraction and constant multiplication between distributions
+    """
+    nc = 17
+    n = 4
+    distri_x = make_distri(nc, n)
+    distri_y = make_distri(nc, n)
+    w = 5
+
+    graph = f"""
+        NC {nc}
+        PROPERTY z = 3*x - y - w
+        VAR MULTI z
+        VAR MULTI x
+        VAR MULTI y
+        PUB SINGLE w
+        """
+
+    graph = FactorGraph(graph)
+    x = np.arange(n) % nc
+    y = (2 * np.arange(n)) % nc
+    graph.sanity_check({"w": w}, {"x": x, "y": y, "z": (3 * x - y - w) % nc})
+    bp_state = BPState(graph, n, {"w": w})
+    bp_state.set_evidence("x", distri_x)
+    bp_state.set_evidence("y", distri_y)
+
+    bp_state.bp_loopy(1, True)
+    distri_z = bp_state.get_distribution("z")
+
+    distri_z_ref = np.zeros(distri_z.shape)
+    for x in range(nc):
+        for y in range(nc):
+            distri_z_ref[:, (3 * x - y - w) % nc] += distri_x[:, x] * distri_y[:, y]
+
+    distri_z_ref = normalize_distr(distri_z_ref)
+    assert np.allclose(distri_z_ref, distri_z)
+
+
+def test_BITS():
+    """
+    Test decomposition of a variable into parts
+    """
+    n = 4
+    distri_x = make_distri(32, n)
+    distri_l = make_distri(4, n)
+    distri_h = make_distri(8, n)
+
+    graph = """
+        NC 4
+        PROPERTY l, h = BITS(x)
+        VAR MULTI x NC 32
+        VAR MULTI l
+        VAR MULTI h NC 8
+        """
+
+    graph = FactorGraph(graph)
+    x = np.arange(n) * 7 % 32
+    graph.sanity_check({}, {"x": x, "l": x % 4, "h": x // 4})
+    bp_state = BPState(graph, n)
+    bp_state.set_evidence("x", distri_x)
+    bp_state.set_evidence("l", distri_l)
+    bp_state.set_evidence("h", distri_h)
+
+    bp_state.bp_loopy(1, True)
+
+    distri_x_ref = distri_x * np.repeat(distri_h, 4, axis=1) * np.tile(distri_l, 8)
+    distri_x_ref = normalize_distr(distri_x_ref)
+    distri_h_ref = normalize_distr(distri_x_ref.reshape((n, 8, 4)).sum(axis=2))
+    distri_l_ref = normalize_distr(distri_x_ref.reshape((n, 8, 4)).sum(axis=1))
+    assert np.allclose(distri_x_ref, bp_state.get_distribution("x"))
+    assert np.allclose(distri_h_ref, bp_state.get_distribution("h"))
+    assert np.allclose(distri_l_ref, bp_state.get_distribution("l"))
+
+
+def test_HD():
+    """
+    Test Hamming distance between a distribution and a public value
+    """
+    nc = 256
+    n = 4
+    distri_x = make_distri(nc, n)
+    distri_h = make_distri(9, n)
+    p = np.random.randint(0, nc, n, dtype=np.uint32)
+
+    graph = f"""
+        NC {nc}
+        PROPERTY h = HD(x, p)
+        VAR MULTI x
+        VAR MULTI h NC 9
+        PUB MULTI p
+        """
+
+    graph = FactorGraph(graph)
+    hw = np.array([bin(x).count("1") for x in range(nc)])
+    x = np.arange(n)
+    graph.sanity_check({"p": p}, {"x": x, "h": hw[x ^ p]})
+    bp_state = BPState(graph, n, {"p": p})
+    bp_state.set_evidence("x", distri_x)
+    bp_state.set_evidence("h", distri_h)
+
+    bp_state.bp_loopy(1, True)
+
+    distri_h_ref = np.zeros(distri_h.shape)
+    distri_x_ref = np.zeros(distri_x.shape)
+    for i in range(n):
+        hd = hw[np.arange(nc) ^ p[i]]
+        distri_x_ref[i] = distri_x[i] * distri_h[i, hd]
+        np.add.at(distri_h_ref[i], hd, distri_x[i])
+    distri_h_ref = normalize_distr(distri_h_ref * distri_h)
+    distri_x_ref = normalize_distr(distri_x_ref)
+    assert np.allclose(distri_h_ref, bp_state.get_distribution("h"))
+    assert np.allclose(distri_x_ref, bp_state.get_distribution("x"))
+
+
 def test_MUL():
     """
     Test MUL between distributions
@@ -594,6 +751,67 @@ def test_MUL():
     assert np.allclose(distri_z_ref, distri_z)
 
 
+def gf256_mul(a, b):
+    """Multiplication in the AES field."""
+    res = 0
+    for _ in range(8):
+        if b & 1:
+            res ^= a
+        b >>= 1
+        a <<= 1
+        if a & 0x100:
+            a ^= 0x11B
+    return res
+
+
+def test_GFMUL():
+    """
+    Test GF(256) multiplication between distributions
+    """
+    nc = 256
+    n = 4
+    distri_x = make_distri(nc, n)
+    distri_y = make_distri(nc, n)
+    distri_z = make_distri(nc, n)
+
+    graph = f"""
+        NC {nc}
+        FIELD 0x11b
+        PROPERTY z = x . y
+        VAR MULTI z
+        VAR MULTI x
+        VAR MULTI y
+        """
+
+    graph = FactorGraph(graph)
+    x = np.array([0, 1, 2, 0x53], dtype=np.uint32)
+    y = np.array([3, 0, 0x57, 0xCA], dtype=np.uint32)
+    z = np.array([gf256_mul(a, b) for a, b in zip(x, y)], dtype=np.uint32)
+    assert z[3] == 1
+    graph.sanity_check({}, {"x": x, "y": y, "z": z})
+    with pytest.raises(ValueError):
+        graph.sanity_check({}, {"x": x, "y": y, "z": z ^ 1})
+
+    bp_state = BPState(graph, n)
+    bp_state.set_evidence("x", distri_x)
+    bp_state.set_evidence("y", distri_y)
+    bp_state.set_evidence("z", distri_z)
+    bp_state.bp_acyclic("z")
+    bp_state.bp_acyclic("x")
+
+    distri_z_ref = np.zeros(distri_z.shape)
+    distri_x_ref = np.zeros(distri_x.shape)
+    for a in range(nc):
+        for b in range(nc):
+            c = gf256_mul(a, b)
+            p = distri_x[:, a] * distri_y[:, b] * distri_z[:, c]
+            distri_z_ref[:, c] += p
+            distri_x_ref[:, a] += p
+
+    assert np.allclose(normalize_distr(distri_z_ref), bp_state.get_distribution("z"))
+    assert np.allclose(normalize_distr(distri_x_ref), bp_state.get_distribution("x"))
+
+
 def test_xor():
     """
     Test XOR between distributions
@@ -1166,3 +1384,547 @@ def test_clear_beliefs():
     assert bp.get_belief_from_var("x", "s1") is not None
     assert bp.get_belief_from_var("a", "s1") is not None
     assert bp.get_belief_from_var("b", "s1") is not None
+
+
+def test_bp_loopy_convergence():
+    """
+    Test damping, convergence tolerance and schedules of loopy BP
+    """
+    nc = 4
+    graph = FactorGraph(
+        f"""
+        NC {nc}
+        VAR SINGLE a
+        VAR SINGLE b
+        VAR SINGLE c
+        VAR SINGLE d
+        VAR SINGLE e
+        PROPERTY c = a ^ b
+        PROPERTY d = a & b
+        PROPERTY e = c + d
+        """
+    )
+    evidence = {v: make_distri(nc, 1)[0] for v in ["a", "b", "c", "d", "e"]}
+
+    def run(it, **kwargs):
+        bp = BPState(graph, 1)
+        for v, d in evidence.items():
+            bp.set_evidence(v, d)
+        n = bp.bp_loopy(it, True, **kwargs)
+        return n, [bp.get_distribution(v) for v in evidence]
+
+    n, reference = run(100)
+    assert n == 100
+    for schedule in ["flooding", "sequential", "sorted_sweep"]:
+        for damping in [0.0, 0.4]:
+            n, distrs = run(100, damping=damping, tol=1e-12, schedule=schedule)
+            assert n < 100
+            for d, r in zip(distrs, reference):
+                assert np.allclose(d, r)
+    with pytest.raises(ValueError):
+        run(1, schedule="random")
+    with pytest.raises(ValueError):
+        run(1, damping=1.0)
+
+
+def test_max_product():
+    """
+    Test max-product BP against brute-force MAP on a tree
+    """
+    nc = 4
+    graph = FactorGraph(
+        f"""
+        NC {nc}
+        VAR SINGLE a
+        VAR SINGLE b
+        VAR SINGLE c
+        VAR SINGLE d
+        PUB SINGLE p
+        PROPERTY c = a ^ b ^ p
+        PROPERTY d = c + b
+        """
+    )
+    p = 3
+    evidence = {v: make_distri(nc, 1)[0] for v in ["a", "b", "c", "d"]}
+    bp = BPState(graph, 1, {"p": p}, max_product=True)
+    for v, d in evidence.items():
+        bp.set_evidence(v, d)
+    bp.bp_loopy(5, True)
+    assignment = bp.map_assignment()
+
+    best, best_p = None, 0.0
+    for a, b in itertools.product(range(nc), repeat=2):
+        c = a ^ b ^ p
+        d = (c + b) % nc
+        prob = np.prod([evidence[v][x] for v, x in zip("abcd", (a, b, c, d))])
+        if prob > best_p:
+            best, best_p = (a, b, c, d), prob
+    a, b, c, d = best
+    assert assignment["a"] == a
+    assert assignment["b"] == b
+    assert assignment["c"] == c
+    assert assignment["d"] == d
+    graph.sanity_check({"p": p}, assignment)
+
+
+def test_log_domain():
+    """
+    Test log-domain products with many traces
+    """
+    nc = 16
+    nexec = 1000
+    graph = FactorGraph(
+        f"""
+        NC {nc}
+        VAR SINGLE k
+        VAR MULTI y
+        PUB MULTI p
+        PROPERTY y = k ^ p
+        """
+    )
+    p = np.random.randint(0, nc, nexec, dtype=np.uint32)
+    ev_y = make_distri(nc, nexec)
+    # Exact log-posterior of k.
+    reference = np.array(
+        [np.sum(np.log(ev_y[np.arange(nexec), k ^ p])) for k in range(nc)]
+    )
+    reference -= np.logaddexp.reduce(reference)
+
+    bp = BPState(graph, nexec, {"p": p}, log_domain=True)
+    bp.set_evidence("y", ev_y)
+    bp.bp_loopy(1, True)
+    assert np.allclose(bp.get_log_distribution("k"), reference)
+    assert np.allclose(bp.get_distribution("k"), np.exp(reference))
+
+    bp = BPState(graph, nexec, {"p": p})
+    bp.set_evidence("y", ev_y)
+    bp.bp_loopy(1, True)
+    assert bp.get_log_distribution("k") is None
+
+
+def test_bp_exact():
+    """
+    Test exact inference on a cyclic graph
+    """
+    nc = 4
+    sbox = np.array([2, 0, 3, 1], dtype=np.uint32)
+    graph = FactorGraph(
+        f"""
+        NC {nc}
+        TABLE sbox
+        VAR SINGLE k
+        VAR SINGLE x
+        VAR SINGLE x0
+        VAR SINGLE x1
+        VAR SINGLE y
+        VAR SINGLE z
+        PUB SINGLE p
+        PROPERTY x = k ^ p
+        PROPERTY x1 = x ^ x0
+        PROPERTY y = sbox[x]
+        PROPERTY z = x0 & x
+        """,
+        {"sbox": sbox},
+    )
+    p = 3
+    names = ["k", "x", "x0", "x1", "y", "z"]
+    evidence = {v: make_distri(nc, 1)[0] for v in names}
+    # Brute-force marginals.
+    reference = {v: np.zeros(nc) for v in names}
+    for k, x0 in itertools.product(range(nc), range(nc)):
+        x = k ^ p
+        values = dict(k=k, x=x, x0=x0, x1=x ^ x0, y=sbox[x], z=x0 & x)
+        prob = np.prod([evidence[v][values[v]] for v in names])
+        for v in names:
+            reference[v][values[v]] += prob
+
+    bp = BPState(graph, 1, {"p": p})
+    assert bp.is_cyclic()
+    for v in names:
+        bp.set_evidence(v, evidence[v])
+    max_clique_size, total_size, width = bp.exact_cost()
+    assert width == 2
+    assert max_clique_size == nc**3
+    with pytest.raises(ValueError):
+        bp.bp_exact(max_clique_size=nc**2)
+    bp.bp_exact()
+    for v in names:
+        assert np.allclose(bp.get_distribution(v), normalize_distr(reference[v]))
+
+
+def test_graph_templates():
+    """
+    Test GRAPH templates and indexed variables
+    """
+    nc = 16
+    nexec = 10
+    sbox = np.random.permutation(nc).astype(np.uint32)
+    graph = FactorGraph(
+        f"""
+        NC {nc}
+        TABLE sbox
+        VAR SINGLE k[4]
+        PUB MULTI p[4]
+        VAR MULTI y[4]
+        GRAPH SBOX(p, k, y)
+            VAR MULTI x
+            PROPERTY x = p ^ k
+            PROPERTY y = sbox[x]
+        END
+        GRAPH LAYER(p[4], k[4], y[4])
+            SBOX(p[0..4], k[0..4], y[0..4])
+        END
+        r0: LAYER(p[0..4], k[0..4], y[0..4])
+        """,
+        {"sbox": sbox},
+    )
+    assert "r0.SBOX_0[3].x" in graph.vars()
+    graph_ref = FactorGraph(
+        f"""
+        NC {nc}
+        TABLE sbox
+        VAR SINGLE k
+        PUB MULTI p
+        VAR MULTI x
+        VAR MULTI y
+        PROPERTY x = p ^ k
+        PROPERTY y = sbox[x]
+        """,
+        {"sbox": sbox},
+    )
+    p = np.random.randint(0, nc, (4, nexec), dtype=np.uint32)
+    ev_y = [make_distri(nc, nexec) for _ in range(4)]
+    bp = BPState(graph, nexec, {f"p[{i}]": p[i] for i in range(4)})
+    for i in range(4):
+        bp.set_evidence(f"y[{i}]", ev_y[i])
+    for i in range(4):
+        bp.bp_acyclic(f"k[{i}]")
+        bp_ref = BPState(graph_ref, nexec, {"p": p[i]})
+        bp_ref.set_evidence("y", ev_y[i])
+        bp_ref.bp_acyclic("k")
+        distr_k = bp.get_distribution(f"k[{i}]")
+        assert np.allclose(distr_k, bp_ref.get_distribution("k"))
+
+
+def test_from_netlist():
+    """
+    Test graph import from gate-level netlists
+    """
+    blif = """
+    .model sbox_in
+    .inputs k[0] k[1] p[0] p[1]
+    .outputs x[0] x[1] y
+    .names k[0] p[0] x[0]
+    10 1
+    01 1
+    .names k[1] p[1] x[1]
+    10 1
+    01 1
+    .names x[0] x[1] y
+    11 1
+    .end
+    """
+    verilog = """
+    module sbox_in(input [1:0] k, input [1:0] p, output [1:0] x, output y);
+      xor (x[0], k[0], p[0]);
+      xor (x[1], k[1], p[1]);
+      and (y, x[0], x[1]);
+    endmodule
+    """
+    nexec = 4
+    p = np.arange(nexec, dtype=np.uint32)
+    ev_x = make_distri(4, nexec)
+    distrs = []
+    for netlist, format in [(blif, "blif"), (verilog, "verilog")]:
+        graph = FactorGraph.from_netlist(
+            netlist, format, single=["k"], public=["p"], probes=["x"]
+        )
+        assert set(graph.vars()) == {"k[0]", "k[1]", "x[0]", "x[1]", "x", "y"}
+        bp = BPState(graph, nexec, {"p[0]": p & 1, "p[1]": p >> 1})
+        bp.set_evidence("x", ev_x)
+        bp.bp_exact()
+        distrs.append([bp.get_distribution(f"k[{i}]") for i in range(2)])
+    # Posterior of the key bits from the evidence on x = k ^ p.
+    post = np.prod([ev_x[i, np.arange(4) ^ p[i]] for i in range(nexec)], axis=0)
+    post /= post.sum()
+    for d in distrs:
+        assert np.allclose(d[0], [post[0] + post[2], post[1] + post[3]])
+        assert np.allclose(d[1], [post[0] + post[1], post[2] + post[3]])
+    with pytest.raises(ValueError):
+        FactorGraph.from_netlist(blif, "edif")
+
+
+def test_graph_export():
+    """
+    Test the description, DOT and GraphML exports
+    """
+    nc = 16
+    sbox = np.random.permutation(nc).astype(np.uint32)
+    graph = FactorGraph(
+        f"""
+        NC {nc}
+        TABLE sbox
+        VAR SINGLE k
+        PUB MULTI p
+        VAR MULTI x
+        VAR MULTI y
+        VAR MULTI h NC 5
+        PROPERTY add: x = k ^ p
+        PROPERTY y = sbox[x]
+        PROPERTY h = HW(y)
+        """,
+        {"sbox": sbox},
+    )
+    desc = graph.to_description()
+    assert "PROPERTY add: x = k ^ p\n" in desc
+    graph2 = FactorGraph(desc)
+    assert graph2.to_description() == desc
+    assert graph2.to_dot() == graph.to_dot()
+    assert graph2.vars() == graph.vars()
+    assert graph2.factors() == graph.factors()
+    assert '"f:add" -- ' not in graph.to_dot()
+    assert '"v:k" -- "f:add";' in graph.to_dot()
+    assert graph.to_graphml().count("<node ") == 4 + 1 + 3
+
+
+def test_graph_analysis():
+    """
+    Test the cycles, treewidth and memory reported by FactorGraph.analyze
+    """
+    graph = FactorGraph(
+        """
+        NC 4
+        VAR SINGLE k0
+        VAR SINGLE k1
+        VAR MULTI x
+        VAR MULTI y
+        PUB MULTI p
+        PROPERTY x = k0 ^ p
+        PROPERTY y = x ^ k1
+        """
+    )
+    analysis = graph.analyze(1)
+    assert not analysis.cyclic
+    assert analysis.cycles == [] and analysis.multi_cycles == []
+    analysis = graph.analyze(10)
+    bp = BPState(graph, 10, {"p": np.zeros(10, dtype=np.uint32)})
+    assert analysis.nmulti == 10
+    assert analysis.cyclic and bp.is_cyclic()
+    assert analysis.multi_cycles == [["k0", "x", "k1"]]
+    assert analysis.treewidth == bp.exact_cost()[2]
+    assert analysis.edge_memory == 2 * 5 * 10 * 4 * 8
+    assert analysis.var_memory == 2 * (2 * 10 + 2) * 4 * 8
+
+    graph = FactorGraph(
+        """
+        NC 16
+        VAR SINGLE a
+        VAR SINGLE b
+        VAR SINGLE c
+        VAR SINGLE d
+        PROPERTY c = a ^ b
+        PROPERTY d = a + c
+        """
+    )
+    analysis = graph.analyze(1)
+    assert analysis.cyclic
+    assert [sorted(c) for c in analysis.cycles] == [["a", "c"]]
+    assert analysis.treewidth == 2
+
+
+def test_simplify():
+    """
+    Test that simplification preserves the distributions
+    """
+    nc = 4
+    graph = FactorGraph(
+        """
+        NC 4
+        TABLE t1 = [1, 3, 0, 2]
+        TABLE t2 = [2, 2, 1, 0]
+        VAR SINGLE k
+        VAR MULTI s
+        VAR MULTI x
+        VAR MULTI nx
+        VAR MULTI c
+        VAR MULTI a
+        VAR MULTI y
+        VAR MULTI z
+        PUB MULTI p
+        PUB MULTI q
+        PROPERTY s = p ^ q
+        PROPERTY x = k ^ s
+        PROPERTY nx = !x
+        PROPERTY a = nx & c
+        PROPERTY y = t1[x]
+        PROPERTY z = t2[y]
+        """
+    )
+    simplified = graph.simplify(["k", "c", "a", "z"])
+    assert simplified.vars() == ["k", "x", "c", "a", "z"]
+    assert simplified.eliminated() == {"s": None, "nx": ("x", True), "y": None}
+    n = 3
+    pubs = {
+        "p": np.array([0, 1, 2], dtype=np.uint32),
+        "q": np.array([3, 3, 1], dtype=np.uint32),
+    }
+    rng = np.random.default_rng(0)
+    evidence = {
+        "k": rng.random(nc),
+        "c": rng.random((n, nc)),
+        "a": rng.random((n, nc)),
+        "z": rng.random((n, nc)),
+    }
+    states = []
+    for g in [graph, simplified]:
+        bp = BPState(g, n, pubs)
+        for var, ev in evidence.items():
+            bp.set_evidence(var, ev)
+        bp.bp_exact()
+        states.append(bp)
+    for var in ["k", "x", "nx"]:
+        d = states[0].get_distribution(var)
+        ds = states[1].get_distribution(var)
+        d = d / d.sum(axis=-1, keepdims=True)
+        ds = ds / ds.sum(axis=-1, keepdims=True)
+        assert np.allclose(d, ds)
+    with pytest.raises(ValueError, match="removed by graph simplification"):
+        states[1].get_distribution("y")
+
+
+def test_diagnostics():
+    """
+    Test entropy, argmax, probability and the loopy BP trace
+    """
+    graph = FactorGraph(
+        """
+        NC 4
+        VAR SINGLE a
+        VAR SINGLE b
+        VAR SINGLE c
+        VAR SINGLE k
+        VAR MULTI d
+        PUB MULTI p
+        PROPERTY c = a ^ b
+        PROPERTY c = a + b
+        PROPERTY d = k ^ p
+        """
+    )
+    bp = BPState(graph, 2, {"p": np.array([1, 3], dtype=np.uint32)})
+    assert bp.entropy("a") == 2.0
+    assert np.array_equal(bp.entropy("d"), [2.0, 2.0])
+    ev_a = np.array([1.0, 2.0, 1.0, 0.0])
+    ev_d = np.array([[1.0, 1.0, 6.0, 2.0], [4.0, 1.0, 2.0, 1.0]])
+    bp.set_evidence("a", ev_a)
+    bp.set_evidence("d", ev_d)
+    bp.set_trace(["c", "d", "k"], {"c": 2, "d": [2, 0]})
+    it = 3
+    assert bp.bp_loopy(it, initialize_states=True) == it
+    assert np.isclose(bp.entropy("a"), 1.5)
+    assert bp.argmax("a") == 1
+    assert np.array_equal(bp.argmax("d"), [2, 0])
+    assert np.allclose(bp.probability("d", [2, 0]), [24 / 29, 24 / 29])
+    with pytest.raises(ValueError):
+        bp.probability("a", 4)
+    trace = bp.get_trace()
+    assert set(trace.keys()) == {"c", "d", "k"}
+    assert trace["c"].entropy.shape == (it,)
+    assert trace["c"].argmax.shape == (it,)
+    assert trace["c"].probability.shape == (it,)
+    assert np.isclose(trace["c"].entropy[-1], bp.entropy("c"))
+    assert np.isclose(trace["c"].probability[-1], bp.probability("c", 2))
+    assert trace["d"].entropy.shape == (it, 2)
+    assert np.array_equal(trace["d"].argmax[-1], [2, 0])
+    assert np.allclose(trace["d"].probability[0], [0.6, 0.5])
+    assert np.allclose(trace["d"].probability[-1], bp.probability("d", [2, 0]))
+    assert trace["k"].probability is None
+    bp.set_trace([])
+    assert bp.get_trace() == {}
+
+
+def test_incremental():
+    """
+    Test that adding executions incrementally gives the same result as acyclic BP
+    """
+    nc = 16
+    n = 30
+    graph = FactorGraph(
+        f"""
+        NC {nc}
+        TABLE sbox
+        VAR SINGLE k
+        VAR MULTI x
+        VAR MULTI y
+        PUB MULTI p
+        PROPERTY x = k ^ p
+        PROPERTY y = sbox[x]
+        """,
+        {"sbox": np.random.permutation(nc).astype(np.uint32)},
+    )
+    p = np.random.randint(0, nc, n, dtype=np.uint32)
+    ev_y = np.random.random((n, nc))
+    ev_k = np.random.random(nc)
+    full = BPState(graph, n, {"p": p})
+    full.set_evidence("k", ev_k)
+    full.set_evidence("y", ev_y)
+    full.bp_acyclic("k")
+    expected = full.get_distribution("k")
+    expected = expected / expected.sum()
+
+    bp = BPState(graph, 0, {"p": np.zeros(0, dtype=np.uint32)})
+    bp.set_evidence("k", ev_k)
+    for start, end in [(0, 10), (10, 25), (25, 30)]:
+        bp.extend(end - start, {"p": p[start:end]}, {"y": ev_y[start:end]})
+        bp.bp_incremental()
+    assert np.allclose(bp.get_distribution("k"), expected)
+    with pytest.raises(ValueError):
+        bp.extend(2, {"p": p[:1]})
+
+
+def test_evidence_from_models():
+    """
+    Test that the evidence from models is the same as with predict_proba
+    """
+    nc = 4
+    n = 500
+    ns = 12
+    x = np.random.randint(0, nc, (n, 2))
+    traces = np.random.randint(-20, 20, (n, ns), dtype=np.int16)
+    traces[:, :3] += (x[:, 0] * 10)[:, np.newaxis].astype(np.int16)
+    traces[:, 8:] += (x[:, 1] * 10)[:, np.newaxis].astype(np.int16)
+    pois_x = [0, 1, 2, 5]
+    pois_y = [8, 9, 10, 11]
+    lda = LDAClassifier(nc, 2, len(pois_x))
+    lda.fit_u(traces[:, pois_x], x[:, 0].astype(np.uint16))
+    lda.solve()
+    rlda = RLDAClassifier(2, len(pois_y), 1, 2)
+    rlda.fit_u(traces[:, pois_y], x[:, 1:].astype(np.uint64))
+    rlda.solve()
+
+    graph = FactorGraph(
+        f"""
+        NC {nc}
+        VAR SINGLE k
+        VAR MULTI x
+        VAR MULTI y
+        PUB MULTI p
+        PROPERTY x = k ^ p
+        PROPERTY y = x ^ p
+        """
+    )
+    p = np.zeros(n, dtype=np.uint32)
+    bp = BPState(graph, n, {"p": p})
+    bp.set_evidence_from_models(traces, {"x": (lda, pois_x), "y": (rlda, pois_y, 0)})
+    ref = BPState(graph, n, {"p": p})
+    ref.set_evidence("x", lda.predict_proba(traces[:, pois_x]))
+    ref.set_evidence("y", rlda.predict_proba(traces[:, pois_y], 0))
+    for state in [bp, ref]:
+        state.bp_acyclic("k")
+    assert np.allclose(bp.get_distribution("k"), ref.get_distribution("k"))
+
+    with pytest.raises(ValueError):
+        bp.set_evidence_from_models(traces[1:], {"x": (lda, pois_x)})
+    with pytest.raises(ValueError):
+        bp.set_evidence_from_models(traces, {"x": (lda, pois_y[:3])})
+    with pytest.raises(ValueError):
+        bp.set_evidence_from_models(traces, {"k": (lda, pois_x)})
@@ -1,6 +1,6 @@
 import pytest
 import numpy as np
-from scalib.postprocessing import rank_accuracy
+from scalib.postprocessing import rank_accuracy, rank_accuracy_bounds, aes_key_search
 
 
 def test_rank_accuracy():
@@ -16,3 +16,58 @@ def test_rank_accuracy():
 
     assert r == 1.0
     assert np.log2(rmax) - np.log2(rmin) <= acc
+
+
+def test_rank_accuracy_histbigint():
+    nc = 256
+    nsubkeys = 16
+    acc = 1.0
+
+    costs = np.random.uniform(0.0, 10.0, (nsubkeys, nc))
+    secret_key = np.random.randint(0, nc, nsubkeys)
+
+    rmin_f, _, rmax_f = rank_accuracy(costs, secret_key, acc_bit=acc)
+    rmin, r, rmax = rank_accuracy(costs, secret_key, acc_bit=acc, method="histbigint")
+
+    assert rmin <= r <= rmax
+    assert rmin <= rmax_f and rmin_f <= rmax
+
+
+def test_rank_accuracy_bounds():
+    nc = 16
+    nsubkeys = 4
+    acc = 0.5
+
+    costs_lower = np.random.uniform(0.0, 5.0, (nsubkeys, nc))
+    costs_upper = costs_lower + np.random.uniform(0.0, 1.0, (nsubkeys, nc))
+    secret_key = np.random.randint(0, nc, nsubkeys)
+
+    rmin, r, rmax = rank_accuracy_bounds(
+        costs_lower, costs_upper, secret_key, acc_bit=acc
+    )
+    assert rmin <= r <= rmax
+    for t in [0.0, 0.5, 1.0]:
+        costs = costs_lower + t * (costs_upper - costs_lower)
+        _, rt, _ = rank_accuracy(costs, secret_key, acc_bit=acc, method="naive")
+        assert rmin <= rt <= rmax
+    with pytest.raises(ValueError):
+        rank_accuracy_bounds(costs_upper, costs_lower, secret_key, acc_bit=acc)
+
+
+def test_aes_key_search():
+    # FIPS-197 AES-128 test vector.
+    key = np.arange(16, dtype=np.uint8)
+    pt = np.frombuffer(bytes.fromhex("00112233445566778899aabbccddeeff"), np.uint8)
+    ct = np.frombuffer(bytes.fromhex("69c4e0d86a7b0430d8cdb78070b4c55a"), np.uint8)
+    costs = np.full((16, 256), 10.0)
+    costs[np.arange(16), key] = 0.0
+    # The right value is the second most likely one for two of the key bytes.
+    costs[[0, 8], key[[0, 8]]] = 1.0
+    costs[[0, 8], key[[0, 8]] + 1] = 0.5
+    found, nb_keys = aes_key_search(costs, [pt], [ct])
+    assert np.array_equal(found, key)
+    assert nb_keys == 4
+    found, nb_keys = aes_key_search(costs, [pt], [ct], max_nb_keys=3)
+    assert found is None and nb_keys == 3
+    with pytest.raises(ValueError):
+        aes_key_search(costs[:8], [pt], [ct])